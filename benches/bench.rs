@@ -87,7 +87,7 @@ fn mpeg2ts_reader(c: &mut Criterion) {
         let l = f.metadata().unwrap().len() as usize;
         let size = l.min(188 * 200_000);
         let mut buf = vec![0; size];
-        f.read(&mut buf[..]).unwrap();
+        f.read_exact(&mut buf[..]).unwrap();
         buf
     };
     let mut ctx = NullDemuxContext::new();
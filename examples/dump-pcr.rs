@@ -54,6 +54,12 @@ impl PcrDumpDemuxContext {
 pub struct PcrPacketFilter<Ctx: DemuxContext> {
     phantom: marker::PhantomData<Ctx>,
 }
+impl<Ctx: DemuxContext> Default for PcrPacketFilter<Ctx> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<Ctx: DemuxContext> PcrPacketFilter<Ctx> {
     pub fn construct(
         _pmt: &psi::pmt::PmtSection,
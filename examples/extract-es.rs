@@ -0,0 +1,149 @@
+//! Writes the raw elementary stream data of the PES streams listed in the PMT to files in the
+//! current directory, named by PID and stream type, e.g. `0100-1b.h264`.
+//!
+//! Usage: `extract-es <input.ts> [pid...]`, where each PID is given in hex.  If no PIDs are
+//! given, every PES stream is extracted.
+
+use mpeg2ts_reader::demultiplex;
+use mpeg2ts_reader::packet;
+use mpeg2ts_reader::pes;
+use mpeg2ts_reader::psi;
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Read};
+
+type EsWriter = pes::ElementaryStreamWriter<BufWriter<File>>;
+
+pub enum ExtractFilterSwitch {
+    Pes(pes::PesPacketFilter<ExtractDemuxContext, EsWriter>),
+    Pat(demultiplex::PatPacketFilter<ExtractDemuxContext>),
+    Pmt(demultiplex::PmtPacketFilter<ExtractDemuxContext>),
+    Null(demultiplex::NullPacketFilter<ExtractDemuxContext>),
+}
+impl demultiplex::PacketFilter for ExtractFilterSwitch {
+    type Ctx = ExtractDemuxContext;
+
+    fn consume(&mut self, ctx: &mut ExtractDemuxContext, pk: &packet::Packet<'_>) {
+        match self {
+            ExtractFilterSwitch::Pes(f) => f.consume(ctx, pk),
+            ExtractFilterSwitch::Pat(f) => f.consume(ctx, pk),
+            ExtractFilterSwitch::Pmt(f) => f.consume(ctx, pk),
+            ExtractFilterSwitch::Null(f) => f.consume(ctx, pk),
+        }
+    }
+
+    fn end_stream(&mut self, ctx: &mut ExtractDemuxContext) {
+        match self {
+            ExtractFilterSwitch::Pes(f) => f.end_stream(ctx),
+            ExtractFilterSwitch::Pat(f) => f.end_stream(ctx),
+            ExtractFilterSwitch::Pmt(f) => f.end_stream(ctx),
+            ExtractFilterSwitch::Null(f) => f.end_stream(ctx),
+        }
+    }
+}
+
+pub struct ExtractDemuxContext {
+    changeset: demultiplex::FilterChangeset<ExtractFilterSwitch>,
+    /// the PIDs to extract, or `None` to extract all PES streams
+    selected: Option<HashSet<packet::Pid>>,
+    /// the PIDs whose output files have already been created, and which should be appended to if
+    /// the PMT announces them again
+    created: HashSet<packet::Pid>,
+}
+impl ExtractDemuxContext {
+    fn new(selected: Option<HashSet<packet::Pid>>) -> Self {
+        ExtractDemuxContext {
+            changeset: demultiplex::FilterChangeset::default(),
+            selected,
+            created: HashSet::new(),
+        }
+    }
+
+    fn open(&mut self, pid: packet::Pid, stream_type: mpeg2ts_reader::StreamType) -> EsWriter {
+        let name = format!(
+            "{:04x}-{:02x}.{}",
+            u16::from(pid),
+            u8::from(stream_type),
+            stream_type.file_extension()
+        );
+        let file = if self.created.insert(pid) {
+            eprintln!("writing {:?} stream on {:?} to {}", stream_type, pid, name);
+            File::create(&name)
+        } else {
+            File::options().append(true).open(&name)
+        };
+        let file = file.unwrap_or_else(|e| panic!("couldn't open {}: {}", name, e));
+        pes::ElementaryStreamWriter::new(BufWriter::new(file))
+    }
+}
+impl demultiplex::DemuxContext for ExtractDemuxContext {
+    type F = ExtractFilterSwitch;
+
+    fn filter_changeset(&mut self) -> &mut demultiplex::FilterChangeset<Self::F> {
+        &mut self.changeset
+    }
+
+    fn construct(&mut self, req: demultiplex::FilterRequest<'_, '_>) -> Self::F {
+        match req {
+            demultiplex::FilterRequest::ByPid(psi::pat::PAT_PID) => {
+                ExtractFilterSwitch::Pat(demultiplex::PatPacketFilter::default())
+            }
+            demultiplex::FilterRequest::ByPid(_) => {
+                ExtractFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+            }
+            demultiplex::FilterRequest::ByStream {
+                stream_type,
+                stream_info,
+                ..
+            } => {
+                let pid = stream_info.elementary_pid();
+                let selected = match self.selected {
+                    Some(ref pids) => pids.contains(&pid),
+                    None => true,
+                };
+                if selected && stream_type.is_pes() {
+                    let writer = self.open(pid, stream_type);
+                    ExtractFilterSwitch::Pes(pes::PesPacketFilter::new(writer))
+                } else {
+                    ExtractFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+                }
+            }
+            demultiplex::FilterRequest::Pmt {
+                pid,
+                program_number,
+            } => ExtractFilterSwitch::Pmt(demultiplex::PmtPacketFilter::new(pid, program_number)),
+            demultiplex::FilterRequest::Nit { .. } => {
+                ExtractFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+            }
+        }
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut args = env::args().skip(1);
+    let name = args.next().expect("usage: extract-es <input.ts> [pid...]");
+    let pids: HashSet<packet::Pid> = args
+        .map(|arg| {
+            let val = u16::from_str_radix(arg.trim_start_matches("0x"), 16)
+                .unwrap_or_else(|_| panic!("invalid PID: {}", arg));
+            packet::Pid::new(val)
+        })
+        .collect();
+    let selected = if pids.is_empty() { None } else { Some(pids) };
+    let mut f = File::open(&name).unwrap_or_else(|_| panic!("file not found: {}", &name));
+
+    let mut ctx = ExtractDemuxContext::new(selected);
+    let mut demux = demultiplex::Demultiplex::new(&mut ctx);
+    let mut buf = [0u8; 188 * 1024];
+    loop {
+        match f.read(&mut buf[..]).expect("read failed") {
+            0 => break,
+            n => demux.push(&mut ctx, &buf[0..n]),
+        }
+    }
+    // deliver the final PES packet of each stream, and flush the output files
+    demux.flush(&mut ctx);
+}
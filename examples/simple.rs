@@ -1,24 +1,15 @@
 #[macro_use]
 extern crate mpeg2ts_reader;
-extern crate hex_slice;
 
-use hex_slice::AsHex;
 use mpeg2ts_reader::demultiplex;
-use mpeg2ts_reader::packet;
-use mpeg2ts_reader::parser;
 
+use mpeg2ts_reader::parser::NALParser;
 use mpeg2ts_reader::pes;
 use mpeg2ts_reader::psi;
 use mpeg2ts_reader::StreamType;
-use std::cmp;
 use std::env;
 use std::fs::File;
 use std::io::Read;
-use std::io::Write;
-use std::io::{BufReader, BufWriter};
-use byteorder::{WriteBytesExt, LittleEndian};
-use mpeg2ts_reader::parser::{HeaderCode, NALParser};
-use mpeg2ts_reader::pes::Timestamp;
 
 // This macro invocation creates an enum called DumpFilterSwitch, encapsulating all possible ways
 // that this application may handle transport stream packets.  Each enum variant is just a wrapper
@@ -98,102 +89,62 @@ impl DumpDemuxContext {
 // Implement the ElementaryStreamConsumer to just dump and PTS/DTS timestamps to stdout
 pub struct PtsDumpElementaryStreamConsumer {
     nal_parser: NALParser,
-    packet_end_ts: f64,
-    is_first_packet: bool
 }
 
 impl PtsDumpElementaryStreamConsumer {
     fn construct(
         _pmt_sect: &psi::pmt::PmtSection,
-        stream_info: &psi::pmt::StreamInfo,
+        _stream_info: &psi::pmt::StreamInfo,
     ) -> DumpFilterSwitch {
         let filter = pes::PesPacketFilter::new(PtsDumpElementaryStreamConsumer {
             nal_parser: NALParser::new(),
-            packet_end_ts: 0.0,
-            is_first_packet: false
         });
         DumpFilterSwitch::Pes(filter)
     }
 
-    fn time_in_seconds(pts: Timestamp) -> f64 {
-        (pts.value() as f64 * 1.0) / (Timestamp::TIMEBASE as f64)
+    fn print_segments(&mut self) {
+        for seg in self.nal_parser.drain_segments() {
+            println!(
+                "{:?} {:?} start={:.3} duration={:.3} size={}",
+                seg.frame_type(),
+                seg.picture_type(),
+                seg.decode_time_secs(),
+                seg.duration_secs(),
+                seg.payload().map(|p| p.len()).unwrap_or(0)
+            );
+        }
     }
 }
 
 impl pes::ElementaryStreamConsumer<DumpDemuxContext> for PtsDumpElementaryStreamConsumer {
-    fn start_stream(&mut self, _ctx: &mut DumpDemuxContext) {
-        self.is_first_packet = true;
-    }
+    fn start_stream(&mut self, _ctx: &mut DumpDemuxContext) {}
 
     fn begin_packet(&mut self, _ctx: &mut DumpDemuxContext, header: pes::PesHeader) {
         match header.contents() {
             pes::PesContents::Parsed(Some(parsed)) => {
-                match parsed.pts_dts() {
-                    Ok(pes::PtsDts::PtsOnly(Ok(pts))) => {
-                        if self.is_first_packet {
-                            self.nal_parser.decoding_delay(Self::time_in_seconds(pts));
-                            self.is_first_packet = false;
-                        } else {
-                            self.nal_parser.set_duration_for_previous_packet(Self::time_in_seconds(pts));
-                        }
-                        self.packet_end_ts = Self::time_in_seconds(pts);
-                    }
-                    _ => (),
-                }
-                let payload = parsed.payload();
-                let mut output_file = File::options().append(true).open("a.dat").expect("test");
-
-                let mut writer = BufWriter::new(output_file);
-                // let startCode:[u8;4] = [ 0x00, 0x00, 0x00, 0x01];
-                // writer.write(&startCode);
-                // writer.write(payload);
-                // println!("Is Unit Access Code",)
-                // println!("Packet begins with payload = {}", payload.len());
-                self.nal_parser.begin_packet(payload);
-                // let accessCode = NALParser::is_four_byte_nalunit(
-                //     6,
-                //     HeaderCode::Sps,
-                //     &payload
-                // );
-                //
-                // if accessCode == true {
-                //     println!("Contains SPS");
-                // }
-                // println!(
-                //     "Start write {:02x}",
-                //     payload[..cmp::min(payload.len(), 16)].plain_hex(false)
-                // )
+                let (pts, dts) = match parsed.pts_dts() {
+                    Ok(pts_dts) => (pts_dts.pts(), pts_dts.dts()),
+                    Err(_) => (None, None),
+                };
+                self.nal_parser.begin_packet(pts, dts, parsed.payload());
             }
             pes::PesContents::Parsed(None) => (),
-            pes::PesContents::Payload(payload) => {
-
-                // println!(
-                //     "......{:?}:                               {:02x}",
-                //     self.pid,
-                //     payload[..cmp::min(payload.len(), 16)].plain_hex(false)
-                // )
-            }
+            pes::PesContents::Payload(_) => (),
         }
     }
 
     fn continue_packet(&mut self, _ctx: &mut DumpDemuxContext, data: &[u8]) {
-        // let output_file = match File::create("a.txt") {
-        //     Ok(file) => file,
-        //     Err(e) => return (),
-        // };
-        let mut output_file = File::options().append(true).open("a.dat").expect("test");
-
-        let mut writer = BufWriter::new(output_file);
-        // println!("Packet continues with payload = {}", data.len());
-        // let startCode:[u8;4] = [ 0x00, 0x00, 0x00, 0x01];
-        // writer.write(&startCode);
-        // writer.write(data);
         self.nal_parser.continue_packet(data);
     }
 
     fn end_packet(&mut self, _ctx: &mut DumpDemuxContext) {
-        println!("Packet end ts = {}", self.packet_end_ts);
-        self.nal_parser.end_packet(self.packet_end_ts);
+        self.nal_parser.end_packet();
+        self.print_segments();
+    }
+
+    fn end_stream(&mut self, _ctx: &mut DumpDemuxContext) {
+        self.nal_parser.flush();
+        self.print_segments();
     }
 
     fn continuity_error(&mut self, _ctx: &mut DumpDemuxContext) {}
@@ -214,42 +165,13 @@ fn main() {
     let mut demux = demultiplex::Demultiplex::new(&mut ctx);
     let mut buf = [0u8; 188 * 1024];
 
-    let buffer: Vec<Vec<i32>> = vec![vec![1, 2, 3], vec![4, 5, 6]];
-
-    // Attempt to create a new file
-    // let output_file = match File::create("a.txt") {
-    //     Ok(file) => file,
-    //     Err(e) => return (),
-    // };
-
-    // let mut writer = BufWriter::new(output_file);
-
-    // for chunk in buffer.iter() {
-    //     for &item in chunk.iter() {
-    //         writer.write_i32::<LittleEndian>(item);
-    //     }
-    // }
-
-
-    // for chunk in data {
-    //     let test = file.expect("REASON").write_all(&chunk);
-    // }
-
+    // consume the input file,
     loop {
         match f.read(&mut buf[..]).expect("read failed") {
-            0 => {
-                break;
-            },
-            n => {
-                demux.push(&mut ctx, &buf[0..n]);
-                // writer.write(&buf);
-            }//demux.push(&mut ctx, &buf[0..n]),
+            0 => break,
+            n => demux.push(&mut ctx, &buf[0..n]),
         }
     }
-
-    // loop {
-    //     f.read(&mut buf[..]).expect("Test");
-    //     writer.write(&buf).expect("Test1");
-    // }
-    // writer.flush();
+    // deliver the final PES packet of each stream
+    demux.flush(&mut ctx);
 }
@@ -12,14 +12,15 @@
 //!    for each type of sub-stream found within the Transport Stream data. possibly by using the
 //!    [`demux_context!()`](../macro.demux_context.html) macro.
 
+use crate::descramble;
 use crate::packet;
 use crate::packet::TransportScramblingControl;
+use crate::pes;
 use crate::psi;
 use crate::psi::pat;
 use crate::psi::pmt::PmtSection;
 use crate::psi::pmt::StreamInfo;
 use crate::StreamType;
-use log::warn;
 use std::marker;
 
 /// Trait to which `Demultiplex` delegates handling of subsets of Transport Stream packets.
@@ -35,6 +36,12 @@ pub trait PacketFilter {
 
     /// Implements filter-specific packet processing logic.
     fn consume(&mut self, ctx: &mut Self::Ctx, pk: &packet::Packet<'_>);
+
+    /// Called by [`Demultiplex::flush()`](struct.Demultiplex.html#method.flush) when the end of
+    /// the stream has been reached, so that the filter can finish processing any data it has
+    /// buffered, or discard data that can now never be completed.  The default implementation
+    /// does nothing.
+    fn end_stream(&mut self, _ctx: &mut Self::Ctx) {}
 }
 
 /// No-op implementation of `PacketFilter`.
@@ -63,7 +70,8 @@ impl<Ctx: DemuxContext> PacketFilter for NullPacketFilter<Ctx> {
 /// This macro takes two arguments; the name for the new type, and the name of an existing
 /// implementation of `PacketFilter`.  It then..
 ///
-/// 1. creates a struct with the given name, wrapping an instance of `FilterChangeset`
+/// 1. creates a struct with the given name, wrapping an instance of `FilterChangeset` and the
+///    current `PacketPosition`
 /// 2. provides an implementation of `default()` for that struct
 /// 3. provides an implementation of `DemuxContext`
 ///
@@ -106,11 +114,13 @@ macro_rules! demux_context {
     ($name:ident, $filter:ty) => {
         pub struct $name {
             changeset: $crate::demultiplex::FilterChangeset<$filter>,
+            position: $crate::demultiplex::PacketPosition,
         }
         impl $name {
             pub fn new() -> Self {
                 $name {
                     changeset: $crate::demultiplex::FilterChangeset::default(),
+                    position: $crate::demultiplex::PacketPosition::default(),
                 }
             }
         }
@@ -120,6 +130,12 @@ macro_rules! demux_context {
             fn filter_changeset(&mut self) -> &mut $crate::demultiplex::FilterChangeset<Self::F> {
                 &mut self.changeset
             }
+            fn set_packet_position(&mut self, position: $crate::demultiplex::PacketPosition) {
+                self.position = position;
+            }
+            fn packet_position(&self) -> $crate::demultiplex::PacketPosition {
+                self.position
+            }
             fn construct(&mut self, req: $crate::demultiplex::FilterRequest<'_, '_>) -> Self::F {
                 self.do_construct(req)
             }
@@ -153,6 +169,11 @@ macro_rules! packet_filter_switch {
 
                 }
             }
+            fn end_stream(&mut self, ctx: &mut $ctx) {
+                match self {
+                    $( &mut $name::$case_name(ref mut f) => f.end_stream(ctx), )*
+                }
+            }
         }
     }
 }
@@ -194,6 +215,11 @@ impl<F: PacketFilter> Filters<F> {
         self.filters_by_pid[usize::from(pid)] = Some(filter);
     }
 
+    /// One more than the highest PID value for which a filter may be present
+    pub fn pid_limit(&self) -> usize {
+        self.filters_by_pid.len()
+    }
+
     pub fn remove(&mut self, pid: packet::Pid) {
         if usize::from(pid) < self.filters_by_pid.len() {
             self.filters_by_pid[usize::from(pid)] = None;
@@ -273,6 +299,41 @@ impl<F: PacketFilter> FilterChangeset<F> {
     }
 }
 
+/// The Pid and stream offset of the packet which a `Demultiplex` instance is currently
+/// processing.
+///
+/// `Demultiplex` passes this to
+/// [`DemuxContext::set_packet_position()`](trait.DemuxContext.html#method.set_packet_position)
+/// before each packet is given to a `PacketFilter`, so that the components making up the filter
+/// can say where in the stream any [`DemuxEvent`](enum.DemuxEvent.html) they report was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketPosition {
+    pid: packet::Pid,
+    offset: u64,
+}
+impl Default for PacketPosition {
+    fn default() -> PacketPosition {
+        PacketPosition {
+            pid: packet::Pid::STUFFING,
+            offset: 0,
+        }
+    }
+}
+impl PacketPosition {
+    /// Create a value for the packet with the given Pid and stream offset.
+    pub fn new(pid: packet::Pid, offset: u64) -> PacketPosition {
+        PacketPosition { pid, offset }
+    }
+    /// The Pid of the packet.
+    pub fn pid(&self) -> packet::Pid {
+        self.pid
+    }
+    /// The offset of the packet from the start of the data given to `Demultiplex::push()`.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
 impl<F: PacketFilter> std::iter::IntoIterator for FilterChangeset<F> {
     type Item = FilterChange<F>;
     type IntoIter = std::vec::IntoIter<FilterChange<F>>;
@@ -341,15 +402,34 @@ impl<Ctx: DemuxContext> PmtProcessor<Ctx> {
         sect: &PmtSection<'_>,
     ) {
         if 0x02 != header.table_id {
-            warn!(
-                "[PMT {:?} program:{}] Expected PMT to have table id 0x2, but got {:#x}",
-                self.pid, self.program_number, header.table_id
-            );
+            let offset = ctx.packet_position().offset();
+            ctx.event(DemuxEvent::WrongTableId {
+                pid: self.pid,
+                offset,
+                expected: 0x02,
+                actual: header.table_id,
+            });
             return;
         }
         // pass the table_id value this far!
         let mut pids_seen = fixedbitset::FixedBitSet::with_capacity(packet::Pid::PID_COUNT);
-        for stream_info in sect.streams() {
+        for stream_info in sect.stream_entries() {
+            let stream_info = match stream_info {
+                Ok(stream_info) => stream_info,
+                Err(DemuxError::NotEnoughData {
+                    expected, actual, ..
+                }) => {
+                    let offset = ctx.packet_position().offset();
+                    ctx.event(DemuxEvent::StreamInfoTruncated {
+                        pid: self.pid,
+                        offset,
+                        program_number: self.program_number,
+                        expected,
+                        actual,
+                    });
+                    break;
+                }
+            };
             let pes_packet_consumer = ctx.construct(FilterRequest::ByStream {
                 program_pid: self.pid,
                 stream_type: stream_info.stream_type(),
@@ -378,21 +458,25 @@ impl<Ctx: DemuxContext> PmtProcessor<Ctx> {
 impl<Ctx: DemuxContext> psi::WholeSectionSyntaxPayloadParser for PmtProcessor<Ctx> {
     type Context = Ctx;
 
-    fn section<'a>(
+    fn section(
         &mut self,
         ctx: &mut Self::Context,
         header: &psi::SectionCommonHeader,
         table_syntax_header: &psi::TableSyntaxHeader<'_>,
-        data: &'a [u8],
+        data: &[u8],
     ) {
         let start = psi::SectionCommonHeader::SIZE + psi::TableSyntaxHeader::SIZE;
         let end = data.len() - 4; // remove CRC bytes
         match PmtSection::from_bytes(&data[start..end]) {
             Ok(sect) => self.new_table(ctx, header, table_syntax_header, &sect),
-            Err(e) => warn!(
-                "[PMT {:?} program:{}] problem reading data: {:?}",
-                self.pid, self.program_number, e
-            ),
+            Err(_) => {
+                let offset = ctx.packet_position().offset();
+                ctx.event(DemuxEvent::InvalidPmt {
+                    pid: self.pid,
+                    offset,
+                    program_number: self.program_number,
+                });
+            }
         }
     }
 }
@@ -451,6 +535,10 @@ impl<Ctx: DemuxContext> PacketFilter for PmtPacketFilter<Ctx> {
     fn consume(&mut self, ctx: &mut Self::Ctx, pk: &packet::Packet<'_>) {
         self.pmt_section_packet_consumer.consume(ctx, pk);
     }
+
+    fn end_stream(&mut self, _ctx: &mut Self::Ctx) {
+        self.pmt_section_packet_consumer.reset();
+    }
 }
 
 struct PatProcessor<Ctx: DemuxContext> {
@@ -475,15 +563,33 @@ impl<Ctx: DemuxContext> PatProcessor<Ctx> {
         sect: &pat::PatSection<'_>,
     ) {
         if 0x00 != header.table_id {
-            warn!(
-                "Expected PAT to have table id 0x0, but got {:#x}",
-                header.table_id
-            );
+            let offset = ctx.packet_position().offset();
+            ctx.event(DemuxEvent::WrongTableId {
+                pid: psi::pat::PAT_PID,
+                offset,
+                expected: 0x00,
+                actual: header.table_id,
+            });
             return;
         }
         let mut pids_seen = fixedbitset::FixedBitSet::with_capacity(packet::Pid::PID_COUNT);
         // add or update filters for descriptors we've not seen before,
-        for desc in sect.programs() {
+        for desc in sect.program_entries() {
+            let desc = match desc {
+                Ok(desc) => desc,
+                Err(DemuxError::NotEnoughData {
+                    expected, actual, ..
+                }) => {
+                    let offset = ctx.packet_position().offset();
+                    ctx.event(DemuxEvent::PatEntryTruncated {
+                        pid: psi::pat::PAT_PID,
+                        offset,
+                        expected,
+                        actual,
+                    });
+                    break;
+                }
+            };
             let filter = match desc {
                 pat::ProgramDescriptor::Program {
                     program_number,
@@ -516,12 +622,12 @@ impl<Ctx: DemuxContext> PatProcessor<Ctx> {
 impl<Ctx: DemuxContext> psi::WholeSectionSyntaxPayloadParser for PatProcessor<Ctx> {
     type Context = Ctx;
 
-    fn section<'a>(
+    fn section(
         &mut self,
         ctx: &mut Self::Context,
         header: &psi::SectionCommonHeader,
         table_syntax_header: &psi::TableSyntaxHeader<'_>,
-        data: &'a [u8],
+        data: &[u8],
     ) {
         let start = psi::SectionCommonHeader::SIZE + psi::TableSyntaxHeader::SIZE;
         let end = data.len() - 4; // remove CRC bytes
@@ -553,6 +659,36 @@ pub trait DemuxContext: Sized {
     /// returns an instance of `PacketFilter` implementing the application's desired handling for
     /// the given content.
     fn construct(&mut self, req: FilterRequest<'_, '_>) -> Self::F;
+
+    /// Called to report problems found in the stream, and changes in the `Demultiplex` instance's
+    /// synchronisation with the packet structure of the stream.  The default implementation
+    /// does nothing.
+    fn event(&mut self, _event: DemuxEvent) {}
+
+    /// Returns the `Descrambler` to be used for packets which are marked as scrambled, if any.
+    /// The default implementation returns `None`, causing scrambled packets to be dropped.
+    fn descrambler(&mut self) -> Option<&mut dyn descramble::Descrambler> {
+        None
+    }
+
+    /// Called by `Demultiplex` with the position of each packet before it is given to a
+    /// `PacketFilter`, and with the end-of-stream position before
+    /// [`PacketFilter::end_stream()`](trait.PacketFilter.html#method.end_stream) is called.
+    ///
+    /// Filters can find the offset of a packet with
+    /// [`Packet::offset()`](../packet/struct.Packet.html#method.offset), but the components
+    /// making up a filter (for example, the PSI section parsers) only have access to the context,
+    /// and use `packet_position()` to say where an event they report was found.  Contexts
+    /// created by [`demux_context!()`](../macro.demux_context.html) retain the position; the
+    /// default implementation discards it.
+    fn set_packet_position(&mut self, _position: PacketPosition) {}
+
+    /// Returns the position last given to `set_packet_position()`.  The default implementation
+    /// returns `PacketPosition::default()`, so that events reported by the components of a filter
+    /// will give `Pid::STUFFING` and an offset of `0`.
+    fn packet_position(&self) -> PacketPosition {
+        PacketPosition::default()
+    }
 }
 
 /// `PacketFilter` implementation which will insert some other `PacketFilter` into the `Demultiplex`
@@ -584,6 +720,316 @@ impl<Ctx: DemuxContext> PacketFilter for PatPacketFilter<Ctx> {
     fn consume(&mut self, ctx: &mut Self::Ctx, pk: &packet::Packet<'_>) {
         self.pat_section_packet_consumer.consume(ctx, pk);
     }
+
+    fn end_stream(&mut self, _ctx: &mut Self::Ctx) {
+        self.pat_section_packet_consumer.reset();
+    }
+}
+
+/// Problems and other noteworthy conditions discovered while demultiplexing, reported via
+/// [`DemuxContext::event()`](trait.DemuxContext.html#method.event).
+///
+/// Offsets are counted in bytes from the start of the first buffer given to `Demultiplex::push()`.
+/// Events relating to the content of a particular packet give the offset of the start of that
+/// packet.  If filters are driven directly rather than by a `Demultiplex` instance, offsets will
+/// be reported as `0`.  Events found by the components of a filter, rather than by the filter
+/// itself, are only given the packet's Pid and offset if the `DemuxContext` retains the
+/// position passed to
+/// [`set_packet_position()`](trait.DemuxContext.html#method.set_packet_position).
+///
+/// New kinds of event may be added in future releases, so code matching on this type needs a
+/// wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DemuxEvent {
+    /// The demultiplexer found the required number of consecutive sync-bytes, and will resume
+    /// processing packets from the given offset.
+    SyncAcquired {
+        /// stream offset of the first packet following the acquisition of sync
+        offset: u64,
+    },
+    /// Too many packets in succession lacked a valid sync-byte, and the demultiplexer will now
+    /// hunt for the packet structure again, starting just after the first of those packets.
+    SyncLost {
+        /// stream offset at which the first bad sync-byte was expected
+        offset: u64,
+    },
+    /// A packet with _transport_error_indicator_ set was dropped.
+    TransportError {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// A scrambled packet was dropped since no `Descrambler` was available.
+    ScrambledPacketDropped {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// A scrambled packet was dropped since the `Descrambler` was unable to process it.
+    DescrambleFailed {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the problem reported by the `Descrambler`
+        error: descramble::DescrambleError,
+    },
+    /// The continuity counter of a packet did not follow on from that of the previous packet
+    /// with the same PID, indicating that data has been lost.
+    ContinuityError {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// Elementary stream data was dropped since the start of the PES packet it belongs to was
+    /// not seen.
+    PesContinuationWithoutStart {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// The _pointer_field_ at the start of a PSI packet payload pointed beyond the end of the
+    /// payload.
+    PsiPointerOverflow {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// A packet carrying PSI data had no payload.
+    PsiNoPayload {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// Too few bytes of a packet payload remained for a PSI section header to be read.
+    SectionHeaderTruncated {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// The _section_syntax_indicator_ of a section did not have the value required by the
+    /// processor of the table.
+    UnexpectedSectionSyntax {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _table_id_ of the section
+        table_id: u8,
+        /// the value of _section_syntax_indicator_ found
+        section_syntax_indicator: bool,
+    },
+    /// The data for a section was too short to contain the required headers.
+    SectionTooShort {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _table_id_ of the section
+        table_id: u8,
+        /// the length of the available section data
+        length: usize,
+    },
+    /// A section's _section_length_ was larger than permitted.
+    SectionTooLong {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _table_id_ of the section
+        table_id: u8,
+        /// the _section_length_ value
+        section_length: usize,
+    },
+    /// The CRC of a section did not match its content, and the section was dropped.
+    SectionCrcMismatch {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _table_id_ of the section
+        table_id: u8,
+    },
+    /// Section data continued after the end of a complete section.
+    UnexpectedSectionData {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+    },
+    /// A section had a _table_id_ other than that expected for the table carried by the PID.
+    WrongTableId {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _table_id_ expected
+        expected: u8,
+        /// the _table_id_ found
+        actual: u8,
+    },
+    /// The content of a Program Map Table section could not be parsed.
+    InvalidPmt {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _program_number_ of the program which the PMT describes
+        program_number: u16,
+    },
+    /// The entries of a Program Association Table section did not fill a whole number of 4 byte
+    /// entries, and the trailing bytes were ignored.
+    PatEntryTruncated {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the size of a PAT entry
+        expected: usize,
+        /// the number of bytes remaining
+        actual: usize,
+    },
+    /// Too few bytes remained in a Program Map Table section to hold the next stream's details,
+    /// and it and any following streams were ignored.
+    StreamInfoTruncated {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _program_number_ of the program which the PMT describes
+        program_number: u16,
+        /// the number of bytes required for the stream's details
+        expected: usize,
+        /// the number of bytes remaining
+        actual: usize,
+    },
+    /// The _adaptation_field_length_ of a packet was invalid for its _adaptation_field_control_,
+    /// so the packet's adaptation field and payload are not available.  The packet is still
+    /// passed on to its filter.
+    AdaptationFieldLengthInvalid {
+        /// the `Pid` of the packet
+        pid: packet::Pid,
+        /// stream offset of the packet
+        offset: u64,
+        /// the _adaptation_field_control_ of the packet
+        adaptation_control: packet::AdaptationControl,
+        /// the _adaptation_field_length_ value
+        length: usize,
+    },
+    /// The header of a PES packet could not be parsed.  If the fixed part of the header was
+    /// invalid the PES packet was dropped, otherwise only its optional header fields are
+    /// unavailable.
+    PesHeaderInvalid {
+        /// the `Pid` of the elementary stream
+        pid: packet::Pid,
+        /// stream offset of the packet in which the PES packet started
+        offset: u64,
+        /// the problem with the header
+        error: pes::PesError,
+    },
+    /// A PES packet was dropped by a `BufferingElementaryStreamConsumer` since it was larger than
+    /// the configured maximum size.
+    PesPacketTooLarge {
+        /// the `Pid` of the elementary stream
+        pid: packet::Pid,
+        /// stream offset of the packet in which the limit was found to be exceeded
+        offset: u64,
+        /// the maximum PES packet size that was configured
+        max_size: usize,
+    },
+    /// A PES packet was dropped by a `BufferingElementaryStreamConsumer` since the stream ended,
+    /// or the next PES packet started, before all of the data indicated by the
+    /// _PES_packet_length_ field had been received.
+    PesPacketTruncated {
+        /// the `Pid` of the elementary stream
+        pid: packet::Pid,
+        /// stream offset of the packet in which the next PES packet started, or of the end of
+        /// the stream
+        offset: u64,
+        /// the size of the PES packet indicated by its header
+        expected: usize,
+        /// the number of bytes actually received
+        actual: usize,
+    },
+}
+impl DemuxEvent {
+    /// The `Pid` of the packet to which this event relates, or `None` for events that don't
+    /// relate to a particular packet.
+    pub fn pid(&self) -> Option<packet::Pid> {
+        match *self {
+            DemuxEvent::SyncAcquired { .. } | DemuxEvent::SyncLost { .. } => None,
+            DemuxEvent::TransportError { pid, .. }
+            | DemuxEvent::ScrambledPacketDropped { pid, .. }
+            | DemuxEvent::DescrambleFailed { pid, .. }
+            | DemuxEvent::ContinuityError { pid, .. }
+            | DemuxEvent::PesContinuationWithoutStart { pid, .. }
+            | DemuxEvent::PsiPointerOverflow { pid, .. }
+            | DemuxEvent::PsiNoPayload { pid, .. }
+            | DemuxEvent::SectionHeaderTruncated { pid, .. }
+            | DemuxEvent::UnexpectedSectionSyntax { pid, .. }
+            | DemuxEvent::SectionTooShort { pid, .. }
+            | DemuxEvent::SectionTooLong { pid, .. }
+            | DemuxEvent::SectionCrcMismatch { pid, .. }
+            | DemuxEvent::UnexpectedSectionData { pid, .. }
+            | DemuxEvent::WrongTableId { pid, .. }
+            | DemuxEvent::InvalidPmt { pid, .. }
+            | DemuxEvent::PatEntryTruncated { pid, .. }
+            | DemuxEvent::StreamInfoTruncated { pid, .. }
+            | DemuxEvent::AdaptationFieldLengthInvalid { pid, .. }
+            | DemuxEvent::PesHeaderInvalid { pid, .. }
+            | DemuxEvent::PesPacketTooLarge { pid, .. }
+            | DemuxEvent::PesPacketTruncated { pid, .. } => Some(pid),
+        }
+    }
+
+    /// The offset within the stream to which this event relates.
+    pub fn offset(&self) -> u64 {
+        match *self {
+            DemuxEvent::SyncAcquired { offset }
+            | DemuxEvent::SyncLost { offset }
+            | DemuxEvent::TransportError { offset, .. }
+            | DemuxEvent::ScrambledPacketDropped { offset, .. }
+            | DemuxEvent::DescrambleFailed { offset, .. }
+            | DemuxEvent::ContinuityError { offset, .. }
+            | DemuxEvent::PesContinuationWithoutStart { offset, .. }
+            | DemuxEvent::PsiPointerOverflow { offset, .. }
+            | DemuxEvent::PsiNoPayload { offset, .. }
+            | DemuxEvent::SectionHeaderTruncated { offset, .. }
+            | DemuxEvent::UnexpectedSectionSyntax { offset, .. }
+            | DemuxEvent::SectionTooShort { offset, .. }
+            | DemuxEvent::SectionTooLong { offset, .. }
+            | DemuxEvent::SectionCrcMismatch { offset, .. }
+            | DemuxEvent::UnexpectedSectionData { offset, .. }
+            | DemuxEvent::WrongTableId { offset, .. }
+            | DemuxEvent::InvalidPmt { offset, .. }
+            | DemuxEvent::PatEntryTruncated { offset, .. }
+            | DemuxEvent::StreamInfoTruncated { offset, .. }
+            | DemuxEvent::AdaptationFieldLengthInvalid { offset, .. }
+            | DemuxEvent::PesHeaderInvalid { offset, .. }
+            | DemuxEvent::PesPacketTooLarge { offset, .. }
+            | DemuxEvent::PesPacketTruncated { offset, .. } => offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncState {
+    /// no data seen yet; the stream is assumed to be synchronised if it starts with a sync-byte
+    /// (unless the packet format is to be detected)
+    Start,
+    /// searching for a run of sync-bytes at packet intervals
+    Hunting,
+    /// packets are being processed, and the given number of immediately preceding packet slots
+    /// did not start with a sync-byte
+    Locked { bad_sync_count: usize },
 }
 
 /// Transport Stream demultiplexer.
@@ -597,16 +1043,68 @@ impl<Ctx: DemuxContext> PacketFilter for PatPacketFilter<Ctx> {
 /// demultiplexing process will resume at the start of one buffer where it left off at the end of
 /// the last.  This supports for example the processing of sections of TS data as they are received
 /// from the network, without needing to copy them out of the source network buffer.
+///
+/// The buffers passed to `push()` do not need to start or end on a packet boundary.  Any bytes at
+/// the end of a buffer which do not make up a complete packet are retained, and the packet is
+/// completed using the data at the start of the next buffer.
+///
+/// # Synchronisation
+///
+/// If the stream starts with a sync-byte, it is assumed to start at a packet boundary.  Otherwise,
+/// and whenever sync is later lost, the demultiplexer hunts for a position at which a sync-byte
+/// appears at the start of a number of consecutive packet slots (5 by default), and resumes
+/// processing packets from there.
+///
+/// Once synchronised, a packet slot that does not start with a sync-byte is skipped, and sync is
+/// considered lost only once a number of consecutive slots (3 by default) have all lacked a
+/// sync-byte.  The hunt then resumes just after the start of the first of those slots, so that
+/// packets following a short run of junk data are not lost.  Both thresholds can be changed with
+/// `set_sync_thresholds()`, and changes in sync are reported to the application via
+/// [`DemuxContext::event()`](trait.DemuxContext.html#method.event).
+///
+/// # Packet formats
+///
+/// By default the stream is expected to consist of plain 188 byte packets.  Other layouts, such
+/// as the 192 byte units of `.m2ts` files, can be selected with `set_packet_format()`, or
+/// `detect_packet_format()` can be used to have the format determined while hunting for sync.
+/// When the format includes a `TP_extra_header`, this is available to filters via
+/// [`Packet::tp_extra_header()`](../packet/struct.Packet.html#method.tp_extra_header).
 pub struct Demultiplex<Ctx: DemuxContext> {
     processor_by_pid: Filters<Ctx::F>,
+    /// data from previous calls to `push()` that could not yet be processed; either the initial
+    /// part of a packet split across buffers, or data being examined while hunting for sync
+    pending: Vec<u8>,
+    /// the run of packet slots most recently found to lack a sync-byte, which will be searched
+    /// again if sync is lost
+    bad_slots: Vec<u8>,
+    /// stream offset of the first byte of the next buffer passed to `push()`
+    offset: u64,
+    sync_state: SyncState,
+    sync_acquire_count: usize,
+    sync_lose_count: usize,
+    /// the packet format given by the application, or `None` if it is to be detected
+    configured_format: Option<packet::PacketFormat>,
+    /// the packet format in use, which is always known once sync has been acquired
+    format: Option<packet::PacketFormat>,
 }
 impl<Ctx: DemuxContext> Demultiplex<Ctx> {
+    const DEFAULT_SYNC_ACQUIRE_COUNT: usize = 5;
+    const DEFAULT_SYNC_LOSE_COUNT: usize = 3;
+
     /// Create a `Dumultiplex` instance, and populate it with an initial `PacketFilter` for
     /// handling PAT packets (which is created by the given `DemuxContext` object).
     /// The returned value does not retain any reference to the given `DemuxContext` reference.
     pub fn new(ctx: &mut Ctx) -> Demultiplex<Ctx> {
         let mut result = Demultiplex {
             processor_by_pid: Filters::default(),
+            pending: Vec::with_capacity(packet::Packet::SIZE),
+            bad_slots: vec![],
+            offset: 0,
+            sync_state: SyncState::Start,
+            sync_acquire_count: Self::DEFAULT_SYNC_ACQUIRE_COUNT,
+            sync_lose_count: Self::DEFAULT_SYNC_LOSE_COUNT,
+            configured_format: Some(packet::PacketFormat::Plain),
+            format: Some(packet::PacketFormat::Plain),
         };
 
         result.processor_by_pid.insert(
@@ -617,68 +1115,353 @@ impl<Ctx: DemuxContext> Demultiplex<Ctx> {
         result
     }
 
+    /// Change the number of consecutive sync-bytes that must be found before sync is acquired
+    /// (`acquire`), and the number of consecutive missing sync-bytes after which sync is
+    /// considered lost (`lose`).
+    ///
+    /// Panics if either value is `0`.
+    pub fn set_sync_thresholds(&mut self, acquire: usize, lose: usize) {
+        assert!(acquire > 0);
+        assert!(lose > 0);
+        self.sync_acquire_count = acquire;
+        self.sync_lose_count = lose;
+    }
+
+    /// Use the given layout of packets within the stream, rather than the default
+    /// `PacketFormat::Plain`.  This should be called before data is first passed to `push()`.
+    pub fn set_packet_format(&mut self, format: packet::PacketFormat) {
+        self.configured_format = Some(format);
+        self.format = Some(format);
+    }
+
+    /// Rather than assuming a particular layout of packets within the stream, try each of the
+    /// supported `PacketFormat`s when hunting for sync.  This should be called before data is
+    /// first passed to `push()`.
+    ///
+    /// Note that this means that sync is never assumed at the start of the stream, so packets
+    /// will not be processed until enough data has been seen to acquire sync.
+    pub fn detect_packet_format(&mut self) {
+        self.configured_format = None;
+        self.format = None;
+    }
+
+    /// The packet format in use, or `None` if the format is still to be detected.
+    pub fn packet_format(&self) -> Option<packet::PacketFormat> {
+        self.format
+    }
+
     /// Parse the Transport Stream packets in the given buffer, using functions from the given
     /// `DemuxContent` object
+    ///
+    /// If the buffer ends part way through a packet, the available part of the packet is copied
+    /// and will be processed together with the rest of the packet at the start of the buffer
+    /// given to the next call to `push()`.
     pub fn push(&mut self, ctx: &mut Ctx, buf: &[u8]) {
-        // TODO: simplify
-        let mut itr = buf
-            .chunks_exact(packet::Packet::SIZE)
-            .map(packet::Packet::try_new);
-        let mut pk = if let Some(Some(p)) = itr.next() {
-            p
-        } else {
-            return;
-        };
-        'outer: loop {
-            let this_pid = pk.pid();
-            if !self.processor_by_pid.contains(this_pid) {
-                self.add_pid_filter(ctx, this_pid);
+        let mut rest = buf;
+        loop {
+            // stream offset of the first byte of `rest`
+            let rest_offset = self.offset + (buf.len() - rest.len()) as u64;
+            let next = match self.sync_state {
+                SyncState::Start if self.format.is_none() => {
+                    self.sync_state = SyncState::Hunting;
+                    Some(rest)
+                }
+                SyncState::Hunting => self.hunt(ctx, rest, rest_offset),
+                SyncState::Start | SyncState::Locked { .. } => {
+                    self.push_locked(ctx, rest, rest_offset)
+                }
             };
-            let this_proc = self.processor_by_pid.get(this_pid).unwrap();
-            'inner: loop {
-                if pk.transport_error_indicator() {
-                    // drop packets that have transport_error_indicator set, on the assumption that
-                    // the contents are nonsense
-                    warn!("{:?} transport_error_indicator", pk.pid());
-                } else if pk.transport_scrambling_control()
-                    != TransportScramblingControl::NotScrambled
-                {
-                    // TODO: allow descrambler to be plugged in
-                    warn!(
-                        "{:?} dropping scrambled packet {:?}",
-                        pk.pid(),
-                        pk.transport_scrambling_control()
-                    );
+            match next {
+                Some(r) => rest = r,
+                None => break,
+            }
+        }
+        self.offset += buf.len() as u64;
+    }
+
+    /// Signal that the end of the stream has been reached, so that data buffered by the
+    /// `PacketFilter` for every PID can be processed; for example, a `PesPacketFilter` will
+    /// deliver the end of any PES packet that was still in progress.  Any trailing bytes that did
+    /// not make up a complete packet are discarded.
+    ///
+    /// Once flushed, the `Demultiplex` instance may be given the data of a new stream via
+    /// `push()`, though any filters created while processing the previous stream will remain.
+    pub fn flush(&mut self, ctx: &mut Ctx) {
+        self.pending.clear();
+        self.bad_slots.clear();
+        self.sync_state = SyncState::Start;
+        self.format = self.configured_format;
+        for pid in 0..self.processor_by_pid.pid_limit() {
+            let pid = packet::Pid::new(pid as u16);
+            if let Some(this_proc) = self.processor_by_pid.get(pid) {
+                ctx.set_packet_position(PacketPosition::new(pid, self.offset));
+                this_proc.end_stream(ctx);
+                if !ctx.filter_changeset().is_empty() {
+                    ctx.filter_changeset().apply(&mut self.processor_by_pid);
+                }
+            }
+        }
+    }
+
+    /// Searches the data made up of `self.pending` followed by `buf` for the start of a run of
+    /// sync-bytes at packet intervals.
+    ///
+    /// If found, `self.pending` is left holding any pending data from that point onwards, and the
+    /// remainder of `buf` to be processed is returned.  Otherwise `None` is returned, and
+    /// `self.pending` holds whatever data may still prove to be the start of such a run once more
+    /// data is available.
+    fn hunt<'a>(&mut self, ctx: &mut Ctx, buf: &'a [u8], buf_offset: u64) -> Option<&'a [u8]> {
+        let configured_formats;
+        let formats = match self.configured_format {
+            Some(format) => {
+                configured_formats = [format];
+                &configured_formats[..]
+            }
+            None => &packet::PacketFormat::ALL[..],
+        };
+        let pending_len = self.pending.len();
+        let len = pending_len + buf.len();
+        let (pos, found) = {
+            let pending = &self.pending;
+            let byte_at = |i: usize| {
+                if i < pending_len {
+                    pending[i]
                 } else {
-                    this_proc.consume(ctx, &pk);
-                    if !ctx.filter_changeset().is_empty() {
-                        println!("inner break");
-                        break 'inner;
+                    buf[i - pending_len]
+                }
+            };
+            // Some(true) if the given format has a run of sync-bytes starting at the given
+            // position, Some(false) if not, or None if we can't yet tell
+            let has_sync_run = |pos: usize, format: packet::PacketFormat| {
+                for k in 0..self.sync_acquire_count {
+                    let i = pos + format.packet_offset() + k * format.size();
+                    if i >= len {
+                        return None;
+                    }
+                    if !packet::Packet::is_sync_byte(byte_at(i)) {
+                        return Some(false);
                     }
                 }
-                pk = if let Some(Some(p)) = itr.next() {
-                    p
-                } else {
-                    // 65 is the header for payload_unit_start_indicator
-                    let packet_force_end_buf: [u8; 4] = [packet::Packet::SYNC_BYTE, 65, 0, 0];
-                    let packet = packet::Packet::force_new(&packet_force_end_buf);
-                    this_proc.consume(ctx, &packet);
-                    break 'outer;
-                };
-                if pk.pid() != this_pid {
-                    continue 'outer;
+                Some(true)
+            };
+            let mut pos = 0;
+            let mut found = None;
+            'candidates: while pos < len {
+                let mut matched = None;
+                for &format in formats {
+                    match has_sync_run(pos, format) {
+                        // wait until all formats can be checked, so that the outcome does not
+                        // depend on how the data was split into buffers
+                        None => break 'candidates,
+                        Some(true) => matched = matched.or(Some(format)),
+                        Some(false) => (),
+                    }
+                }
+                if matched.is_some() {
+                    found = matched;
+                    break;
                 }
+                pos += 1;
             }
-            if !ctx.filter_changeset().is_empty() {
-                ctx.filter_changeset().apply(&mut self.processor_by_pid);
+            (pos, found)
+        };
+        let rest = if pos < pending_len {
+            self.pending.drain(..pos);
+            buf
+        } else {
+            self.pending.clear();
+            &buf[pos - pending_len..]
+        };
+        if let Some(format) = found {
+            self.format = Some(format);
+            self.sync_state = SyncState::Locked { bad_sync_count: 0 };
+            ctx.event(DemuxEvent::SyncAcquired {
+                offset: buf_offset - pending_len as u64 + pos as u64,
+            });
+            Some(rest)
+        } else {
+            self.pending.extend_from_slice(rest);
+            None
+        }
+    }
+
+    /// Processes packets from `self.pending` followed by `buf`.  If sync is lost, returns the
+    /// remainder of `buf` from which the hunt for sync should continue (with `self.pending`
+    /// holding any earlier data that should also be searched), or `None` once all data has been
+    /// consumed.
+    fn push_locked<'a>(
+        &mut self,
+        ctx: &mut Ctx,
+        buf: &'a [u8],
+        buf_offset: u64,
+    ) -> Option<&'a [u8]> {
+        let unit_size = self.format.expect("packet format not known").size();
+        let whole_buf = buf;
+        let mut buf = buf;
+        // take the buffer out of self for the duration of the call, so that it can be
+        // borrowed while self is also borrowed mutably
+        let mut pending = std::mem::take(&mut self.pending);
+        let pending_offset = buf_offset - pending.len() as u64;
+        let whole_packets_len = pending.len() - pending.len() % unit_size;
+        let mut result = self.push_packets(ctx, &pending[..whole_packets_len], pending_offset);
+        if result.is_ok() && pending.len() > whole_packets_len {
+            let needed = unit_size - (pending.len() - whole_packets_len);
+            if buf.len() < needed {
+                pending.drain(..whole_packets_len);
+                pending.extend_from_slice(buf);
+                self.pending = pending;
+                return None;
             }
-            debug_assert!(ctx.filter_changeset().is_empty());
-            pk = if let Some(Some(p)) = itr.next() {
-                p
+            let (head, tail) = buf.split_at(needed);
+            pending.extend_from_slice(head);
+            buf = tail;
+            result = self
+                .push_packets(
+                    ctx,
+                    &pending[whole_packets_len..],
+                    pending_offset + whole_packets_len as u64,
+                )
+                .map_err(|resume| whole_packets_len + resume);
+        }
+        match result {
+            Ok(()) => pending.clear(),
+            Err(resume) => {
+                // the hunt continues over any bad packet slots left in self.pending, followed by
+                // the rest of the pending data
+                self.pending.extend_from_slice(&pending[resume..]);
+                return Some(buf);
+            }
+        }
+        self.pending = pending;
+
+        let buf_offset = buf_offset + (whole_buf.len() - buf.len()) as u64;
+        let whole_packets_len = buf.len() - buf.len() % unit_size;
+        let (packets, remainder) = buf.split_at(whole_packets_len);
+        match self.push_packets(ctx, packets, buf_offset) {
+            Ok(()) => {
+                self.pending.extend_from_slice(remainder);
+                None
+            }
+            Err(resume) => Some(&buf[resume..]),
+        }
+    }
+
+    /// Process the packets in the given buffer, which must have a length that is a multiple of
+    /// the size of the current packet format.  If sync is lost, `self.pending` is left holding
+    /// the data from just after the start of the first bad packet slot to the end of the current
+    /// slot, and
+    /// `Err` gives the position in the buffer from which the hunt for sync should then continue.
+    fn push_packets(&mut self, ctx: &mut Ctx, buf: &[u8], buf_offset: u64) -> Result<(), usize> {
+        let format = self.format.expect("packet format not known");
+        let packet_offset = format.packet_offset();
+        for (i, chunk) in buf.chunks_exact(format.size()).enumerate() {
+            let packet_buf = &chunk[packet_offset..packet_offset + packet::Packet::SIZE];
+            if let Some(mut pk) = packet::Packet::try_new(packet_buf) {
+                if format == packet::PacketFormat::M2ts {
+                    pk = pk.with_tp_extra_header(packet::TpExtraHeader::new(
+                        &chunk[..packet::TpExtraHeader::SIZE],
+                    ));
+                }
+                self.sync_state = SyncState::Locked { bad_sync_count: 0 };
+                self.bad_slots.clear();
+                let offset = buf_offset + (i * format.size() + packet_offset) as u64;
+                self.push_packet(ctx, &pk.with_offset(offset));
             } else {
-                break 'outer;
-            };
+                let pos = i * format.size();
+                let bad_sync_count = match self.sync_state {
+                    SyncState::Locked { bad_sync_count } => bad_sync_count + 1,
+                    SyncState::Start | SyncState::Hunting => {
+                        // the stream didn't start with a packet after all
+                        self.sync_state = SyncState::Hunting;
+                        self.pending.extend_from_slice(&chunk[1..]);
+                        return Err(pos + format.size());
+                    }
+                };
+                self.bad_slots.extend_from_slice(chunk);
+                if bad_sync_count >= self.sync_lose_count {
+                    self.sync_state = SyncState::Hunting;
+                    if self.configured_format.is_none() {
+                        self.format = None;
+                    }
+                    let first_bad_offset = buf_offset + (pos + format.size()) as u64
+                        - self.bad_slots.len() as u64
+                        + packet_offset as u64;
+                    ctx.event(DemuxEvent::SyncLost {
+                        offset: first_bad_offset,
+                    });
+                    // resume the hunt just after the start of the first bad packet slot
+                    self.pending.extend_from_slice(&self.bad_slots[1..]);
+                    self.bad_slots.clear();
+                    return Err(pos + format.size());
+                }
+                self.sync_state = SyncState::Locked { bad_sync_count };
+            }
         }
+        Ok(())
+    }
+
+    fn push_packet(&mut self, ctx: &mut Ctx, pk: &packet::Packet<'_>) {
+        let this_pid = pk.pid();
+        let offset = pk.offset();
+        if !self.processor_by_pid.contains(this_pid) {
+            self.add_pid_filter(ctx, this_pid);
+        };
+        ctx.set_packet_position(PacketPosition::new(this_pid, offset));
+        if pk.transport_error_indicator() {
+            // drop packets that have transport_error_indicator set, on the assumption that
+            // the contents are nonsense
+            ctx.event(DemuxEvent::TransportError {
+                pid: this_pid,
+                offset,
+            });
+        } else if pk.transport_scrambling_control() != TransportScramblingControl::NotScrambled {
+            let mut clear = [0u8; packet::Packet::SIZE];
+            match ctx.descrambler() {
+                None => {
+                    ctx.event(DemuxEvent::ScrambledPacketDropped {
+                        pid: this_pid,
+                        offset,
+                    });
+                }
+                Some(descrambler) => {
+                    match descramble::descramble_packet(descrambler, pk, &mut clear) {
+                        Ok(()) => {
+                            let mut clear_pk = packet::Packet::new(&clear[..]).with_offset(offset);
+                            if let Some(hdr) = pk.tp_extra_header() {
+                                clear_pk = clear_pk.with_tp_extra_header(hdr);
+                            }
+                            self.consume_packet(ctx, &clear_pk);
+                        }
+                        Err(error) => {
+                            ctx.event(DemuxEvent::DescrambleFailed {
+                                pid: this_pid,
+                                offset,
+                                error,
+                            });
+                        }
+                    }
+                }
+            }
+        } else {
+            self.consume_packet(ctx, pk);
+        }
+    }
+
+    fn consume_packet(&mut self, ctx: &mut Ctx, pk: &packet::Packet<'_>) {
+        if let Some(length) = pk.invalid_adaptation_field_length() {
+            ctx.event(DemuxEvent::AdaptationFieldLengthInvalid {
+                pid: pk.pid(),
+                offset: pk.offset(),
+                adaptation_control: pk.adaptation_control(),
+                length,
+            });
+        }
+        let this_proc = self.processor_by_pid.get(pk.pid()).unwrap();
+        this_proc.consume(ctx, pk);
+        if !ctx.filter_changeset().is_empty() {
+            ctx.filter_changeset().apply(&mut self.processor_by_pid);
+        }
+        debug_assert!(ctx.filter_changeset().is_empty());
     }
 
     fn add_pid_filter(&mut self, ctx: &mut Ctx, this_pid: packet::Pid) {
@@ -694,9 +1477,13 @@ pub(crate) mod test {
     use std::io;
 
     use crate::demultiplex;
+    use crate::descramble;
+    use crate::mux;
     use crate::packet;
+    use crate::pes;
     use crate::psi;
     use crate::psi::WholeSectionSyntaxPayloadParser;
+    use crate::StreamType;
     use bitstream_io::BigEndian;
 
     packet_filter_switch! {
@@ -746,6 +1533,664 @@ pub(crate) mod test {
         deplex.push(&mut ctx, &buf[..]);
     }
 
+    /// `DemuxContext` that uses `RecordingPacketFilter` for every PID, so that tests can check
+    /// exactly which packets the `Demultiplex` delivered
+    pub(crate) struct RecordingDemuxContext {
+        changeset: demultiplex::FilterChangeset<RecordingPacketFilter>,
+        position: demultiplex::PacketPosition,
+        packets: Vec<Vec<u8>>,
+        tp_extra_headers: Vec<Option<packet::TpExtraHeader>>,
+        offsets: Vec<u64>,
+        events: Vec<demultiplex::DemuxEvent>,
+        descrambler: Option<descramble::XorDescrambler>,
+        ended: Vec<packet::Pid>,
+    }
+    impl RecordingDemuxContext {
+        pub fn new() -> Self {
+            RecordingDemuxContext {
+                changeset: demultiplex::FilterChangeset::default(),
+                position: demultiplex::PacketPosition::default(),
+                packets: vec![],
+                tp_extra_headers: vec![],
+                offsets: vec![],
+                events: vec![],
+                descrambler: None,
+                ended: vec![],
+            }
+        }
+    }
+    impl demultiplex::DemuxContext for RecordingDemuxContext {
+        type F = RecordingPacketFilter;
+
+        fn filter_changeset(&mut self) -> &mut demultiplex::FilterChangeset<Self::F> {
+            &mut self.changeset
+        }
+
+        fn set_packet_position(&mut self, position: demultiplex::PacketPosition) {
+            self.position = position;
+        }
+        fn packet_position(&self) -> demultiplex::PacketPosition {
+            self.position
+        }
+        fn construct(&mut self, _req: demultiplex::FilterRequest<'_, '_>) -> Self::F {
+            RecordingPacketFilter
+        }
+        fn event(&mut self, event: demultiplex::DemuxEvent) {
+            self.events.push(event);
+        }
+        fn descrambler(&mut self) -> Option<&mut dyn descramble::Descrambler> {
+            self.descrambler
+                .as_mut()
+                .map(|d| d as &mut dyn descramble::Descrambler)
+        }
+    }
+    pub(crate) struct RecordingPacketFilter;
+    impl demultiplex::PacketFilter for RecordingPacketFilter {
+        type Ctx = RecordingDemuxContext;
+
+        fn consume(&mut self, ctx: &mut Self::Ctx, pk: &packet::Packet<'_>) {
+            ctx.packets.push(pk.buffer().to_vec());
+            ctx.tp_extra_headers.push(pk.tp_extra_header());
+            ctx.offsets.push(pk.offset());
+        }
+
+        fn end_stream(&mut self, ctx: &mut Self::Ctx) {
+            let pid = ctx.position.pid();
+            ctx.ended.push(pid);
+        }
+    }
+
+    /// A sequence of well-formed packets on a few different PIDs, each with distinct content
+    fn make_packets(count: usize) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(count * packet::Packet::SIZE);
+        for i in 0..count {
+            let pid = 0x100 + (i % 3) as u16;
+            buf.push(packet::Packet::SYNC_BYTE);
+            buf.push((pid >> 8) as u8);
+            buf.push(pid as u8);
+            buf.push(0x10 | ((i / 3) & 0xf) as u8); // payload only + continuity_counter
+            for j in 0..(packet::Packet::SIZE - 4) {
+                // avoid producing values that look like sync-bytes
+                buf.push((i + j) as u8 & 0x3f);
+            }
+        }
+        buf
+    }
+
+    fn demux_in_chunks(data: &[u8], chunk_sizes: &mut dyn Iterator<Item = usize>) -> Vec<Vec<u8>> {
+        demux_in_chunks_with_events(data, chunk_sizes).0
+    }
+
+    fn demux_in_chunks_with_events(
+        data: &[u8],
+        chunk_sizes: &mut dyn Iterator<Item = usize>,
+    ) -> (Vec<Vec<u8>>, Vec<demultiplex::DemuxEvent>) {
+        let ctx = demux_with(data, chunk_sizes, |_| ());
+        (ctx.packets, ctx.events)
+    }
+
+    fn demux_with<F>(
+        data: &[u8],
+        chunk_sizes: &mut dyn Iterator<Item = usize>,
+        setup: F,
+    ) -> RecordingDemuxContext
+    where
+        F: Fn(&mut demultiplex::Demultiplex<RecordingDemuxContext>),
+    {
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        setup(&mut deplex);
+        let mut rest = data;
+        while !rest.is_empty() {
+            let size = chunk_sizes.next().unwrap().min(rest.len());
+            let (chunk, tail) = rest.split_at(size);
+            deplex.push(&mut ctx, chunk);
+            rest = tail;
+        }
+        ctx
+    }
+
+    /// simple xorshift PRNG, so that tests are repeatable without needing extra dependencies
+    fn random_sizes(max: usize) -> impl Iterator<Item = usize> {
+        let mut state = 0x2545_f491_u32;
+        std::iter::from_fn(move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Some(state as usize % max)
+        })
+    }
+
+    #[test]
+    fn push_whole_packets() {
+        let data = make_packets(10);
+        let packets = demux_in_chunks(&data, &mut std::iter::repeat(data.len()));
+        assert_eq!(packets.len(), 10);
+        for (actual, expected) in packets.iter().zip(data.chunks(packet::Packet::SIZE)) {
+            assert_eq!(&actual[..], expected);
+        }
+    }
+
+    #[test]
+    fn push_single_bytes() {
+        let data = make_packets(4);
+        let expected = demux_in_chunks(&data, &mut std::iter::repeat(data.len()));
+        let actual = demux_in_chunks(&data, &mut std::iter::repeat(1));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn push_random_chunks() {
+        let data = make_packets(50);
+        let expected = demux_in_chunks(&data, &mut std::iter::repeat(data.len()));
+        let mut sizes = random_sizes(packet::Packet::SIZE * 3);
+        for _ in 0..20 {
+            let actual = demux_in_chunks(&data, &mut sizes);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    packet_filter_switch! {
+        ProgramFilterSwitch<ProgramDemuxContext> {
+            Pat: demultiplex::PatPacketFilter<ProgramDemuxContext>,
+            Pmt: demultiplex::PmtPacketFilter<ProgramDemuxContext>,
+            Pes: pes::PesPacketFilter<
+                ProgramDemuxContext,
+                pes::BufferingElementaryStreamConsumer<RecordingPesPacketConsumer>,
+            >,
+            Nul: demultiplex::NullPacketFilter<ProgramDemuxContext>,
+        }
+    }
+    /// `DemuxContext` that follows the PAT and PMT, recording the PES packets of every stream
+    /// and any events reported along the way
+    #[derive(Default)]
+    pub struct ProgramDemuxContext {
+        changeset: demultiplex::FilterChangeset<ProgramFilterSwitch>,
+        position: demultiplex::PacketPosition,
+        pes_packets: Vec<(packet::Pid, Option<pes::Timestamp>, Vec<u8>)>,
+        events: Vec<demultiplex::DemuxEvent>,
+    }
+    impl demultiplex::DemuxContext for ProgramDemuxContext {
+        type F = ProgramFilterSwitch;
+
+        fn filter_changeset(&mut self) -> &mut demultiplex::FilterChangeset<Self::F> {
+            &mut self.changeset
+        }
+
+        fn set_packet_position(&mut self, position: demultiplex::PacketPosition) {
+            self.position = position;
+        }
+        fn packet_position(&self) -> demultiplex::PacketPosition {
+            self.position
+        }
+        fn construct(&mut self, req: demultiplex::FilterRequest<'_, '_>) -> Self::F {
+            match req {
+                demultiplex::FilterRequest::ByPid(psi::pat::PAT_PID) => {
+                    ProgramFilterSwitch::Pat(demultiplex::PatPacketFilter::default())
+                }
+                demultiplex::FilterRequest::ByStream { stream_info, .. } => {
+                    ProgramFilterSwitch::Pes(pes::PesPacketFilter::new(
+                        pes::BufferingElementaryStreamConsumer::new(
+                            stream_info.elementary_pid(),
+                            RecordingPesPacketConsumer,
+                        ),
+                    ))
+                }
+                demultiplex::FilterRequest::Pmt {
+                    pid,
+                    program_number,
+                } => {
+                    ProgramFilterSwitch::Pmt(demultiplex::PmtPacketFilter::new(pid, program_number))
+                }
+                _ => ProgramFilterSwitch::Nul(demultiplex::NullPacketFilter::default()),
+            }
+        }
+        fn event(&mut self, event: demultiplex::DemuxEvent) {
+            self.events.push(event);
+        }
+    }
+    pub struct RecordingPesPacketConsumer;
+    impl pes::PesPacketConsumer<ProgramDemuxContext> for RecordingPesPacketConsumer {
+        fn pes_packet(&mut self, ctx: &mut ProgramDemuxContext, packet: &pes::PesPacket<'_>) {
+            ctx.pes_packets
+                .push((packet.pid(), packet.pts(), packet.payload().to_vec()));
+        }
+    }
+
+    /// A single program, with interleaved video and audio PES packets following the PAT and PMT
+    fn make_program_stream() -> Vec<u8> {
+        let pmt_pid = packet::Pid::new(0x1000);
+        let video_pid = packet::Pid::new(0x100);
+        let audio_pid = packet::Pid::new(0x101);
+        let pat = psi::pat::PatSectionBuilder::new(1)
+            .program(psi::pat::ProgramDescriptor::Program {
+                program_number: 1,
+                pid: pmt_pid,
+            })
+            .build()
+            .unwrap();
+        let pmt = psi::pmt::PmtSectionBuilder::new(1, video_pid)
+            .stream(psi::pmt::StreamInfoBuilder::new(
+                StreamType::H264,
+                video_pid,
+            ))
+            .stream(psi::pmt::StreamInfoBuilder::new(
+                StreamType::Adts,
+                audio_pid,
+            ))
+            .build()
+            .unwrap();
+        let mut ts = vec![];
+        mux::SectionPacketiser::new(psi::pat::PAT_PID)
+            .write_sections(&mut ts, &[&pat])
+            .unwrap();
+        mux::SectionPacketiser::new(pmt_pid)
+            .write_sections(&mut ts, &[&pmt])
+            .unwrap();
+        let mut video = mux::PesPacketiser::new(video_pid);
+        let mut audio = mux::PesPacketiser::new(audio_pid);
+        for i in 0..30 {
+            let pts = pes::Timestamp::from_u64(90_000 + i as u64 * 3_000);
+            let payload: Vec<u8> = (0..(100 + i * 37)).map(|j| (i + j) as u8).collect();
+            let header = mux::PesHeaderBuilder::new(pes::StreamId::Video(0))
+                .pts(pts)
+                .unbounded(true);
+            video
+                .write_packet(&mut ts, &header, &payload, None)
+                .unwrap();
+            let header = mux::PesHeaderBuilder::new(pes::StreamId::Audio(0)).pts(pts);
+            audio
+                .write_packet(&mut ts, &header, &payload[..50 + i], None)
+                .unwrap();
+        }
+        ts
+    }
+
+    fn demux_program_in_chunks(
+        data: &[u8],
+        chunk_sizes: &mut dyn Iterator<Item = usize>,
+    ) -> ProgramDemuxContext {
+        let mut ctx = ProgramDemuxContext::default();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        let mut rest = data;
+        while !rest.is_empty() {
+            let size = chunk_sizes.next().unwrap().min(rest.len());
+            let (chunk, tail) = rest.split_at(size);
+            deplex.push(&mut ctx, chunk);
+            rest = tail;
+        }
+        deplex.flush(&mut ctx);
+        ctx
+    }
+
+    #[test]
+    fn push_random_chunks_of_program() {
+        let data = make_program_stream();
+        let expected = demux_program_in_chunks(&data, &mut std::iter::repeat(data.len()));
+        assert_eq!(expected.pes_packets.len(), 60);
+        assert!(expected.events.is_empty(), "{:?}", expected.events);
+        let mut sizes = random_sizes(packet::Packet::SIZE * 3);
+        for _ in 0..20 {
+            let actual = demux_program_in_chunks(&data, &mut sizes);
+            assert_eq!(actual.pes_packets, expected.pes_packets);
+            assert_eq!(actual.events, expected.events);
+        }
+    }
+
+    #[test]
+    fn pes_header_invalid() {
+        let mut data = make_program_stream();
+        // the first video PES packet starts in the packet following the PAT and PMT
+        let start = packet::Packet::SIZE * 2;
+        let pk = packet::Packet::new(&data[start..start + packet::Packet::SIZE]);
+        assert_eq!(pk.pid(), packet::Pid::new(0x100));
+        assert!(pk.payload_unit_start_indicator());
+        let payload_start = start + packet::Packet::SIZE - pk.payload().unwrap().len();
+        data[payload_start + 2] = 0x02; // corrupt packet_start_code_prefix
+        let ctx = demux_program_in_chunks(&data, &mut std::iter::repeat(data.len()));
+        assert_eq!(ctx.pes_packets.len(), 59);
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::PesHeaderInvalid {
+                pid: packet::Pid::new(0x100),
+                offset: start as u64,
+                error: pes::PesError::PacketStartCodePrefixInvalid(0x000002),
+            }]
+        );
+    }
+
+    #[test]
+    fn push_retains_incomplete_packet() {
+        let data = make_packets(2);
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data[..packet::Packet::SIZE + 100]);
+        assert_eq!(ctx.packets.len(), 1);
+        deplex.push(&mut ctx, &data[packet::Packet::SIZE + 100..]);
+        assert_eq!(ctx.packets.len(), 2);
+        assert_eq!(&ctx.packets[1][..], &data[packet::Packet::SIZE..]);
+    }
+
+    #[test]
+    fn flush() {
+        let data = make_packets(4);
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data[..packet::Packet::SIZE * 3 + 100]);
+        assert!(ctx.ended.is_empty());
+        deplex.flush(&mut ctx);
+        assert_eq!(
+            ctx.ended,
+            vec![
+                psi::pat::PAT_PID,
+                packet::Pid::new(0x100),
+                packet::Pid::new(0x101),
+                packet::Pid::new(0x102),
+            ]
+        );
+        // the incomplete packet is discarded rather than being completed by the next stream
+        deplex.push(&mut ctx, &data[..]);
+        assert_eq!(ctx.packets.len(), 3 + 4);
+        assert_eq!(&ctx.packets[3][..], &data[..packet::Packet::SIZE]);
+    }
+
+    fn assert_consistent_in_chunks(
+        data: &[u8],
+        expected_packets: &[&[u8]],
+        expected_events: &[demultiplex::DemuxEvent],
+    ) {
+        let (packets, events) =
+            demux_in_chunks_with_events(data, &mut std::iter::repeat(data.len()));
+        assert_eq!(packets, expected_packets);
+        assert_eq!(events, expected_events);
+        let mut sizes = random_sizes(packet::Packet::SIZE * 3);
+        for _ in 0..20 {
+            let (packets, events) = demux_in_chunks_with_events(data, &mut sizes);
+            assert_eq!(packets, expected_packets);
+            assert_eq!(events, expected_events);
+        }
+    }
+
+    #[test]
+    fn sync_acquired_mid_packet() {
+        let packets = make_packets(10);
+        let mut data = vec![0xff; 50];
+        data.extend_from_slice(&packets);
+        let expected: Vec<_> = packets.chunks(packet::Packet::SIZE).collect();
+        assert_consistent_in_chunks(
+            &data,
+            &expected,
+            &[demultiplex::DemuxEvent::SyncAcquired { offset: 50 }],
+        );
+    }
+
+    #[test]
+    fn sync_not_acquired_without_enough_packets() {
+        let packets = make_packets(4);
+        let mut data = vec![0xff; 50];
+        data.extend_from_slice(&packets);
+        assert_consistent_in_chunks(&data, &[], &[]);
+    }
+
+    #[test]
+    fn sync_survives_single_bad_sync_byte() {
+        let mut data = make_packets(10);
+        data[3 * packet::Packet::SIZE] = 0;
+        let expected: Vec<_> = data
+            .chunks(packet::Packet::SIZE)
+            .enumerate()
+            .filter(|(i, _)| *i != 3)
+            .map(|(_, pk)| pk)
+            .collect();
+        assert_consistent_in_chunks(&data, &expected, &[]);
+    }
+
+    #[test]
+    fn sync_lost_and_reacquired() {
+        let packets = make_packets(20);
+        // insert some junk after the 5th packet, so that following packets are misaligned
+        let junk_offset = 5 * packet::Packet::SIZE;
+        let mut data = packets[..junk_offset].to_vec();
+        data.extend_from_slice(&[0xff; 7]);
+        data.extend_from_slice(&packets[junk_offset..]);
+        // the hunt resumes just after the first misaligned packet slot, so that the packets in
+        // the slots examined before sync was considered lost are still delivered
+        let expected: Vec<_> = packets.chunks(packet::Packet::SIZE).collect();
+        assert_consistent_in_chunks(
+            &data,
+            &expected,
+            &[
+                demultiplex::DemuxEvent::SyncLost {
+                    offset: junk_offset as u64,
+                },
+                demultiplex::DemuxEvent::SyncAcquired {
+                    offset: junk_offset as u64 + 7,
+                },
+            ],
+        );
+    }
+
+    /// `make_packets()`, but in the 192 byte M2TS format
+    fn make_m2ts_packets(count: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        for (i, pk) in make_packets(count).chunks(packet::Packet::SIZE).enumerate() {
+            buf.extend_from_slice(&[0b0100_0000, 0, 0, i as u8]);
+            buf.extend_from_slice(pk);
+        }
+        buf
+    }
+
+    /// `make_packets()`, but in the 204 byte format with trailing parity bytes
+    fn make_rs_packets(count: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        for pk in make_packets(count).chunks(packet::Packet::SIZE) {
+            buf.extend_from_slice(pk);
+            buf.extend_from_slice(&[0x55; 16]);
+        }
+        buf
+    }
+
+    #[test]
+    fn m2ts_packets() {
+        let data = make_m2ts_packets(10);
+        let expected: Vec<_> = make_packets(10)
+            .chunks(packet::Packet::SIZE)
+            .map(|pk| pk.to_vec())
+            .collect();
+        let mut sizes = random_sizes(packet::Packet::SIZE * 3);
+        for _ in 0..20 {
+            let ctx = demux_with(&data, &mut sizes, |d| {
+                d.set_packet_format(packet::PacketFormat::M2ts)
+            });
+            assert_eq!(ctx.packets, expected);
+            for (i, hdr) in ctx.tp_extra_headers.iter().enumerate() {
+                let hdr = hdr.unwrap();
+                assert_eq!(hdr.copy_permission_indicator(), 1);
+                assert_eq!(hdr.arrival_time_stamp(), i as u32);
+            }
+            // offsets give the position of each packet's sync-byte, following its TP_extra_header
+            let offsets: Vec<u64> = (0..10).map(|i| i * 192 + 4).collect();
+            assert_eq!(ctx.offsets, offsets);
+            assert!(ctx.events.is_empty());
+        }
+    }
+
+    #[test]
+    fn detect_packet_formats() {
+        let expected: Vec<_> = make_packets(10)
+            .chunks(packet::Packet::SIZE)
+            .map(|pk| pk.to_vec())
+            .collect();
+        for (format, packets) in [
+            (packet::PacketFormat::Plain, make_packets(10)),
+            (packet::PacketFormat::M2ts, make_m2ts_packets(10)),
+            (packet::PacketFormat::ReedSolomon, make_rs_packets(10)),
+        ] {
+            let mut data = vec![0xff; 50];
+            data.extend_from_slice(&packets);
+            let mut sizes = random_sizes(packet::Packet::SIZE * 3);
+            for _ in 0..20 {
+                let mut ctx = RecordingDemuxContext::new();
+                let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+                deplex.detect_packet_format();
+                assert_eq!(deplex.packet_format(), None);
+                let mut rest = &data[..];
+                while !rest.is_empty() {
+                    let size = sizes.next().unwrap().min(rest.len());
+                    deplex.push(&mut ctx, &rest[..size]);
+                    rest = &rest[size..];
+                }
+                assert_eq!(deplex.packet_format(), Some(format));
+                assert_eq!(ctx.packets, expected);
+                assert_eq!(
+                    ctx.events,
+                    [demultiplex::DemuxEvent::SyncAcquired { offset: 50 }]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scrambled_packets() {
+        let packets = make_packets(3);
+        let mut data = packets.clone();
+        // scramble the payload of the second packet with the 'odd' key
+        let scrambled = &mut data[packet::Packet::SIZE..packet::Packet::SIZE * 2];
+        scrambled[3] |= 0b1100_0000;
+        for b in &mut scrambled[4..] {
+            *b ^= 0x5a;
+        }
+        let expected: Vec<_> = packets.chunks(packet::Packet::SIZE).collect();
+
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data);
+        assert_eq!(ctx.packets, [expected[0], expected[2]]);
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::ScrambledPacketDropped {
+                pid: packet::Pid::new(0x101),
+                offset: packet::Packet::SIZE as u64,
+            }]
+        );
+
+        let mut ctx = RecordingDemuxContext::new();
+        ctx.descrambler = Some(descramble::XorDescrambler::new([0; 16], [0x5a; 16]));
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data);
+        assert_eq!(ctx.packets, expected);
+    }
+
+    #[test]
+    fn transport_error_indicator() {
+        let mut data = make_packets(3);
+        data[packet::Packet::SIZE * 2 + 1] |= 0b1000_0000;
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data);
+        assert_eq!(ctx.packets.len(), 2);
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::TransportError {
+                pid: packet::Pid::new(0x102),
+                offset: packet::Packet::SIZE as u64 * 2,
+            }]
+        );
+    }
+
+    packet_filter_switch! {
+        EventFilterSwitch<EventDemuxContext> {
+            Pat: demultiplex::PatPacketFilter<EventDemuxContext>,
+            Nul: demultiplex::NullPacketFilter<EventDemuxContext>,
+        }
+    }
+    /// `DemuxContext` that records the `DemuxEvent`s reported by the standard filters
+    pub struct EventDemuxContext {
+        changeset: demultiplex::FilterChangeset<EventFilterSwitch>,
+        position: demultiplex::PacketPosition,
+        events: Vec<demultiplex::DemuxEvent>,
+    }
+    impl demultiplex::DemuxContext for EventDemuxContext {
+        type F = EventFilterSwitch;
+
+        fn filter_changeset(&mut self) -> &mut demultiplex::FilterChangeset<Self::F> {
+            &mut self.changeset
+        }
+
+        fn set_packet_position(&mut self, position: demultiplex::PacketPosition) {
+            self.position = position;
+        }
+        fn packet_position(&self) -> demultiplex::PacketPosition {
+            self.position
+        }
+        fn construct(&mut self, req: demultiplex::FilterRequest<'_, '_>) -> Self::F {
+            match req {
+                demultiplex::FilterRequest::ByPid(psi::pat::PAT_PID) => {
+                    EventFilterSwitch::Pat(demultiplex::PatPacketFilter::default())
+                }
+                _ => EventFilterSwitch::Nul(demultiplex::NullPacketFilter::default()),
+            }
+        }
+        fn event(&mut self, event: demultiplex::DemuxEvent) {
+            self.events.push(event);
+        }
+    }
+
+    #[test]
+    fn adaptation_field_length_invalid() {
+        let mut data = make_packets(3);
+        // adaptation field and payload, but adaptation_field_length leaves no room for a payload
+        data[packet::Packet::SIZE + 3] |= 0x30;
+        data[packet::Packet::SIZE + 4] = 183;
+        let mut ctx = RecordingDemuxContext::new();
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &data);
+        assert_eq!(ctx.packets.len(), 3);
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::AdaptationFieldLengthInvalid {
+                pid: packet::Pid::new(0x101),
+                offset: packet::Packet::SIZE as u64,
+                adaptation_control: packet::AdaptationControl::AdaptationFieldAndPayload,
+                length: 183,
+            }]
+        );
+    }
+
+    #[test]
+    fn pat_crc_mismatch() {
+        // (table data is the same as for the pat() test)
+        let mut buf = hex!("474000150000B00D0001C100000001E1E02D507804").to_vec();
+        buf.resize(packet::Packet::SIZE, 0xff);
+        buf.extend_from_slice(&make_packets(1));
+        let mut ctx = EventDemuxContext {
+            changeset: demultiplex::FilterChangeset::default(),
+            position: demultiplex::PacketPosition::default(),
+            events: vec![],
+        };
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &buf[..]);
+        assert_eq!(ctx.events, []);
+
+        // corrupt the section, so that the CRC no longer matches
+        buf[16] ^= 0xff;
+        let mut ctx = EventDemuxContext {
+            changeset: demultiplex::FilterChangeset::default(),
+            position: demultiplex::PacketPosition::default(),
+            events: vec![],
+        };
+        let mut deplex = demultiplex::Demultiplex::new(&mut ctx);
+        deplex.push(&mut ctx, &buf[..]);
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::SectionCrcMismatch {
+                pid: psi::pat::PAT_PID,
+                offset: 0,
+                table_id: 0,
+            }]
+        );
+    }
+
     #[test]
     fn pat_no_existing_program() {
         let mut processor = demultiplex::PatProcessor::default();
@@ -811,6 +2256,44 @@ pub(crate) mod test {
         }
     }
 
+    #[test]
+    fn pat_entry_truncated() {
+        let mut processor = demultiplex::PatProcessor::default();
+        let section = vec![
+            0, 0, 0, // common header
+            // table syntax header
+            0x0F, 0x00, 0b00000001, 0xC1, 0x00, // PAT section
+            0, 1, // program_number
+            0, 101, // pid
+            0, 2, // truncated entry
+            0, 0, 0, 0, // CRC (incorrect!)
+        ];
+        let header = psi::SectionCommonHeader::new(&section[..psi::SectionCommonHeader::SIZE]);
+        let table_syntax_header =
+            psi::TableSyntaxHeader::new(&section[psi::SectionCommonHeader::SIZE..]);
+        let mut ctx = EventDemuxContext {
+            changeset: demultiplex::FilterChangeset::default(),
+            position: demultiplex::PacketPosition::new(psi::pat::PAT_PID, 376),
+            events: vec![],
+        };
+        processor.section(&mut ctx, &header, &table_syntax_header, &section[..]);
+        let mut changes = ctx.changeset.updates.into_iter();
+        if let Some(demultiplex::FilterChange::Insert(pid, _)) = changes.next() {
+            assert_eq!(packet::Pid::new(101), pid);
+        } else {
+            panic!();
+        }
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::PatEntryTruncated {
+                pid: psi::pat::PAT_PID,
+                offset: 376,
+                expected: 4,
+                actual: 2,
+            }]
+        );
+    }
+
     pub(crate) fn make_test_data<F>(builder: F) -> Vec<u8>
     where
         F: Fn(&mut BitWriter<Vec<u8>, BE>) -> Result<(), io::Error>,
@@ -879,4 +2362,75 @@ pub(crate) mod test {
             panic!();
         }
     }
+
+    #[test]
+    fn pmt_stream_info_truncated() {
+        let pid = packet::Pid::new(101);
+        let program_number = 1001;
+        let mut processor = demultiplex::PmtProcessor::new(pid, program_number);
+        let section = make_test_data(|w| {
+            // common section header,
+            w.write(8, 0x02)?; // table_id
+            w.write_bit(true)?; // section_syntax_indicator
+            w.write_bit(false)?; // private_indicator
+            w.write(2, 3)?; // reserved
+            w.write(12, 26)?; // section_length
+
+            // section syntax header,
+            w.write(16, 0)?; // id
+            w.write(2, 3)?; // reserved
+            w.write(5, 0)?; // version
+            w.write(1, 1)?; // current_next_indicator
+            w.write(8, 0)?; // section_number
+            w.write(8, 0)?; // last_section_number
+
+            // PMT section payload
+            w.write(3, 7)?; // reserved
+            w.write(13, 123)?; // pcr_pid
+            w.write(4, 15)?; // reserved
+            w.write(12, 0)?; // program_info_length
+
+            // a complete stream info entry,
+            w.write(8, 0)?; // stream_type
+            w.write(3, 7)?; // reserved
+            w.write(13, 201)?; // elementary_pid
+            w.write(4, 15)?; // reserved
+            w.write(12, 0)?; // es_info_length
+
+            // and one whose es_info_length extends beyond the end of the section
+            w.write(8, 0)?; // stream_type
+            w.write(3, 7)?; // reserved
+            w.write(13, 202)?; // elementary_pid
+            w.write(4, 15)?; // reserved
+            w.write(12, 6)?; // es_info_length
+            w.write(8, 0)?; // descriptor_tag
+            w.write(32, 0) // CRC (incorrect)
+        });
+        let header = psi::SectionCommonHeader::new(&section[..psi::SectionCommonHeader::SIZE]);
+        let table_syntax_header =
+            psi::TableSyntaxHeader::new(&section[psi::SectionCommonHeader::SIZE..]);
+        let mut ctx = EventDemuxContext {
+            changeset: demultiplex::FilterChangeset::default(),
+            position: demultiplex::PacketPosition::new(pid, 376),
+            events: vec![],
+        };
+        processor.section(&mut ctx, &header, &table_syntax_header, &section[..]);
+        let mut changes = ctx.changeset.updates.into_iter();
+        if let Some(demultiplex::FilterChange::Insert(pid, _)) = changes.next() {
+            assert_eq!(packet::Pid::new(201), pid);
+        } else {
+            panic!();
+        }
+        assert!(changes.next().is_none());
+        assert_eq!(
+            ctx.events,
+            [demultiplex::DemuxEvent::StreamInfoTruncated {
+                pid,
+                offset: 376,
+                program_number,
+                expected: 11,
+                actual: 6,
+            }]
+        );
+    }
 }
@@ -0,0 +1,323 @@
+//! Support for descrambling the payloads of packets which have a _transport_scrambling_control_
+//! value indicating that they are scrambled.
+//!
+//! The scrambling scheme used is not defined by _ISO/IEC 13818-1_, so an implementation of
+//! [`Descrambler`](trait.Descrambler.html) suitable for the stream being processed must be
+//! made available by the application's
+//! [`DemuxContext::descrambler()`](../demultiplex/trait.DemuxContext.html#method.descrambler)
+//! implementation.  Without one, the demultiplexer drops scrambled packets.
+//!
+//! The descrambler is given a copy of the packet payload to decrypt in place, and the packet is
+//! then passed on to the relevant `PacketFilter` with its _transport_scrambling_control_ field
+//! changed to indicate that it is no longer scrambled.
+
+use crate::packet;
+use crate::packet::TransportScramblingControl;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use std::fmt;
+
+/// Indicates which of the two currently valid keys was used to scramble a packet.
+///
+/// Per the convention used by DVB and others, a _transport_scrambling_control_ value of `2`
+/// indicates the even key, and `3` the odd key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyParity {
+    /// The packet was scrambled with the 'even' key.
+    Even,
+    /// The packet was scrambled with the 'odd' key.
+    Odd,
+}
+impl KeyParity {
+    /// Returns the key to use for the given _transport_scrambling_control_ value, or `None` if
+    /// the value does not indicate that the packet is scrambled with an even or odd key.
+    pub fn from_scrambling_control(control: &TransportScramblingControl) -> Option<KeyParity> {
+        match control {
+            TransportScramblingControl::Undefined(2) => Some(KeyParity::Even),
+            TransportScramblingControl::Undefined(3) => Some(KeyParity::Odd),
+            _ => None,
+        }
+    }
+}
+
+/// Problems which may prevent a packet from being descrambled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescrambleError {
+    /// The packet's _transport_scrambling_control_ field had a value that does not indicate the
+    /// even or odd key.
+    UnsupportedScramblingControl(u8),
+    /// No key of the given parity is currently available.
+    NoKey(KeyParity),
+}
+impl fmt::Display for DescrambleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DescrambleError::UnsupportedScramblingControl(val) => {
+                write!(f, "unsupported transport_scrambling_control value {}", val)
+            }
+            DescrambleError::NoKey(parity) => write!(f, "no {:?} key available", parity),
+        }
+    }
+}
+impl std::error::Error for DescrambleError {}
+
+/// Trait for objects which can recover the cleartext payload of a scrambled packet.
+pub trait Descrambler {
+    /// Descramble the given `payload` in place.  The `payload` is a copy of the payload of the
+    /// given (still scrambled) packet, which is supplied so that implementations can take into
+    /// account details like the packet's PID.
+    fn descramble(
+        &mut self,
+        pk: &packet::Packet<'_>,
+        parity: KeyParity,
+        payload: &mut [u8],
+    ) -> Result<(), DescrambleError>;
+}
+
+/// Copies the given scrambled packet into `out`, clearing the _transport_scrambling_control_
+/// field and using the given `Descrambler` to descramble the copy of the payload.
+pub(crate) fn descramble_packet(
+    descrambler: &mut dyn Descrambler,
+    pk: &packet::Packet<'_>,
+    out: &mut [u8; packet::Packet::SIZE],
+) -> Result<(), DescrambleError> {
+    let control = pk.transport_scrambling_control();
+    let parity = KeyParity::from_scrambling_control(&control).ok_or(match control {
+        TransportScramblingControl::Undefined(val) => {
+            DescrambleError::UnsupportedScramblingControl(val)
+        }
+        TransportScramblingControl::NotScrambled => {
+            DescrambleError::UnsupportedScramblingControl(0)
+        }
+    })?;
+    out.copy_from_slice(pk.buffer());
+    out[3] &= 0b0011_1111;
+    if let Some(payload) = pk.payload() {
+        let offset = packet::Packet::SIZE - payload.len();
+        descrambler.descramble(pk, parity, &mut out[offset..])?;
+    }
+    Ok(())
+}
+
+/// How the final bytes of a payload whose length is not a multiple of the 16 byte AES block size
+/// were scrambled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResidualBlockMode {
+    /// The residual bytes were left in the clear, as specified by DVB-CISSA and DVB-IPTV.
+    Clear,
+    /// The residual bytes were XORed with the encryption of the last whole ciphertext block (or
+    /// of the initialisation vector, if the payload is shorter than one block), per the
+    /// residual block termination of ANSI/SCTE 52 as also used by ATIS IDSA.
+    Scte52,
+}
+
+/// AES-128 in CBC mode, as used by DVB-CISSA and several IPTV conditional access systems.
+///
+/// The payload of each packet is decrypted independently, starting from the same
+/// initialisation vector.  By default, any final partial block of fewer than 16 bytes at the end
+/// of the payload is assumed to have been left in the clear; use `with_residual_mode()` for
+/// systems which scramble these bytes too.
+pub struct Aes128CbcDescrambler {
+    iv: [u8; 16],
+    residual_mode: ResidualBlockMode,
+    even: Option<aes::Aes128>,
+    odd: Option<aes::Aes128>,
+}
+impl Aes128CbcDescrambler {
+    /// The initialisation vector specified for DVB-CISSA, the ASCII text `"DVBTMCPTAESCISSA"`.
+    pub const CISSA_IV: [u8; 16] = *b"DVBTMCPTAESCISSA";
+
+    /// Create a descrambler which will use the given initialisation vector, and which initially
+    /// has no keys.  Residual blocks are assumed to be in the clear.
+    pub fn new(iv: [u8; 16]) -> Aes128CbcDescrambler {
+        Aes128CbcDescrambler {
+            iv,
+            residual_mode: ResidualBlockMode::Clear,
+            even: None,
+            odd: None,
+        }
+    }
+
+    /// Change how any residual bytes following the last whole block of each payload are to be
+    /// descrambled.
+    pub fn with_residual_mode(mut self, mode: ResidualBlockMode) -> Aes128CbcDescrambler {
+        self.residual_mode = mode;
+        self
+    }
+
+    /// Set the key to be used for packets scrambled with the key of the given parity (as
+    /// typically delivered in an ECM).
+    pub fn set_key(&mut self, parity: KeyParity, key: [u8; 16]) {
+        let cipher = aes::Aes128::new(&GenericArray::from(key));
+        match parity {
+            KeyParity::Even => self.even = Some(cipher),
+            KeyParity::Odd => self.odd = Some(cipher),
+        }
+    }
+
+    /// Forget the key of the given parity, so that packets scrambled with it can no longer be
+    /// descrambled.
+    pub fn clear_key(&mut self, parity: KeyParity) {
+        match parity {
+            KeyParity::Even => self.even = None,
+            KeyParity::Odd => self.odd = None,
+        }
+    }
+}
+impl Descrambler for Aes128CbcDescrambler {
+    fn descramble(
+        &mut self,
+        _pk: &packet::Packet<'_>,
+        parity: KeyParity,
+        payload: &mut [u8],
+    ) -> Result<(), DescrambleError> {
+        let cipher = match parity {
+            KeyParity::Even => self.even.as_ref(),
+            KeyParity::Odd => self.odd.as_ref(),
+        }
+        .ok_or(DescrambleError::NoKey(parity))?;
+        let mut prev = self.iv;
+        let mut chunks = payload.chunks_exact_mut(16);
+        for chunk in &mut chunks {
+            let mut ciphertext = [0u8; 16];
+            ciphertext.copy_from_slice(chunk);
+            cipher.decrypt_block(GenericArray::from_mut_slice(chunk));
+            for (b, p) in chunk.iter_mut().zip(prev.iter()) {
+                *b ^= p;
+            }
+            prev = ciphertext;
+        }
+        let residual = chunks.into_remainder();
+        if self.residual_mode == ResidualBlockMode::Scte52 && !residual.is_empty() {
+            let mut keystream = GenericArray::from(prev);
+            cipher.encrypt_block(&mut keystream);
+            for (b, k) in residual.iter_mut().zip(keystream.iter()) {
+                *b ^= k;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A trivial 'scrambling' scheme which XORs the payload with a repeating 16 byte key, offering
+/// no security whatsoever.
+///
+/// Intended for use in tests that need to exercise the handling of scrambled packets without
+/// depending on a real scrambling algorithm.
+pub struct XorDescrambler {
+    even: [u8; 16],
+    odd: [u8; 16],
+}
+impl XorDescrambler {
+    /// Create a descrambler using the given even and odd keys.
+    pub fn new(even: [u8; 16], odd: [u8; 16]) -> XorDescrambler {
+        XorDescrambler { even, odd }
+    }
+}
+impl Descrambler for XorDescrambler {
+    fn descramble(
+        &mut self,
+        _pk: &packet::Packet<'_>,
+        parity: KeyParity,
+        payload: &mut [u8],
+    ) -> Result<(), DescrambleError> {
+        let key = match parity {
+            KeyParity::Even => &self.even,
+            KeyParity::Odd => &self.odd,
+        };
+        for (b, k) in payload.iter_mut().zip(key.iter().cycle()) {
+            *b ^= k;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::*;
+
+    fn dummy_packet(buf: &mut [u8; packet::Packet::SIZE]) -> packet::Packet<'_> {
+        buf[0] = packet::Packet::SYNC_BYTE;
+        packet::Packet::new(&buf[..])
+    }
+
+    #[test]
+    fn aes_cbc() {
+        // test vector from NIST SP 800-38A, F.2.1
+        let mut d = Aes128CbcDescrambler::new(hex!("000102030405060708090a0b0c0d0e0f"));
+        d.set_key(KeyParity::Odd, hex!("2b7e151628aed2a6abf7158809cf4f3c"));
+        let mut payload =
+            hex!("7649abac8119b246cee98e9b12e9197d 5086cb9b507219ee95db113a917678b2 010203");
+        let mut buf = [0u8; packet::Packet::SIZE];
+        let pk = dummy_packet(&mut buf);
+        assert_eq!(
+            d.descramble(&pk, KeyParity::Even, &mut payload[..]),
+            Err(DescrambleError::NoKey(KeyParity::Even))
+        );
+        d.descramble(&pk, KeyParity::Odd, &mut payload[..]).unwrap();
+        assert_eq!(
+            payload,
+            hex!("6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51 010203")
+        );
+    }
+
+    #[test]
+    fn aes_cbc_residual_clear() {
+        let mut d = Aes128CbcDescrambler::new(hex!("000102030405060708090a0b0c0d0e0f"));
+        d.set_key(KeyParity::Even, hex!("2b7e151628aed2a6abf7158809cf4f3c"));
+        let mut buf = [0u8; packet::Packet::SIZE];
+        let pk = dummy_packet(&mut buf);
+        let mut payload = hex!("7649abac8119b246cee98e9b12e9197d 0102030405");
+        d.descramble(&pk, KeyParity::Even, &mut payload[..])
+            .unwrap();
+        assert_eq!(payload, hex!("6bc1bee22e409f96e93d7e117393172a 0102030405"));
+        // a payload shorter than one block is left entirely alone
+        let mut payload = hex!("0102030405");
+        d.descramble(&pk, KeyParity::Even, &mut payload[..])
+            .unwrap();
+        assert_eq!(payload, hex!("0102030405"));
+    }
+
+    #[test]
+    fn aes_cbc_residual_scte52() {
+        let mut d = Aes128CbcDescrambler::new(hex!("000102030405060708090a0b0c0d0e0f"))
+            .with_residual_mode(ResidualBlockMode::Scte52);
+        d.set_key(KeyParity::Even, hex!("2b7e151628aed2a6abf7158809cf4f3c"));
+        let mut buf = [0u8; packet::Packet::SIZE];
+        let pk = dummy_packet(&mut buf);
+        let mut payload =
+            hex!("7649abac8119b246cee98e9b12e9197d 5086cb9b507219ee95db113a917678b2 40cb70");
+        d.descramble(&pk, KeyParity::Even, &mut payload[..])
+            .unwrap();
+        assert_eq!(
+            payload,
+            hex!("6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51 010203")
+        );
+        // a payload shorter than one block is XORed with the encrypted IV
+        let mut payload = hex!("51fc64c89c");
+        d.descramble(&pk, KeyParity::Even, &mut payload[..])
+            .unwrap();
+        assert_eq!(payload, hex!("0102030405"));
+    }
+
+    #[test]
+    fn descramble_packet_clears_scrambling_control() {
+        let mut buf = [0xaau8; packet::Packet::SIZE];
+        buf[0] = packet::Packet::SYNC_BYTE;
+        buf[1] = 0x01;
+        buf[2] = 0x00;
+        buf[3] = 0b1101_0000; // odd key, payload only
+        let pk = packet::Packet::new(&buf[..]);
+        let mut d = XorDescrambler::new([0; 16], [0xff; 16]);
+        let mut out = [0u8; packet::Packet::SIZE];
+        descramble_packet(&mut d, &pk, &mut out).unwrap();
+        let clear = packet::Packet::new(&out[..]);
+        assert_eq!(
+            clear.transport_scrambling_control(),
+            TransportScramblingControl::NotScrambled
+        );
+        assert_eq!(clear.pid(), pk.pid());
+        assert!(clear.payload().unwrap().iter().all(|&b| b == 0x55));
+    }
+}
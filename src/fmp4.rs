@@ -0,0 +1,1090 @@
+//! Remuxing of demultiplexed H.264 (or H.265) video and ADTS AAC audio into fragmented MP4, in
+//! the form used by CMAF (_ISO/IEC 23000-19_) and by HLS / DASH players.
+//!
+//! A [`Remuxer`](struct.Remuxer.html) accepts the
+//! [`DemuxedSegment`](../parser/struct.DemuxedSegment.html)s produced by a
+//! [`NALParser`](../parser/struct.NALParser.html), and either the payloads of ADTS audio PES
+//! packets or the [`AdtsFrame`](../parser/adts/struct.AdtsFrame.html)s produced by an
+//! [`AdtsParser`](../parser/adts/struct.AdtsParser.html), and writes
+//!
+//! - an _initialisation segment_ (`ftyp` and `moov` boxes, with an `avcC`, `hvcC` or `esds`
+//!   decoder configuration for each track), once the configuration of every track is known
+//! - a _media segment_ (a `moof` and `mdat` box pair) for each group of pictures, cut
+//!   immediately before each video key frame.  Audio-only output is cut roughly every second.
+//!
+//! Video sample durations and composition offsets are taken from the PES DTS / PTS values.  Audio
+//! samples are assumed to be contiguous from the PTS of the first AAC frame, with 1024 samples
+//! per frame.  The two tracks share a timeline starting at whichever of the first video DTS and
+//! first audio PTS is the earlier.
+//!
+//! ```
+//! # use mpeg2ts_reader::fmp4::Remuxer;
+//! # use mpeg2ts_reader::parser::NALParser;
+//! let mut parser = NALParser::new();
+//! let mut remuxer = Remuxer::new().video(true);
+//! let mut out = vec![];
+//! // ...pass PES payloads to the parser, and then...
+//! for seg in parser.drain_segments() {
+//!     remuxer.push_video(&seg, &mut out).unwrap();
+//! }
+//! remuxer.flush(&mut out);
+//! ```
+
+use crate::parser::adts::{self, AdtsError, AdtsFrame, AdtsHeader};
+use crate::parser::avcc::{annexb_to_avcc, AvcConfigBuilder, AvccError, HevcConfigBuilder};
+use crate::parser::h264::{self, ParamSetError};
+use crate::parser::{hevc, timestamp_difference, DemuxedSegment, VideoCodec};
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+/// The number of PCM samples decoded from each AAC frame
+const AAC_FRAME_SAMPLES: u64 = adts::SAMPLES_PER_RAW_DATA_BLOCK as u64;
+
+/// the _sample_depends_on_ value 2, i.e. a sync sample
+const SAMPLE_FLAGS_KEY: u32 = 0x0200_0000;
+/// the _sample_depends_on_ value 1, and _sample_is_non_sync_sample_ set
+const SAMPLE_FLAGS_DELTA: u32 = 0x0101_0000;
+const IDENTITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Problems which may be found while remuxing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fmp4Error {
+    /// Data was given for a track that was not enabled when the `Remuxer` was constructed.
+    NoSuchTrack,
+    /// The video decoder configuration record could not be built.
+    Config(AvccError),
+    /// An ADTS frame header could not be parsed.
+    Adts(AdtsError),
+    /// The audio data did not consist of complete ADTS frames, each holding a single AAC frame.
+    InvalidAdts,
+    /// The audio format given in an ADTS header differed from that of the first frame.
+    AudioConfigChanged,
+}
+impl From<AvccError> for Fmp4Error {
+    fn from(e: AvccError) -> Self {
+        Fmp4Error::Config(e)
+    }
+}
+impl From<AdtsError> for Fmp4Error {
+    fn from(e: AdtsError) -> Self {
+        Fmp4Error::Adts(e)
+    }
+}
+impl From<ParamSetError> for Fmp4Error {
+    fn from(e: ParamSetError) -> Self {
+        Fmp4Error::Config(AvccError::ParamSet(e))
+    }
+}
+impl fmt::Display for Fmp4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fmp4Error::NoSuchTrack => write!(f, "track not enabled"),
+            Fmp4Error::Config(e) => write!(f, "problem with decoder configuration: {}", e),
+            Fmp4Error::Adts(e) => write!(f, "problem with ADTS header: {}", e),
+            Fmp4Error::InvalidAdts => write!(f, "incomplete or multi-block ADTS frame"),
+            Fmp4Error::AudioConfigChanged => write!(f, "audio configuration changed"),
+        }
+    }
+}
+impl std::error::Error for Fmp4Error {}
+
+struct VideoConfig {
+    codec: VideoCodec,
+    record: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+impl VideoConfig {
+    fn from_segment(seg: &DemuxedSegment) -> Result<Option<VideoConfig>, Fmp4Error> {
+        let (sps, pps) = match (seg.sps(), seg.pps()) {
+            (Some(sps), Some(pps)) => (sps, pps),
+            _ => return Ok(None),
+        };
+        let config = match seg.codec() {
+            VideoCodec::H264 => {
+                let record = AvcConfigBuilder::new().sps(sps).pps(pps).build()?;
+                let sps = h264::SeqParameterSet::from_bytes(sps)?;
+                VideoConfig {
+                    codec: VideoCodec::H264,
+                    record,
+                    width: sps.width(),
+                    height: sps.height(),
+                }
+            }
+            VideoCodec::H265 => {
+                let vps = match seg.vps() {
+                    Some(vps) => vps,
+                    None => return Ok(None),
+                };
+                let record = HevcConfigBuilder::new()
+                    .vps(vps)
+                    .sps(sps)
+                    .pps(pps)
+                    .build()?;
+                let sps = hevc::SeqParameterSet::from_bytes(sps)?;
+                VideoConfig {
+                    codec: VideoCodec::H265,
+                    record,
+                    width: sps.width(),
+                    height: sps.height(),
+                }
+            }
+        };
+        Ok(Some(config))
+    }
+}
+
+/// The format of the audio track, as given by the header of its first ADTS frame
+#[derive(Debug, Clone, Copy)]
+struct AudioConfig(AdtsHeader);
+impl AudioConfig {
+    /// `true` if the given header describes audio of the same format
+    fn matches(&self, header: &AdtsHeader) -> bool {
+        self.0.profile == header.profile
+            && self.0.sampling_frequency_index == header.sampling_frequency_index
+            && self.0.channel_configuration == header.channel_configuration
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0.sample_rate()
+    }
+}
+
+struct VideoSample {
+    data: Vec<u8>,
+    /// decode time in 90kHz units, relative to the first sample of the track
+    decode_time: u64,
+    duration: u32,
+    composition_offset: i32,
+    key: bool,
+}
+
+#[derive(Default)]
+struct VideoTrack {
+    config: Option<VideoConfig>,
+    /// the DTS and `DemuxedSegment::decode_time()` of the first sample of the track
+    start: Option<(Timestamp, u64)>,
+    /// the time of the first sample of the track on the shared timeline, in 90kHz units
+    offset: u64,
+    samples: Vec<VideoSample>,
+}
+impl VideoTrack {
+    fn time(&self, sample: &VideoSample) -> u64 {
+        self.offset + sample.decode_time
+    }
+}
+
+#[derive(Default)]
+struct AudioTrack {
+    config: Option<AudioConfig>,
+    /// the PTS of the first frame of the track
+    start: Option<Timestamp>,
+    /// the time of the first frame of the track on the shared timeline, in audio samples
+    offset: u64,
+    /// the number of frames already written to fragments
+    written: u64,
+    frames: Vec<Vec<u8>>,
+}
+impl AudioTrack {
+    /// The time of the given pending frame on the shared timeline, in audio samples
+    fn time(&self, index: usize) -> u64 {
+        self.offset + (self.written + index as u64) * AAC_FRAME_SAMPLES
+    }
+
+    /// The time of the given pending frame on the shared timeline, in 90kHz units
+    fn time_90k(&self, rate: u32, index: usize) -> u64 {
+        self.time(index) * Timestamp::TIMEBASE / u64::from(rate)
+    }
+}
+
+/// Writes fragmented MP4 output from demultiplexed video access units and ADTS audio.
+///
+/// The output of all the `push_*()` methods is appended to the given `Vec`; nothing is written
+/// until the decoder configuration of every enabled track is known.  Video access units before
+/// the first key frame carrying parameter sets are discarded, as are audio frames before the
+/// first with a PTS.  Changes to the video parameter sets after the first key frame are not
+/// reflected in the output.
+#[derive(Default)]
+pub struct Remuxer {
+    video: Option<VideoTrack>,
+    audio: Option<AudioTrack>,
+    init_written: bool,
+    sequence_number: u32,
+}
+impl Remuxer {
+    /// Create a remuxer with no tracks.  At least one of `video()` or `audio()` should be used
+    /// to enable a track.
+    pub fn new() -> Remuxer {
+        Remuxer::default()
+    }
+
+    /// Enable or disable the video track, which is given the _track_ID_ `1`.
+    pub fn video(self, enabled: bool) -> Remuxer {
+        Remuxer {
+            video: enabled.then(VideoTrack::default),
+            ..self
+        }
+    }
+
+    /// Enable or disable the audio track, which is given the _track_ID_ `2`.
+    pub fn audio(self, enabled: bool) -> Remuxer {
+        Remuxer {
+            audio: enabled.then(AudioTrack::default),
+            ..self
+        }
+    }
+
+    /// `true` once the initialisation segment has been written.
+    pub fn init_written(&self) -> bool {
+        self.init_written
+    }
+
+    /// Add a video access unit, writing a fragment holding the preceding group of pictures if
+    /// this is a key frame.
+    pub fn push_video(&mut self, seg: &DemuxedSegment, out: &mut Vec<u8>) -> Result<(), Fmp4Error> {
+        let video = self.video.as_mut().ok_or(Fmp4Error::NoSuchTrack)?;
+        let payload = match seg.payload() {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let (_, start_time) = match video.start {
+            Some(start) => start,
+            None => {
+                let dts = match seg.dts() {
+                    Some(dts) if seg.is_key_frame() => dts,
+                    _ => {
+                        trace!("discarding access unit before first key frame");
+                        return Ok(());
+                    }
+                };
+                video.config = match VideoConfig::from_segment(seg)? {
+                    Some(config) => Some(config),
+                    None => {
+                        trace!("discarding key frame without parameter sets");
+                        return Ok(());
+                    }
+                };
+                *video.start.insert((dts, seg.decode_time()))
+            }
+        };
+        video.samples.push(VideoSample {
+            data: annexb_to_avcc(payload),
+            // a stream whose DTS goes backwards could give a time before the first sample
+            decode_time: seg.decode_time().saturating_sub(start_time),
+            duration: seg.duration() as u32,
+            composition_offset: seg.composition_offset() as i32,
+            key: seg.is_key_frame(),
+        });
+        if seg.is_key_frame() {
+            self.write_ready(out, false);
+        }
+        Ok(())
+    }
+
+    /// Add the payload of an audio PES packet, which must consist of one or more complete ADTS
+    /// frames.  The PTS of the PES packet is needed only for the first frame of the stream.
+    ///
+    /// Where PES packets do not hold whole ADTS frames, use an
+    /// [`AdtsParser`](../parser/adts/struct.AdtsParser.html) and `push_adts_frame()` instead.
+    pub fn push_audio(
+        &mut self,
+        pts: Option<Timestamp>,
+        mut data: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), Fmp4Error> {
+        while !data.is_empty() {
+            let header = AdtsHeader::from_bytes(data)?;
+            let frame_length = usize::from(header.frame_length);
+            if frame_length > data.len() {
+                return Err(Fmp4Error::InvalidAdts);
+            }
+            self.add_audio_frame(pts, &header, &data[..frame_length])?;
+            data = &data[frame_length..];
+        }
+        self.write_audio_only(out);
+        Ok(())
+    }
+
+    /// Add an ADTS frame, as produced by an [`AdtsParser`](../parser/adts/struct.AdtsParser.html).
+    /// The PTS of the frame is needed only for the first frame of the stream.
+    pub fn push_adts_frame(
+        &mut self,
+        frame: &AdtsFrame,
+        out: &mut Vec<u8>,
+    ) -> Result<(), Fmp4Error> {
+        self.add_audio_frame(frame.pts(), frame.header(), frame.data())?;
+        self.write_audio_only(out);
+        Ok(())
+    }
+
+    fn add_audio_frame(
+        &mut self,
+        pts: Option<Timestamp>,
+        header: &AdtsHeader,
+        frame: &[u8],
+    ) -> Result<(), Fmp4Error> {
+        let audio = self.audio.as_mut().ok_or(Fmp4Error::NoSuchTrack)?;
+        if audio.start.is_none() {
+            match pts {
+                Some(pts) => audio.start = Some(pts),
+                None => {
+                    trace!("discarding audio before first PTS");
+                    return Ok(());
+                }
+            }
+        }
+        if header.number_of_raw_data_blocks_in_frame != 0 {
+            return Err(Fmp4Error::InvalidAdts);
+        }
+        if !audio
+            .config
+            .get_or_insert(AudioConfig(*header))
+            .matches(header)
+        {
+            return Err(Fmp4Error::AudioConfigChanged);
+        }
+        audio.frames.push(frame[header.header_length()..].to_vec());
+        Ok(())
+    }
+
+    /// Without a video track, fragments are cut whenever enough audio is available.
+    fn write_audio_only(&mut self, out: &mut Vec<u8>) {
+        if self.video.is_none() {
+            self.write_ready(out, false);
+        }
+    }
+
+    /// Write any remaining samples, and the initialisation segment if that has not been written
+    /// yet.  Enabled tracks whose configuration is still unknown are left out of the output.
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        self.write_ready(out, true);
+    }
+
+    fn is_configured(&self) -> bool {
+        self.video.as_ref().map(|v| v.config.is_some()) != Some(false)
+            && self.audio.as_ref().map(|a| a.config.is_some()) != Some(false)
+    }
+
+    fn write_ready(&mut self, out: &mut Vec<u8>, flush: bool) {
+        if !self.init_written {
+            if !(self.is_configured() || flush) {
+                return;
+            }
+            if self.video.as_ref().map(|v| v.config.is_none()) == Some(true) {
+                self.video = None;
+            }
+            if self.audio.as_ref().map(|a| a.config.is_none()) == Some(true) {
+                self.audio = None;
+            }
+            if self.video.is_none() && self.audio.is_none() {
+                return;
+            }
+            self.start_timeline();
+            self.write_init(out);
+            self.init_written = true;
+        }
+        loop {
+            let (video_count, audio_count) = self.next_fragment(flush);
+            if video_count == 0 && audio_count == 0 {
+                break;
+            }
+            self.write_fragment(out, video_count, audio_count);
+        }
+    }
+
+    /// Places the tracks on a shared timeline starting with the earliest first timestamp.
+    fn start_timeline(&mut self) {
+        let video_start = self
+            .video
+            .as_ref()
+            .and_then(|v| v.start)
+            .map(|(dts, _)| dts);
+        let audio_start = self.audio.as_ref().and_then(|a| a.start);
+        let origin = match (video_start, audio_start) {
+            (Some(v), Some(a)) if timestamp_difference(a, v) < 0 => a,
+            (Some(v), _) => v,
+            (None, Some(a)) => a,
+            (None, None) => return,
+        };
+        if let (Some(video), Some(start)) = (self.video.as_mut(), video_start) {
+            video.offset = timestamp_difference(start, origin) as u64;
+        }
+        if let Some(audio) = self.audio.as_mut() {
+            if let (Some(config), Some(start)) = (audio.config, audio_start) {
+                let offset = timestamp_difference(start, origin) as u64;
+                audio.offset = offset * u64::from(config.sample_rate()) / Timestamp::TIMEBASE;
+            }
+        }
+    }
+
+    /// The number of pending video samples and audio frames to put in the next fragment.
+    fn next_fragment(&self, flush: bool) -> (usize, usize) {
+        let audio_pending = |before: Option<u64>| match self.audio {
+            Some(ref audio) => {
+                let rate = audio.config.map(|c| c.sample_rate()).unwrap_or(1);
+                match before {
+                    Some(t) => (0..audio.frames.len())
+                        .take_while(|&i| audio.time_90k(rate, i) < t)
+                        .count(),
+                    None => audio.frames.len(),
+                }
+            }
+            None => 0,
+        };
+        if let Some(ref video) = self.video {
+            // the first pending sample will always be a key frame
+            let cut = video.samples.iter().skip(1).position(|s| s.key);
+            match cut {
+                Some(i) => {
+                    let count = i + 1;
+                    (
+                        count,
+                        audio_pending(Some(video.time(&video.samples[count]))),
+                    )
+                }
+                None if flush => (video.samples.len(), audio_pending(None)),
+                None => (0, 0),
+            }
+        } else if let Some(ref audio) = self.audio {
+            let per_fragment = match audio.config {
+                Some(config) => (u64::from(config.sample_rate()) / AAC_FRAME_SAMPLES) as usize + 1,
+                None => return (0, 0),
+            };
+            if audio.frames.len() >= per_fragment {
+                (0, per_fragment)
+            } else if flush {
+                (0, audio.frames.len())
+            } else {
+                (0, 0)
+            }
+        } else {
+            (0, 0)
+        }
+    }
+
+    fn write_init(&self, out: &mut Vec<u8>) {
+        write_box(out, b"ftyp", |out| {
+            out.extend_from_slice(b"iso6");
+            out.extend_from_slice(&0u32.to_be_bytes());
+            for brand in [b"iso6", b"cmfc", b"mp41"] {
+                out.extend_from_slice(brand);
+            }
+        });
+        write_box(out, b"moov", |out| {
+            write_full_box(out, b"mvhd", 0, 0, |out| {
+                out.extend_from_slice(&[0; 8]); // creation_time, modification_time
+                out.extend_from_slice(&(Timestamp::TIMEBASE as u32).to_be_bytes());
+                out.extend_from_slice(&0u32.to_be_bytes()); // duration
+                out.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate
+                out.extend_from_slice(&0x0100u16.to_be_bytes()); // volume
+                out.extend_from_slice(&[0; 10]);
+                write_matrix(out);
+                out.extend_from_slice(&[0; 24]); // pre_defined
+                out.extend_from_slice(&(AUDIO_TRACK_ID + 1).to_be_bytes());
+            });
+            if let Some(config) = self.video.as_ref().and_then(|v| v.config.as_ref()) {
+                let size = (config.width, config.height);
+                write_trak(
+                    out,
+                    VIDEO_TRACK_ID,
+                    Timestamp::TIMEBASE as u32,
+                    size,
+                    |out| write_video_sample_entry(out, config),
+                );
+            }
+            if let Some(config) = self.audio.as_ref().and_then(|a| a.config) {
+                write_trak(out, AUDIO_TRACK_ID, config.sample_rate(), (0, 0), |out| {
+                    write_audio_sample_entry(out, config)
+                });
+            }
+            write_box(out, b"mvex", |out| {
+                for (track_id, enabled) in [
+                    (VIDEO_TRACK_ID, self.video.is_some()),
+                    (AUDIO_TRACK_ID, self.audio.is_some()),
+                ] {
+                    if enabled {
+                        write_full_box(out, b"trex", 0, 0, |out| {
+                            out.extend_from_slice(&track_id.to_be_bytes());
+                            out.extend_from_slice(&1u32.to_be_bytes());
+                            out.extend_from_slice(&[0; 12]);
+                        });
+                    }
+                }
+            });
+        });
+    }
+
+    fn write_fragment(&mut self, out: &mut Vec<u8>, video_count: usize, audio_count: usize) {
+        self.sequence_number += 1;
+        let moof_start = out.len();
+        let mut video_offset_pos = None;
+        let mut audio_offset_pos = None;
+        let video = self.video.as_ref().filter(|_| video_count > 0);
+        let audio = self.audio.as_ref().filter(|_| audio_count > 0);
+        write_box(out, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| {
+                out.extend_from_slice(&self.sequence_number.to_be_bytes());
+            });
+            if let Some(video) = video {
+                let samples = &video.samples[..video_count];
+                write_traf(out, VIDEO_TRACK_ID, video.time(&samples[0]), |out| {
+                    // version 1, with data_offset and per-sample duration, size, flags and
+                    // signed composition time offset
+                    write_full_box(out, b"trun", 1, 0x000f01, |out| {
+                        out.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+                        video_offset_pos = Some(out.len());
+                        out.extend_from_slice(&[0; 4]);
+                        for s in samples {
+                            out.extend_from_slice(&s.duration.to_be_bytes());
+                            out.extend_from_slice(&(s.data.len() as u32).to_be_bytes());
+                            let flags = if s.key {
+                                SAMPLE_FLAGS_KEY
+                            } else {
+                                SAMPLE_FLAGS_DELTA
+                            };
+                            out.extend_from_slice(&flags.to_be_bytes());
+                            out.extend_from_slice(&s.composition_offset.to_be_bytes());
+                        }
+                    });
+                });
+            }
+            if let Some(audio) = audio {
+                let frames = &audio.frames[..audio_count];
+                write_traf(out, AUDIO_TRACK_ID, audio.time(0), |out| {
+                    // data_offset, and per-sample duration and size
+                    write_full_box(out, b"trun", 0, 0x000301, |out| {
+                        out.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+                        audio_offset_pos = Some(out.len());
+                        out.extend_from_slice(&[0; 4]);
+                        for f in frames {
+                            out.extend_from_slice(&(AAC_FRAME_SAMPLES as u32).to_be_bytes());
+                            out.extend_from_slice(&(f.len() as u32).to_be_bytes());
+                        }
+                    });
+                });
+            }
+        });
+        // data offsets are relative to the start of the moof box, and the data of the video
+        // track comes first within the mdat box
+        let mut data_offset = out.len() - moof_start + 8;
+        if let Some(pos) = video_offset_pos {
+            out[pos..pos + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+            data_offset += video.map_or(0, |v| {
+                v.samples[..video_count].iter().map(|s| s.data.len()).sum()
+            });
+        }
+        if let Some(pos) = audio_offset_pos {
+            out[pos..pos + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        }
+        write_box(out, b"mdat", |out| {
+            if let Some(video) = video {
+                for s in &video.samples[..video_count] {
+                    out.extend_from_slice(&s.data);
+                }
+            }
+            if let Some(audio) = audio {
+                for f in &audio.frames[..audio_count] {
+                    out.extend_from_slice(f);
+                }
+            }
+        });
+        if let Some(video) = self.video.as_mut() {
+            video.samples.drain(..video_count);
+        }
+        if let Some(audio) = self.audio.as_mut() {
+            audio.frames.drain(..audio_count);
+            audio.written += audio_count as u64;
+        }
+    }
+}
+
+/// Writes a box of the given type, whose body is written by `f`.
+fn write_box(out: &mut Vec<u8>, box_type: &[u8; 4], f: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(box_type);
+    f(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// Writes a 'full box', with the given version and 24-bit flags, whose body is written by `f`.
+fn write_full_box(
+    out: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, box_type, |out| {
+        out.push(version);
+        out.extend_from_slice(&flags.to_be_bytes()[1..]);
+        f(out);
+    })
+}
+
+fn write_matrix(out: &mut Vec<u8>) {
+    for val in IDENTITY_MATRIX {
+        out.extend_from_slice(&val.to_be_bytes());
+    }
+}
+
+fn write_trak(
+    out: &mut Vec<u8>,
+    track_id: u32,
+    timescale: u32,
+    (width, height): (u32, u32),
+    sample_entry: impl FnOnce(&mut Vec<u8>),
+) {
+    let is_video = track_id == VIDEO_TRACK_ID;
+    write_box(out, b"trak", |out| {
+        // track_enabled | track_in_movie
+        write_full_box(out, b"tkhd", 0, 0b11, |out| {
+            out.extend_from_slice(&[0; 8]); // creation_time, modification_time
+            out.extend_from_slice(&track_id.to_be_bytes());
+            out.extend_from_slice(&[0; 4]);
+            out.extend_from_slice(&0u32.to_be_bytes()); // duration
+            out.extend_from_slice(&[0; 8]);
+            out.extend_from_slice(&[0; 4]); // layer, alternate_group
+            let volume: u16 = if is_video { 0 } else { 0x0100 };
+            out.extend_from_slice(&volume.to_be_bytes());
+            out.extend_from_slice(&[0; 2]);
+            write_matrix(out);
+            // 16.16 fixed point values
+            out.extend_from_slice(&(width << 16).to_be_bytes());
+            out.extend_from_slice(&(height << 16).to_be_bytes());
+        });
+        write_box(out, b"mdia", |out| {
+            write_full_box(out, b"mdhd", 0, 0, |out| {
+                out.extend_from_slice(&[0; 8]); // creation_time, modification_time
+                out.extend_from_slice(&timescale.to_be_bytes());
+                out.extend_from_slice(&0u32.to_be_bytes()); // duration
+                out.extend_from_slice(&0x55c4u16.to_be_bytes()); // language 'und'
+                out.extend_from_slice(&[0; 2]);
+            });
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                out.extend_from_slice(&[0; 4]);
+                out.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+                out.extend_from_slice(&[0; 12]);
+                let name: &[u8] = if is_video {
+                    b"VideoHandler\0"
+                } else {
+                    b"SoundHandler\0"
+                };
+                out.extend_from_slice(name);
+            });
+            write_box(out, b"minf", |out| {
+                if is_video {
+                    write_full_box(out, b"vmhd", 0, 1, |out| out.extend_from_slice(&[0; 8]));
+                } else {
+                    write_full_box(out, b"smhd", 0, 0, |out| out.extend_from_slice(&[0; 4]));
+                }
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        out.extend_from_slice(&1u32.to_be_bytes());
+                        // self-contained
+                        write_full_box(out, b"url ", 0, 1, |_| ());
+                    });
+                });
+                write_box(out, b"stbl", |out| {
+                    write_full_box(out, b"stsd", 0, 0, |out| {
+                        out.extend_from_slice(&1u32.to_be_bytes());
+                        sample_entry(out);
+                    });
+                    // the samples themselves are described by the fragments
+                    for box_type in [b"stts", b"stsc", b"stco"] {
+                        write_full_box(out, box_type, 0, 0, |out| {
+                            out.extend_from_slice(&0u32.to_be_bytes())
+                        });
+                    }
+                    write_full_box(out, b"stsz", 0, 0, |out| out.extend_from_slice(&[0; 8]));
+                });
+            });
+        });
+    });
+}
+
+fn write_video_sample_entry(out: &mut Vec<u8>, config: &VideoConfig) {
+    let (entry_type, record_type) = match config.codec {
+        VideoCodec::H264 => (b"avc1", b"avcC"),
+        VideoCodec::H265 => (b"hvc1", b"hvcC"),
+    };
+    write_box(out, entry_type, |out| {
+        out.extend_from_slice(&[0; 6]);
+        out.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&(config.width as u16).to_be_bytes());
+        out.extend_from_slice(&(config.height as u16).to_be_bytes());
+        out.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // horizresolution, 72 dpi
+        out.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // vertresolution
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+        out.extend_from_slice(&[0; 32]); // compressorname
+        out.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+        out.extend_from_slice(&0xffffu16.to_be_bytes()); // pre_defined = -1
+        write_box(out, record_type, |out| {
+            out.extend_from_slice(&config.record)
+        });
+    });
+}
+
+fn write_audio_sample_entry(out: &mut Vec<u8>, config: AudioConfig) {
+    write_box(out, b"mp4a", |out| {
+        out.extend_from_slice(&[0; 6]);
+        out.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        out.extend_from_slice(&[0; 8]);
+        let channel_count = match config.0.channel_configuration {
+            7 => 8,
+            n => u16::from(n),
+        };
+        out.extend_from_slice(&channel_count.to_be_bytes());
+        out.extend_from_slice(&16u16.to_be_bytes()); // samplesize
+        out.extend_from_slice(&[0; 4]);
+        // the 16.16 fixed point value can only represent rates below 65536Hz
+        let rate = config.sample_rate().min(0xffff);
+        out.extend_from_slice(&(rate << 16).to_be_bytes());
+        write_full_box(out, b"esds", 0, 0, |out| {
+            let asc = config.0.audio_specific_config();
+            // ES_Descriptor
+            out.extend_from_slice(&[0x03, 3 + 15 + 2 + asc.len() as u8 + 3]);
+            // ES_ID, flags
+            out.extend_from_slice(&[0, 0, 0]);
+            // DecoderConfigDescriptor, for MPEG-4 audio (0x40) in an AudioStream (0x05)
+            out.extend_from_slice(&[0x04, 13 + 2 + asc.len() as u8, 0x40, 0x05 << 2 | 1]);
+            // bufferSizeDB, maxBitrate, avgBitrate
+            out.extend_from_slice(&[0; 11]);
+            // DecoderSpecificInfo
+            out.extend_from_slice(&[0x05, asc.len() as u8]);
+            out.extend_from_slice(&asc);
+            // SLConfigDescriptor, predefined for MP4
+            out.extend_from_slice(&[0x06, 1, 2]);
+        });
+    });
+}
+
+fn write_traf(
+    out: &mut Vec<u8>,
+    track_id: u32,
+    base_media_decode_time: u64,
+    trun: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, b"traf", |out| {
+        // default-base-is-moof
+        write_full_box(out, b"tfhd", 0, 0x02_0000, |out| {
+            out.extend_from_slice(&track_id.to_be_bytes());
+        });
+        write_full_box(out, b"tfdt", 1, 0, |out| {
+            out.extend_from_slice(&base_media_decode_time.to_be_bytes());
+        });
+        trun(out);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::adts::AdtsParser;
+    use crate::parser::NALParser;
+    use hex_literal::hex;
+
+    const IDR_AU: [u8; 50] = hex!(
+        "00000001 6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654
+         00000001 68ce0fc8
+         00000001 6588843f 11bbc920"
+    );
+    const P_AU: [u8; 12] = hex!("00000001 419a204f 3f5783ce");
+    /// an AAC-LC, 48kHz, stereo ADTS frame with a two byte payload
+    const ADTS_FRAME: [u8; 9] = hex!("fff14c80 013ffc 2112");
+
+    /// The type and body of each box in the given data
+    fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut result = vec![];
+        while data.len() >= 8 {
+            let size = u32_at(data, 0) as usize;
+            result.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+        result
+    }
+
+    /// The body of the first box found by following the given path of box types
+    fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        let (first, rest) = path.split_first()?;
+        let (_, body) = boxes(data).into_iter().find(|(t, _)| t == first)?;
+        if rest.is_empty() {
+            Some(body)
+        } else {
+            find_box(body, rest)
+        }
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn video_and_audio() {
+        let mut parser = NALParser::new();
+        let aus: [(u64, u64, &[u8]); 4] = [
+            (90_000, 90_000, &IDR_AU),
+            (96_000, 93_000, &P_AU),
+            (99_000, 96_000, &IDR_AU),
+            (102_000, 99_000, &P_AU),
+        ];
+        for (pts, dts, au) in aus {
+            parser.begin_packet(
+                Some(Timestamp::from_u64(pts)),
+                Some(Timestamp::from_u64(dts)),
+                au,
+            );
+            parser.end_packet();
+        }
+        parser.flush();
+        let mut remuxer = Remuxer::new().video(true).audio(true);
+        let mut out = vec![];
+        // audio starts one 48kHz frame (1920 ticks at 90kHz) before the video
+        let six_frames = ADTS_FRAME.repeat(6);
+        remuxer
+            .push_audio(Some(Timestamp::from_u64(88_080)), &six_frames, &mut out)
+            .unwrap();
+        for seg in parser.drain_segments() {
+            remuxer.push_video(&seg, &mut out).unwrap();
+        }
+        assert!(remuxer.init_written());
+        remuxer.flush(&mut out);
+        let top = boxes(&out);
+        let types: Vec<_> = top.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            types,
+            [b"ftyp", b"moov", b"moof", b"mdat", b"moof", b"mdat"]
+        );
+
+        let traks: Vec<_> = boxes(top[1].1)
+            .into_iter()
+            .filter(|(t, _)| t == b"trak")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(traks.len(), 2);
+        let stsd = [b"mdia", b"minf", b"stbl", b"stsd"];
+        let avc1 = find_box(&find_box(traks[0], &stsd).unwrap()[8..], &[b"avc1"]).unwrap();
+        // width and height
+        assert_eq!(avc1[24..28], hex!("0354 01e0"));
+        let avcc = find_box(&avc1[78..], &[b"avcC"]).unwrap();
+        assert_eq!(avcc[..6], hex!("0142c01f ffe1"));
+        let mp4a = find_box(&find_box(traks[1], &stsd).unwrap()[8..], &[b"mp4a"]).unwrap();
+        assert_eq!(mp4a[16..28], hex!("0002 0010 00000000 bb800000")[..]);
+        let esds = find_box(&mp4a[28..], &[b"esds"]).unwrap();
+        // the AudioSpecificConfig for AAC-LC, 48kHz, stereo and the SLConfigDescriptor
+        assert_eq!(esds[24..], hex!("05 02 1190 060102"));
+
+        // the first fragment holds the first group of pictures, and the audio before it
+        let fragment = &out[8 + top[0].1.len() + 8 + top[1].1.len()..];
+        let mfhd = find_box(fragment, &[b"moof", b"mfhd"]).unwrap();
+        assert_eq!(u32_at(mfhd, 4), 1);
+        let trafs: Vec<_> = boxes(top[2].1)
+            .into_iter()
+            .filter(|(t, _)| t == b"traf")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(
+            find_box(trafs[0], &[b"tfdt"]).unwrap()[4..],
+            1920u64.to_be_bytes()
+        );
+        assert_eq!(
+            find_box(trafs[1], &[b"tfdt"]).unwrap()[4..],
+            0u64.to_be_bytes()
+        );
+        let trun = find_box(trafs[0], &[b"trun"]).unwrap();
+        assert_eq!(u32_at(trun, 4), 2);
+        // duration, size, flags and composition offset of each sample
+        assert_eq!(
+            trun[12..],
+            hex!(
+                "00000bb8 0000000c 02000000 00000000
+                 00000bb8 0000000c 01010000 00000bb8"
+            )
+        );
+        // the first sample is the IDR slice, converted to AVCC
+        let data_offset = u32_at(trun, 8) as usize;
+        assert_eq!(
+            fragment[data_offset..data_offset + 12],
+            hex!("00000008 6588843f 11bbc920")
+        );
+        let trun = find_box(trafs[1], &[b"trun"]).unwrap();
+        assert_eq!(u32_at(trun, 4), 5);
+        let data_offset = u32_at(trun, 8) as usize;
+        assert_eq!(fragment[data_offset..data_offset + 2], hex!("2112"));
+
+        // the second fragment follows on
+        let trafs: Vec<_> = boxes(top[4].1)
+            .into_iter()
+            .filter(|(t, _)| t == b"traf")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(
+            find_box(trafs[0], &[b"tfdt"]).unwrap()[4..],
+            7920u64.to_be_bytes()
+        );
+        assert_eq!(
+            find_box(trafs[1], &[b"tfdt"]).unwrap()[4..],
+            5120u64.to_be_bytes()
+        );
+        assert_eq!(u32_at(find_box(trafs[1], &[b"trun"]).unwrap(), 4), 1);
+    }
+
+    #[test]
+    fn hevc_sample_entry() {
+        let au = hex!(
+            "00000001 4001 0c01ffff 01600000 03009000 00030000 03005d95 9809
+             00000001 42010101 60000003 00900000 03000003 005da003 c0801107 cb96
+             00000001 4401 c172b462 40
+             00000001 2601 af10"
+        );
+        let mut parser = NALParser::new_hevc();
+        parser.begin_packet(Some(Timestamp::from_u64(90_000)), None, &au);
+        parser.end_packet();
+        parser.flush();
+        let mut remuxer = Remuxer::new().video(true);
+        let mut out = vec![];
+        for seg in parser.drain_segments() {
+            remuxer.push_video(&seg, &mut out).unwrap();
+        }
+        remuxer.flush(&mut out);
+        let top = boxes(&out);
+        let types: Vec<_> = top.iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [b"ftyp", b"moov", b"moof", b"mdat"]);
+
+        let stsd = [b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+        let hvc1 = find_box(&find_box(top[1].1, &stsd).unwrap()[8..], &[b"hvc1"]).unwrap();
+        // width and height
+        assert_eq!(hvc1[24..28], hex!("0780 0438"));
+        let hvcc = find_box(&hvc1[78..], &[b"hvcC"]).unwrap();
+        // configurationVersion, and the profile, tier and level of the SPS
+        assert_eq!(hvcc[..13], hex!("01 01 60000000 900000000000 5d"));
+        // the IDR slice, converted to the same length-prefixed form as the records
+        assert_eq!(top[3].1, hex!("00000004 2601af10"));
+    }
+
+    #[test]
+    fn b_frame_composition_offsets() {
+        let mut parser = NALParser::new();
+        // decode order I, P, B; the P frame is presented after the B frame
+        let aus: [(u64, u64, &[u8]); 3] = [
+            (93_000, 90_000, &IDR_AU),
+            (99_000, 93_000, &P_AU),
+            (96_000, 96_000, &P_AU),
+        ];
+        for (pts, dts, au) in aus {
+            parser.begin_packet(
+                Some(Timestamp::from_u64(pts)),
+                Some(Timestamp::from_u64(dts)),
+                au,
+            );
+            parser.end_packet();
+        }
+        parser.flush();
+        let mut remuxer = Remuxer::new().video(true);
+        let mut out = vec![];
+        for seg in parser.drain_segments() {
+            remuxer.push_video(&seg, &mut out).unwrap();
+        }
+        remuxer.flush(&mut out);
+        let top = boxes(&out);
+        let trun = find_box(top[2].1, &[b"traf", b"trun"]).unwrap();
+        // version 1, so that composition offsets are signed
+        assert_eq!(trun[0], 1);
+        assert_eq!(u32_at(trun, 4), 3);
+        // duration, size, flags and composition offset of each sample
+        assert_eq!(
+            trun[12..],
+            hex!(
+                "00000bb8 0000000c 02000000 00000bb8
+                 00000bb8 0000000c 01010000 00001770
+                 00000bb8 0000000c 01010000 00000000"
+            )
+        );
+    }
+
+    #[test]
+    fn video_decode_time_before_start() {
+        let mut parser = NALParser::new();
+        // the P frame before the first key frame is discarded, so the timeline starts at the
+        // key frame, but the following access unit has an earlier DTS
+        let aus: [(u64, &[u8]); 3] = [(90_000, &P_AU), (96_000, &IDR_AU), (93_000, &P_AU)];
+        for (dts, au) in aus {
+            parser.begin_packet(Some(Timestamp::from_u64(dts)), None, au);
+            parser.end_packet();
+        }
+        parser.flush();
+        let mut remuxer = Remuxer::new().video(true);
+        let mut out = vec![];
+        for seg in parser.drain_segments() {
+            remuxer.push_video(&seg, &mut out).unwrap();
+        }
+        remuxer.flush(&mut out);
+        let top = boxes(&out);
+        let types: Vec<_> = top.iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [b"ftyp", b"moov", b"moof", b"mdat"]);
+        let traf = find_box(top[2].1, &[b"traf"]).unwrap();
+        assert_eq!(find_box(traf, &[b"tfdt"]).unwrap()[4..], 0u64.to_be_bytes());
+        assert_eq!(u32_at(find_box(traf, &[b"trun"]).unwrap(), 4), 2);
+    }
+
+    #[test]
+    fn audio_only() {
+        let mut remuxer = Remuxer::new().audio(true);
+        let mut out = vec![];
+        // without a PTS, the timeline can't start
+        remuxer.push_audio(None, &ADTS_FRAME, &mut out).unwrap();
+        remuxer
+            .push_audio(
+                Some(Timestamp::from_u64(0)),
+                &ADTS_FRAME.repeat(48),
+                &mut out,
+            )
+            .unwrap();
+        // a little over a second of audio goes into each fragment
+        let top = boxes(&out);
+        let types: Vec<_> = top.iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [b"ftyp", b"moov", b"moof", b"mdat"]);
+        assert_eq!(top[3].1.len(), 47 * 2);
+        remuxer.flush(&mut out);
+        let top = boxes(&out);
+        assert_eq!(top.len(), 6);
+        assert_eq!(top[5].1.len(), 2);
+
+        // frames from an AdtsParser give the same result
+        let mut parser = AdtsParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(0)), &ADTS_FRAME.repeat(48));
+        parser.end_packet();
+        let mut from_parser = Remuxer::new().audio(true);
+        let mut out2 = vec![];
+        for frame in parser.drain_frames() {
+            from_parser.push_adts_frame(&frame, &mut out2).unwrap();
+        }
+        from_parser.flush(&mut out2);
+        assert_eq!(out2, out);
+
+        assert_eq!(
+            remuxer.push_audio(None, &ADTS_FRAME[..8], &mut out),
+            Err(Fmp4Error::InvalidAdts)
+        );
+        // 44.1kHz rather than 48kHz
+        assert_eq!(
+            remuxer.push_audio(None, &hex!("fff15080 013ffc 2112"), &mut out),
+            Err(Fmp4Error::AudioConfigChanged)
+        );
+        let mut parser = NALParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(0)), None, &IDR_AU);
+        parser.end_packet();
+        parser.flush();
+        let seg = parser.drain_segments().next().unwrap();
+        assert_eq!(
+            remuxer.push_video(&seg, &mut out),
+            Err(Fmp4Error::NoSuchTrack)
+        );
+    }
+}
@@ -25,7 +25,9 @@
 //!   - Currently mutable state is stored in the instance for each type of syntax parser, and
 //!     it would be nice to explore extracting this out into parser-specific context types
 //! - Event generation / remove `warn!()`
-//!   - currently problems are reported to the `log` crate
+//!   - problems found by the demultiplexer and the standard filters are now reported as
+//!     [`DemuxEvent`](demultiplex/enum.DemuxEvent.html) values, but the elementary stream parsers
+//!     still report skipped data to the `log` crate
 //!   - I would much prefer a way to emit 'events' for interesting data that can't just be an error
 //!     return value, and to not have any logging code mixed with parsing logic
 //! - General
@@ -37,11 +39,14 @@
 pub mod packet;
 #[macro_use]
 pub mod demultiplex;
+pub mod descramble;
 pub mod descriptor;
+pub mod fmp4;
 pub mod mpegts_crc;
+pub mod mux;
+pub mod parser;
 pub mod pes;
 pub mod psi;
-pub mod parser;
 
 /// The types of Elementary Stream specified in _ISO/IEC 13818-1_.
 ///
@@ -173,6 +178,22 @@ impl StreamType {
             }
         }
     }
+
+    /// A conventional file name extension for the raw elementary stream data of this
+    /// `stream_type`, or `"bin"` for stream types whose content is not known.
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            StreamType::Iso11172Video | StreamType::H262 => "m2v",
+            StreamType::Iso11172Audio | StreamType::Iso138183Audio => "mpa",
+            StreamType::Adts => "aac",
+            StreamType::Latm => "latm",
+            StreamType::Iso144962Visual => "m4v",
+            StreamType::H264 => "h264",
+            StreamType::H265 => "h265",
+            StreamType::AtscDolbyDigitalAudio => "ac3",
+            _ => "bin",
+        }
+    }
 }
 impl From<u8> for StreamType {
     fn from(val: u8) -> Self {
@@ -291,4 +312,11 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn file_extension() {
+        assert_eq!(StreamType::H264.file_extension(), "h264");
+        assert_eq!(StreamType::Adts.file_extension(), "aac");
+        assert_eq!(StreamType::H2220PesPrivateData.file_extension(), "bin");
+    }
 }
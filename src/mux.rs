@@ -0,0 +1,761 @@
+//! Support for writing Transport Stream data, as the counterpart of the parsing types in
+//! [`packet`](../packet/index.html) and [`pes`](../pes/index.html).
+//!
+//! - [`PacketBuilder`](struct.PacketBuilder.html) produces a single 188 byte packet, including
+//!   an optional [`AdaptationFieldBuilder`](struct.AdaptationFieldBuilder.html)
+//! - [`PesPacketiser`](struct.PesPacketiser.html) splits a PES packet, whose header is described
+//!   by a [`PesHeaderBuilder`](struct.PesHeaderBuilder.html), into a sequence of TS packets
+//! - [`SectionPacketiser`](struct.SectionPacketiser.html) splits complete PSI sections into a
+//!   sequence of TS packets
+//!
+//! The packetisers keep track of the _continuity_counter_ for the PID that they write, so a
+//! single instance should be used for all the data written to a particular PID.
+//!
+//! # Example
+//!
+//! ```
+//! # use mpeg2ts_reader::{mux, packet, pes};
+//! let mut out = vec![];
+//! let mut video = mux::PesPacketiser::new(packet::Pid::new(0x100));
+//! let header = mux::PesHeaderBuilder::new(pes::StreamId::Video(0))
+//!     .pts(pes::Timestamp::from_u64(90_000));
+//! video
+//!     .write_packet(&mut out, &header, &[0, 0, 0, 1, 9, 0xf0], None)
+//!     .unwrap();
+//! assert_eq!(out.len(), packet::Packet::SIZE);
+//! let pk = packet::Packet::new(&out[..]);
+//! assert!(pk.payload_unit_start_indicator());
+//! ```
+
+use crate::packet;
+use crate::packet::ClockRef;
+use crate::pes;
+use crate::psi;
+use std::io;
+
+/// Builds the optional _adaptation_field_ of a transport stream packet, the counterpart of
+/// [`packet::AdaptationField`](../packet/struct.AdaptationField.html).
+///
+/// Any stuffing needed to fill the packet is added by
+/// [`PacketBuilder`](struct.PacketBuilder.html) when the packet is written.
+#[derive(Debug, Clone, Default)]
+pub struct AdaptationFieldBuilder {
+    discontinuity_indicator: bool,
+    random_access_indicator: bool,
+    elementary_stream_priority_indicator: bool,
+    pcr: Option<ClockRef>,
+    opcr: Option<ClockRef>,
+    splice_countdown: Option<u8>,
+}
+impl AdaptationFieldBuilder {
+    const PCR_SIZE: usize = 6;
+
+    /// Create a builder for an adaptation field with all flags clear and no optional fields.
+    pub fn new() -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder::default()
+    }
+
+    /// Set the _discontinuity_indicator_ field.
+    pub fn discontinuity_indicator(self, val: bool) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            discontinuity_indicator: val,
+            ..self
+        }
+    }
+
+    /// Set the _random_access_indicator_ field.
+    pub fn random_access_indicator(self, val: bool) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            random_access_indicator: val,
+            ..self
+        }
+    }
+
+    /// Set the _elementary_stream_priority_indicator_ field.
+    pub fn elementary_stream_priority_indicator(self, val: bool) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            elementary_stream_priority_indicator: val,
+            ..self
+        }
+    }
+
+    /// Include the given _Program Clock Reference_ value.
+    pub fn pcr(self, pcr: ClockRef) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            pcr: Some(pcr),
+            ..self
+        }
+    }
+
+    /// Include the given _Original Program Clock Reference_ value.
+    pub fn opcr(self, opcr: ClockRef) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            opcr: Some(opcr),
+            ..self
+        }
+    }
+
+    /// Include the given _splice_countdown_ value.
+    pub fn splice_countdown(self, val: u8) -> AdaptationFieldBuilder {
+        AdaptationFieldBuilder {
+            splice_countdown: Some(val),
+            ..self
+        }
+    }
+
+    /// The number of bytes this adaptation field will occupy, not including the
+    /// _adaptation_field_length_ byte itself, or any stuffing.
+    fn size(&self) -> usize {
+        let mut size = 1;
+        if self.pcr.is_some() {
+            size += Self::PCR_SIZE;
+        }
+        if self.opcr.is_some() {
+            size += Self::PCR_SIZE;
+        }
+        if self.splice_countdown.is_some() {
+            size += 1;
+        }
+        size
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(
+            (self.discontinuity_indicator as u8) << 7
+                | (self.random_access_indicator as u8) << 6
+                | (self.elementary_stream_priority_indicator as u8) << 5
+                | (self.pcr.is_some() as u8) << 4
+                | (self.opcr.is_some() as u8) << 3
+                | (self.splice_countdown.is_some() as u8) << 2,
+        );
+        if let Some(pcr) = self.pcr {
+            write_clock_ref(pcr, out);
+        }
+        if let Some(opcr) = self.opcr {
+            write_clock_ref(opcr, out);
+        }
+        if let Some(splice_countdown) = self.splice_countdown {
+            out.push(splice_countdown);
+        }
+    }
+}
+
+fn write_clock_ref(clock: ClockRef, out: &mut Vec<u8>) {
+    let base = clock.base();
+    let extension = clock.extension();
+    out.push((base >> 25) as u8);
+    out.push((base >> 17) as u8);
+    out.push((base >> 9) as u8);
+    out.push((base >> 1) as u8);
+    out.push(((base & 1) as u8) << 7 | 0b0111_1110 | (extension >> 8) as u8);
+    out.push(extension as u8);
+}
+
+/// Builds a single transport stream packet, the counterpart of
+/// [`packet::Packet`](../packet/struct.Packet.html).
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    pid: packet::Pid,
+    continuity_counter: packet::ContinuityCounter,
+    payload_unit_start_indicator: bool,
+    transport_priority: bool,
+    adaptation_field: Option<AdaptationFieldBuilder>,
+}
+impl PacketBuilder {
+    /// Create a builder for a packet with the given PID and _continuity_counter_ value, and with
+    /// no adaptation field.
+    pub fn new(pid: packet::Pid, continuity_counter: packet::ContinuityCounter) -> PacketBuilder {
+        PacketBuilder {
+            pid,
+            continuity_counter,
+            payload_unit_start_indicator: false,
+            transport_priority: false,
+            adaptation_field: None,
+        }
+    }
+
+    /// Set the _payload_unit_start_indicator_ field.
+    pub fn payload_unit_start_indicator(self, val: bool) -> PacketBuilder {
+        PacketBuilder {
+            payload_unit_start_indicator: val,
+            ..self
+        }
+    }
+
+    /// Set the _transport_priority_ field.
+    pub fn transport_priority(self, val: bool) -> PacketBuilder {
+        PacketBuilder {
+            transport_priority: val,
+            ..self
+        }
+    }
+
+    /// Include the given adaptation field in the packet.
+    pub fn adaptation_field(self, adaptation_field: AdaptationFieldBuilder) -> PacketBuilder {
+        PacketBuilder {
+            adaptation_field: Some(adaptation_field),
+            ..self
+        }
+    }
+
+    /// The maximum number of payload bytes that the packet can hold, given the adaptation field
+    /// (if any) that it will contain.
+    pub fn max_payload_len(&self) -> usize {
+        packet::Packet::SIZE
+            - 4
+            - self
+                .adaptation_field
+                .as_ref()
+                .map(|af| 1 + af.size())
+                .unwrap_or(0)
+    }
+
+    /// Write the packet to the end of `out`, including as much of the given payload as will fit,
+    /// and returns the number of payload bytes written.
+    ///
+    /// If the payload does not fill the packet, the packet is padded with stuffing bytes in the
+    /// adaptation field.  If the payload is empty, the packet will have an adaptation field and
+    /// no payload (and per _ISO/IEC 13818-1_ the _continuity_counter_ should then have the same
+    /// value as the previous packet with the same PID).
+    pub fn write(&self, payload: &[u8], out: &mut Vec<u8>) -> usize {
+        let payload_len = payload.len().min(self.max_payload_len());
+        let adaptation_field_control = if payload_len == 0 {
+            0b10
+        } else if payload_len < 184 || self.adaptation_field.is_some() {
+            0b11
+        } else {
+            0b01
+        };
+        let start = out.len();
+        out.push(packet::Packet::SYNC_BYTE);
+        out.push(
+            (self.payload_unit_start_indicator as u8) << 6
+                | (self.transport_priority as u8) << 5
+                | (u16::from(self.pid) >> 8) as u8,
+        );
+        out.push(u16::from(self.pid) as u8);
+        out.push(adaptation_field_control << 4 | self.continuity_counter.count());
+        if adaptation_field_control & 0b10 != 0 {
+            let adaptation_field_length = packet::Packet::SIZE - 5 - payload_len;
+            out.push(adaptation_field_length as u8);
+            if adaptation_field_length > 0 {
+                match self.adaptation_field {
+                    Some(ref af) => af.write(out),
+                    None => AdaptationFieldBuilder::new().write(out),
+                }
+                out.resize(start + packet::Packet::SIZE - payload_len, 0xff);
+            }
+        }
+        out.extend_from_slice(&payload[..payload_len]);
+        debug_assert_eq!(out.len() - start, packet::Packet::SIZE);
+        payload_len
+    }
+}
+
+/// Builds the header of a PES packet, the counterpart of
+/// [`pes::PesHeader`](../pes/struct.PesHeader.html) and
+/// [`pes::PesParsedContents`](../pes/struct.PesParsedContents.html).
+#[derive(Debug, Clone)]
+pub struct PesHeaderBuilder {
+    stream_id: u8,
+    pts: Option<pes::Timestamp>,
+    dts: Option<pes::Timestamp>,
+    data_alignment_indicator: bool,
+    unbounded: bool,
+}
+impl PesHeaderBuilder {
+    /// Create a builder for the header of a PES packet with the given `stream_id`, and no
+    /// timestamps.
+    pub fn new(stream_id: pes::StreamId) -> PesHeaderBuilder {
+        PesHeaderBuilder {
+            stream_id: stream_id.into(),
+            pts: None,
+            dts: None,
+            data_alignment_indicator: false,
+            unbounded: false,
+        }
+    }
+
+    /// Include the given Presentation Time Stamp.
+    pub fn pts(self, pts: pes::Timestamp) -> PesHeaderBuilder {
+        PesHeaderBuilder {
+            pts: Some(pts),
+            dts: None,
+            ..self
+        }
+    }
+
+    /// Include the given Presentation and Decode Time Stamps.
+    pub fn pts_dts(self, pts: pes::Timestamp, dts: pes::Timestamp) -> PesHeaderBuilder {
+        PesHeaderBuilder {
+            pts: Some(pts),
+            dts: Some(dts),
+            ..self
+        }
+    }
+
+    /// Set the _data_alignment_indicator_ field.
+    pub fn data_alignment_indicator(self, val: bool) -> PesHeaderBuilder {
+        PesHeaderBuilder {
+            data_alignment_indicator: val,
+            ..self
+        }
+    }
+
+    /// Always give a _PES_packet_length_ of `0`, rather than the actual length of the packet.
+    /// This is only permitted for video streams, but by default a length of `0` is also used if
+    /// the packet is too large for its length to be given.
+    pub fn unbounded(self, val: bool) -> PesHeaderBuilder {
+        PesHeaderBuilder {
+            unbounded: val,
+            ..self
+        }
+    }
+
+    /// Write the header for a PES packet carrying the given number of bytes of payload to the
+    /// end of `out`.
+    pub fn write(&self, payload_len: usize, out: &mut Vec<u8>) {
+        let mut header = vec![];
+        if pes::StreamId::from(self.stream_id).is_parsed() {
+            let pts_dts_flags = match (self.pts, self.dts) {
+                (Some(_), Some(_)) => 0b11,
+                (Some(_), None) => 0b10,
+                _ => 0b00,
+            };
+            header.push(0b1000_0000 | (self.data_alignment_indicator as u8) << 2);
+            header.push(pts_dts_flags << 6);
+            header.push(0); // PES_header_data_length, filled in below
+            if let Some(pts) = self.pts {
+                write_timestamp(
+                    if self.dts.is_some() { 0b0011 } else { 0b0010 },
+                    pts,
+                    &mut header,
+                );
+            }
+            if let Some(dts) = self.dts {
+                write_timestamp(0b0001, dts, &mut header);
+            }
+            header[2] = (header.len() - 3) as u8;
+        }
+        let len = header.len() + payload_len;
+        let pes_packet_length = if self.unbounded || len > usize::from(u16::MAX) {
+            0
+        } else {
+            len as u16
+        };
+        out.extend_from_slice(&[0, 0, 1, self.stream_id]);
+        out.extend_from_slice(&pes_packet_length.to_be_bytes());
+        out.extend_from_slice(&header);
+    }
+}
+
+fn write_timestamp(prefix: u8, ts: pes::Timestamp, out: &mut Vec<u8>) {
+    let val = ts.value();
+    out.push(prefix << 4 | ((val >> 29) as u8 & 0b1110) | 1);
+    out.push((val >> 22) as u8);
+    out.push((val >> 14) as u8 | 1);
+    out.push((val >> 7) as u8);
+    out.push((val << 1) as u8 | 1);
+}
+
+/// Splits PES packets into a sequence of transport stream packets with the given PID.
+pub struct PesPacketiser {
+    pid: packet::Pid,
+    continuity_counter: packet::ContinuityCounter,
+    buf: Vec<u8>,
+}
+impl PesPacketiser {
+    /// Create a packetiser for the given PID, whose first packet will have a
+    /// _continuity_counter_ of `0`.
+    pub fn new(pid: packet::Pid) -> PesPacketiser {
+        PesPacketiser {
+            pid,
+            continuity_counter: packet::ContinuityCounter::new(0),
+            buf: Vec::with_capacity(packet::Packet::SIZE),
+        }
+    }
+
+    /// The _continuity_counter_ value that will be given to the next packet written.
+    pub fn continuity_counter(&self) -> packet::ContinuityCounter {
+        self.continuity_counter
+    }
+
+    /// Write the TS packets carrying a PES packet with the given header and payload to `w`.  If
+    /// an `adaptation_field` is given (for example, to include a PCR, or to set the
+    /// _random_access_indicator_), it is included in the first of these packets.
+    ///
+    /// Any space left in the final packet is filled with adaptation field stuffing.
+    pub fn write_packet<W: io::Write>(
+        &mut self,
+        w: &mut W,
+        header: &PesHeaderBuilder,
+        payload: &[u8],
+        adaptation_field: Option<AdaptationFieldBuilder>,
+    ) -> io::Result<()> {
+        let mut pes = Vec::with_capacity(payload.len() + 19);
+        header.write(payload.len(), &mut pes);
+        pes.extend_from_slice(payload);
+        let mut rest = &pes[..];
+        let mut first = true;
+        while !rest.is_empty() {
+            let mut builder = PacketBuilder::new(self.pid, self.continuity_counter)
+                .payload_unit_start_indicator(first);
+            if first {
+                if let Some(ref af) = adaptation_field {
+                    builder = builder.adaptation_field(af.clone());
+                }
+            }
+            self.buf.clear();
+            let len = builder.write(rest, &mut self.buf);
+            w.write_all(&self.buf)?;
+            rest = &rest[len..];
+            first = false;
+            self.continuity_counter = next(self.continuity_counter);
+        }
+        Ok(())
+    }
+}
+
+/// Splits PSI sections into a sequence of transport stream packets with the given PID.
+pub struct SectionPacketiser {
+    pid: packet::Pid,
+    continuity_counter: packet::ContinuityCounter,
+    buf: Vec<u8>,
+}
+impl SectionPacketiser {
+    /// Create a packetiser for the given PID, whose first packet will have a
+    /// _continuity_counter_ of `0`.
+    pub fn new(pid: packet::Pid) -> SectionPacketiser {
+        SectionPacketiser {
+            pid,
+            continuity_counter: packet::ContinuityCounter::new(0),
+            buf: Vec::with_capacity(packet::Packet::SIZE),
+        }
+    }
+
+    /// The _continuity_counter_ value that will be given to the next packet written.
+    pub fn continuity_counter(&self) -> packet::ContinuityCounter {
+        self.continuity_counter
+    }
+
+    /// Write the TS packets carrying the given complete sections to `w`.  The sections are packed
+    /// one after another, with the _pointer_field_ in each packet where a section starts
+    /// locating the first such section.  A section is not started in a packet unless its 8-byte
+    /// header fits in the remaining space, which is instead filled with `0xff` stuffing bytes, as
+    /// is any space left in the final packet.
+    pub fn write_sections<W: io::Write>(
+        &mut self,
+        w: &mut W,
+        sections: &[&[u8]],
+    ) -> io::Result<()> {
+        let mut data = vec![];
+        let mut starts = vec![];
+        for sect in sections {
+            starts.push(data.len());
+            data.extend_from_slice(sect);
+        }
+        const MAX_PAYLOAD: usize = packet::Packet::SIZE - 4;
+        // readers expect the whole of a section's header to be in the packet where it starts
+        const HEADER_SIZE: usize = psi::SectionCommonHeader::SIZE + psi::TableSyntaxHeader::SIZE;
+        let mut pos = 0;
+        while pos < data.len() {
+            let mut pending = starts.iter().copied().filter(|&s| s >= pos);
+            let first = pending.next();
+            // the next section starts in this packet if its header fits after the pointer_field
+            let start = first.filter(|&s| s + HEADER_SIZE < pos + MAX_PAYLOAD);
+            let limit = match start {
+                Some(_) => pos + MAX_PAYLOAD - 1,
+                None => pos + MAX_PAYLOAD,
+            };
+            // end the packet early, filling it with stuffing, at the start of any section that
+            // can't start in this packet, so that it will start the next packet instead
+            let deferred = match start {
+                Some(_) => pending.find(|&s| s + HEADER_SIZE > limit),
+                None => first,
+            };
+            let end = deferred.map_or(limit, |s| s.min(limit)).min(data.len());
+            let mut payload = Vec::with_capacity(MAX_PAYLOAD);
+            if let Some(s) = start {
+                payload.push((s - pos) as u8);
+            }
+            payload.extend_from_slice(&data[pos..end]);
+            payload.resize(MAX_PAYLOAD, 0xff);
+            pos = end;
+            self.buf.clear();
+            PacketBuilder::new(self.pid, self.continuity_counter)
+                .payload_unit_start_indicator(start.is_some())
+                .write(&payload, &mut self.buf);
+            w.write_all(&self.buf)?;
+            self.continuity_counter = next(self.continuity_counter);
+        }
+        Ok(())
+    }
+}
+
+fn next(cc: packet::ContinuityCounter) -> packet::ContinuityCounter {
+    packet::ContinuityCounter::new((cc.count() + 1) & 0b1111)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::demultiplex::test::NullDemuxContext;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn packet_with_adaptation_field() {
+        let pcr = ClockRef::from_parts(0x1_2345_6789, 0x123);
+        let builder =
+            PacketBuilder::new(packet::Pid::new(0x1234), packet::ContinuityCounter::new(7))
+                .payload_unit_start_indicator(true)
+                .adaptation_field(
+                    AdaptationFieldBuilder::new()
+                        .discontinuity_indicator(true)
+                        .random_access_indicator(true)
+                        .pcr(pcr),
+                );
+        let payload = [0xaa; 200];
+        let mut out = vec![];
+        let len = builder.write(&payload, &mut out);
+        assert_eq!(len, 184 - 8);
+        assert_eq!(out.len(), packet::Packet::SIZE);
+        let pk = packet::Packet::new(&out[..]);
+        assert_eq!(pk.pid(), packet::Pid::new(0x1234));
+        assert!(pk.payload_unit_start_indicator());
+        assert_eq!(pk.continuity_counter().count(), 7);
+        let af = pk.adaptation_field().unwrap();
+        assert!(af.discontinuity_indicator());
+        assert!(af.random_access_indicator());
+        assert_eq!(af.pcr(), Ok(pcr));
+        assert_eq!(
+            af.opcr(),
+            Err(packet::AdaptationFieldError::FieldNotPresent)
+        );
+        assert_eq!(pk.payload(), Some(&payload[..len]));
+    }
+
+    #[test]
+    fn packet_stuffing() {
+        let pid = packet::Pid::new(0x100);
+        let cc = packet::ContinuityCounter::new(0);
+        for &len in &[0, 1, 100, 182, 183, 184] {
+            let payload = vec![0x55; len];
+            let mut out = vec![];
+            assert_eq!(PacketBuilder::new(pid, cc).write(&payload, &mut out), len);
+            assert_eq!(out.len(), packet::Packet::SIZE);
+            let pk = packet::Packet::new(&out[..]);
+            if len == 0 {
+                assert_eq!(
+                    pk.adaptation_control(),
+                    packet::AdaptationControl::AdaptationFieldOnly
+                );
+                assert!(pk.adaptation_field().is_some());
+                assert_eq!(pk.payload(), None);
+            } else {
+                assert_eq!(pk.payload(), Some(&payload[..]));
+            }
+        }
+    }
+
+    #[test]
+    fn pes_packetiser() {
+        let pid = packet::Pid::new(0x101);
+        let mut packetiser = PesPacketiser::new(pid);
+        let header = PesHeaderBuilder::new(pes::StreamId::Video(0))
+            .pts_dts(
+                pes::Timestamp::from_u64(123_456),
+                pes::Timestamp::from_u64(120_000),
+            )
+            .data_alignment_indicator(true);
+        let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let pcr = ClockRef::from_parts(100_000, 0);
+        let mut out = vec![];
+        for _ in 0..3 {
+            packetiser
+                .write_packet(
+                    &mut out,
+                    &header,
+                    &payload,
+                    Some(AdaptationFieldBuilder::new().pcr(pcr)),
+                )
+                .unwrap();
+        }
+        let packets: Vec<_> = out
+            .chunks(packet::Packet::SIZE)
+            .map(packet::Packet::new)
+            .collect();
+        assert_eq!(packets.len(), 3 * 6);
+        for (i, pk) in packets.iter().enumerate() {
+            assert_eq!(pk.pid(), pid);
+            assert_eq!(pk.continuity_counter().count(), (i % 16) as u8);
+            assert_eq!(pk.payload_unit_start_indicator(), i % 6 == 0);
+            let has_pcr = pk.adaptation_field().map(|af| af.pcr().is_ok());
+            assert_eq!(has_pcr == Some(true), i % 6 == 0);
+        }
+        let pes_data: Vec<u8> = packets[..6]
+            .iter()
+            .flat_map(|pk| pk.payload().unwrap().iter().copied())
+            .collect();
+        let pes_header = pes::PesHeader::from_bytes(&pes_data).unwrap();
+        assert_eq!(pes_header.stream_id(), pes::StreamId::Video(0));
+        assert!(matches!(
+            pes_header.pes_packet_length(),
+            pes::PesLength::Bounded(len) if usize::from(len.get()) == pes_data.len() - 6
+        ));
+        match pes_header.contents() {
+            pes::PesContents::Parsed(Some(parsed)) => {
+                assert_eq!(
+                    parsed.data_alignment_indicator(),
+                    pes::DataAlignment::Aligned
+                );
+                assert_eq!(
+                    parsed.pts_dts(),
+                    Ok(pes::PtsDts::Both {
+                        pts: Ok(pes::Timestamp::from_u64(123_456)),
+                        dts: Ok(pes::Timestamp::from_u64(120_000)),
+                    })
+                );
+                assert_eq!(parsed.payload(), &payload[..]);
+            }
+            _ => panic!("expected parsed PES contents"),
+        }
+    }
+
+    #[test]
+    fn pes_header_unbounded() {
+        let mut out = vec![];
+        PesHeaderBuilder::new(pes::StreamId::Video(0)).write(70_000, &mut out);
+        let header = pes::PesHeader::from_bytes(&out).unwrap();
+        assert!(matches!(
+            header.pes_packet_length(),
+            pes::PesLength::Unbounded
+        ));
+        out.clear();
+        PesHeaderBuilder::new(pes::StreamId::PaddingStream).write(10, &mut out);
+        assert_eq!(out, [0, 0, 1, 0xbe, 0, 10]);
+    }
+
+    struct SectionRecorder {
+        sections: Rc<RefCell<Vec<Vec<u8>>>>,
+    }
+    impl psi::WholeSectionSyntaxPayloadParser for SectionRecorder {
+        type Context = NullDemuxContext;
+
+        fn section<'a>(
+            &mut self,
+            _ctx: &mut Self::Context,
+            _header: &psi::SectionCommonHeader,
+            _table_syntax_header: &psi::TableSyntaxHeader<'a>,
+            data: &'a [u8],
+        ) {
+            self.sections.borrow_mut().push(data.to_vec());
+        }
+    }
+
+    fn make_section(len: usize, fill: u8) -> Vec<u8> {
+        let section_length = len - 3;
+        let mut sect = vec![
+            0x42,
+            0b1011_0000 | (section_length >> 8) as u8,
+            section_length as u8,
+        ];
+        sect.resize(len, fill);
+        sect
+    }
+
+    /// The sections found in the given packets by `SectionPacketConsumer`
+    fn read_sections(packets: &[u8]) -> Vec<Vec<u8>> {
+        let recorded = Rc::new(RefCell::new(vec![]));
+        let mut consumer =
+            psi::SectionPacketConsumer::new(psi::SectionSyntaxSectionProcessor::new(
+                psi::BufferSectionSyntaxParser::new(SectionRecorder {
+                    sections: recorded.clone(),
+                }),
+            ));
+        let mut ctx = NullDemuxContext::new();
+        for chunk in packets.chunks(packet::Packet::SIZE) {
+            consumer.consume(&mut ctx, &packet::Packet::new(chunk));
+        }
+        let sections = recorded.borrow().clone();
+        sections
+    }
+
+    #[test]
+    fn section_packetiser() {
+        let pid = packet::Pid::new(0x20);
+        let mut packetiser = SectionPacketiser::new(pid);
+        let sections = [
+            make_section(500, 1),
+            make_section(1000, 2),
+            make_section(20, 3),
+        ];
+        let mut out = vec![];
+        for sect in &sections {
+            packetiser.write_sections(&mut out, &[&sect[..]]).unwrap();
+        }
+        assert_eq!(packetiser.continuity_counter().count(), 3 + 6 + 1);
+        for (i, chunk) in out.chunks(packet::Packet::SIZE).enumerate() {
+            let pk = packet::Packet::new(chunk);
+            assert_eq!(pk.pid(), pid);
+            assert_eq!(pk.continuity_counter().count(), (i % 16) as u8);
+        }
+        assert_eq!(read_sections(&out), sections);
+    }
+
+    #[test]
+    fn section_packing() {
+        let mut packetiser = SectionPacketiser::new(packet::Pid::new(0x20));
+        // 'b' would start in the final byte of the second packet, which has no pointer_field, so
+        // that packet is ended early and 'b' starts the third packet
+        let a = make_section(366, 1);
+        let b = make_section(100, 2);
+        let c = make_section(10, 3);
+        let mut out = vec![];
+        packetiser
+            .write_sections(&mut out, &[&a[..], &b[..], &c[..]])
+            .unwrap();
+        let packets: Vec<_> = out
+            .chunks(packet::Packet::SIZE)
+            .map(packet::Packet::new)
+            .collect();
+        assert_eq!(packets.len(), 3);
+        assert!(packets[0].payload_unit_start_indicator());
+        assert_eq!(packets[0].payload().unwrap()[0], 0);
+        assert_eq!(&packets[0].payload().unwrap()[1..], &a[..183]);
+        assert!(!packets[1].payload_unit_start_indicator());
+        assert_eq!(&packets[1].payload().unwrap()[..183], &a[183..]);
+        assert_eq!(packets[1].payload().unwrap()[183], 0xff);
+        assert!(packets[2].payload_unit_start_indicator());
+        let payload = packets[2].payload().unwrap();
+        assert_eq!(payload[0], 0);
+        assert_eq!(&payload[1..101], &b[..]);
+        assert_eq!(&payload[101..111], &c[..]);
+        assert!(payload[111..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn section_header_not_split() {
+        // for these lengths of 'a', 'b' would start in the last 7 bytes of the second packet, so
+        // its header would be split across packets
+        for len in 363..=366 {
+            let a = make_section(len, 1);
+            let b = make_section(100, 2);
+            let mut out = vec![];
+            SectionPacketiser::new(packet::Pid::new(0x20))
+                .write_sections(&mut out, &[&a[..], &b[..]])
+                .unwrap();
+            let packets: Vec<_> = out
+                .chunks(packet::Packet::SIZE)
+                .map(packet::Packet::new)
+                .collect();
+            assert_eq!(packets.len(), 3);
+            assert!(!packets[1].payload_unit_start_indicator());
+            assert!(packets[1].payload().unwrap()[len - 183..]
+                .iter()
+                .all(|&b| b == 0xff));
+            assert!(packets[2].payload_unit_start_indicator());
+            assert_eq!(read_sections(&out), vec![a, b]);
+        }
+    }
+}
@@ -1,14 +1,13 @@
 //! A [`Packet`](./struct.Packet.html) struct and associated infrastructure to read an MPEG Transport Stream packet
 
 use crate::pes;
-use log::warn;
 use std::cmp::Ordering;
 use std::convert::TryFrom;
 use std::fmt;
 
 /// the different values indicating whether a `Packet`'s `adaptation_field()` and `payload()`
 /// methods will return `Some` or `None`.
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum AdaptationControl {
     /// This value is used if the transport stream packet `adaptation_control` field uses the value
     /// `0b00`, which is not defined by the spec.
@@ -502,10 +501,84 @@ impl fmt::Debug for Pid {
     }
 }
 
+/// The ways in which transport stream packets may be laid out within a stream of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// Plain 188 byte packets, one immediately following another.
+    Plain,
+    /// 192 byte units, as used by BDAV / Blu-ray `.m2ts` files, where each 188 byte packet is
+    /// preceded by a 4 byte `TP_extra_header` (see [`TpExtraHeader`](struct.TpExtraHeader.html)).
+    M2ts,
+    /// 204 byte units, where each 188 byte packet is followed by 16 bytes of Reed-Solomon
+    /// parity data (which is ignored).
+    ReedSolomon,
+}
+impl PacketFormat {
+    /// All supported formats, in the order that they are tried when the format is being detected.
+    pub const ALL: [PacketFormat; 3] = [
+        PacketFormat::Plain,
+        PacketFormat::M2ts,
+        PacketFormat::ReedSolomon,
+    ];
+
+    /// The number of bytes occupied by each packet, including any extra data associated with the
+    /// packet by this format.
+    pub fn size(self) -> usize {
+        match self {
+            PacketFormat::Plain => Packet::SIZE,
+            PacketFormat::M2ts => TpExtraHeader::SIZE + Packet::SIZE,
+            PacketFormat::ReedSolomon => Packet::SIZE + 16,
+        }
+    }
+
+    /// The offset of the packet's sync-byte from the start of each unit of this format.
+    pub fn packet_offset(self) -> usize {
+        match self {
+            PacketFormat::Plain | PacketFormat::ReedSolomon => 0,
+            PacketFormat::M2ts => TpExtraHeader::SIZE,
+        }
+    }
+}
+
+/// The 4 byte header which precedes each packet in the
+/// [`PacketFormat::M2ts`](enum.PacketFormat.html#variant.M2ts) format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TpExtraHeader {
+    val: u32,
+}
+impl TpExtraHeader {
+    /// The size of the header in bytes.
+    pub const SIZE: usize = 4;
+
+    /// Panics if the given buffer is not exactly 4 bytes long.
+    pub fn new(buf: &[u8]) -> TpExtraHeader {
+        assert_eq!(buf.len(), Self::SIZE);
+        TpExtraHeader {
+            val: u32::from(buf[0]) << 24
+                | u32::from(buf[1]) << 16
+                | u32::from(buf[2]) << 8
+                | u32::from(buf[3]),
+        }
+    }
+
+    /// The 2-bit _copy_permission_indicator_ value.
+    pub fn copy_permission_indicator(self) -> u8 {
+        (self.val >> 30) as u8
+    }
+
+    /// The 30-bit _arrival_time_stamp_, the time at which the packet arrived at the recorder,
+    /// in units of a 27MHz clock.
+    pub fn arrival_time_stamp(self) -> u32 {
+        self.val & 0x3fff_ffff
+    }
+}
+
 /// A transport stream `Packet` is a wrapper around a byte slice which allows the bytes to be
 /// interpreted as a packet structure per _ISO/IEC 13818-1, Section 2.4.3.3_.
 pub struct Packet<'buf> {
     buf: &'buf [u8],
+    tp_extra_header: Option<TpExtraHeader>,
+    offset: u64,
 }
 
 const FIXED_HEADER_SIZE: usize = 4;
@@ -536,7 +609,11 @@ impl<'buf> Packet<'buf> {
     pub fn new(buf: &'buf [u8]) -> Packet<'buf> {
         assert_eq!(buf.len(), Self::SIZE);
         assert!(Packet::is_sync_byte(buf[0]));
-        Packet { buf }
+        Packet {
+            buf,
+            tp_extra_header: None,
+            offset: 0,
+        }
     }
 
     /// Like `new()`, but returns `None` if the sync-byte has incorrect value (still panics if the
@@ -545,17 +622,41 @@ impl<'buf> Packet<'buf> {
     pub fn try_new(buf: &'buf [u8]) -> Option<Packet<'buf>> {
         assert_eq!(buf.len(), Self::SIZE);
         if Packet::is_sync_byte(buf[0]) {
-            Some(Packet { buf })
+            Some(Packet {
+                buf,
+                tp_extra_header: None,
+                offset: 0,
+            })
         } else {
             None
         }
     }
 
-    /// Use with caution !!!
-    /// Use only to create a packet with hardcoded values for a
-    /// very specific purpose
-    pub fn force_new(buf: &'buf [u8]) -> Packet<'buf> {
-        Packet { buf }
+    /// Returns this packet, associated with the given `TP_extra_header` that preceded it in the
+    /// stream.
+    pub fn with_tp_extra_header(self, tp_extra_header: TpExtraHeader) -> Packet<'buf> {
+        Packet {
+            tp_extra_header: Some(tp_extra_header),
+            ..self
+        }
+    }
+
+    /// The `TP_extra_header` which preceded this packet, if the stream uses the
+    /// [`PacketFormat::M2ts`](enum.PacketFormat.html#variant.M2ts) format.
+    pub fn tp_extra_header(&self) -> Option<TpExtraHeader> {
+        self.tp_extra_header
+    }
+
+    /// Returns this packet, associated with the given offset of its first byte within the stream.
+    pub fn with_offset(self, offset: u64) -> Packet<'buf> {
+        Packet { offset, ..self }
+    }
+
+    /// The offset of this packet's sync-byte from the start of the data given to
+    /// [`Demultiplex::push()`](../demultiplex/struct.Demultiplex.html#method.push), or `0` if
+    /// the packet was not created by a `Demultiplex` instance.
+    pub fn offset(&self) -> u64 {
+        self.offset
     }
 
     /// *May* have been set if some previous processing of this TS data detected at least
@@ -619,10 +720,6 @@ impl<'buf> Packet<'buf> {
             AdaptationControl::AdaptationFieldOnly => {
                 let len = self.adaptation_field_length();
                 if len != (Self::SIZE - ADAPTATION_FIELD_OFFSET) {
-                    warn!(
-                        "invalid adaptation_field_length for AdaptationFieldOnly: {}",
-                        len
-                    );
                     // TODO: Option<Result<AdaptationField>> instead?
                     return None;
                 }
@@ -631,10 +728,6 @@ impl<'buf> Packet<'buf> {
             AdaptationControl::AdaptationFieldAndPayload => {
                 let len = self.adaptation_field_length();
                 if len > 182 {
-                    warn!(
-                        "invalid adaptation_field_length for AdaptationFieldAndPayload: {}",
-                        len
-                    );
                     // TODO: Option<Result<AdaptationField>> instead?
                     return None;
                 }
@@ -669,21 +762,28 @@ impl<'buf> Packet<'buf> {
         let offset = self.content_offset();
         let len = self.buf.len();
         match offset.cmp(&len) {
-            Ordering::Equal => {
-                warn!("no payload data present");
-                None
-            }
-            Ordering::Greater => {
-                warn!(
-                    "adaptation_field_length {} too large",
-                    self.adaptation_field_length()
-                );
-                None
-            }
+            Ordering::Equal | Ordering::Greater => None,
             Ordering::Less => Some(&self.buf[offset..]),
         }
     }
 
+    /// Returns the _adaptation_field_length_ value if it is not valid for this packet's
+    /// `adaptation_control()`, in which case `adaptation_field()` and `payload()` will
+    /// return `None`.
+    pub(crate) fn invalid_adaptation_field_length(&self) -> Option<usize> {
+        let len = self.adaptation_field_length();
+        let valid = match self.adaptation_control() {
+            AdaptationControl::Reserved | AdaptationControl::PayloadOnly => true,
+            AdaptationControl::AdaptationFieldOnly => len == Self::SIZE - ADAPTATION_FIELD_OFFSET,
+            AdaptationControl::AdaptationFieldAndPayload => len <= 182,
+        };
+        if valid {
+            None
+        } else {
+            Some(len)
+        }
+    }
+
     /// borrow a reference to the underlying buffer of this packet
     pub fn buffer(&self) -> &'buf [u8] {
         self.buf
@@ -786,6 +886,19 @@ mod test {
         assert!(pk.adaptation_field().is_none());
     }
 
+    #[test]
+    fn tp_extra_header() {
+        let hdr = TpExtraHeader::new(&[0b1011_1111, 0xff, 0x00, 0x01]);
+        assert_eq!(hdr.copy_permission_indicator(), 0b10);
+        assert_eq!(hdr.arrival_time_stamp(), 0x3fff_0001);
+        let mut buf = [0xffu8; Packet::SIZE];
+        buf[0] = Packet::SYNC_BYTE;
+        let pk = Packet::new(&buf[..]);
+        assert_eq!(pk.tp_extra_header(), None);
+        let pk = pk.with_tp_extra_header(hdr);
+        assert_eq!(pk.tp_extra_header(), Some(hdr));
+    }
+
     #[test]
     fn empty_adaptation_field_extension() {
         assert!(AdaptationFieldExtension::new(b"").is_err());
@@ -1,269 +1,860 @@
-use std::io::Read;
+pub mod ac3;
+pub mod adts;
+pub mod annexb;
+pub mod avcc;
+pub mod h262;
+pub mod h264;
+pub mod hevc;
+pub mod latm;
+pub mod mpa;
+pub mod rbsp;
+pub mod sei;
+
+use crate::parser::annexb::{AnnexBSplitter, NalUnit};
+use crate::parser::sei::{CcData, ItuTT35, RecoveryPoint, SeiMessage, SeiMessages};
 use crate::parser::VideoFrameType::{DeltaFrame, KeyFrame};
-
-#[derive(Clone, Copy)]
-pub enum HeaderCode {
-    Delimiter = 0x09,
-    //00 00 00 01 09
-    Sps = 0x67,
-    // 00 00 00 01 67
-    Pps = 0x68,
-    // 00 00 00 01 68
-    KeyFrame = 0x65,
-    // 00 00 01 65
-    Sei = 0x06,     // 00 00 01 06
+use crate::pes::Timestamp;
+use log::trace;
+
+/// The video coding standards whose access units `NALParser` can find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    /// _ITU-T H.264_ / AVC
+    H264,
+    /// _ITU-T H.265_ / HEVC
+    H265,
 }
-
-impl HeaderCode {
-    fn header_prefix_byte_count(&self) -> usize {
-        return match self {
-            HeaderCode::Sei | HeaderCode::KeyFrame => 3,
-            _ => 4,
-        };
-    }
-
-    fn iframe_header_prefix_byte_count() -> usize {
-        return Self::delimiter_length() + 4;
+impl VideoCodec {
+    fn nal_header_size(self) -> usize {
+        match self {
+            VideoCodec::H264 => 1,
+            VideoCodec::H265 => hevc::NalHeader::SIZE,
+        }
     }
+}
 
-    fn delimiter_length() -> usize {
-        return 6;
+/// The role of a NAL unit within an access unit, as far as `NALParser` is concerned
+#[derive(Debug, PartialEq)]
+enum NalKind {
+    Vps,
+    Sps,
+    Pps,
+    Sei,
+    /// a slice, and whether it belongs to a picture from which decoding can start, and
+    /// specifically to an IDR picture
+    Slice {
+        key: bool,
+        idr: bool,
+    },
+    Other,
+}
+impl NalKind {
+    fn of(codec: VideoCodec, nal: &NalUnit<'_>) -> NalKind {
+        match codec {
+            VideoCodec::H264 => match nal.nal_unit_type() {
+                h264::NAL_UNIT_TYPE_SPS => NalKind::Sps,
+                h264::NAL_UNIT_TYPE_PPS => NalKind::Pps,
+                h264::NAL_UNIT_TYPE_SEI => NalKind::Sei,
+                // NAL units of types 1 to 5 contain slice data (the 'VCL NAL units')
+                t @ 1..=h264::NAL_UNIT_TYPE_IDR => NalKind::Slice {
+                    key: t == h264::NAL_UNIT_TYPE_IDR,
+                    idr: t == h264::NAL_UNIT_TYPE_IDR,
+                },
+                _ => NalKind::Other,
+            },
+            VideoCodec::H265 => match hevc::NalHeader::from_nal(nal) {
+                Some(hdr) if hdr.is_vcl() => NalKind::Slice {
+                    key: hdr.is_irap(),
+                    idr: hdr.is_idr(),
+                },
+                Some(hdr) if hdr.is_sei() => NalKind::Sei,
+                Some(hdr) => match hdr.nal_unit_type {
+                    hevc::NAL_UNIT_TYPE_VPS => NalKind::Vps,
+                    hevc::NAL_UNIT_TYPE_SPS => NalKind::Sps,
+                    hevc::NAL_UNIT_TYPE_PPS => NalKind::Pps,
+                    _ => NalKind::Other,
+                },
+                None => NalKind::Other,
+            },
+        }
     }
 }
 
-enum VideoFrameType {
+/// Distinguishes access units that can be decoded independently of any others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFrameType {
+    /// The access unit contains an IDR picture (or for HEVC, any IRAP picture), or for H.264 an
+    /// I picture with a recovery point SEI message, from which decoding can start
     KeyFrame,
+    /// The access unit depends on previously decoded pictures
     DeltaFrame,
 }
 
-enum ParseState {
-    Pps,
-    Sps,
-    Sei,
-    KeyFrame,
-    PFrame,
+/// The kind of picture in an access unit, as determined from the types of its slices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PictureType {
+    /// A picture with only intra coded (I or SI) slices
+    I,
+    /// A picture having P or SP slices, but no B slices
+    P,
+    /// A picture having B slices
+    B,
+    /// An IDR picture, after which no picture refers to any preceding picture
+    Idr,
+}
+impl PictureType {
+    fn of(slice_type: h264::SliceType) -> PictureType {
+        match slice_type {
+            h264::SliceType::I | h264::SliceType::SI => PictureType::I,
+            h264::SliceType::P | h264::SliceType::SP => PictureType::P,
+            h264::SliceType::B => PictureType::B,
+        }
+    }
 }
 
+/// A single access unit found by `NALParser`, with its timing and any parameter sets and SEI
+/// messages that preceded the picture.
+#[derive(Debug)]
 pub struct DemuxedSegment {
+    codec: VideoCodec,
+    vps: Option<Vec<u8>>,
     sps: Option<Vec<u8>>,
     pps: Option<Vec<u8>>,
     frame_type: VideoFrameType,
+    picture_type: Option<PictureType>,
+    slice_header: Option<h264::SliceHeader>,
+    recovery_point: Option<RecoveryPoint>,
+    sei: Vec<Vec<u8>>,
     frame_payload: Option<Vec<u8>>,
-    start_ts: f64, // in seconds
-    duration: f64 // in seconds
+    pts: Option<Timestamp>,
+    dts: Option<Timestamp>,
+    decode_time: u64, // in 90kHz units
+    duration: u64,    // in 90kHz units
 }
 
 impl DemuxedSegment {
-    fn new(frame_type: VideoFrameType, start_ts: f64) -> DemuxedSegment {
+    fn new(
+        codec: VideoCodec,
+        frame_type: VideoFrameType,
+        pts: Option<Timestamp>,
+        dts: Option<Timestamp>,
+    ) -> DemuxedSegment {
         DemuxedSegment {
+            codec,
+            vps: None,
             sps: None,
             pps: None,
             frame_type,
+            picture_type: None,
+            slice_header: None,
+            recovery_point: None,
+            sei: Vec::new(),
             frame_payload: None,
-            start_ts,
-            duration: 0.0,
+            pts,
+            dts,
+            decode_time: 0,
+            duration: 0,
+        }
+    }
+
+    /// Whether decoding can start from this access unit
+    pub fn frame_type(&self) -> VideoFrameType {
+        self.frame_type
+    }
+
+    /// `true` if this access unit is a key frame
+    pub fn is_key_frame(&self) -> bool {
+        self.frame_type == KeyFrame
+    }
+
+    /// The kind of picture in this access unit.  For H.264, this is determined from the slice
+    /// headers, and will be `None` if they could not be parsed (for instance because the stream
+    /// was joined part way, and the relevant parameter sets have not been seen).  For HEVC, only
+    /// IDR pictures are identified.
+    pub fn picture_type(&self) -> Option<PictureType> {
+        self.picture_type
+    }
+
+    /// The leading fields of the slice header of the first slice of an H.264 access unit, if it
+    /// could be parsed
+    pub fn slice_header(&self) -> Option<&h264::SliceHeader> {
+        self.slice_header.as_ref()
+    }
+
+    /// The recovery point SEI message that preceded the first slice of an H.264 access unit, if
+    /// any
+    pub fn recovery_point(&self) -> Option<RecoveryPoint> {
+        self.recovery_point
+    }
+
+    /// The Video Parameter Set NAL unit (including its header) if one appeared in this access
+    /// unit; always `None` for H.264, which has no VPS
+    pub fn vps(&self) -> Option<&[u8]> {
+        self.vps.as_deref()
+    }
+
+    /// The Sequence Parameter Set NAL unit (including its header) if one appeared in this access
+    /// unit
+    pub fn sps(&self) -> Option<&[u8]> {
+        self.sps.as_deref()
+    }
+
+    /// The Picture Parameter Set NAL unit (including its header) if one appeared in this access
+    /// unit
+    pub fn pps(&self) -> Option<&[u8]> {
+        self.pps.as_deref()
+    }
+
+    /// The SEI NAL units (including their headers) that appeared in this access unit before the
+    /// first slice
+    pub fn sei(&self) -> impl Iterator<Item = &[u8]> {
+        self.sei.iter().map(|s| &s[..])
+    }
+
+    /// The CEA-608 / CEA-708 caption data carried in ATSC A/53 format by the SEI NAL units of
+    /// this access unit.  Any SEI messages that can't be decoded are ignored.
+    pub fn cc_data(&self) -> Vec<CcData> {
+        let mut result = Vec::new();
+        for nal in &self.sei {
+            for_each_sei_message(self.codec, nal, |msg| {
+                if msg.payload_type == sei::PAYLOAD_TYPE_USER_DATA_REGISTERED_ITU_T_T35 {
+                    match ItuTT35::from_payload(msg.payload).and_then(|t35| t35.atsc_cc_data()) {
+                        Ok(Some(cc_data)) => result.push(cc_data),
+                        Ok(None) => {}
+                        Err(e) => trace!("problem with caption data: {}", e),
+                    }
+                }
+            });
+        }
+        result
+    }
+
+    /// The codec of the stream from which this access unit came
+    pub fn codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    /// The access unit data from the first slice NAL unit onwards, or `None` if the access unit
+    /// contained no slices
+    pub fn payload(&self) -> Option<&[u8]> {
+        self.frame_payload.as_deref()
+    }
+
+    /// The Presentation Time Stamp given for this access unit, if its PES packet had one
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The Decode Time Stamp given for this access unit.  If its PES packet had a PTS but no DTS,
+    /// the two are equal, and the PTS is returned.
+    pub fn dts(&self) -> Option<Timestamp> {
+        self.dts
+    }
+
+    /// The difference between the presentation and decode times of this access unit in 90kHz
+    /// units, which will be non-zero for streams where pictures are reordered (e.g. those using
+    /// B-frames)
+    pub fn composition_offset(&self) -> i64 {
+        match (self.pts, self.dts) {
+            (Some(pts), Some(dts)) => timestamp_difference(pts, dts),
+            _ => 0,
         }
     }
+
+    /// The decode time of this access unit in 90kHz units, relative to the first access unit of
+    /// the stream, and continuing to increase where the 33-bit DTS value wraps around.
+    ///
+    /// Access units without timestamps are given times evenly spaced between those of the
+    /// surrounding access units.
+    pub fn decode_time(&self) -> u64 {
+        self.decode_time
+    }
+
+    /// The duration of this access unit in 90kHz units.  This is the difference between its
+    /// decode time and that of the following access unit, or for the final access unit of a
+    /// stream, is assumed to be the same as the duration of the previous access unit.
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    /// `decode_time()` converted to seconds
+    pub fn decode_time_secs(&self) -> f64 {
+        self.decode_time as f64 / Timestamp::TIMEBASE as f64
+    }
+
+    /// `duration()` converted to seconds
+    pub fn duration_secs(&self) -> f64 {
+        self.duration as f64 / Timestamp::TIMEBASE as f64
+    }
 }
 
-pub struct NALParser {
-    state: ParseState,
-    decoding_delay: f64, // in seconds
-    raw_data: Option<Vec<u8>>,
-    segments: Option<Vec<DemuxedSegment>>,
-    sei_found: bool,
+/// Calls the given function for each message within the given SEI NAL unit, stopping at the
+/// first that can't be read.
+fn for_each_sei_message(codec: VideoCodec, nal: &[u8], f: impl FnMut(SeiMessage<'_>)) {
+    let header_size = codec.nal_header_size().min(nal.len());
+    let rbsp = rbsp::decode_nal(&nal[header_size..]);
+    SeiMessages::new(&rbsp)
+        .map_while(|msg| msg.ok())
+        .for_each(f);
 }
 
-impl NALParser {
+/// Returns the first recovery point message within the given H.264 SEI NAL unit, if any.
+fn find_recovery_point(nal: &[u8]) -> Option<RecoveryPoint> {
+    let mut result = None;
+    for_each_sei_message(VideoCodec::H264, nal, |msg| {
+        if result.is_none() && msg.payload_type == sei::PAYLOAD_TYPE_RECOVERY_POINT {
+            result = Some(RecoveryPoint::from_payload(msg.payload));
+        }
+    });
+    match result {
+        Some(Ok(recovery_point)) => Some(recovery_point),
+        Some(Err(e)) => {
+            trace!("problem with recovery point: {}", e);
+            None
+        }
+        None => None,
+    }
+}
 
+/// The signed difference `a - b` between two timestamps, assuming that they are less than half
+/// the 33-bit timestamp range apart.
+pub(crate) fn timestamp_difference(a: Timestamp, b: Timestamp) -> i64 {
+    const RANGE: i64 = 1 << 33;
+    let diff = (a.value() as i64 - b.value() as i64).rem_euclid(RANGE);
+    if diff > RANGE / 2 {
+        diff - RANGE
+    } else {
+        diff
+    }
+}
+
+/// Maps 33-bit decode timestamps onto a continuous timeline starting at zero.
+#[derive(Default)]
+struct Timeline {
+    first: Option<Timestamp>,
+    last: Option<Timestamp>,
+    wraps: u64,
+}
+impl Timeline {
+    fn decode_time(&mut self, dts: Timestamp) -> u64 {
+        if let Some(last) = self.last {
+            if dts.likely_wrapped_since(last) {
+                self.wraps += 1;
+            }
+        }
+        self.last = Some(dts);
+        let first = *self.first.get_or_insert(dts);
+        (self.wraps * (Timestamp::MAX.value() + 1) + dts.value()).saturating_sub(first.value())
+    }
+}
+
+/// Splits the PES packets of an H.264 or H.265 stream into `DemuxedSegment`s, one per access
+/// unit, which can be retrieved with `drain_segments()`.
+///
+/// Timestamps are supplied to `begin_packet()` as given in the PES header.  Durations are
+/// calculated in decode order, from the DTS of each access unit (or from the PTS where no DTS
+/// is given).
+pub struct NALParser {
+    codec: VideoCodec,
+    param_sets: h264::ParamSets,
+    timeline: Timeline,
+    splitter: AnnexBSplitter,
+    in_packet: bool,
+    packet_pts: Option<Timestamp>,
+    packet_dts: Option<Timestamp>,
+    segments: Vec<DemuxedSegment>,
+    /// the number of leading entries in `segments` whose duration is known
+    complete: usize,
+    /// the decode time and duration of the last segment to be completed
+    last_decode_time: u64,
+    last_duration: u64,
+}
+
+impl Default for NALParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NALParser {
+    /// Create a parser for an H.264 / AVC stream
     pub fn new() -> Self {
-        return NALParser {
-            state: ParseState::Sps,
-            decoding_delay: 0.0,
-            raw_data: None,
-            segments: Some(Vec::new()),
-            sei_found: false,
-        };
+        Self::for_codec(VideoCodec::H264)
+    }
+
+    /// Create a parser for an H.265 / HEVC stream
+    pub fn new_hevc() -> Self {
+        Self::for_codec(VideoCodec::H265)
+    }
+
+    /// Create a parser for a stream using the given codec
+    pub fn for_codec(codec: VideoCodec) -> Self {
+        NALParser {
+            codec,
+            param_sets: h264::ParamSets::new(),
+            timeline: Timeline::default(),
+            splitter: AnnexBSplitter::new(),
+            in_packet: false,
+            packet_pts: None,
+            packet_dts: None,
+            segments: Vec::new(),
+            complete: 0,
+            last_decode_time: 0,
+            last_duration: 0,
+        }
+    }
+
+    /// The codec of the stream being parsed
+    pub fn codec(&self) -> VideoCodec {
+        self.codec
     }
 
-    pub fn decoding_delay(&mut self, delay_ts: f64) {
-        self.decoding_delay = delay_ts;
+    /// The H.264 parameter sets seen so far in the stream
+    pub fn param_sets(&self) -> &h264::ParamSets {
+        &self.param_sets
     }
 
-    pub fn begin_packet(&mut self, payload: &[u8]) {
-        self.raw_data = Some(payload.to_vec());
+    /// Start a new access unit, with the PTS and DTS from the header of the PES packet carrying
+    /// it (see `PtsDts::pts()` and `PtsDts::dts()`) and the initial part of its payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, dts: Option<Timestamp>, payload: &[u8]) {
+        self.splitter.clear();
+        self.splitter.push(payload);
+        self.in_packet = true;
+        self.packet_pts = pts;
+        // when a PES packet has only a PTS, its DTS has the same value
+        self.packet_dts = dts.or(pts);
     }
 
+    /// Supply the next part of the payload of the access unit started by `begin_packet()`.  This
+    /// is ignored if no access unit has been started.
     pub fn continue_packet(&mut self, payload: &[u8]) {
-        if let Some(ref mut vec) = self.raw_data {
-            vec.extend_from_slice(payload);
+        if self.in_packet {
+            self.splitter.push(payload);
         }
     }
 
-    pub fn set_duration_for_previous_packet(&mut self, next_packet_pts: f64) {
-        if let Some(ref mut segments) = self.segments {
-            let last_segment = segments.last_mut().unwrap();
-            last_segment.duration = next_packet_pts - last_segment.start_ts - self.decoding_delay;
-            // println!("Packet start = {} and duration = {}", last_segment.start_ts, last_segment.duration);
+    /// Completes the segments not yet complete, now that the decode time of the following
+    /// segment is known.  Any segments without timestamps share the time available equally.
+    fn complete_pending(&mut self, next_decode_time: u64) {
+        let pending = &mut self.segments[self.complete..];
+        if let Some(start) = pending.first().map(|seg| seg.decode_time) {
+            let step = next_decode_time.saturating_sub(start) / pending.len() as u64;
+            let mut decode_time = start;
+            for seg in pending.iter_mut() {
+                seg.decode_time = decode_time;
+                seg.duration = step;
+                decode_time += step;
+            }
+            let last = pending.last_mut().unwrap();
+            last.duration = next_decode_time.saturating_sub(last.decode_time);
+            self.last_decode_time = last.decode_time;
+            self.last_duration = last.duration;
         }
+        self.complete = self.segments.len();
     }
 
-    pub fn end_packet(&mut self, pts: f64) {
-        if let Some(ref vec) = self.raw_data {
-            let sps_header_index = NALParser::index_of_payload(vec,
-                                                               HeaderCode::delimiter_length(),
-                                                               HeaderCode::Sps);
-            let mut active_segment: DemuxedSegment;
-
-            // duration for the packet will be calculated when we get the pts for next packet
-            let start_ts = pts - self.decoding_delay;
-
-            if sps_header_index.start_index.is_some() { // key frame
-                active_segment = DemuxedSegment::new(KeyFrame, start_ts);
-                let pps_header_index = NALParser::index_of_payload(vec,
-                                                                   sps_header_index.end_index.unwrap(),
-                                                                   HeaderCode::Pps,);
-                if let Some(pps_end_index) = pps_header_index.end_index {
-                    active_segment.sps = get_header_payload(&pps_header_index, &vec);
-                    Self::print(&pps_header_index, HeaderCode::Sps);
-
-                    let header = if self.sei_found == true { HeaderCode::KeyFrame } else { HeaderCode::Sei };
-                    let sei_or_key_frame_index = NALParser::index_of_payload(vec,
-                                                                             pps_end_index, header);
-                    let key_frame_header_end_index: usize;
-
-                    if self.sei_found == false {
-
-                        if let Some(sei_or_key_frame_end_index) = sei_or_key_frame_index.end_index {
-                            active_segment.pps = get_header_payload(&sei_or_key_frame_index, &vec);
-                            Self::print(&sei_or_key_frame_index, HeaderCode::Pps);
-                        }
-                        let key_frame_index = NALParser::index_of_payload(vec,
-                                                                          sei_or_key_frame_index.end_index.unwrap_or_else(|| 0),
-                                                                          HeaderCode::KeyFrame);
-                        key_frame_header_end_index = key_frame_index.end_index.unwrap_or_else(|| 0);
-                    } else {
-                        active_segment.pps = get_header_payload(&sei_or_key_frame_index, &vec);
-                        key_frame_header_end_index = sei_or_key_frame_index.end_index.unwrap_or_else(|| 0);
+    /// Removes and returns the segments whose duration is now known, in stream order.  The most
+    /// recent segment is only returned once an access unit with a timestamp follows it, or once
+    /// `flush()` has been called at the end of the stream.
+    pub fn drain_segments(&mut self) -> impl Iterator<Item = DemuxedSegment> + '_ {
+        let complete = self.complete;
+        self.complete = 0;
+        self.segments.drain(..complete)
+    }
 
-                        Self::print(&sei_or_key_frame_index, HeaderCode::Pps);
-                    }
-                    self.sei_found = true;
+    /// Signal the end of the stream, so that the final segments are returned by
+    /// `drain_segments()`.  Since no following access unit gives their duration, they are
+    /// assumed to last as long as the last segment whose duration was known.
+    pub fn flush(&mut self) {
+        if let Some(first) = self.segments.get(self.complete) {
+            let next = first.decode_time
+                + self.last_duration * (self.segments.len() - self.complete) as u64;
+            self.complete_pending(next);
+        }
+    }
 
-                    if let Some(key_frame_data) = &self.raw_data {
-                        active_segment.frame_payload = Some(vec[key_frame_header_end_index..key_frame_data.len()].to_vec());
-                        // println!("Packet :KeyFrame Payload size {}", key_frame_data.len() - key_frame_header_end_index);
+    /// Signal the end of the current access unit, which is then split into NAL units to make a
+    /// new `DemuxedSegment`.
+    ///
+    /// If this access unit has a timestamp, the segments before it are completed, since their
+    /// durations are now known, and become available from `drain_segments()`.  The new segment
+    /// itself is completed by the next access unit with a timestamp, or by `flush()`.
+    pub fn end_packet(&mut self) {
+        if !self.in_packet {
+            return;
+        }
+        self.in_packet = false;
+        self.splitter.finish();
+
+        let mut active_segment =
+            DemuxedSegment::new(self.codec, DeltaFrame, self.packet_pts, self.packet_dts);
+        // the duration of the previous segment can be calculated now that this one's decode time
+        // is known; segments without timestamps will get a decode time once one is known
+        match self.packet_dts {
+            Some(dts) => {
+                active_segment.decode_time = self.timeline.decode_time(dts);
+                self.complete_pending(active_segment.decode_time);
+            }
+            None if self.complete == self.segments.len() => {
+                active_segment.decode_time = self.last_decode_time + self.last_duration;
+            }
+            None => {}
+        }
+        let units = self.splitter.units().zip(self.splitter.unit_ranges());
+        for (nal, range) in units {
+            let kind = NalKind::of(self.codec, &nal);
+            trace!("NAL unit {:?} size {}", kind, nal.data().len());
+            if self.codec == VideoCodec::H264 {
+                let result = match kind {
+                    NalKind::Sps => self.param_sets.put_sps(nal.data()).map(|_| ()),
+                    NalKind::Pps => self.param_sets.put_pps(nal.data()).map(|_| ()),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    trace!("problem with {:?}: {}", kind, e);
+                }
+            }
+            match kind {
+                NalKind::Vps if active_segment.vps.is_none() => {
+                    active_segment.vps = Some(nal.data().to_vec());
+                }
+                NalKind::Sps if active_segment.sps.is_none() => {
+                    active_segment.sps = Some(nal.data().to_vec());
+                }
+                NalKind::Pps if active_segment.pps.is_none() => {
+                    active_segment.pps = Some(nal.data().to_vec());
+                }
+                NalKind::Sei if active_segment.frame_payload.is_none() => {
+                    if self.codec == VideoCodec::H264 && active_segment.recovery_point.is_none() {
+                        active_segment.recovery_point = find_recovery_point(nal.data());
                     }
+                    active_segment.sei.push(nal.data().to_vec());
                 }
-            } else { // delta frame
-                active_segment = DemuxedSegment::new(DeltaFrame, start_ts);
-
-                let header_size = HeaderCode::iframe_header_prefix_byte_count();
-                if let Some(delta_frame_data) = &self.raw_data {
-                    active_segment.frame_payload = Some(vec[header_size..delta_frame_data.len()].to_vec());
-                    println!("delta frame size {}", delta_frame_data.len() - header_size);
+                NalKind::Slice { key, idr } => {
+                    if key {
+                        active_segment.frame_type = KeyFrame;
+                    }
+                    let header = if self.codec == VideoCodec::H264 {
+                        h264::SliceHeader::from_bytes(nal.data(), &self.param_sets)
+                            .map_err(|e| trace!("problem with slice header: {}", e))
+                            .ok()
+                    } else {
+                        None
+                    };
+                    let picture_type = if idr {
+                        Some(PictureType::Idr)
+                    } else {
+                        header.as_ref().map(|h| PictureType::of(h.slice_type))
+                    };
+                    if let Some(header) = header {
+                        active_segment.slice_header.get_or_insert(header);
+                    }
+                    // the picture type is that of the 'most predicted' slice
+                    active_segment.picture_type = active_segment.picture_type.max(picture_type);
+                    if active_segment.frame_payload.is_none() {
+                        // the payload runs from the first slice to the end of the access unit
+                        let end = self.splitter.unit_ranges().last().unwrap().end;
+                        active_segment.frame_payload =
+                            Some(self.splitter.buffer()[range.start..end].to_vec());
+                    }
                 }
+                _ => {}
             }
-            if let Some(ref mut demux_segments) = self.segments {
-                demux_segments.push(active_segment);
-            } else {
-                self.segments = Some(vec![active_segment]);
-            }
         }
+        // in streams without IDR pictures, decoding can start from I pictures at recovery points
+        if active_segment.recovery_point.is_some()
+            && active_segment.picture_type == Some(PictureType::I)
+        {
+            active_segment.frame_type = KeyFrame;
+        }
+        self.segments.push(active_segment);
     }
+}
 
-    fn print(indices: &HeaderIndices, header_code: HeaderCode) {
-        let header = match header_code {
-            HeaderCode::Delimiter => "Delimiter",
-            HeaderCode::Sps => "SPS",
-            HeaderCode::Pps => "PPS",
-            HeaderCode::KeyFrame => "KEYFRAME",
-            HeaderCode::Sei => "IFRAME",
-        };
-        println!("Packet :{} Payload size {}", header, (indices.start_index.unwrap_or_else(|| 0)
-            - indices.prev_header_end.unwrap_or_else(|| 0)))
-    }
-
-    // Return value is Header start , Header end, Previous Header End
-    fn index_of_payload(data_stream: &[u8], start_index: usize, header_coder: HeaderCode) -> HeaderIndices {
-        match header_coder.header_prefix_byte_count() {
-            3 => Self::index_of_three_byte_nal_unit(start_index, header_coder, data_stream),
-            4 => Self::index_of_four_byte_nal_unit(start_index, header_coder, data_stream),
-            _ => HeaderIndices::new(None, None, None)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn key_frame_without_aud() {
+        // no AUD, 3-byte start codes for the parameter sets, and an IDR slice with a nal_ref_idc
+        // of 1, split across several calls to continue_packet()
+        let au = hex!("000001 6742c01e 000001 68ce3c80 00000001 25888480 000001 25889980");
+        let mut parser = NALParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(90_000)), None, &au[..5]);
+        for chunk in au[5..].chunks(3) {
+            parser.continue_packet(chunk);
         }
+        parser.end_packet();
+        parser.flush();
+        let segments: Vec<_> = parser.drain_segments().collect();
+        assert_eq!(segments.len(), 1);
+        let seg = &segments[0];
+        assert!(seg.is_key_frame());
+        assert_eq!(seg.sps(), Some(&hex!("6742c01e")[..]));
+        assert_eq!(seg.pps(), Some(&hex!("68ce3c80")[..]));
+        assert_eq!(seg.payload(), Some(&hex!("25888480 000001 25889980")[..]));
+        assert_eq!(seg.pts(), Some(Timestamp::from_u64(90_000)));
+        assert_eq!(seg.dts(), seg.pts());
+        assert_eq!(seg.decode_time(), 0);
     }
 
-    pub fn index_of_four_byte_nal_unit(start_index: usize, header_code: HeaderCode, data_stream: &[u8]) -> HeaderIndices {
-        let start = start_index;
-        let mut iterator = start_index;
-        while iterator < (data_stream.len() - header_code.header_prefix_byte_count()) {
-            let byte_zero = data_stream[iterator];
-            let byte_one = data_stream[iterator + 1];
-            let byte_two = data_stream[iterator + 2];
-            let byte_three = data_stream[iterator + 3];
-            let byte_four = data_stream[iterator + 4];
-
-            let contains = (byte_zero | byte_one | byte_two | byte_three) == 1 && byte_four == (header_code as u8);
-            if contains {
-                if Some(start).unwrap() == Some(iterator).unwrap() {
-                    return HeaderIndices::new(Some(iterator), Some(iterator + 4), None);
-                }
-                return HeaderIndices::new(Some(iterator), Some(iterator + 4), Some(start));
-            }
-            iterator = iterator + 1;
+    #[test]
+    fn delta_frame() {
+        let au = hex!("00000001 0910 00000001 41e0 0000");
+        let mut parser = NALParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(180_000)), None, &au);
+        parser.end_packet();
+        // a second end_packet() without begin_packet() must not produce another segment
+        parser.end_packet();
+        parser.flush();
+        let segments: Vec<_> = parser.drain_segments().collect();
+        assert_eq!(segments.len(), 1);
+        let seg = &segments[0];
+        assert_eq!(seg.frame_type(), DeltaFrame);
+        assert!(seg.sps().is_none());
+        assert_eq!(seg.payload(), Some(&hex!("41e0")[..]));
+    }
+
+    #[test]
+    fn drain_segments() {
+        let mut parser = NALParser::new();
+        for (i, pts) in [900_000, 903_600, 907_200].iter().enumerate() {
+            let pts = Some(Timestamp::from_u64(*pts));
+            parser.begin_packet(pts, None, &hex!("00000001 41e0"));
+            parser.end_packet();
+            // only the segments before the one just ended have a known duration
+            assert_eq!(parser.drain_segments().count(), usize::from(i > 0));
         }
-        return HeaderIndices::new(None, None, None);
-    }
-
-    pub fn index_of_three_byte_nal_unit(start_index: usize, header_code: HeaderCode, data_stream: &[u8]) -> HeaderIndices {
-        let start = start_index;
-        let mut iterator = start_index;
-        while iterator < (data_stream.len() - header_code.header_prefix_byte_count()) {
-            let byte_zero = data_stream[iterator];
-            let byte_one = data_stream[iterator + 1];
-            let byte_two = data_stream[iterator + 2];
-            let byte_three = data_stream[iterator + 3];
-
-            let contains = (byte_zero | byte_one | byte_two) == 1 && byte_three == header_code as u8;
-            if contains {
-                if Some(start).unwrap_or_else(|| 0) == Some(iterator).unwrap_or_else(|| 0) {
-                    return HeaderIndices::new(Some(iterator), Some(iterator + 3), None )
-                }
-                return HeaderIndices::new(Some(iterator), Some(iterator + 3), Some(start));
-            }
-            iterator = iterator + 1;
+        assert_eq!(parser.drain_segments().count(), 0);
+        parser.flush();
+        let last: Vec<_> = parser.drain_segments().collect();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].decode_time(), 7_200);
+        assert!((last[0].decode_time_secs() - 0.08).abs() < 1e-9);
+        // the final duration is assumed to match the one before
+        assert_eq!(last[0].duration(), 3_600);
+    }
+
+    fn timed_segments(timestamps: &[(Option<u64>, Option<u64>)]) -> Vec<DemuxedSegment> {
+        let mut parser = NALParser::new();
+        for (pts, dts) in timestamps {
+            parser.begin_packet(
+                pts.map(Timestamp::from_u64),
+                dts.map(Timestamp::from_u64),
+                &hex!("00000001 41e0"),
+            );
+            parser.end_packet();
         }
-        return HeaderIndices::new(None, None, None);
+        parser.flush();
+        parser.drain_segments().collect()
     }
-}
 
-pub struct HeaderIndices {
-    start_index : Option<usize>,
-    end_index : Option<usize>,
-    prev_header_end : Option<usize>,
-}
+    #[test]
+    fn b_frames() {
+        // I P B B in decode order, with presentation order I B B P
+        let segments = timed_segments(&[
+            (Some(3_000), Some(0)),
+            (Some(12_000), Some(3_000)),
+            (Some(6_000), Some(6_000)),
+            (Some(9_000), None),
+        ]);
+        let times: Vec<_> = segments
+            .iter()
+            .map(|s| (s.decode_time(), s.duration(), s.composition_offset()))
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                (0, 3_000, 3_000),
+                (3_000, 3_000, 9_000),
+                (6_000, 3_000, 0),
+                (9_000, 3_000, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_wrap() {
+        let max = Timestamp::MAX.value();
+        let segments = timed_segments(&[
+            (Some(max - 2_999), None),
+            (Some(1), None),
+            (Some(3_001), None),
+        ]);
+        let times: Vec<_> = segments
+            .iter()
+            .map(|s| (s.decode_time(), s.duration()))
+            .collect();
+        assert_eq!(times, vec![(0, 3_001), (3_001, 3_000), (6_001, 3_000)]);
+        assert_eq!(
+            timestamp_difference(Timestamp::from_u64(1), Timestamp::from_u64(max)),
+            2
+        );
+        assert_eq!(
+            timestamp_difference(Timestamp::from_u64(max), Timestamp::from_u64(1)),
+            -2
+        );
+    }
 
-impl HeaderIndices {
-    pub fn new(
-        start_index: Option<usize>,
-        end_index: Option<usize>,
-        prev_header_end_index: Option<usize>
-    ) -> HeaderIndices {
-        HeaderIndices {
-            start_index,
-            end_index,
-            prev_header_end: prev_header_end_index,
+    #[test]
+    fn missing_timestamps() {
+        // the access units without timestamps share the time between those with timestamps
+        let segments = timed_segments(&[
+            (Some(1_000), None),
+            (None, None),
+            (None, None),
+            (Some(10_000), None),
+        ]);
+        let times: Vec<_> = segments
+            .iter()
+            .map(|s| (s.decode_time(), s.duration()))
+            .collect();
+        assert_eq!(
+            times,
+            vec![(0, 3_000), (3_000, 3_000), (6_000, 3_000), (9_000, 3_000)]
+        );
+        assert!(segments[1].pts().is_none());
+    }
+
+    fn parse_all(codec: VideoCodec, access_units: &[&[u8]]) -> Vec<DemuxedSegment> {
+        let mut parser = NALParser::for_codec(codec);
+        for (i, au) in access_units.iter().enumerate() {
+            let pts = Timestamp::from_u64(i as u64 * 3_000);
+            parser.begin_packet(Some(pts), None, au);
+            parser.end_packet();
         }
+        parser.flush();
+        parser.drain_segments().collect()
+    }
+
+    #[test]
+    fn picture_types() {
+        // the parameter sets, IDR slice and following P slice of the sample stream, truncated
+        let segments = parse_all(
+            VideoCodec::H264,
+            &[
+                &hex!(
+                    "00000001 6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654
+                     00000001 68ce0fc8
+                     00000001 6588843f 11bbc920"
+                ),
+                &hex!("00000001 419a204f 3f5783ce"),
+                // a slice referring to a PPS that hasn't been seen
+                &hex!("00000001 4198d0"),
+            ],
+        );
+        assert_eq!(segments[0].picture_type(), Some(PictureType::Idr));
+        assert_eq!(segments[0].slice_header().unwrap().idr_pic_id, Some(0));
+        assert_eq!(segments[1].picture_type(), Some(PictureType::P));
+        assert_eq!(segments[1].slice_header().unwrap().frame_num, 1);
+        assert_eq!(segments[2].picture_type(), None);
+        assert!(segments[2].slice_header().is_none());
+    }
+
+    #[test]
+    fn recovery_point() {
+        // a stream without IDR pictures, starting with an I picture having a recovery point SEI
+        let segments = parse_all(
+            VideoCodec::H264,
+            &[
+                &hex!(
+                    "00000001 67640028 ad844108 b6501e01 13f7ff80 02000188 00001f48 00075307
+                              00007d20 07d15ed5 c1da0884 5960
+                     00000001 685ae32c c0422580
+                     00000001 06 0601c4 80
+                     00000001 21884002 c0"
+                ),
+                // a B picture
+                &hex!("00000001 019d18ac"),
+            ],
+        );
+        assert_eq!(segments[0].picture_type(), Some(PictureType::I));
+        assert!(segments[0].recovery_point().unwrap().exact_match_flag);
+        assert_eq!(segments[0].frame_type(), KeyFrame);
+        assert_eq!(segments[1].picture_type(), Some(PictureType::B));
+        assert_eq!(
+            segments[1].slice_header().unwrap().pic_order_cnt_lsb,
+            Some(10)
+        );
+        assert_eq!(segments[1].frame_type(), DeltaFrame);
+    }
+
+    #[test]
+    fn hevc_key_frame() {
+        // AUD, VPS, SPS, PPS, prefix SEI, then two slice segments of a CRA picture and a
+        // suffix SEI
+        let au = hex!(
+            "00000001 4601 50
+             00000001 4001 0c01
+             00000001 4201 0101
+             000001 4401 c172
+             000001 4e01 0501
+             000001 2a01 af10
+             000001 2a01 1234
+             000001 5001 0a01"
+        );
+        let mut parser = NALParser::new_hevc();
+        assert_eq!(parser.codec(), VideoCodec::H265);
+        parser.begin_packet(Some(Timestamp::from_u64(45_000)), None, &au);
+        parser.end_packet();
+        parser.flush();
+        let segments: Vec<_> = parser.drain_segments().collect();
+        assert_eq!(segments.len(), 1);
+        let seg = &segments[0];
+        assert!(seg.is_key_frame());
+        assert_eq!(seg.vps(), Some(&hex!("4001 0c01")[..]));
+        assert_eq!(seg.sps(), Some(&hex!("4201 0101")[..]));
+        assert_eq!(seg.pps(), Some(&hex!("4401 c172")[..]));
+        assert_eq!(seg.sei().collect::<Vec<_>>(), vec![&hex!("4e01 0501")[..]]);
+        assert!(seg.cc_data().is_empty());
+        assert_eq!(
+            seg.payload(),
+            Some(&hex!("2a01 af10 000001 2a01 1234 000001 5001 0a01")[..])
+        );
+    }
+
+    #[test]
+    fn captions() {
+        // an HEVC prefix SEI with caption data, and an H.264 SEI with a recovery point message
+        // then caption data, followed by a user_data_unregistered message
+        let hevc = hex!(
+            "000001 4e01 04 11 b5 0031 47413934 03 c2ff fc9420 fd8080 ff 80
+             000001 0201 d0"
+        );
+        let h264 = hex!(
+            "000001 06 0601c4 04 0e b5 0031 47413934 03 c1ff fc1c20 ff 80
+             000001 06 05 14 dc45e9bd e6d948b7 962cd820 d923eeef 78323634 80
+             000001 4198d0"
+        );
+        let cc = parse_all(VideoCodec::H265, &[&hevc])[0].cc_data();
+        assert_eq!(cc.len(), 1);
+        assert_eq!(
+            cc[0].cea608_field(1).collect::<Vec<_>>(),
+            vec![[0x94, 0x20]]
+        );
+        let seg = &parse_all(VideoCodec::H264, &[&h264])[0];
+        assert!(seg.recovery_point().is_some());
+        let cc = seg.cc_data();
+        assert_eq!(cc.len(), 1);
+        assert_eq!(
+            cc[0].cea608_field(1).collect::<Vec<_>>(),
+            vec![[0x1c, 0x20]]
+        );
     }
-}
 
-pub fn get_header_payload(next_header_indices: &HeaderIndices, vec: &Vec<u8>) -> Option<Vec<u8>> {
-    if next_header_indices.start_index.is_some() && next_header_indices.prev_header_end.is_some() {
-        let slice = &vec[next_header_indices.prev_header_end.unwrap()..next_header_indices.start_index.unwrap()];
-        return Some(slice.to_vec())
+    #[test]
+    fn hevc_delta_frame() {
+        // a TRAIL_R slice with no parameter sets
+        let au = hex!("00000001 4601 50 00000001 0201 d0");
+        let mut parser = NALParser::new_hevc();
+        parser.begin_packet(Some(Timestamp::from_u64(45_000)), None, &au);
+        parser.end_packet();
+        parser.flush();
+        let seg = parser.drain_segments().next().unwrap();
+        assert_eq!(seg.frame_type(), DeltaFrame);
+        assert!(seg.vps().is_none());
+        assert_eq!(seg.payload(), Some(&hex!("0201 d0")[..]));
     }
-    None
-}
\ No newline at end of file
+}
@@ -0,0 +1,502 @@
+//! Splitting of Dolby Digital (AC-3) and Dolby Digital Plus (E-AC-3) audio into syncframes,
+//! per _ETSI TS 102 366_.
+//!
+//! AC-3 is carried as `StreamType::AtscDolbyDigitalAudio` in ATSC streams, and as
+//! `StreamType::H2220PesPrivateData` with an `AC-3_descriptor` or `enhanced_AC-3_descriptor` in
+//! DVB streams.  The two formats share the same _syncword_, and are told apart by the _bsid_
+//! field of each syncframe, so an [`Ac3Parser`](struct.Ac3Parser.html) handles either.
+//!
+//! An E-AC-3 stream may carry _dependent substreams_ (e.g. the extra channels of 7.1 audio)
+//! following each frame of the _independent substream_.  These frames are given the same PTS as
+//! the independent frame they accompany.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::ac3::Ac3Parser;
+//! # use mpeg2ts_reader::pes::Timestamp;
+//! // a 48kHz, 32 kbit/s AC-3 syncframe, which is 128 bytes long
+//! let mut frame = vec![0; 128];
+//! frame[..7].copy_from_slice(&[0x0b, 0x77, 0x00, 0x00, 0x00, 0x40, 0xe1]);
+//! let mut parser = Ac3Parser::new();
+//! parser.begin_packet(Some(Timestamp::from_u64(90_000)), &frame.repeat(2));
+//! parser.end_packet();
+//! let pts: Vec<_> = parser.drain_frames().map(|f| f.pts().unwrap().value()).collect();
+//! // 1536 samples at 48kHz is 2880 ticks of the 90kHz clock
+//! assert_eq!(pts, [90_000, 92_880]);
+//! ```
+
+use crate::parser::adts::AudioClock;
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+/// The sample rates indicated by each value of _fscod_ (`3` being reserved).
+const SAMPLE_RATES: [u32; 3] = [48000, 44100, 32000];
+/// The sample rates indicated by each value of the E-AC-3 _fscod2_ field (`3` being reserved).
+const REDUCED_SAMPLE_RATES: [u32; 3] = [24000, 22050, 16000];
+/// The nominal bit rate, in kbit/s, for each pair of _frmsizecod_ values.
+const BIT_RATES: [u32; 19] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+];
+/// The number of audio blocks in each frame for each value of the E-AC-3 _numblkscod_ field.
+const AUDIO_BLOCKS: [u8; 4] = [1, 2, 3, 6];
+/// The number of PCM samples per channel decoded from each audio block.
+pub const SAMPLES_PER_AUDIO_BLOCK: u32 = 256;
+
+/// Problems which may be found in an AC-3 or E-AC-3 syncframe header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ac3Error {
+    /// Fewer than the 7 bytes of the header were available.
+    NotEnoughData,
+    /// The data did not start with the _syncword_ `0x0b77`.
+    BadSyncWord,
+    /// The _bsid_ field had a value greater than 16, indicating a format this implementation
+    /// doesn't understand.
+    BadBitstreamId(u8),
+    /// The _fscod_ (or E-AC-3 _fscod2_) field had the reserved value `3`.
+    BadSampleRateCode(u8),
+    /// The AC-3 _frmsizecod_ field had a reserved value, or the E-AC-3 _frmsiz_ field gave a
+    /// length shorter than the header itself.
+    BadFrameSizeCode(u16),
+    /// The E-AC-3 _strmtyp_ field had the reserved value `3`.
+    BadStreamType(u8),
+}
+impl fmt::Display for Ac3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ac3Error::NotEnoughData => write!(f, "not enough data for AC-3 syncframe header"),
+            Ac3Error::BadSyncWord => write!(f, "AC-3 syncword not found"),
+            Ac3Error::BadBitstreamId(bsid) => write!(f, "unsupported bsid {}", bsid),
+            Ac3Error::BadSampleRateCode(code) => write!(f, "reserved fscod {}", code),
+            Ac3Error::BadFrameSizeCode(code) => write!(f, "invalid frame size code {}", code),
+            Ac3Error::BadStreamType(strmtyp) => write!(f, "reserved strmtyp {}", strmtyp),
+        }
+    }
+}
+impl std::error::Error for Ac3Error {}
+
+/// The kind of substream a syncframe belongs to, given by the E-AC-3 _strmtyp_ field.  AC-3
+/// syncframes are always `Independent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubstreamType {
+    /// A substream which may be decoded on its own.
+    Independent,
+    /// A substream carrying additional channels for the preceding independent substream.
+    Dependent,
+    /// An independent substream converted from AC-3, which may be converted back.
+    Ac3Convert,
+}
+
+/// The fields at the start of an AC-3 or E-AC-3 syncframe describing the format of the audio and
+/// the size of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncFrameHeader {
+    /// the _bsid_ field, which is `10` or less for AC-3, and between `11` and `16` for E-AC-3
+    pub bsid: u8,
+    /// the E-AC-3 _strmtyp_ field
+    pub substream_type: SubstreamType,
+    /// the E-AC-3 _substreamid_ field, or `0` for AC-3
+    pub substream_id: u8,
+    /// the _fscod_ field
+    pub fscod: u8,
+    /// the sample rate of the audio in Hz, which for E-AC-3 may instead be given by the
+    /// _fscod2_ field, and for AC-3 is reduced if _bsid_ is `9` or `10`
+    pub sample_rate: u32,
+    /// the AC-3 _frmsizecod_ field, or the E-AC-3 _frmsiz_ field
+    pub frame_size_code: u16,
+    /// the size of the whole syncframe in bytes
+    pub frame_size: u16,
+    /// the number of audio blocks in the syncframe, which is always `6` for AC-3
+    pub audio_blocks: u8,
+    /// the _acmod_ field, giving the arrangement of the full bandwidth channels
+    pub acmod: u8,
+    /// the _lfeon_ field, `true` if a low frequency effects channel is present
+    pub lfeon: bool,
+}
+impl SyncFrameHeader {
+    /// The size of the header fields that are parsed.
+    pub const SIZE: usize = 7;
+
+    /// Parse the header at the start of the given data.
+    pub fn from_bytes(data: &[u8]) -> Result<SyncFrameHeader, Ac3Error> {
+        if data.len() < 2 {
+            return Err(Ac3Error::NotEnoughData);
+        }
+        if data[0] != 0x0b || data[1] != 0x77 {
+            return Err(Ac3Error::BadSyncWord);
+        }
+        if data.len() < Self::SIZE {
+            return Err(Ac3Error::NotEnoughData);
+        }
+        // bsid is in the same place in both formats
+        let bsid = data[5] >> 3;
+        match bsid {
+            0..=10 => Self::ac3_from_bytes(bsid, data),
+            11..=16 => Self::eac3_from_bytes(bsid, data),
+            _ => Err(Ac3Error::BadBitstreamId(bsid)),
+        }
+    }
+
+    fn ac3_from_bytes(bsid: u8, data: &[u8]) -> Result<SyncFrameHeader, Ac3Error> {
+        let fscod = data[4] >> 6;
+        let rate = *SAMPLE_RATES
+            .get(usize::from(fscod))
+            .ok_or(Ac3Error::BadSampleRateCode(fscod))?;
+        let frmsizecod = data[4] & 0b11_1111;
+        let bit_rate = *BIT_RATES
+            .get(usize::from(frmsizecod >> 1))
+            .ok_or(Ac3Error::BadFrameSizeCode(u16::from(frmsizecod)))?;
+        // the number of 16-bit words needed for 1536 samples at the given bit rate, where at
+        // 44.1kHz the odd frmsizecod values add a word to make up the fractional part
+        let mut words = bit_rate * 96_000 / rate;
+        if fscod == 1 {
+            words += u32::from(frmsizecod & 1);
+        }
+        let acmod = data[6] >> 5;
+        // skip the optional cmixlev, surmixlev and dsurmod fields
+        let mut skip = 0;
+        if acmod & 1 != 0 && acmod != 1 {
+            skip += 2;
+        }
+        if acmod & 4 != 0 {
+            skip += 2;
+        }
+        if acmod == 2 {
+            skip += 2;
+        }
+        Ok(SyncFrameHeader {
+            bsid,
+            substream_type: SubstreamType::Independent,
+            substream_id: 0,
+            fscod,
+            // the 'half rate' and 'quarter rate' variants
+            sample_rate: rate >> bsid.saturating_sub(8),
+            frame_size_code: u16::from(frmsizecod),
+            frame_size: (words * 2) as u16,
+            audio_blocks: 6,
+            acmod,
+            lfeon: data[6] >> (4 - skip) & 1 == 1,
+        })
+    }
+
+    fn eac3_from_bytes(bsid: u8, data: &[u8]) -> Result<SyncFrameHeader, Ac3Error> {
+        let substream_type = match data[2] >> 6 {
+            0 => SubstreamType::Independent,
+            1 => SubstreamType::Dependent,
+            2 => SubstreamType::Ac3Convert,
+            strmtyp => return Err(Ac3Error::BadStreamType(strmtyp)),
+        };
+        let frmsiz = u16::from(data[2] & 0b111) << 8 | u16::from(data[3]);
+        let frame_size = (frmsiz + 1) * 2;
+        if usize::from(frame_size) < Self::SIZE {
+            return Err(Ac3Error::BadFrameSizeCode(frmsiz));
+        }
+        let fscod = data[4] >> 6;
+        let (sample_rate, audio_blocks) = if fscod == 3 {
+            let fscod2 = data[4] >> 4 & 0b11;
+            let rate = *REDUCED_SAMPLE_RATES
+                .get(usize::from(fscod2))
+                .ok_or(Ac3Error::BadSampleRateCode(fscod2))?;
+            (rate, 6)
+        } else {
+            let numblkscod = data[4] >> 4 & 0b11;
+            (
+                SAMPLE_RATES[usize::from(fscod)],
+                AUDIO_BLOCKS[usize::from(numblkscod)],
+            )
+        };
+        Ok(SyncFrameHeader {
+            bsid,
+            substream_type,
+            substream_id: data[2] >> 3 & 0b111,
+            fscod,
+            sample_rate,
+            frame_size_code: frmsiz,
+            frame_size,
+            audio_blocks,
+            acmod: data[4] >> 1 & 0b111,
+            lfeon: data[4] & 1 == 1,
+        })
+    }
+
+    /// `true` if this is an E-AC-3 syncframe, rather than AC-3.
+    pub fn is_eac3(&self) -> bool {
+        self.bsid > 10
+    }
+
+    /// The number of channels in the syncframe, including the LFE channel, if any.
+    pub fn channels(&self) -> u8 {
+        const FULL_BANDWIDTH_CHANNELS: [u8; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+        FULL_BANDWIDTH_CHANNELS[usize::from(self.acmod)] + u8::from(self.lfeon)
+    }
+
+    /// The number of PCM samples per channel decoded from this syncframe.
+    pub fn samples(&self) -> u32 {
+        SAMPLES_PER_AUDIO_BLOCK * u32::from(self.audio_blocks)
+    }
+
+    /// `true` if this syncframe begins a new period of audio, rather than carrying further
+    /// substreams for the same period as the previous syncframe.
+    fn starts_period(&self) -> bool {
+        self.substream_type != SubstreamType::Dependent && self.substream_id == 0
+    }
+}
+
+/// A complete AC-3 or E-AC-3 syncframe, as produced by [`Ac3Parser`](struct.Ac3Parser.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ac3Frame {
+    header: SyncFrameHeader,
+    data: Vec<u8>,
+    pts: Option<Timestamp>,
+}
+impl Ac3Frame {
+    /// The header of this syncframe.
+    pub fn header(&self) -> &SyncFrameHeader {
+        &self.header
+    }
+
+    /// The whole syncframe, including its header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The presentation time of this syncframe.  This is the PTS of the PES packet for the
+    /// first independent syncframe starting within a packet, and is interpolated from the
+    /// number of samples in the preceding syncframes otherwise.  Dependent substreams, and
+    /// independent substreams other than the first, share the PTS of the syncframe they follow.
+    /// `None` if no PES packet so far in the stream had a PTS.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The duration of this syncframe, in 90kHz units.
+    pub fn duration(&self) -> u64 {
+        u64::from(self.header.samples()) * Timestamp::TIMEBASE / u64::from(self.header.sample_rate)
+    }
+}
+
+/// Splits the payloads of the PES packets of an AC-3 or E-AC-3 stream into
+/// [`Ac3Frame`](struct.Ac3Frame.html)s, which can be retrieved with `drain_frames()`.
+///
+/// Data that does not form a valid syncframe header is skipped until the next _syncword_.
+#[derive(Debug, Default)]
+pub struct Ac3Parser {
+    buf: Vec<u8>,
+    /// the position in the stream of the first byte of `buf`
+    buf_position: u64,
+    clock: AudioClock,
+    /// the PTS of the last syncframe which started a new period of audio
+    period_pts: Option<Timestamp>,
+    frames: Vec<Ac3Frame>,
+}
+impl Ac3Parser {
+    /// Create a parser with no data.
+    pub fn new() -> Ac3Parser {
+        Ac3Parser::default()
+    }
+
+    /// Start a new PES packet, with the PTS from its header and the initial part of its
+    /// payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, payload: &[u8]) {
+        self.clock
+            .packet(self.buf_position + self.buf.len() as u64, pts);
+        self.continue_packet(payload);
+    }
+
+    /// Add more of the payload of the current PES packet.
+    pub fn continue_packet(&mut self, payload: &[u8]) {
+        self.buf.extend_from_slice(payload);
+        self.parse();
+    }
+
+    /// Signal the end of the current PES packet.  Since syncframes may span PES packets, this
+    /// has no effect on the data buffered so far.
+    pub fn end_packet(&mut self) {}
+
+    /// Discard any incomplete syncframe at the end of the stream.
+    pub fn flush(&mut self) {
+        self.buf_position += self.buf.len() as u64;
+        self.buf.clear();
+    }
+
+    /// Removes and returns the syncframes found so far.
+    pub fn drain_frames(&mut self) -> impl Iterator<Item = Ac3Frame> + '_ {
+        self.frames.drain(..)
+    }
+
+    fn parse(&mut self) {
+        let mut pos = 0;
+        while pos < self.buf.len() {
+            let header = match SyncFrameHeader::from_bytes(&self.buf[pos..]) {
+                Ok(header) => header,
+                Err(Ac3Error::NotEnoughData) => break,
+                Err(e) => {
+                    if e != Ac3Error::BadSyncWord {
+                        trace!("skipping invalid AC-3 syncframe header: {}", e);
+                    }
+                    pos += 1;
+                    continue;
+                }
+            };
+            let end = pos + usize::from(header.frame_size);
+            if end > self.buf.len() {
+                break;
+            }
+            if header.starts_period() {
+                self.period_pts = self.clock.frame(
+                    self.buf_position + pos as u64,
+                    header.samples(),
+                    header.sample_rate,
+                );
+            }
+            self.frames.push(Ac3Frame {
+                header,
+                data: self.buf[pos..end].to_vec(),
+                pts: self.period_pts,
+            });
+            pos = end;
+        }
+        self.buf.drain(..pos);
+        self.buf_position += pos as u64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    /// Build a syncframe of the given size starting with the given header fields.
+    fn frame(header: &[u8], size: usize) -> Vec<u8> {
+        let mut data = vec![0; size];
+        data[..header.len()].copy_from_slice(header);
+        data
+    }
+
+    #[test]
+    fn ac3_header() {
+        // 48kHz, 32 kbit/s, 3/2 channels with LFE
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 0000 00 40 e1")).unwrap();
+        assert_eq!(
+            header,
+            SyncFrameHeader {
+                bsid: 8,
+                substream_type: SubstreamType::Independent,
+                substream_id: 0,
+                fscod: 0,
+                sample_rate: 48000,
+                frame_size_code: 0,
+                frame_size: 128,
+                audio_blocks: 6,
+                acmod: 7,
+                lfeon: true,
+            }
+        );
+        assert!(!header.is_eac3());
+        assert_eq!(header.channels(), 6);
+        assert_eq!(header.samples(), 1536);
+
+        // 44.1kHz, 640 kbit/s, with the extra word, 2/0 channels with dsurmod then lfeon
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 0000 65 40 44")).unwrap();
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.frame_size, 1394 * 2);
+        assert_eq!(header.acmod, 2);
+        assert!(header.lfeon);
+        assert_eq!(header.channels(), 3);
+        // 32kHz, 448 kbit/s
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 0000 9e 40 40")).unwrap();
+        assert_eq!(header.sample_rate, 32000);
+        assert_eq!(header.frame_size, 1344 * 2);
+
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 0000 00 40")),
+            Err(Ac3Error::NotEnoughData)
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b78 0000 00 40 e1")),
+            Err(Ac3Error::BadSyncWord)
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 0000 c0 40 e1")),
+            Err(Ac3Error::BadSampleRateCode(3))
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 0000 26 40 e1")),
+            Err(Ac3Error::BadFrameSizeCode(38))
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 0000 00 88 e1")),
+            Err(Ac3Error::BadBitstreamId(17))
+        );
+    }
+
+    #[test]
+    fn eac3_header() {
+        // independent substream, 64 bytes, 48kHz, 6 blocks, 3/2 channels with LFE
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 001f 3f 80 00")).unwrap();
+        assert_eq!(
+            header,
+            SyncFrameHeader {
+                bsid: 16,
+                substream_type: SubstreamType::Independent,
+                substream_id: 0,
+                fscod: 0,
+                sample_rate: 48000,
+                frame_size_code: 31,
+                frame_size: 64,
+                audio_blocks: 6,
+                acmod: 7,
+                lfeon: true,
+            }
+        );
+        assert!(header.is_eac3());
+        assert_eq!(header.channels(), 6);
+
+        // dependent substream 1, 24kHz (via fscod2), 2/0 channels
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 481f c4 80 00")).unwrap();
+        assert_eq!(header.substream_type, SubstreamType::Dependent);
+        assert_eq!(header.substream_id, 1);
+        assert_eq!(header.sample_rate, 24000);
+        assert_eq!(header.samples(), 1536);
+        assert_eq!(header.channels(), 2);
+
+        // two blocks per frame
+        let header = SyncFrameHeader::from_bytes(&hex!("0b77 001f 1f 80 00")).unwrap();
+        assert_eq!(header.samples(), 512);
+
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 c01f 3f 80 00")),
+            Err(Ac3Error::BadStreamType(3))
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 0002 3f 80 00")),
+            Err(Ac3Error::BadFrameSizeCode(2))
+        );
+        assert_eq!(
+            SyncFrameHeader::from_bytes(&hex!("0b77 001f f4 80 00")),
+            Err(Ac3Error::BadSampleRateCode(3))
+        );
+    }
+
+    #[test]
+    fn dependent_substreams() {
+        let independent = frame(&hex!("0b77 001f 3f 80 00"), 64);
+        let dependent = frame(&hex!("0b77 401f 34 80 00"), 64);
+        let period = [&independent[..], &dependent[..]].concat();
+        let data = period.repeat(3);
+        let mut parser = Ac3Parser::new();
+        // junk, then a packet boundary part way through the second period
+        parser.begin_packet(Some(Timestamp::from_u64(1000)), &hex!("0b"));
+        parser.continue_packet(&data[..150]);
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::from_u64(7000)), &data[150..]);
+        parser.end_packet();
+        parser.continue_packet(&independent[..10]);
+        parser.flush();
+        let frames: Vec<_> = parser.drain_frames().collect();
+        let pts: Vec<_> = frames.iter().map(|f| f.pts().unwrap().value()).collect();
+        assert_eq!(pts, [1000, 1000, 3880, 3880, 7000, 7000]);
+        assert_eq!(frames[1].header().substream_type, SubstreamType::Dependent);
+        assert_eq!(frames[1].data(), &dependent[..]);
+        assert_eq!(frames[0].duration(), 2880);
+    }
+}
@@ -0,0 +1,496 @@
+//! Splitting of AAC audio in _Audio Data Transport Stream_ format (`StreamType::Adts`) into
+//! frames, per _ISO/IEC 13818-7_.
+//!
+//! Each ADTS frame begins with a header giving the format of the audio and the length of the
+//! frame.  An [`AdtsParser`](struct.AdtsParser.html) finds these headers in the payloads of PES
+//! packets, reassembling frames which span PES packet boundaries, and gives each frame a PTS.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::adts::AdtsParser;
+//! # use mpeg2ts_reader::pes::Timestamp;
+//! // two AAC-LC 48kHz stereo frames
+//! let frame = [0xff, 0xf1, 0x4c, 0x80, 0x01, 0x3f, 0xfc, 0x21, 0x12];
+//! let mut parser = AdtsParser::new();
+//! parser.begin_packet(Some(Timestamp::from_u64(90_000)), &frame);
+//! parser.continue_packet(&frame);
+//! parser.end_packet();
+//! let pts: Vec<_> = parser.drain_frames().map(|f| f.pts().unwrap().value()).collect();
+//! // 1024 samples at 48kHz is 1920 ticks of the 90kHz clock
+//! assert_eq!(pts, [90_000, 91_920]);
+//! ```
+
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+/// The sample rates indicated by each value of _sampling_frequency_index_ (larger values are
+/// reserved or escape values).
+pub const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+/// The number of PCM samples decoded from each raw data block (i.e. AAC frame).
+pub const SAMPLES_PER_RAW_DATA_BLOCK: u32 = 1024;
+
+/// Problems which may be found in an ADTS header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdtsError {
+    /// Fewer than the 7 (or with a CRC, 9) bytes of the header were available.
+    NotEnoughData,
+    /// The data did not start with the 12-bit _syncword_ `0xfff`.
+    BadSyncWord,
+    /// The _layer_ field had a value other than `0`.
+    BadLayer(u8),
+    /// The _sampling_frequency_index_ field had a reserved value.
+    BadSamplingFrequencyIndex(u8),
+    /// The _frame_length_ field gave a length shorter than the header itself.
+    BadFrameLength(u16),
+}
+impl fmt::Display for AdtsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdtsError::NotEnoughData => write!(f, "not enough data for ADTS header"),
+            AdtsError::BadSyncWord => write!(f, "ADTS syncword not found"),
+            AdtsError::BadLayer(layer) => write!(f, "invalid ADTS layer {}", layer),
+            AdtsError::BadSamplingFrequencyIndex(idx) => {
+                write!(f, "reserved sampling_frequency_index {}", idx)
+            }
+            AdtsError::BadFrameLength(len) => write!(f, "invalid ADTS frame_length {}", len),
+        }
+    }
+}
+impl std::error::Error for AdtsError {}
+
+/// The fixed and variable header fields at the start of each ADTS frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// the _ID_ field; `true` for MPEG-2 AAC, `false` for MPEG-4
+    pub mpeg2: bool,
+    /// `false` if the _protection_absent_ field indicates that a CRC follows the header
+    pub protection_absent: bool,
+    /// the _profile_ field, which is one less than the MPEG-4 _audioObjectType_ (so `1` is
+    /// AAC-LC)
+    pub profile: u8,
+    /// the _sampling_frequency_index_ field
+    pub sampling_frequency_index: u8,
+    /// the _private_bit_ field
+    pub private_bit: bool,
+    /// the _channel_configuration_ field, or `0` if the configuration is given in-band
+    pub channel_configuration: u8,
+    /// the _original_copy_ field
+    pub original_copy: bool,
+    /// the _home_ field
+    pub home: bool,
+    /// the _copyright_identification_bit_ field
+    pub copyright_identification_bit: bool,
+    /// the _copyright_identification_start_ field
+    pub copyright_identification_start: bool,
+    /// the _frame_length_ field, the size of the whole frame including this header
+    pub frame_length: u16,
+    /// the _adts_buffer_fullness_ field, where `0x7ff` indicates a variable rate stream
+    pub adts_buffer_fullness: u16,
+    /// the _number_of_raw_data_blocks_in_frame_ field, one less than the number of AAC frames
+    pub number_of_raw_data_blocks_in_frame: u8,
+}
+impl AdtsHeader {
+    /// The size of a header without a CRC.
+    pub const MIN_SIZE: usize = 7;
+
+    /// Parse the header at the start of the given data.
+    pub fn from_bytes(data: &[u8]) -> Result<AdtsHeader, AdtsError> {
+        if data.len() < 2 {
+            return Err(AdtsError::NotEnoughData);
+        }
+        if data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+            return Err(AdtsError::BadSyncWord);
+        }
+        let layer = data[1] >> 1 & 0b11;
+        if layer != 0 {
+            return Err(AdtsError::BadLayer(layer));
+        }
+        let protection_absent = data[1] & 1 == 1;
+        let size = if protection_absent { 7 } else { 9 };
+        if data.len() < size {
+            return Err(AdtsError::NotEnoughData);
+        }
+        let sampling_frequency_index = data[2] >> 2 & 0b1111;
+        if usize::from(sampling_frequency_index) >= SAMPLE_RATES.len() {
+            return Err(AdtsError::BadSamplingFrequencyIndex(
+                sampling_frequency_index,
+            ));
+        }
+        let frame_length =
+            u16::from(data[3] & 0b11) << 11 | u16::from(data[4]) << 3 | u16::from(data[5] >> 5);
+        if usize::from(frame_length) < size {
+            return Err(AdtsError::BadFrameLength(frame_length));
+        }
+        Ok(AdtsHeader {
+            mpeg2: data[1] & 0b1000 != 0,
+            protection_absent,
+            profile: data[2] >> 6,
+            sampling_frequency_index,
+            private_bit: data[2] & 0b10 != 0,
+            channel_configuration: (data[2] & 1) << 2 | data[3] >> 6,
+            original_copy: data[3] & 0b10_0000 != 0,
+            home: data[3] & 0b1_0000 != 0,
+            copyright_identification_bit: data[3] & 0b1000 != 0,
+            copyright_identification_start: data[3] & 0b100 != 0,
+            frame_length,
+            adts_buffer_fullness: u16::from(data[5] & 0b1_1111) << 6 | u16::from(data[6] >> 2),
+            number_of_raw_data_blocks_in_frame: data[6] & 0b11,
+        })
+    }
+
+    /// The encoding of this header, without any CRC that follows it.
+    pub fn to_bytes(&self) -> [u8; Self::MIN_SIZE] {
+        [
+            0xff,
+            0xf0 | u8::from(self.mpeg2) << 3 | u8::from(self.protection_absent),
+            self.profile << 6
+                | self.sampling_frequency_index << 2
+                | u8::from(self.private_bit) << 1
+                | self.channel_configuration >> 2,
+            (self.channel_configuration & 0b11) << 6
+                | u8::from(self.original_copy) << 5
+                | u8::from(self.home) << 4
+                | u8::from(self.copyright_identification_bit) << 3
+                | u8::from(self.copyright_identification_start) << 2
+                | (self.frame_length >> 11) as u8 & 0b11,
+            (self.frame_length >> 3) as u8,
+            (self.frame_length as u8 & 0b111) << 5 | (self.adts_buffer_fullness >> 6) as u8,
+            (self.adts_buffer_fullness as u8 & 0b11_1111) << 2
+                | self.number_of_raw_data_blocks_in_frame,
+        ]
+    }
+
+    /// The size of this header, which is larger if a CRC is present.
+    pub fn header_length(&self) -> usize {
+        if self.protection_absent {
+            Self::MIN_SIZE
+        } else {
+            Self::MIN_SIZE + 2
+        }
+    }
+
+    /// The MPEG-4 _audioObjectType_ of the audio (`2` for AAC-LC).
+    pub fn audio_object_type(&self) -> u8 {
+        self.profile + 1
+    }
+
+    /// The sample rate of the audio, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[usize::from(self.sampling_frequency_index)]
+    }
+
+    /// The number of PCM samples decoded from this frame.
+    pub fn samples(&self) -> u32 {
+        SAMPLES_PER_RAW_DATA_BLOCK * (u32::from(self.number_of_raw_data_blocks_in_frame) + 1)
+    }
+
+    /// The two byte MPEG-4 `AudioSpecificConfig` describing this audio, as needed to decode it
+    /// from a container other than ADTS (e.g. MP4).
+    pub fn audio_specific_config(&self) -> [u8; 2] {
+        let val = u16::from(self.audio_object_type()) << 11
+            | u16::from(self.sampling_frequency_index) << 7
+            | u16::from(self.channel_configuration) << 3;
+        val.to_be_bytes()
+    }
+}
+
+/// A complete ADTS frame, as produced by [`AdtsParser`](struct.AdtsParser.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdtsFrame {
+    header: AdtsHeader,
+    data: Vec<u8>,
+    pts: Option<Timestamp>,
+}
+impl AdtsFrame {
+    /// Build a frame from the given header, which must not call for a CRC, and raw AAC data.
+    /// The header's `frame_length` is set to match.
+    pub(crate) fn from_payload(
+        mut header: AdtsHeader,
+        payload: &[u8],
+        pts: Option<Timestamp>,
+    ) -> AdtsFrame {
+        debug_assert!(header.protection_absent);
+        header.frame_length = (AdtsHeader::MIN_SIZE + payload.len()) as u16;
+        let mut data = Vec::with_capacity(usize::from(header.frame_length));
+        data.extend_from_slice(&header.to_bytes());
+        data.extend_from_slice(payload);
+        AdtsFrame { header, data, pts }
+    }
+
+    /// The header of this frame.
+    pub fn header(&self) -> &AdtsHeader {
+        &self.header
+    }
+
+    /// The whole frame, including its header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The raw AAC data following the header (and CRC, if any).
+    pub fn payload(&self) -> &[u8] {
+        &self.data[self.header.header_length()..]
+    }
+
+    /// The presentation time of this frame.  This is the PTS of the PES packet for the first
+    /// frame starting within a packet, and is interpolated from the number of samples in the
+    /// preceding frames otherwise.  `None` if no PES packet so far in the stream had a PTS.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The duration of this frame, in 90kHz units.
+    pub fn duration(&self) -> u64 {
+        u64::from(self.header.samples()) * Timestamp::TIMEBASE
+            / u64::from(self.header.sample_rate())
+    }
+}
+
+/// Gives PTS values to the frames of an audio stream, using the PTS of a PES packet for the
+/// first frame starting within that packet, and adding up the durations of the frames since
+/// for the others.
+#[derive(Debug, Default)]
+pub(crate) struct AudioClock {
+    /// the PTS of a PES packet not yet given to a frame, and the position in the stream where
+    /// the packet's data starts
+    pending: Option<(u64, Timestamp)>,
+    /// the last PTS given by a PES packet, and the number of samples since, at the given rate
+    anchor: Option<(Timestamp, u64, u32)>,
+}
+impl AudioClock {
+    /// Record the PTS (if any) of a PES packet whose data will start at the given position.
+    pub(crate) fn packet(&mut self, position: u64, pts: Option<Timestamp>) {
+        self.pending = pts.map(|pts| (position, pts));
+    }
+
+    /// The PTS of a frame starting at the given position, which lasts for the given number of
+    /// samples at the given sample rate.
+    pub(crate) fn frame(&mut self, position: u64, samples: u32, rate: u32) -> Option<Timestamp> {
+        match self.pending {
+            Some((start, pts)) if position >= start => {
+                self.pending = None;
+                self.anchor = Some((pts, 0, rate));
+            }
+            _ => {
+                // re-anchor at the current time if the rate changes, so that the time so far
+                // is not rescaled
+                if let Some((_, _, anchor_rate)) = self.anchor {
+                    if anchor_rate != rate {
+                        let now = self.now()?;
+                        self.anchor = Some((now, 0, rate));
+                    }
+                }
+            }
+        }
+        let pts = self.now();
+        if let Some((_, ref mut elapsed, _)) = self.anchor {
+            *elapsed += u64::from(samples);
+        }
+        pts
+    }
+
+    fn now(&self) -> Option<Timestamp> {
+        let (pts, elapsed, rate) = self.anchor?;
+        let ticks = elapsed * Timestamp::TIMEBASE / u64::from(rate);
+        Some(Timestamp::from_u64(
+            (pts.value() + ticks) & Timestamp::MAX.value(),
+        ))
+    }
+}
+
+/// Splits the payloads of the PES packets of an ADTS stream into
+/// [`AdtsFrame`](struct.AdtsFrame.html)s, which can be retrieved with `drain_frames()`.
+///
+/// Data that does not form a valid frame header is skipped until the next _syncword_.
+#[derive(Debug, Default)]
+pub struct AdtsParser {
+    buf: Vec<u8>,
+    /// the position in the stream of the first byte of `buf`
+    buf_position: u64,
+    clock: AudioClock,
+    frames: Vec<AdtsFrame>,
+}
+impl AdtsParser {
+    /// Create a parser with no data.
+    pub fn new() -> AdtsParser {
+        AdtsParser::default()
+    }
+
+    /// Start a new PES packet, with the PTS from its header and the initial part of its
+    /// payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, payload: &[u8]) {
+        self.clock
+            .packet(self.buf_position + self.buf.len() as u64, pts);
+        self.continue_packet(payload);
+    }
+
+    /// Add more of the payload of the current PES packet.
+    pub fn continue_packet(&mut self, payload: &[u8]) {
+        self.buf.extend_from_slice(payload);
+        self.parse();
+    }
+
+    /// Signal the end of the current PES packet.  Since frames may span PES packets, this has
+    /// no effect on the data buffered so far.
+    pub fn end_packet(&mut self) {}
+
+    /// Discard any incomplete frame at the end of the stream.
+    pub fn flush(&mut self) {
+        self.buf_position += self.buf.len() as u64;
+        self.buf.clear();
+    }
+
+    /// Removes and returns the frames found so far.
+    pub fn drain_frames(&mut self) -> impl Iterator<Item = AdtsFrame> + '_ {
+        self.frames.drain(..)
+    }
+
+    fn parse(&mut self) {
+        let mut pos = 0;
+        while pos < self.buf.len() {
+            let header = match AdtsHeader::from_bytes(&self.buf[pos..]) {
+                Ok(header) => header,
+                Err(AdtsError::NotEnoughData) => break,
+                Err(e) => {
+                    if e != AdtsError::BadSyncWord {
+                        trace!("skipping invalid ADTS header: {}", e);
+                    }
+                    pos += 1;
+                    continue;
+                }
+            };
+            let end = pos + usize::from(header.frame_length);
+            if end > self.buf.len() {
+                break;
+            }
+            let pts = self.clock.frame(
+                self.buf_position + pos as u64,
+                header.samples(),
+                header.sample_rate(),
+            );
+            self.frames.push(AdtsFrame {
+                header,
+                data: self.buf[pos..end].to_vec(),
+                pts,
+            });
+            pos = end;
+        }
+        self.buf.drain(..pos);
+        self.buf_position += pos as u64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    /// AAC-LC, 48kHz, stereo, with a two byte payload
+    const FRAME: [u8; 9] = hex!("fff14c80 013ffc 2112");
+
+    #[test]
+    fn header() {
+        let header = AdtsHeader::from_bytes(&FRAME).unwrap();
+        assert_eq!(
+            header,
+            AdtsHeader {
+                mpeg2: false,
+                protection_absent: true,
+                profile: 1,
+                sampling_frequency_index: 3,
+                private_bit: false,
+                channel_configuration: 2,
+                original_copy: false,
+                home: false,
+                copyright_identification_bit: false,
+                copyright_identification_start: false,
+                frame_length: 9,
+                adts_buffer_fullness: 0x7ff,
+                number_of_raw_data_blocks_in_frame: 0,
+            }
+        );
+        assert_eq!(header.header_length(), 7);
+        assert_eq!(header.audio_object_type(), 2);
+        assert_eq!(header.sample_rate(), 48000);
+        assert_eq!(header.audio_specific_config(), hex!("1190"));
+        assert_eq!(header.to_bytes(), FRAME[..7]);
+
+        // MPEG-2, HE-AAC (signalled as AAC-LC at half the rate) 24kHz mono with CRC, and two
+        // raw data blocks
+        let header = AdtsHeader::from_bytes(&hex!("fff8 5840 017f fd 1234")).unwrap();
+        assert!(header.mpeg2);
+        assert!(!header.protection_absent);
+        assert_eq!(header.header_length(), 9);
+        assert_eq!(header.sample_rate(), 24000);
+        assert_eq!(header.channel_configuration, 1);
+        assert_eq!(header.samples(), 2048);
+        assert_eq!(header.to_bytes(), hex!("fff8 5840 017f fd"));
+
+        assert_eq!(
+            AdtsHeader::from_bytes(&FRAME[..6]),
+            Err(AdtsError::NotEnoughData)
+        );
+        assert_eq!(
+            AdtsHeader::from_bytes(&hex!("fff8 5840 017f fd")),
+            Err(AdtsError::NotEnoughData)
+        );
+        assert_eq!(
+            AdtsHeader::from_bytes(&hex!("ffe1 4c80 013ffc")),
+            Err(AdtsError::BadSyncWord)
+        );
+        assert_eq!(
+            AdtsHeader::from_bytes(&hex!("fff3 4c80 013ffc")),
+            Err(AdtsError::BadLayer(1))
+        );
+        assert_eq!(
+            AdtsHeader::from_bytes(&hex!("fff1 7480 013ffc")),
+            Err(AdtsError::BadSamplingFrequencyIndex(13))
+        );
+        assert_eq!(
+            AdtsHeader::from_bytes(&hex!("fff1 4c80 00dffc")),
+            Err(AdtsError::BadFrameLength(6))
+        );
+    }
+
+    #[test]
+    fn split_across_packets() {
+        let mut parser = AdtsParser::new();
+        let data = FRAME.repeat(3);
+        // the first packet ends part way through the second frame, so the PTS of the next
+        // packet belongs to the third frame
+        parser.begin_packet(Some(Timestamp::from_u64(1000)), &data[..12]);
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::from_u64(5000)), &data[12..14]);
+        parser.continue_packet(&data[14..]);
+        parser.end_packet();
+        // a packet without a PTS, starting with junk
+        parser.begin_packet(None, &hex!("00ff"));
+        parser.continue_packet(&FRAME);
+        parser.continue_packet(&FRAME[..5]);
+        parser.end_packet();
+        parser.flush();
+        let frames: Vec<_> = parser.drain_frames().collect();
+        let pts: Vec<_> = frames.iter().map(|f| f.pts().unwrap().value()).collect();
+        assert_eq!(pts, [1000, 2920, 5000, 6920]);
+        assert!(frames.iter().all(|f| f.data() == FRAME));
+        assert_eq!(frames[0].payload(), hex!("2112"));
+        assert_eq!(frames[0].duration(), 1920);
+    }
+
+    #[test]
+    fn no_pts() {
+        let mut parser = AdtsParser::new();
+        parser.begin_packet(None, &FRAME);
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::MAX), &FRAME.repeat(2));
+        parser.end_packet();
+        let pts: Vec<_> = parser.drain_frames().map(|f| f.pts()).collect();
+        // interpolated timestamps wrap around
+        assert_eq!(
+            pts,
+            [None, Some(Timestamp::MAX), Some(Timestamp::from_u64(1919))]
+        );
+    }
+}
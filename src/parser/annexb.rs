@@ -0,0 +1,216 @@
+//! Splitting of _Annex B_ byte streams (as carried in the PES packets of H.264 and H.265 streams)
+//! into individual NAL units.
+//!
+//! Per _ITU-T H.264 Annex B_, each NAL unit is preceded by the 3-byte start code prefix
+//! `00 00 01`, which may itself be preceded by any number of zero bytes (the `zero_byte` of a
+//! 4-byte start code, or `trailing_zero_8bits` following the previous NAL unit).  None of these
+//! zero bytes are part of any NAL unit.
+//!
+//! [`AnnexBSplitter`](struct.AnnexBSplitter.html) accepts the byte stream a piece at a time, so
+//! start codes split between two calls to `push()` are still found.
+
+use std::ops::Range;
+
+/// A single NAL unit, including its header byte(s) but not the preceding start code.
+///
+/// The accessors for header fields assume the one-byte H.264 NAL unit header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NalUnit<'buf> {
+    data: &'buf [u8],
+}
+impl<'buf> NalUnit<'buf> {
+    /// Wrap the given NAL unit data, which must be at least one byte long.
+    pub fn new(data: &'buf [u8]) -> NalUnit<'buf> {
+        assert!(!data.is_empty());
+        NalUnit { data }
+    }
+
+    /// The complete data of the NAL unit, starting with the NAL unit header.
+    pub fn data(&self) -> &'buf [u8] {
+        self.data
+    }
+
+    /// The `nal_ref_idc` field of the H.264 NAL unit header; non-zero if the NAL unit contains
+    /// data used in decoding other pictures.
+    pub fn nal_ref_idc(&self) -> u8 {
+        self.data[0] >> 5 & 0b11
+    }
+
+    /// The `nal_unit_type` field of the H.264 NAL unit header.
+    pub fn nal_unit_type(&self) -> u8 {
+        self.data[0] & 0b1_1111
+    }
+}
+impl<'buf> std::fmt::Debug for NalUnit<'buf> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NalUnit")
+            .field("nal_ref_idc", &self.nal_ref_idc())
+            .field("nal_unit_type", &self.nal_unit_type())
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+/// Incrementally locates the NAL units within an Annex B byte stream.
+///
+/// Data is accumulated with `push()` and scanned for start codes as it arrives.  A NAL unit is
+/// only known to be complete once the start code of the following NAL unit is seen, or
+/// `finish()` is called to signal the end of the data.
+///
+/// ```
+/// # use mpeg2ts_reader::parser::annexb::AnnexBSplitter;
+/// let mut splitter = AnnexBSplitter::new();
+/// splitter.push(&[0, 0, 0, 1, 0x09, 0xf0, 0, 0]);
+/// splitter.push(&[1, 0x65, 0x88, 0x84]);
+/// splitter.finish();
+/// let types: Vec<u8> = splitter.units().map(|nal| nal.nal_unit_type()).collect();
+/// assert_eq!(types, [9, 5]);
+/// ```
+#[derive(Debug, Default)]
+pub struct AnnexBSplitter {
+    buf: Vec<u8>,
+    /// the position in `buf` of the next byte to be scanned
+    scan_pos: usize,
+    /// the number of consecutive zero bytes immediately before `scan_pos`
+    zero_count: usize,
+    /// the position of the first byte of the NAL unit currently being scanned, if a start code
+    /// has been found
+    unit_start: Option<usize>,
+    units: Vec<Range<usize>>,
+}
+impl AnnexBSplitter {
+    /// Create a splitter with no data.
+    pub fn new() -> AnnexBSplitter {
+        AnnexBSplitter::default()
+    }
+
+    /// Discard all data and NAL units, ready to start splitting a new piece of the byte stream.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.scan_pos = 0;
+        self.zero_count = 0;
+        self.unit_start = None;
+        self.units.clear();
+    }
+
+    /// Append the given data and scan it for start codes.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        while self.scan_pos < self.buf.len() {
+            let b = self.buf[self.scan_pos];
+            if b == 0 {
+                self.zero_count += 1;
+            } else {
+                if b == 1 && self.zero_count >= 2 {
+                    let prefix_start = self.scan_pos - self.zero_count;
+                    self.end_unit(prefix_start);
+                    self.unit_start = Some(self.scan_pos + 1);
+                }
+                self.zero_count = 0;
+            }
+            self.scan_pos += 1;
+        }
+    }
+
+    /// Signal that no more data follows, so that the final NAL unit is complete.  Any zero bytes
+    /// at the end of the data are taken to be `trailing_zero_8bits` rather than part of the NAL
+    /// unit.
+    pub fn finish(&mut self) {
+        let end = self.buf.len() - self.zero_count;
+        self.end_unit(end);
+        self.unit_start = None;
+    }
+
+    fn end_unit(&mut self, end: usize) {
+        if let Some(start) = self.unit_start {
+            // a start code immediately followed by another does not delimit a NAL unit
+            if end > start {
+                self.units.push(start..end);
+            }
+        }
+    }
+
+    /// All the data pushed since construction, or the last call to `clear()`.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Iterate over the complete NAL units found so far.
+    pub fn units(&self) -> impl Iterator<Item = NalUnit<'_>> {
+        self.units
+            .iter()
+            .map(move |r| NalUnit::new(&self.buf[r.clone()]))
+    }
+
+    /// The positions within `buffer()` of the complete NAL units found so far.
+    pub fn unit_ranges(&self) -> &[Range<usize>] {
+        &self.units
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    fn split_all(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+        let mut splitter = AnnexBSplitter::new();
+        for chunk in data.chunks(chunk_size) {
+            splitter.push(chunk);
+        }
+        splitter.finish();
+        splitter.units().map(|nal| nal.data().to_vec()).collect()
+    }
+
+    #[test]
+    fn start_code_lengths() {
+        // 4-byte start code, 3-byte start code, trailing_zero_8bits, and an empty NAL unit
+        let data = hex!("00000001 0910 000001 6742 0000 00000001 68ce 000001 000001 65888480 0000");
+        let expected = vec![
+            hex!("0910").to_vec(),
+            hex!("6742").to_vec(),
+            hex!("68ce").to_vec(),
+            hex!("65888480").to_vec(),
+        ];
+        for chunk_size in 1..=data.len() {
+            assert_eq!(
+                split_all(&data, chunk_size),
+                expected,
+                "chunk_size={}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn header_fields() {
+        let nal = NalUnit::new(&[0x65, 0x88]);
+        assert_eq!(nal.nal_ref_idc(), 3);
+        assert_eq!(nal.nal_unit_type(), 5);
+        let nal = NalUnit::new(&[0x21, 0x9a]);
+        assert_eq!(nal.nal_ref_idc(), 1);
+        assert_eq!(nal.nal_unit_type(), 1);
+    }
+
+    #[test]
+    fn leading_garbage() {
+        // data before the first start code is not part of any NAL unit
+        assert_eq!(
+            split_all(&hex!("ff0000 000001 0910"), 3),
+            vec![vec![0x09, 0x10]]
+        );
+    }
+
+    #[test]
+    fn incomplete_until_finished() {
+        let mut splitter = AnnexBSplitter::new();
+        splitter.push(&hex!("000001 0910 000001 4101"));
+        assert_eq!(splitter.units().count(), 1);
+        splitter.finish();
+        assert_eq!(splitter.units().count(), 2);
+        assert_eq!(splitter.unit_ranges(), &[3..5, 8..10]);
+        splitter.clear();
+        assert_eq!(splitter.units().count(), 0);
+        assert!(splitter.buffer().is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+//! Conversion between the _Annex B_ byte stream format, in which NAL units are delimited by
+//! start codes, and the length-prefixed format used by MP4 files (often called 'AVCC'), along
+//! with builders for the `AVCDecoderConfigurationRecord` (`avcC`) and
+//! `HEVCDecoderConfigurationRecord` (`hvcC`) structures that describe such streams, per
+//! _ISO/IEC 14496-15_.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::avcc::{annexb_to_avcc, avcc_to_annexb};
+//! let annexb = [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65, 0x88, 0x84];
+//! let avcc = annexb_to_avcc(&annexb);
+//! assert_eq!(avcc, [0, 0, 0, 2, 0x09, 0xf0, 0, 0, 0, 3, 0x65, 0x88, 0x84]);
+//! assert_eq!(avcc_to_annexb(&avcc, 4).unwrap()[4..6], [0x09, 0xf0]);
+//! ```
+
+use crate::parser::annexb::AnnexBSplitter;
+use crate::parser::h264::{self, ParamSetError};
+use crate::parser::hevc;
+use std::fmt;
+
+/// The size of the NAL unit length prefixes written by `annexb_to_avcc()`, and signalled by the
+/// configuration records built here.
+pub const LENGTH_SIZE: usize = 4;
+
+/// Problems which may be found while converting NAL units, or building configuration records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AvccError {
+    /// A NAL unit length prefix gave a length greater than that of the remaining data.
+    Truncated,
+    /// No parameter set NAL unit of the kind named was supplied, but at least one is needed.
+    MissingParamSet(&'static str),
+    /// The first Sequence Parameter Set could not be decoded.
+    ParamSet(ParamSetError),
+    /// A parameter set was too long to have its length recorded in 16 bits, or there were too
+    /// many parameter sets of one kind.
+    TooLarge,
+}
+impl From<ParamSetError> for AvccError {
+    fn from(e: ParamSetError) -> Self {
+        AvccError::ParamSet(e)
+    }
+}
+impl fmt::Display for AvccError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AvccError::Truncated => write!(f, "NAL unit length exceeds the data remaining"),
+            AvccError::MissingParamSet(kind) => write!(f, "no {} given", kind),
+            AvccError::ParamSet(e) => write!(f, "problem decoding SPS: {}", e),
+            AvccError::TooLarge => write!(f, "parameter sets too large for record"),
+        }
+    }
+}
+impl std::error::Error for AvccError {}
+
+/// Converts the given Annex B data (containing one or more NAL units, each preceded by a start
+/// code) to a sequence of NAL units each preceded by a 4-byte big-endian length.
+///
+/// Any data before the first start code is taken to be a NAL unit too, so that the payload of
+/// a [`DemuxedSegment`](../struct.DemuxedSegment.html#method.payload), which begins with the
+/// first slice NAL unit rather than a start code, can be converted directly.
+pub fn annexb_to_avcc(data: &[u8]) -> Vec<u8> {
+    let mut splitter = AnnexBSplitter::new();
+    // a start code immediately followed by another delimits no NAL unit, so this is harmless if
+    // the data already begins with one
+    splitter.push(&[0, 0, 1]);
+    splitter.push(data);
+    splitter.finish();
+    let mut result = Vec::with_capacity(data.len() + LENGTH_SIZE);
+    for nal in splitter.units() {
+        result.extend_from_slice(&(nal.data().len() as u32).to_be_bytes());
+        result.extend_from_slice(nal.data());
+    }
+    result
+}
+
+/// Converts the given sequence of length-prefixed NAL units to Annex B format, in which each NAL
+/// unit is preceded by the 4-byte start code `00 00 00 01`.
+///
+/// The `length_size` of the prefixes must be 1, 2, 3 or 4 bytes.
+pub fn avcc_to_annexb(mut data: &[u8], length_size: usize) -> Result<Vec<u8>, AvccError> {
+    assert!((1..=4).contains(&length_size));
+    let mut result = Vec::with_capacity(data.len() + data.len() / 8);
+    while !data.is_empty() {
+        if data.len() < length_size {
+            return Err(AvccError::Truncated);
+        }
+        let (prefix, rest) = data.split_at(length_size);
+        let len = prefix
+            .iter()
+            .fold(0usize, |acc, &b| acc << 8 | usize::from(b));
+        if len > rest.len() {
+            return Err(AvccError::Truncated);
+        }
+        result.extend_from_slice(&[0, 0, 0, 1]);
+        result.extend_from_slice(&rest[..len]);
+        data = &rest[len..];
+    }
+    Ok(result)
+}
+
+/// Appends the given NAL units, each preceded by a 16-bit length.
+fn write_nal_units(out: &mut Vec<u8>, units: &[Vec<u8>]) -> Result<(), AvccError> {
+    for nal in units {
+        if nal.len() > usize::from(u16::MAX) {
+            return Err(AvccError::TooLarge);
+        }
+        out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    Ok(())
+}
+
+/// Builds an `AVCDecoderConfigurationRecord`, as carried in the `avcC` box of an MP4 sample
+/// entry, from H.264 SPS and PPS NAL units.
+///
+/// ```
+/// # use mpeg2ts_reader::parser::avcc::AvcConfigBuilder;
+/// # use hex_literal::hex;
+/// let avcc = AvcConfigBuilder::new()
+///     .sps(&hex!("6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654"))
+///     .pps(&hex!("68ce0fc8"))
+///     .build()
+///     .unwrap();
+/// assert_eq!(avcc[..6], hex!("0142c01f ffe1"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AvcConfigBuilder {
+    sps: Vec<Vec<u8>>,
+    pps: Vec<Vec<u8>>,
+}
+impl AvcConfigBuilder {
+    /// Create a builder with no parameter sets.
+    pub fn new() -> AvcConfigBuilder {
+        AvcConfigBuilder::default()
+    }
+
+    /// Add an SPS NAL unit (including its header byte).  The profile and level given in the
+    /// record are taken from the first SPS added.
+    pub fn sps(mut self, nal: &[u8]) -> Self {
+        self.sps.push(nal.to_vec());
+        self
+    }
+
+    /// Add a PPS NAL unit (including its header byte).
+    pub fn pps(mut self, nal: &[u8]) -> Self {
+        self.pps.push(nal.to_vec());
+        self
+    }
+
+    /// Produce the record, which will signal 4-byte NAL unit lengths.
+    pub fn build(&self) -> Result<Vec<u8>, AvccError> {
+        let first = self.sps.first().ok_or(AvccError::MissingParamSet("SPS"))?;
+        if self.pps.is_empty() {
+            return Err(AvccError::MissingParamSet("PPS"));
+        }
+        if self.sps.len() > 0b1_1111 || self.pps.len() > usize::from(u8::MAX) {
+            return Err(AvccError::TooLarge);
+        }
+        let sps = h264::SeqParameterSet::from_bytes(first)?;
+        let mut record = vec![
+            1, // configurationVersion
+            sps.profile_idc,
+            sps.constraint_flags,
+            sps.level_idc,
+            0b1111_1100 | (LENGTH_SIZE as u8 - 1),
+            0b1110_0000 | self.sps.len() as u8,
+        ];
+        write_nal_units(&mut record, &self.sps)?;
+        record.push(self.pps.len() as u8);
+        write_nal_units(&mut record, &self.pps)?;
+        // the profiles which may use chroma formats and bit depths other than the defaults
+        if !matches!(sps.profile_idc, 66 | 77 | 88) {
+            record.push(0b1111_1100 | sps.chroma_format.idc());
+            record.push(0b1111_1000 | sps.bit_depth_luma_minus8 as u8);
+            record.push(0b1111_1000 | sps.bit_depth_chroma_minus8 as u8);
+            record.push(0); // numOfSequenceParameterSetExt
+        }
+        Ok(record)
+    }
+}
+
+/// Builds an `HEVCDecoderConfigurationRecord`, as carried in the `hvcC` box of an MP4 sample
+/// entry, from HEVC VPS, SPS and PPS NAL units.
+#[derive(Debug, Clone, Default)]
+pub struct HevcConfigBuilder {
+    vps: Vec<Vec<u8>>,
+    sps: Vec<Vec<u8>>,
+    pps: Vec<Vec<u8>>,
+}
+impl HevcConfigBuilder {
+    /// Create a builder with no parameter sets.
+    pub fn new() -> HevcConfigBuilder {
+        HevcConfigBuilder::default()
+    }
+
+    /// Add a VPS NAL unit (including its header).
+    pub fn vps(mut self, nal: &[u8]) -> Self {
+        self.vps.push(nal.to_vec());
+        self
+    }
+
+    /// Add an SPS NAL unit (including its header).  The profile, level and format details given
+    /// in the record are taken from the first SPS added.
+    pub fn sps(mut self, nal: &[u8]) -> Self {
+        self.sps.push(nal.to_vec());
+        self
+    }
+
+    /// Add a PPS NAL unit (including its header).
+    pub fn pps(mut self, nal: &[u8]) -> Self {
+        self.pps.push(nal.to_vec());
+        self
+    }
+
+    /// Produce the record, which will signal 4-byte NAL unit lengths, and that all parameter
+    /// sets are present in the record (_array_completeness_).
+    pub fn build(&self) -> Result<Vec<u8>, AvccError> {
+        if self.vps.is_empty() {
+            return Err(AvccError::MissingParamSet("VPS"));
+        }
+        let first = self.sps.first().ok_or(AvccError::MissingParamSet("SPS"))?;
+        if self.pps.is_empty() {
+            return Err(AvccError::MissingParamSet("PPS"));
+        }
+        let sps = hevc::SeqParameterSet::from_bytes(first)?;
+        let ptl = &sps.profile_tier_level;
+        let mut record = vec![
+            1, // configurationVersion
+            ptl.general_profile_space << 6
+                | (ptl.general_tier_flag as u8) << 5
+                | ptl.general_profile_idc,
+        ];
+        record.extend_from_slice(&ptl.general_profile_compatibility_flags.to_be_bytes());
+        record.extend_from_slice(&ptl.general_constraint_indicator_flags.to_be_bytes()[2..]);
+        record.extend_from_slice(&[
+            ptl.general_level_idc,
+            0b1111_0000, // min_spatial_segmentation_idc unknown, so 0
+            0x00,
+            0b1111_1100, // parallelismType unknown, so 0
+            0b1111_1100 | sps.chroma_format.idc(),
+            0b1111_1000 | sps.bit_depth_luma_minus8 as u8,
+            0b1111_1000 | sps.bit_depth_chroma_minus8 as u8,
+            0, // avgFrameRate unspecified
+            0,
+            // constantFrameRate unknown, so 0
+            (sps.sps_max_sub_layers_minus1 + 1) << 3
+                | (sps.sps_temporal_id_nesting_flag as u8) << 2
+                | (LENGTH_SIZE as u8 - 1),
+            3, // numOfArrays
+        ]);
+        for (nal_unit_type, units) in [
+            (hevc::NAL_UNIT_TYPE_VPS, &self.vps),
+            (hevc::NAL_UNIT_TYPE_SPS, &self.sps),
+            (hevc::NAL_UNIT_TYPE_PPS, &self.pps),
+        ] {
+            if units.len() > usize::from(u16::MAX) {
+                return Err(AvccError::TooLarge);
+            }
+            record.push(0b1000_0000 | nal_unit_type);
+            record.extend_from_slice(&(units.len() as u16).to_be_bytes());
+            write_nal_units(&mut record, units)?;
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{NALParser, VideoCodec};
+    use crate::pes::Timestamp;
+    use hex_literal::hex;
+
+    fn key_frame(codec: VideoCodec, au: &[u8]) -> crate::parser::DemuxedSegment {
+        let mut parser = NALParser::for_codec(codec);
+        parser.begin_packet(Some(Timestamp::from_u64(0)), None, au);
+        parser.end_packet();
+        parser.flush();
+        let seg = parser.drain_segments().next().unwrap();
+        seg
+    }
+
+    #[test]
+    fn round_trip() {
+        let annexb = hex!("000001 0910 00000001 6588 8480 000001 0601 0580");
+        let avcc = annexb_to_avcc(&annexb);
+        assert_eq!(
+            avcc,
+            hex!("00000002 0910 00000004 65888480 00000004 06010580")
+        );
+        assert_eq!(
+            avcc_to_annexb(&avcc, 4).unwrap(),
+            hex!("00000001 0910 00000001 65888480 00000001 06010580")
+        );
+        assert_eq!(
+            avcc_to_annexb(&hex!("02 0910 01 06"), 1).unwrap(),
+            hex!("00000001 0910 00000001 06")
+        );
+        assert_eq!(
+            avcc_to_annexb(&avcc[..avcc.len() - 1], 4),
+            Err(AvccError::Truncated)
+        );
+        assert_eq!(avcc_to_annexb(&hex!("0000"), 4), Err(AvccError::Truncated));
+    }
+
+    #[test]
+    fn avc_config() {
+        // the parameter sets and start of the first IDR slice of the sample stream
+        let seg = key_frame(
+            VideoCodec::H264,
+            &hex!(
+                "00000001 6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654
+                 00000001 68ce0fc8
+                 00000001 6588843f"
+            ),
+        );
+        let record = AvcConfigBuilder::new()
+            .sps(seg.sps().unwrap())
+            .pps(seg.pps().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(
+            record,
+            hex!(
+                "0142c01f ffe1 001a 6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654
+                 01 0004 68ce0fc8"
+            )
+        );
+        assert_eq!(
+            annexb_to_avcc(seg.payload().unwrap()),
+            hex!("00000004 6588843f")
+        );
+        assert_eq!(
+            AvcConfigBuilder::new().sps(seg.sps().unwrap()).build(),
+            Err(AvccError::MissingParamSet("PPS"))
+        );
+    }
+
+    #[test]
+    fn avc_config_high() {
+        let record = AvcConfigBuilder::new()
+            .sps(&hex!(
+                "67640028 ad844108 b6501e01 13f7ff80 02000188 00001f48 00075307 00007d20
+                 07d15ed5 c1da0884 5960"
+            ))
+            .pps(&hex!("685ae32c c0422580"))
+            .build()
+            .unwrap();
+        assert_eq!(record[..6], hex!("01640028 ffe1"));
+        // chroma_format_idc and bit depths follow the PPS
+        assert_eq!(record[record.len() - 4..], hex!("fdf8f800"));
+    }
+
+    #[test]
+    fn hevc_config() {
+        let seg = key_frame(
+            VideoCodec::H265,
+            &hex!(
+                "00000001 4001 0c01ffff 01600000 03009000 00030000 03005d95 9809
+                 00000001 42010101 60000003 00900000 03000003 005da003 c0801107 cb96
+                 00000001 4401 c172b462 40
+                 00000001 2601 af10"
+            ),
+        );
+        let record = HevcConfigBuilder::new()
+            .vps(seg.vps().unwrap())
+            .sps(seg.sps().unwrap())
+            .pps(seg.pps().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(
+            record,
+            hex!(
+                "01 01 60000000 900000000000 5d f000 fc fd f8 f8 0000 0f 03
+                 a0 0001 0018 40010c01 ffff0160 00000300 90000003 00000300 5d959809
+                 a1 0001 001a 42010101 60000003 00900000 03000003 005da003 c0801107 cb96
+                 a2 0001 0007 4401c172 b46240"
+            )
+        );
+        assert_eq!(
+            HevcConfigBuilder::new()
+                .sps(seg.sps().unwrap())
+                .pps(seg.pps().unwrap())
+                .build(),
+            Err(AvccError::MissingParamSet("VPS"))
+        );
+    }
+}
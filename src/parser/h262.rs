@@ -0,0 +1,775 @@
+//! Parsing of MPEG-2 video (`StreamType::H262`, _ITU-T H.262_ / _ISO/IEC 13818-2_), and of the
+//! MPEG-1 video it extends, into pictures.
+//!
+//! The video is a sequence of start codes (`00 00 01` followed by a byte giving the kind of
+//! data), each followed by a sequence header, extension, group of pictures (GOP) header,
+//! picture header or slice.  An [`H262Parser`](struct.H262Parser.html) finds these in the
+//! payloads of PES packets, and groups them into [`Picture`](struct.Picture.html)s, each
+//! made up of a picture header and its slices, together with any sequence and GOP headers that
+//! preceded it.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::h262::{H262Parser, PictureCodingType};
+//! # use mpeg2ts_reader::pes::Timestamp;
+//! # use hex_literal::hex;
+//! let data = hex!(
+//!     "000001b3 2d024023249f2380  000001b5 148200010000
+//!      00000100 008ffff8  00000101 1234"
+//! );
+//! let mut parser = H262Parser::new();
+//! parser.begin_packet(Some(Timestamp::from_u64(3600)), None, &data);
+//! parser.end_packet();
+//! parser.flush();
+//! let picture = parser.drain_pictures().next().unwrap();
+//! assert_eq!(picture.picture_coding_type(), PictureCodingType::I);
+//! assert_eq!((picture.width(), picture.height()), (Some(720), Some(576)));
+//! assert_eq!(picture.frame_rate(), Some((25, 1)));
+//! assert_eq!(picture.pts(), Some(Timestamp::from_u64(3600)));
+//! ```
+
+use crate::parser::rbsp::{BitReader, BitReaderError};
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+const START_CODE_PREFIX: [u8; 3] = [0, 0, 1];
+const PICTURE_START_CODE: u8 = 0x00;
+const SEQUENCE_HEADER_CODE: u8 = 0xb3;
+const EXTENSION_START_CODE: u8 = 0xb5;
+const SEQUENCE_END_CODE: u8 = 0xb7;
+const GROUP_START_CODE: u8 = 0xb8;
+const SEQUENCE_EXTENSION_ID: u8 = 1;
+const PICTURE_CODING_EXTENSION_ID: u8 = 8;
+
+/// The frame rates indicated by each non-zero value of _frame_rate_code_, as numerator and
+/// denominator.
+const FRAME_RATES: [(u32, u32); 8] = [
+    (24000, 1001),
+    (24, 1),
+    (25, 1),
+    (30000, 1001),
+    (30, 1),
+    (50, 1),
+    (60000, 1001),
+    (60, 1),
+];
+
+/// Problems which may be found in the headers of an MPEG-2 video stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum H262Error {
+    /// The header was truncated.
+    Bits(BitReaderError),
+    /// A _marker_bit_ had the value `0`.
+    MissingMarkerBit(&'static str),
+    /// The _picture_coding_type_ field had a forbidden or reserved value.
+    BadPictureCodingType(u8),
+}
+impl From<BitReaderError> for H262Error {
+    fn from(e: BitReaderError) -> Self {
+        H262Error::Bits(e)
+    }
+}
+impl fmt::Display for H262Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            H262Error::Bits(e) => write!(f, "{}", e),
+            H262Error::MissingMarkerBit(field) => write!(f, "missing marker_bit after {}", field),
+            H262Error::BadPictureCodingType(t) => write!(f, "invalid picture_coding_type {}", t),
+        }
+    }
+}
+impl std::error::Error for H262Error {}
+
+fn read_marker_bit(r: &mut BitReader<'_>, after: &'static str) -> Result<(), H262Error> {
+    if r.read_bool("marker_bit")? {
+        Ok(())
+    } else {
+        Err(H262Error::MissingMarkerBit(after))
+    }
+}
+
+/// The meaning of the _aspect_ratio_information_ field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AspectRatio {
+    /// the samples are square
+    SquareSamples,
+    /// the display has an aspect ratio of 4:3
+    Display4By3,
+    /// the display has an aspect ratio of 16:9
+    Display16By9,
+    /// the display has an aspect ratio of 2.21:1
+    Display221By100,
+}
+
+/// The fields of a `sequence_header()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceHeader {
+    /// the _horizontal_size_value_ field, the low 12 bits of the width of the pictures
+    pub horizontal_size_value: u16,
+    /// the _vertical_size_value_ field, the low 12 bits of the height of the pictures
+    pub vertical_size_value: u16,
+    /// the _aspect_ratio_information_ field
+    pub aspect_ratio_information: u8,
+    /// the _frame_rate_code_ field
+    pub frame_rate_code: u8,
+    /// the _bit_rate_value_ field, the low 18 bits of the bit rate in units of 400 bit/s
+    pub bit_rate_value: u32,
+    /// the _vbv_buffer_size_value_ field
+    pub vbv_buffer_size_value: u16,
+    /// the _constrained_parameters_flag_ field
+    pub constrained_parameters_flag: bool,
+}
+impl SequenceHeader {
+    /// Parse the header from the data following its start code.
+    pub fn from_bytes(data: &[u8]) -> Result<SequenceHeader, H262Error> {
+        let mut r = BitReader::new(data);
+        let horizontal_size_value = r.read_u16(12, "horizontal_size_value")?;
+        let vertical_size_value = r.read_u16(12, "vertical_size_value")?;
+        let aspect_ratio_information = r.read_u8(4, "aspect_ratio_information")?;
+        let frame_rate_code = r.read_u8(4, "frame_rate_code")?;
+        let bit_rate_value = r.read_u32(18, "bit_rate_value")?;
+        read_marker_bit(&mut r, "bit_rate_value")?;
+        Ok(SequenceHeader {
+            horizontal_size_value,
+            vertical_size_value,
+            aspect_ratio_information,
+            frame_rate_code,
+            bit_rate_value,
+            vbv_buffer_size_value: r.read_u16(10, "vbv_buffer_size_value")?,
+            constrained_parameters_flag: r.read_bool("constrained_parameters_flag")?,
+        })
+    }
+
+    /// The aspect ratio given by _aspect_ratio_information_, or `None` for reserved values.
+    ///
+    /// Note that the values have different meanings in MPEG-1 video, where they give the aspect
+    /// ratio of the samples rather than of the display.
+    pub fn aspect_ratio(&self) -> Option<AspectRatio> {
+        match self.aspect_ratio_information {
+            1 => Some(AspectRatio::SquareSamples),
+            2 => Some(AspectRatio::Display4By3),
+            3 => Some(AspectRatio::Display16By9),
+            4 => Some(AspectRatio::Display221By100),
+            _ => None,
+        }
+    }
+}
+
+/// The fields of a `sequence_extension()`, whose presence distinguishes MPEG-2 video from
+/// MPEG-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceExtension {
+    /// the _profile_and_level_indication_ field
+    pub profile_and_level_indication: u8,
+    /// the _progressive_sequence_ field, `true` if the sequence has only progressive frames
+    pub progressive_sequence: bool,
+    /// the _chroma_format_ field (`1` for 4:2:0, `2` for 4:2:2 and `3` for 4:4:4)
+    pub chroma_format: u8,
+    /// the _horizontal_size_extension_ field, the high 2 bits of the width of the pictures
+    pub horizontal_size_extension: u8,
+    /// the _vertical_size_extension_ field, the high 2 bits of the height of the pictures
+    pub vertical_size_extension: u8,
+    /// the _bit_rate_extension_ field, the high 12 bits of the bit rate
+    pub bit_rate_extension: u16,
+    /// the _vbv_buffer_size_extension_ field
+    pub vbv_buffer_size_extension: u8,
+    /// the _low_delay_ field, `true` if the sequence has no B pictures
+    pub low_delay: bool,
+    /// the _frame_rate_extension_n_ field
+    pub frame_rate_extension_n: u8,
+    /// the _frame_rate_extension_d_ field
+    pub frame_rate_extension_d: u8,
+}
+impl SequenceExtension {
+    /// Parse the extension from the data following its extension start code, starting with
+    /// the _extension_start_code_identifier_.
+    pub fn from_bytes(data: &[u8]) -> Result<SequenceExtension, H262Error> {
+        let mut r = BitReader::new(data);
+        r.skip(4, "extension_start_code_identifier")?;
+        let profile_and_level_indication = r.read_u8(8, "profile_and_level_indication")?;
+        let progressive_sequence = r.read_bool("progressive_sequence")?;
+        let chroma_format = r.read_u8(2, "chroma_format")?;
+        let horizontal_size_extension = r.read_u8(2, "horizontal_size_extension")?;
+        let vertical_size_extension = r.read_u8(2, "vertical_size_extension")?;
+        let bit_rate_extension = r.read_u16(12, "bit_rate_extension")?;
+        read_marker_bit(&mut r, "bit_rate_extension")?;
+        Ok(SequenceExtension {
+            profile_and_level_indication,
+            progressive_sequence,
+            chroma_format,
+            horizontal_size_extension,
+            vertical_size_extension,
+            bit_rate_extension,
+            vbv_buffer_size_extension: r.read_u8(8, "vbv_buffer_size_extension")?,
+            low_delay: r.read_bool("low_delay")?,
+            frame_rate_extension_n: r.read_u8(2, "frame_rate_extension_n")?,
+            frame_rate_extension_d: r.read_u8(5, "frame_rate_extension_d")?,
+        })
+    }
+}
+
+/// The fields of a `group_of_pictures_header()`, including the SMPTE timecode of the first
+/// picture of the group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GopHeader {
+    /// the _drop_frame_flag_ of the timecode
+    pub drop_frame_flag: bool,
+    /// the _time_code_hours_ field
+    pub hours: u8,
+    /// the _time_code_minutes_ field
+    pub minutes: u8,
+    /// the _time_code_seconds_ field
+    pub seconds: u8,
+    /// the _time_code_pictures_ field
+    pub pictures: u8,
+    /// the _closed_gop_ field, `true` if no picture of the group refers to the previous group
+    pub closed_gop: bool,
+    /// the _broken_link_ field, `true` if the first B pictures of the group can't be decoded
+    pub broken_link: bool,
+}
+impl GopHeader {
+    /// Parse the header from the data following its start code.
+    pub fn from_bytes(data: &[u8]) -> Result<GopHeader, H262Error> {
+        let mut r = BitReader::new(data);
+        let drop_frame_flag = r.read_bool("drop_frame_flag")?;
+        let hours = r.read_u8(5, "time_code_hours")?;
+        let minutes = r.read_u8(6, "time_code_minutes")?;
+        read_marker_bit(&mut r, "time_code_minutes")?;
+        Ok(GopHeader {
+            drop_frame_flag,
+            hours,
+            minutes,
+            seconds: r.read_u8(6, "time_code_seconds")?,
+            pictures: r.read_u8(6, "time_code_pictures")?,
+            closed_gop: r.read_bool("closed_gop")?,
+            broken_link: r.read_bool("broken_link")?,
+        })
+    }
+}
+
+/// The kind of picture, given by the _picture_coding_type_ field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PictureCodingType {
+    /// an intra-coded picture
+    I,
+    /// a picture predicted from the previous I or P picture
+    P,
+    /// a picture predicted from the I or P pictures either side of it
+    B,
+    /// an MPEG-1 'DC intra-coded' picture
+    D,
+}
+
+/// The fields of a `picture_header()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PictureHeader {
+    /// the _temporal_reference_ field, giving the display order of the picture within its GOP
+    pub temporal_reference: u16,
+    /// the _picture_coding_type_ field
+    pub picture_coding_type: PictureCodingType,
+    /// the _vbv_delay_ field
+    pub vbv_delay: u16,
+}
+impl PictureHeader {
+    /// Parse the header from the data following its start code.
+    pub fn from_bytes(data: &[u8]) -> Result<PictureHeader, H262Error> {
+        let mut r = BitReader::new(data);
+        let temporal_reference = r.read_u16(10, "temporal_reference")?;
+        let picture_coding_type = match r.read_u8(3, "picture_coding_type")? {
+            1 => PictureCodingType::I,
+            2 => PictureCodingType::P,
+            3 => PictureCodingType::B,
+            4 => PictureCodingType::D,
+            t => return Err(H262Error::BadPictureCodingType(t)),
+        };
+        Ok(PictureHeader {
+            temporal_reference,
+            picture_coding_type,
+            vbv_delay: r.read_u16(16, "vbv_delay")?,
+        })
+    }
+}
+
+/// The fields of a `picture_coding_extension()` describing how the picture is to be displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PictureCodingExtension {
+    /// the _picture_structure_ field (`1` for a top field, `2` for a bottom field and `3` for a
+    /// frame)
+    pub picture_structure: u8,
+    /// the _top_field_first_ field
+    pub top_field_first: bool,
+    /// the _repeat_first_field_ field
+    pub repeat_first_field: bool,
+    /// the _progressive_frame_ field
+    pub progressive_frame: bool,
+}
+impl PictureCodingExtension {
+    /// Parse the extension from the data following its extension start code, starting with
+    /// the _extension_start_code_identifier_.
+    pub fn from_bytes(data: &[u8]) -> Result<PictureCodingExtension, H262Error> {
+        let mut r = BitReader::new(data);
+        r.skip(4 + 16 + 2, "f_code")?;
+        let picture_structure = r.read_u8(2, "picture_structure")?;
+        let top_field_first = r.read_bool("top_field_first")?;
+        r.skip(5, "frame_pred_frame_dct")?;
+        let repeat_first_field = r.read_bool("repeat_first_field")?;
+        r.skip(1, "chroma_420_type")?;
+        Ok(PictureCodingExtension {
+            picture_structure,
+            top_field_first,
+            repeat_first_field,
+            progressive_frame: r.read_bool("progressive_frame")?,
+        })
+    }
+}
+
+/// A picture found by [`H262Parser`](struct.H262Parser.html), with its timing and the headers
+/// that apply to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    sequence_header: Option<SequenceHeader>,
+    sequence_extension: Option<SequenceExtension>,
+    gop_header: Option<GopHeader>,
+    picture_header: PictureHeader,
+    picture_coding_extension: Option<PictureCodingExtension>,
+    data: Vec<u8>,
+    pts: Option<Timestamp>,
+    dts: Option<Timestamp>,
+}
+impl Picture {
+    /// The sequence header in effect for this picture, which may have been sent before earlier
+    /// pictures.  `None` if no sequence header has been seen yet.
+    pub fn sequence_header(&self) -> Option<&SequenceHeader> {
+        self.sequence_header.as_ref()
+    }
+
+    /// The sequence extension in effect for this picture, or `None` for MPEG-1 video.
+    pub fn sequence_extension(&self) -> Option<&SequenceExtension> {
+        self.sequence_extension.as_ref()
+    }
+
+    /// The GOP header immediately preceding this picture, if any.
+    pub fn gop_header(&self) -> Option<&GopHeader> {
+        self.gop_header.as_ref()
+    }
+
+    /// The header of this picture.
+    pub fn picture_header(&self) -> &PictureHeader {
+        &self.picture_header
+    }
+
+    /// Whether this is an I, P or B picture.
+    pub fn picture_coding_type(&self) -> PictureCodingType {
+        self.picture_header.picture_coding_type
+    }
+
+    /// The picture coding extension of this picture, present in MPEG-2 video.
+    pub fn picture_coding_extension(&self) -> Option<&PictureCodingExtension> {
+        self.picture_coding_extension.as_ref()
+    }
+
+    /// The data of this picture, starting with the start code of the first sequence header, GOP
+    /// header or picture header belonging to it, and including all its slices.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The PTS of the PES packet in which this picture's header started, if the picture was
+    /// the first to start in that packet.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The DTS of the PES packet in which this picture's header started, if the picture was
+    /// the first to start in that packet.  Where the PES packet gave only a PTS, this has the
+    /// same value.
+    pub fn dts(&self) -> Option<Timestamp> {
+        self.dts
+    }
+
+    /// The width of the picture, in samples.
+    pub fn width(&self) -> Option<u32> {
+        let value = u32::from(self.sequence_header?.horizontal_size_value);
+        let extension = self
+            .sequence_extension
+            .map(|ext| u32::from(ext.horizontal_size_extension))
+            .unwrap_or(0);
+        Some(extension << 12 | value)
+    }
+
+    /// The height of the picture, in lines.
+    pub fn height(&self) -> Option<u32> {
+        let value = u32::from(self.sequence_header?.vertical_size_value);
+        let extension = self
+            .sequence_extension
+            .map(|ext| u32::from(ext.vertical_size_extension))
+            .unwrap_or(0);
+        Some(extension << 12 | value)
+    }
+
+    /// The frame rate of the sequence as a numerator and denominator, or `None` if the
+    /// _frame_rate_code_ has a reserved value.
+    pub fn frame_rate(&self) -> Option<(u32, u32)> {
+        let code = usize::from(self.sequence_header?.frame_rate_code);
+        let (num, den) = *FRAME_RATES.get(code.checked_sub(1)?)?;
+        match self.sequence_extension {
+            Some(ext) => Some((
+                num * (u32::from(ext.frame_rate_extension_n) + 1),
+                den * (u32::from(ext.frame_rate_extension_d) + 1),
+            )),
+            None => Some((num, den)),
+        }
+    }
+
+    /// The bit rate of the sequence, in bits per second.
+    pub fn bit_rate(&self) -> Option<u64> {
+        let value = u64::from(self.sequence_header?.bit_rate_value);
+        let extension = self
+            .sequence_extension
+            .map(|ext| u64::from(ext.bit_rate_extension))
+            .unwrap_or(0);
+        Some((extension << 18 | value) * 400)
+    }
+}
+
+/// Finds the position of the next start code prefix in `data`, at or after `from`.
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(START_CODE_PREFIX.len())
+        .position(|w| w == START_CODE_PREFIX)
+        .map(|pos| from + pos)
+}
+
+/// Splits the payloads of the PES packets of an MPEG-2 (or MPEG-1) video stream into
+/// [`Picture`](struct.Picture.html)s, which can be retrieved with `drain_pictures()`.
+///
+/// Pictures may span PES packets, so a picture is only known to be complete once the start code
+/// of the following picture, sequence header or GOP header is seen, or `flush()` is called at
+/// the end of the stream.
+#[derive(Debug, Default)]
+pub struct H262Parser {
+    buf: Vec<u8>,
+    /// the position in the stream of the first byte of `buf`
+    buf_position: u64,
+    /// the position in `buf` from which to continue looking for start codes
+    scan_pos: usize,
+    /// the positions in the stream where the data of recent PES packets starts, and their PTS
+    /// and DTS, until they are given to a picture
+    pending: Vec<(u64, Option<Timestamp>, Option<Timestamp>)>,
+    sequence_header: Option<SequenceHeader>,
+    sequence_extension: Option<SequenceExtension>,
+    /// a GOP header not yet followed by a picture
+    gop_header: Option<GopHeader>,
+    /// the data of sequence headers, GOP headers and so on which will start the next picture
+    prefix: Vec<u8>,
+    current: Option<Picture>,
+    pictures: Vec<Picture>,
+}
+impl H262Parser {
+    /// Create a parser with no data.
+    pub fn new() -> H262Parser {
+        H262Parser::default()
+    }
+
+    /// Start a new PES packet, with the PTS and DTS from its header (see `PtsDts::pts()` and
+    /// `PtsDts::dts()`) and the initial part of its payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, dts: Option<Timestamp>, payload: &[u8]) {
+        let position = self.buf_position + self.buf.len() as u64;
+        // when a PES packet has only a PTS, its DTS has the same value
+        self.pending.push((position, pts, dts.or(pts)));
+        self.continue_packet(payload);
+    }
+
+    /// Add more of the payload of the current PES packet.
+    pub fn continue_packet(&mut self, payload: &[u8]) {
+        self.buf.extend_from_slice(payload);
+        self.parse(false);
+    }
+
+    /// Signal the end of the current PES packet.  Since pictures may span PES packets, this has
+    /// no effect on the data buffered so far.
+    pub fn end_packet(&mut self) {}
+
+    /// Signal the end of the stream, so that the final picture is returned by
+    /// `drain_pictures()`.
+    pub fn flush(&mut self) {
+        self.parse(true);
+        self.finish_picture();
+        self.prefix.clear();
+        self.pending.clear();
+        self.gop_header = None;
+    }
+
+    /// Removes and returns the pictures known to be complete, in stream order.
+    pub fn drain_pictures(&mut self) -> impl Iterator<Item = Picture> + '_ {
+        self.pictures.drain(..)
+    }
+
+    fn parse(&mut self, end_of_stream: bool) {
+        let buf = std::mem::take(&mut self.buf);
+        let mut unit_start = if buf.starts_with(&START_CODE_PREFIX) {
+            Some(0)
+        } else {
+            None
+        };
+        let mut scan = self.scan_pos.max(1);
+        while let Some(next) = find_start_code(&buf, scan) {
+            if let Some(start) = unit_start {
+                self.unit(start, &buf[start..next]);
+            }
+            unit_start = Some(next);
+            scan = next + START_CODE_PREFIX.len();
+        }
+        let consumed = match unit_start {
+            Some(start) if end_of_stream => {
+                self.unit(start, &buf[start..]);
+                buf.len()
+            }
+            Some(start) => start,
+            // keep the last bytes, which could be the start of a start code prefix
+            None if end_of_stream => buf.len(),
+            None => buf.len().saturating_sub(START_CODE_PREFIX.len() - 1),
+        };
+        self.buf = buf;
+        self.buf.drain(..consumed);
+        self.buf_position += consumed as u64;
+        self.scan_pos = self.buf.len().saturating_sub(START_CODE_PREFIX.len() - 1);
+    }
+
+    /// Handles the data from the start code at the given position in the buffer, up to the next
+    /// start code.
+    fn unit(&mut self, start: usize, data: &[u8]) {
+        let code = match data.get(START_CODE_PREFIX.len()) {
+            Some(&code) => code,
+            None => return,
+        };
+        let position = self.buf_position + start as u64;
+        if let Err(e) = self.read_unit(position, code, data) {
+            trace!("problem with MPEG-2 video start code {:#04x}: {}", code, e);
+        }
+    }
+
+    fn read_unit(&mut self, position: u64, code: u8, data: &[u8]) -> Result<(), H262Error> {
+        let body = &data[START_CODE_PREFIX.len() + 1..];
+        match code {
+            PICTURE_START_CODE => {
+                self.finish_picture();
+                let picture_header = PictureHeader::from_bytes(body)?;
+                // the timestamps of a PES packet belong to the first picture starting within it
+                let (pts, dts) = match self.pending.iter().rposition(|p| p.0 <= position) {
+                    Some(i) => {
+                        let (_, pts, dts) = self.pending[i];
+                        self.pending.drain(..=i);
+                        (pts, dts)
+                    }
+                    None => (None, None),
+                };
+                let mut picture_data = std::mem::take(&mut self.prefix);
+                picture_data.extend_from_slice(data);
+                self.current = Some(Picture {
+                    sequence_header: self.sequence_header,
+                    sequence_extension: self.sequence_extension,
+                    gop_header: self.gop_header.take(),
+                    picture_header,
+                    picture_coding_extension: None,
+                    data: picture_data,
+                    pts,
+                    dts,
+                });
+                return Ok(());
+            }
+            SEQUENCE_HEADER_CODE => {
+                self.finish_picture();
+                self.sequence_header = Some(SequenceHeader::from_bytes(body)?);
+                // until a sequence extension follows, this is MPEG-1 video
+                self.sequence_extension = None;
+            }
+            GROUP_START_CODE => {
+                self.finish_picture();
+                self.gop_header = Some(GopHeader::from_bytes(body)?);
+            }
+            EXTENSION_START_CODE => match body.first().map(|b| b >> 4) {
+                Some(SEQUENCE_EXTENSION_ID) => {
+                    self.sequence_extension = Some(SequenceExtension::from_bytes(body)?);
+                }
+                Some(PICTURE_CODING_EXTENSION_ID) => {
+                    if let Some(ref mut picture) = self.current {
+                        picture.picture_coding_extension =
+                            Some(PictureCodingExtension::from_bytes(body)?);
+                    }
+                }
+                _ => {}
+            },
+            SEQUENCE_END_CODE => {
+                if let Some(ref mut picture) = self.current {
+                    picture.data.extend_from_slice(data);
+                }
+                self.finish_picture();
+                return Ok(());
+            }
+            _ => {}
+        }
+        // slices, extensions and user data belong to the current picture, or if there is none,
+        // will start the next one
+        match self.current {
+            Some(ref mut picture) => picture.data.extend_from_slice(data),
+            None => self.prefix.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    fn finish_picture(&mut self) {
+        if let Some(picture) = self.current.take() {
+            self.pictures.push(picture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn headers() {
+        let header = SequenceHeader::from_bytes(&hex!("2d024023249f2380")).unwrap();
+        assert_eq!(
+            header,
+            SequenceHeader {
+                horizontal_size_value: 720,
+                vertical_size_value: 576,
+                aspect_ratio_information: 2,
+                frame_rate_code: 3,
+                bit_rate_value: 37500,
+                vbv_buffer_size_value: 112,
+                constrained_parameters_flag: false,
+            }
+        );
+        assert_eq!(header.aspect_ratio(), Some(AspectRatio::Display4By3));
+        assert_eq!(
+            SequenceHeader::from_bytes(&hex!("2d024023249f0380")),
+            Err(H262Error::MissingMarkerBit("bit_rate_value"))
+        );
+
+        let ext = SequenceExtension::from_bytes(&hex!("148200010000")).unwrap();
+        assert_eq!(ext.profile_and_level_indication, 0x48);
+        assert!(!ext.progressive_sequence);
+        assert_eq!(ext.chroma_format, 1);
+        assert!(!ext.low_delay);
+
+        let gop = GopHeader::from_bytes(&hex!("294bc2c0")).unwrap();
+        assert_eq!(
+            gop,
+            GopHeader {
+                drop_frame_flag: false,
+                hours: 10,
+                minutes: 20,
+                seconds: 30,
+                pictures: 5,
+                closed_gop: true,
+                broken_link: false,
+            }
+        );
+
+        let picture = PictureHeader::from_bytes(&hex!("005ffffbb8")).unwrap();
+        assert_eq!(picture.temporal_reference, 1);
+        assert_eq!(picture.picture_coding_type, PictureCodingType::B);
+        assert_eq!(picture.vbv_delay, 0xffff);
+        assert_eq!(
+            PictureHeader::from_bytes(&hex!("0007fff8")),
+            Err(H262Error::BadPictureCodingType(0))
+        );
+
+        let ext = PictureCodingExtension::from_bytes(&hex!("8ffff38100")).unwrap();
+        assert_eq!(
+            ext,
+            PictureCodingExtension {
+                picture_structure: 3,
+                top_field_first: true,
+                repeat_first_field: false,
+                progressive_frame: false,
+            }
+        );
+    }
+
+    #[test]
+    fn pictures() {
+        let i_picture = hex!(
+            "000001b3 2d024023249f2380  000001b5 148200010000  000001b8 294bc2c0
+             00000100 008ffff8  000001b5 8ffff38100  000001b2 4741  00000101 1234  0000"
+        );
+        let p_picture = hex!("00000100 0017fffb80  00000101 5678");
+        let b_picture = hex!("00000100 005ffffbb8  00000101 9abc  000001b7");
+        let mut parser = H262Parser::new();
+        // junk before the first start code, and a picture split across PES packets, with the
+        // second packet's PTS belonging to the following picture
+        parser.begin_packet(
+            Some(Timestamp::from_u64(7200)),
+            Some(Timestamp::from_u64(3600)),
+            &hex!("ff00"),
+        );
+        parser.continue_packet(&i_picture[..40]);
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::from_u64(10800)), None, &i_picture[40..]);
+        parser.continue_packet(&p_picture[..2]);
+        parser.end_packet();
+        assert_eq!(parser.drain_pictures().count(), 0);
+        parser.begin_packet(None, None, &p_picture[2..]);
+        parser.continue_packet(&b_picture);
+        parser.end_packet();
+        parser.flush();
+        let pictures: Vec<_> = parser.drain_pictures().collect();
+        let types: Vec<_> = pictures.iter().map(|p| p.picture_coding_type()).collect();
+        assert_eq!(
+            types,
+            [
+                PictureCodingType::I,
+                PictureCodingType::P,
+                PictureCodingType::B
+            ]
+        );
+        let timestamps: Vec<_> = pictures
+            .iter()
+            .map(|p| (p.pts().map(|t| t.value()), p.dts().map(|t| t.value())))
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                (Some(7200), Some(3600)),
+                (Some(10800), Some(10800)),
+                (None, None)
+            ]
+        );
+
+        let i = &pictures[0];
+        assert_eq!(i.data(), i_picture);
+        assert_eq!(i.gop_header().unwrap().seconds, 30);
+        assert_eq!(i.picture_coding_extension().unwrap().picture_structure, 3);
+        assert_eq!(i.width(), Some(720));
+        assert_eq!(i.height(), Some(576));
+        assert_eq!(i.frame_rate(), Some((25, 1)));
+        assert_eq!(i.bit_rate(), Some(15_000_000));
+        assert_eq!(pictures[1].data(), p_picture);
+        assert!(pictures[1].gop_header().is_none());
+        assert_eq!(pictures[1].sequence_header(), i.sequence_header());
+        assert_eq!(pictures[2].data(), b_picture);
+    }
+
+    #[test]
+    fn sequence_extension_values() {
+        // 1920x1080 at 30000/1001 doubled to 60000/1001 by the extension, with a bit rate too
+        // large for bit_rate_value alone
+        let mut r = vec![];
+        r.extend_from_slice(&hex!("000001b3 78043834ffffe000"));
+        r.extend_from_slice(&hex!("000001b5 148200030020"));
+        r.extend_from_slice(&hex!("00000100 008ffff8"));
+        let mut parser = H262Parser::new();
+        parser.begin_packet(None, None, &r);
+        parser.flush();
+        let picture = parser.drain_pictures().next().unwrap();
+        assert_eq!(picture.width(), Some(1920));
+        assert_eq!(picture.height(), Some(1080));
+        assert_eq!(picture.frame_rate(), Some((60000, 1001)));
+        assert_eq!(picture.bit_rate(), Some(((1 << 18) | 0x3ffff) * 400));
+    }
+}
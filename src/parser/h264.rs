@@ -0,0 +1,1214 @@
+//! Decoding of the H.264 _Sequence Parameter Set_ and _Picture Parameter Set_ NAL units, per
+//! _ITU-T H.264_ sections 7.3.2.1 and 7.3.2.2, and of the start of slice headers, per section
+//! 7.3.3.
+//!
+//! The parameter sets give details needed to describe the stream to other systems (for instance
+//! the resolution, and the profile and level from which an RFC 6381 codec string is built), and
+//! to interpret the slice headers that follow.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::h264::SeqParameterSet;
+//! # use hex_literal::hex;
+//! let sps = SeqParameterSet::from_bytes(&hex!(
+//!     "6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654"
+//! ))
+//! .unwrap();
+//! assert_eq!(sps.codec_string(), "avc1.42C01F");
+//! assert_eq!((sps.width(), sps.height()), (852, 480));
+//! ```
+
+use crate::parser::rbsp::{self, BitReader, BitReaderError};
+use std::fmt;
+
+/// _nal_unit_type_ of a NAL unit containing a slice of a non-IDR picture
+pub const NAL_UNIT_TYPE_NON_IDR: u8 = 1;
+/// _nal_unit_type_ of a NAL unit containing a slice of an IDR picture
+pub const NAL_UNIT_TYPE_IDR: u8 = 5;
+/// _nal_unit_type_ of a Supplemental Enhancement Information NAL unit
+pub const NAL_UNIT_TYPE_SEI: u8 = 6;
+/// _nal_unit_type_ of a Sequence Parameter Set NAL unit
+pub const NAL_UNIT_TYPE_SPS: u8 = 7;
+/// _nal_unit_type_ of a Picture Parameter Set NAL unit
+pub const NAL_UNIT_TYPE_PPS: u8 = 8;
+
+/// Problems which may be found while decoding a parameter set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamSetError {
+    /// The data could not be read, because it was truncated or contained an invalid Exp-Golomb
+    /// value.
+    Bits(BitReaderError),
+    /// The NAL unit was empty, or had a different _nal_unit_type_ than the one expected.
+    WrongNalUnitType {
+        /// the _nal_unit_type_ expected
+        expected: u8,
+        /// the _nal_unit_type_ found, or `None` if there was no NAL unit header
+        actual: Option<u8>,
+    },
+    /// A field had a value outside the range permitted by the specification.
+    FieldValueTooLarge {
+        /// the name of the field
+        field: &'static str,
+        /// the value found
+        value: u32,
+    },
+    /// A Picture Parameter Set referred to a Sequence Parameter Set that has not been seen.
+    UndefinedSeqParameterSetId(u32),
+    /// A slice header referred to a Picture Parameter Set that has not been seen.
+    UndefinedPicParameterSetId(u32),
+}
+impl From<BitReaderError> for ParamSetError {
+    fn from(e: BitReaderError) -> Self {
+        ParamSetError::Bits(e)
+    }
+}
+impl fmt::Display for ParamSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamSetError::Bits(e) => e.fmt(f),
+            ParamSetError::WrongNalUnitType { expected, actual } => {
+                write!(f, "expected nal_unit_type {}, found {:?}", expected, actual)
+            }
+            ParamSetError::FieldValueTooLarge { field, value } => {
+                write!(f, "value {} of {} is too large", value, field)
+            }
+            ParamSetError::UndefinedSeqParameterSetId(id) => {
+                write!(f, "undefined seq_parameter_set_id {}", id)
+            }
+            ParamSetError::UndefinedPicParameterSetId(id) => {
+                write!(f, "undefined pic_parameter_set_id {}", id)
+            }
+        }
+    }
+}
+impl std::error::Error for ParamSetError {}
+
+/// Checks the NAL unit header, and returns the RBSP that follows it.
+pub(crate) fn nal_rbsp(data: &[u8], expected: u8) -> Result<Vec<u8>, ParamSetError> {
+    match data.first() {
+        Some(header) if header & 0b1_1111 == expected => {
+            Ok(rbsp::decode_nal(&data[1..]).into_owned())
+        }
+        other => Err(ParamSetError::WrongNalUnitType {
+            expected,
+            actual: other.map(|h| h & 0b1_1111),
+        }),
+    }
+}
+
+pub(crate) fn read_ue_max(
+    r: &mut BitReader<'_>,
+    max: u32,
+    field: &'static str,
+) -> Result<u32, ParamSetError> {
+    let value = r.read_ue(field)?;
+    if value > max {
+        Err(ParamSetError::FieldValueTooLarge { field, value })
+    } else {
+        Ok(value)
+    }
+}
+
+/// Reads a `scaling_list()` structure, which is skipped rather than retained.
+fn skip_scaling_list(r: &mut BitReader<'_>, size: usize) -> Result<(), ParamSetError> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = r.read_se("delta_scale")?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `*_scaling_list_present_flag` values and any `scaling_list()` structures for the
+/// given number of lists, the first six of which are 4x4 lists, and the remainder 8x8.
+fn skip_scaling_matrix(r: &mut BitReader<'_>, count: usize) -> Result<(), ParamSetError> {
+    for i in 0..count {
+        if r.read_bool("scaling_list_present_flag")? {
+            skip_scaling_list(r, if i < 6 { 16 } else { 64 })?;
+        }
+    }
+    Ok(())
+}
+
+/// The _chroma_format_idc_ of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaFormat {
+    /// Only luma samples are present
+    Monochrome,
+    /// Chroma has half the horizontal and vertical resolution of luma
+    YUV420,
+    /// Chroma has half the horizontal resolution of luma
+    YUV422,
+    /// Chroma has the same resolution as luma
+    YUV444,
+}
+impl ChromaFormat {
+    pub(crate) fn from_idc(idc: u32) -> ChromaFormat {
+        match idc {
+            0 => ChromaFormat::Monochrome,
+            1 => ChromaFormat::YUV420,
+            2 => ChromaFormat::YUV422,
+            _ => ChromaFormat::YUV444,
+        }
+    }
+    /// The _chroma_format_idc_ value signalling this format
+    pub fn idc(self) -> u8 {
+        match self {
+            ChromaFormat::Monochrome => 0,
+            ChromaFormat::YUV420 => 1,
+            ChromaFormat::YUV422 => 2,
+            ChromaFormat::YUV444 => 3,
+        }
+    }
+    /// `(SubWidthC, SubHeightC)`, or `None` for monochrome
+    pub(crate) fn subsampling(self) -> Option<(u32, u32)> {
+        match self {
+            ChromaFormat::Monochrome => None,
+            ChromaFormat::YUV420 => Some((2, 2)),
+            ChromaFormat::YUV422 => Some((2, 1)),
+            ChromaFormat::YUV444 => Some((1, 1)),
+        }
+    }
+}
+
+/// The method used to derive picture order count values, with its associated fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PicOrderCntType {
+    /// _pic_order_cnt_type_ 0, where slice headers carry the picture order count LSBs
+    TypeZero {
+        /// the _log2_max_pic_order_cnt_lsb_minus4_ field
+        log2_max_pic_order_cnt_lsb_minus4: u8,
+    },
+    /// _pic_order_cnt_type_ 1, where picture order count is derived from _frame_num_ and the
+    /// offsets given here
+    TypeOne {
+        /// the _delta_pic_order_always_zero_flag_ field
+        delta_pic_order_always_zero_flag: bool,
+        /// the _offset_for_non_ref_pic_ field
+        offset_for_non_ref_pic: i32,
+        /// the _offset_for_top_to_bottom_field_ field
+        offset_for_top_to_bottom_field: i32,
+        /// the _offset_for_ref_frame_ values
+        offsets_for_ref_frame: Vec<i32>,
+    },
+    /// _pic_order_cnt_type_ 2, where output order is the same as decoding order
+    TypeTwo,
+}
+impl PicOrderCntType {
+    fn read(r: &mut BitReader<'_>) -> Result<PicOrderCntType, ParamSetError> {
+        match read_ue_max(r, 2, "pic_order_cnt_type")? {
+            0 => Ok(PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4: read_ue_max(
+                    r,
+                    12,
+                    "log2_max_pic_order_cnt_lsb_minus4",
+                )? as u8,
+            }),
+            1 => {
+                let delta_pic_order_always_zero_flag =
+                    r.read_bool("delta_pic_order_always_zero_flag")?;
+                let offset_for_non_ref_pic = r.read_se("offset_for_non_ref_pic")?;
+                let offset_for_top_to_bottom_field = r.read_se("offset_for_top_to_bottom_field")?;
+                let count = read_ue_max(r, 255, "num_ref_frames_in_pic_order_cnt_cycle")?;
+                let offsets_for_ref_frame = (0..count)
+                    .map(|_| r.read_se("offset_for_ref_frame"))
+                    .collect::<Result<_, _>>()?;
+                Ok(PicOrderCntType::TypeOne {
+                    delta_pic_order_always_zero_flag,
+                    offset_for_non_ref_pic,
+                    offset_for_top_to_bottom_field,
+                    offsets_for_ref_frame,
+                })
+            }
+            _ => Ok(PicOrderCntType::TypeTwo),
+        }
+    }
+}
+
+/// The frame cropping rectangle, in units of `CropUnitX` / `CropUnitY` as defined by the
+/// specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameCropping {
+    /// the _frame_crop_left_offset_ field
+    pub left_offset: u32,
+    /// the _frame_crop_right_offset_ field
+    pub right_offset: u32,
+    /// the _frame_crop_top_offset_ field
+    pub top_offset: u32,
+    /// the _frame_crop_bottom_offset_ field
+    pub bottom_offset: u32,
+}
+
+/// Hypothetical Reference Decoder parameters, of which only the lengths of fields found in
+/// other syntax structures (such as the _pic_timing_ SEI message) are retained.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrdParameters {
+    /// the _cpb_cnt_minus1_ field
+    pub cpb_cnt_minus1: u32,
+    /// the _initial_cpb_removal_delay_length_minus1_ field
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    /// the _cpb_removal_delay_length_minus1_ field
+    pub cpb_removal_delay_length_minus1: u8,
+    /// the _dpb_output_delay_length_minus1_ field
+    pub dpb_output_delay_length_minus1: u8,
+    /// the _time_offset_length_ field
+    pub time_offset_length: u8,
+}
+impl HrdParameters {
+    fn read(r: &mut BitReader<'_>) -> Result<HrdParameters, ParamSetError> {
+        let cpb_cnt_minus1 = read_ue_max(r, 31, "cpb_cnt_minus1")?;
+        r.skip(8, "bit_rate_scale")?; // and cpb_size_scale
+        for _ in 0..=cpb_cnt_minus1 {
+            r.read_ue("bit_rate_value_minus1")?;
+            r.read_ue("cpb_size_value_minus1")?;
+            r.read_bool("cbr_flag")?;
+        }
+        Ok(HrdParameters {
+            cpb_cnt_minus1,
+            initial_cpb_removal_delay_length_minus1: r
+                .read_u8(5, "initial_cpb_removal_delay_length_minus1")?,
+            cpb_removal_delay_length_minus1: r.read_u8(5, "cpb_removal_delay_length_minus1")?,
+            dpb_output_delay_length_minus1: r.read_u8(5, "dpb_output_delay_length_minus1")?,
+            time_offset_length: r.read_u8(5, "time_offset_length")?,
+        })
+    }
+}
+
+/// The _timing_info_ fields of the VUI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingInfo {
+    /// the _num_units_in_tick_ field
+    pub num_units_in_tick: u32,
+    /// the _time_scale_ field
+    pub time_scale: u32,
+    /// the _fixed_frame_rate_flag_ field
+    pub fixed_frame_rate_flag: bool,
+}
+
+/// The _bitstream_restriction_ fields of the VUI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitstreamRestrictions {
+    /// the _motion_vectors_over_pic_boundaries_flag_ field
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    /// the _max_bytes_per_pic_denom_ field
+    pub max_bytes_per_pic_denom: u32,
+    /// the _max_bits_per_mb_denom_ field
+    pub max_bits_per_mb_denom: u32,
+    /// the _log2_max_mv_length_horizontal_ field
+    pub log2_max_mv_length_horizontal: u32,
+    /// the _log2_max_mv_length_vertical_ field
+    pub log2_max_mv_length_vertical: u32,
+    /// the _max_num_reorder_frames_ field
+    pub max_num_reorder_frames: u32,
+    /// the _max_dec_frame_buffering_ field
+    pub max_dec_frame_buffering: u32,
+}
+
+/// The _Video Usability Information_ of a sequence, per _ITU-T H.264_ Annex E.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VuiParameters {
+    /// the _aspect_ratio_idc_ field, if present
+    pub aspect_ratio_idc: Option<u8>,
+    /// the _sar_width_ and _sar_height_ fields, present if `aspect_ratio_idc` is `255`
+    pub sample_aspect_ratio: Option<(u16, u16)>,
+    /// the _overscan_appropriate_flag_ field, if present
+    pub overscan_appropriate: Option<bool>,
+    /// the _video_format_ field, if present
+    pub video_format: Option<u8>,
+    /// the _video_full_range_flag_ field, if present
+    pub video_full_range_flag: Option<bool>,
+    /// the _colour_primaries_, _transfer_characteristics_ and _matrix_coefficients_ fields, if
+    /// present
+    pub colour_description: Option<(u8, u8, u8)>,
+    /// the _chroma_sample_loc_type_top_field_ and _chroma_sample_loc_type_bottom_field_ fields,
+    /// if present
+    pub chroma_sample_loc: Option<(u32, u32)>,
+    /// the _timing_info_ fields, if present
+    pub timing_info: Option<TimingInfo>,
+    /// the NAL HRD parameters, if present
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    /// the VCL HRD parameters, if present
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    /// the _low_delay_hrd_flag_ field, present if either kind of HRD parameters is present
+    pub low_delay_hrd_flag: Option<bool>,
+    /// the _pic_struct_present_flag_ field
+    pub pic_struct_present_flag: bool,
+    /// the _bitstream_restriction_ fields, if present
+    pub bitstream_restrictions: Option<BitstreamRestrictions>,
+}
+impl VuiParameters {
+    const EXTENDED_SAR: u8 = 255;
+
+    fn read(r: &mut BitReader<'_>) -> Result<VuiParameters, ParamSetError> {
+        let mut vui = VuiParameters::default();
+        if r.read_bool("aspect_ratio_info_present_flag")? {
+            let idc = r.read_u8(8, "aspect_ratio_idc")?;
+            vui.aspect_ratio_idc = Some(idc);
+            if idc == Self::EXTENDED_SAR {
+                vui.sample_aspect_ratio =
+                    Some((r.read_u16(16, "sar_width")?, r.read_u16(16, "sar_height")?));
+            }
+        }
+        if r.read_bool("overscan_info_present_flag")? {
+            vui.overscan_appropriate = Some(r.read_bool("overscan_appropriate_flag")?);
+        }
+        if r.read_bool("video_signal_type_present_flag")? {
+            vui.video_format = Some(r.read_u8(3, "video_format")?);
+            vui.video_full_range_flag = Some(r.read_bool("video_full_range_flag")?);
+            if r.read_bool("colour_description_present_flag")? {
+                vui.colour_description = Some((
+                    r.read_u8(8, "colour_primaries")?,
+                    r.read_u8(8, "transfer_characteristics")?,
+                    r.read_u8(8, "matrix_coefficients")?,
+                ));
+            }
+        }
+        if r.read_bool("chroma_loc_info_present_flag")? {
+            vui.chroma_sample_loc = Some((
+                read_ue_max(r, 5, "chroma_sample_loc_type_top_field")?,
+                read_ue_max(r, 5, "chroma_sample_loc_type_bottom_field")?,
+            ));
+        }
+        if r.read_bool("timing_info_present_flag")? {
+            vui.timing_info = Some(TimingInfo {
+                num_units_in_tick: r.read_u32(32, "num_units_in_tick")?,
+                time_scale: r.read_u32(32, "time_scale")?,
+                fixed_frame_rate_flag: r.read_bool("fixed_frame_rate_flag")?,
+            });
+        }
+        if r.read_bool("nal_hrd_parameters_present_flag")? {
+            vui.nal_hrd_parameters = Some(HrdParameters::read(r)?);
+        }
+        if r.read_bool("vcl_hrd_parameters_present_flag")? {
+            vui.vcl_hrd_parameters = Some(HrdParameters::read(r)?);
+        }
+        if vui.nal_hrd_parameters.is_some() || vui.vcl_hrd_parameters.is_some() {
+            vui.low_delay_hrd_flag = Some(r.read_bool("low_delay_hrd_flag")?);
+        }
+        vui.pic_struct_present_flag = r.read_bool("pic_struct_present_flag")?;
+        if r.read_bool("bitstream_restriction_flag")? {
+            vui.bitstream_restrictions = Some(BitstreamRestrictions {
+                motion_vectors_over_pic_boundaries_flag: r
+                    .read_bool("motion_vectors_over_pic_boundaries_flag")?,
+                max_bytes_per_pic_denom: r.read_ue("max_bytes_per_pic_denom")?,
+                max_bits_per_mb_denom: r.read_ue("max_bits_per_mb_denom")?,
+                log2_max_mv_length_horizontal: r.read_ue("log2_max_mv_length_horizontal")?,
+                log2_max_mv_length_vertical: r.read_ue("log2_max_mv_length_vertical")?,
+                max_num_reorder_frames: r.read_ue("max_num_reorder_frames")?,
+                max_dec_frame_buffering: r.read_ue("max_dec_frame_buffering")?,
+            });
+        }
+        Ok(vui)
+    }
+}
+
+/// A decoded Sequence Parameter Set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqParameterSet {
+    /// the _profile_idc_ field
+    pub profile_idc: u8,
+    /// the eight bits of _constraint_set0_flag_ to _constraint_set5_flag_ and _reserved_zero_2bits_
+    pub constraint_flags: u8,
+    /// the _level_idc_ field
+    pub level_idc: u8,
+    /// the _seq_parameter_set_id_ field
+    pub seq_parameter_set_id: u32,
+    /// the chroma format, which is 4:2:0 unless signalled otherwise by a 'high' profile
+    pub chroma_format: ChromaFormat,
+    /// the _separate_colour_plane_flag_ field
+    pub separate_colour_plane_flag: bool,
+    /// the _bit_depth_luma_minus8_ field
+    pub bit_depth_luma_minus8: u32,
+    /// the _bit_depth_chroma_minus8_ field
+    pub bit_depth_chroma_minus8: u32,
+    /// the _qpprime_y_zero_transform_bypass_flag_ field
+    pub qpprime_y_zero_transform_bypass_flag: bool,
+    /// the _seq_scaling_matrix_present_flag_ field (the scaling lists themselves are not
+    /// retained)
+    pub seq_scaling_matrix_present_flag: bool,
+    /// the _log2_max_frame_num_minus4_ field
+    pub log2_max_frame_num_minus4: u8,
+    /// the _pic_order_cnt_type_ field, and its associated values
+    pub pic_order_cnt: PicOrderCntType,
+    /// the _max_num_ref_frames_ field
+    pub max_num_ref_frames: u32,
+    /// the _gaps_in_frame_num_value_allowed_flag_ field
+    pub gaps_in_frame_num_value_allowed_flag: bool,
+    /// the _pic_width_in_mbs_minus1_ field
+    pub pic_width_in_mbs_minus1: u32,
+    /// the _pic_height_in_map_units_minus1_ field
+    pub pic_height_in_map_units_minus1: u32,
+    /// the _frame_mbs_only_flag_ field; `false` if the sequence may contain field pictures
+    pub frame_mbs_only_flag: bool,
+    /// the _mb_adaptive_frame_field_flag_ field
+    pub mb_adaptive_frame_field_flag: bool,
+    /// the _direct_8x8_inference_flag_ field
+    pub direct_8x8_inference_flag: bool,
+    /// the frame cropping rectangle, if present
+    pub frame_cropping: Option<FrameCropping>,
+    /// the VUI parameters, if present
+    pub vui_parameters: Option<VuiParameters>,
+}
+impl SeqParameterSet {
+    /// Decode the given Sequence Parameter Set NAL unit, starting with its NAL unit header byte
+    /// (as returned by [`DemuxedSegment::sps()`](../struct.DemuxedSegment.html#method.sps)).
+    pub fn from_bytes(data: &[u8]) -> Result<SeqParameterSet, ParamSetError> {
+        let rbsp = nal_rbsp(data, NAL_UNIT_TYPE_SPS)?;
+        let mut r = BitReader::new(&rbsp);
+        let profile_idc = r.read_u8(8, "profile_idc")?;
+        let constraint_flags = r.read_u8(8, "constraint_flags")?;
+        let level_idc = r.read_u8(8, "level_idc")?;
+        let seq_parameter_set_id = read_ue_max(&mut r, 31, "seq_parameter_set_id")?;
+        let mut sps = SeqParameterSet {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            seq_parameter_set_id,
+            chroma_format: ChromaFormat::YUV420,
+            separate_colour_plane_flag: false,
+            bit_depth_luma_minus8: 0,
+            bit_depth_chroma_minus8: 0,
+            qpprime_y_zero_transform_bypass_flag: false,
+            seq_scaling_matrix_present_flag: false,
+            log2_max_frame_num_minus4: 0,
+            pic_order_cnt: PicOrderCntType::TypeTwo,
+            max_num_ref_frames: 0,
+            gaps_in_frame_num_value_allowed_flag: false,
+            pic_width_in_mbs_minus1: 0,
+            pic_height_in_map_units_minus1: 0,
+            frame_mbs_only_flag: true,
+            mb_adaptive_frame_field_flag: false,
+            direct_8x8_inference_flag: false,
+            frame_cropping: None,
+            vui_parameters: None,
+        };
+        if sps.has_chroma_info() {
+            let chroma_format_idc = read_ue_max(&mut r, 3, "chroma_format_idc")?;
+            sps.chroma_format = ChromaFormat::from_idc(chroma_format_idc);
+            if sps.chroma_format == ChromaFormat::YUV444 {
+                sps.separate_colour_plane_flag = r.read_bool("separate_colour_plane_flag")?;
+            }
+            sps.bit_depth_luma_minus8 = read_ue_max(&mut r, 6, "bit_depth_luma_minus8")?;
+            sps.bit_depth_chroma_minus8 = read_ue_max(&mut r, 6, "bit_depth_chroma_minus8")?;
+            sps.qpprime_y_zero_transform_bypass_flag =
+                r.read_bool("qpprime_y_zero_transform_bypass_flag")?;
+            sps.seq_scaling_matrix_present_flag = r.read_bool("seq_scaling_matrix_present_flag")?;
+            if sps.seq_scaling_matrix_present_flag {
+                let count = if sps.chroma_format == ChromaFormat::YUV444 {
+                    12
+                } else {
+                    8
+                };
+                skip_scaling_matrix(&mut r, count)?;
+            }
+        }
+        sps.log2_max_frame_num_minus4 = read_ue_max(&mut r, 12, "log2_max_frame_num_minus4")? as u8;
+        sps.pic_order_cnt = PicOrderCntType::read(&mut r)?;
+        sps.max_num_ref_frames = r.read_ue("max_num_ref_frames")?;
+        sps.gaps_in_frame_num_value_allowed_flag =
+            r.read_bool("gaps_in_frame_num_value_allowed_flag")?;
+        sps.pic_width_in_mbs_minus1 = read_ue_max(&mut r, 1023, "pic_width_in_mbs_minus1")?;
+        sps.pic_height_in_map_units_minus1 =
+            read_ue_max(&mut r, 1023, "pic_height_in_map_units_minus1")?;
+        sps.frame_mbs_only_flag = r.read_bool("frame_mbs_only_flag")?;
+        if !sps.frame_mbs_only_flag {
+            sps.mb_adaptive_frame_field_flag = r.read_bool("mb_adaptive_frame_field_flag")?;
+        }
+        sps.direct_8x8_inference_flag = r.read_bool("direct_8x8_inference_flag")?;
+        if r.read_bool("frame_cropping_flag")? {
+            sps.frame_cropping = Some(FrameCropping {
+                left_offset: r.read_ue("frame_crop_left_offset")?,
+                right_offset: r.read_ue("frame_crop_right_offset")?,
+                top_offset: r.read_ue("frame_crop_top_offset")?,
+                bottom_offset: r.read_ue("frame_crop_bottom_offset")?,
+            });
+        }
+        if r.read_bool("vui_parameters_present_flag")? {
+            sps.vui_parameters = Some(VuiParameters::read(&mut r)?);
+        }
+        Ok(sps)
+    }
+
+    /// The 'high' profiles include extra fields giving the chroma format and bit depth
+    fn has_chroma_info(&self) -> bool {
+        matches!(
+            self.profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        )
+    }
+
+    /// The `ChromaArrayType` variable, which is `0` when the colour planes are coded separately
+    fn chroma_array_type(&self) -> ChromaFormat {
+        if self.separate_colour_plane_flag {
+            ChromaFormat::Monochrome
+        } else {
+            self.chroma_format
+        }
+    }
+
+    /// `(CropUnitX, CropUnitY)`
+    fn crop_units(&self) -> (u32, u32) {
+        let field_factor = 2 - self.frame_mbs_only_flag as u32;
+        match self.chroma_array_type().subsampling() {
+            None => (1, field_factor),
+            Some((sub_width, sub_height)) => (sub_width, sub_height * field_factor),
+        }
+    }
+
+    /// The width of the decoded pictures in luma samples, after cropping.
+    pub fn width(&self) -> u32 {
+        let width = (self.pic_width_in_mbs_minus1 + 1) * 16;
+        match self.frame_cropping {
+            None => width,
+            Some(ref crop) => {
+                let (crop_unit_x, _) = self.crop_units();
+                width.saturating_sub(crop_unit_x * (crop.left_offset + crop.right_offset))
+            }
+        }
+    }
+
+    /// The height of the decoded frames in luma samples, after cropping.
+    pub fn height(&self) -> u32 {
+        let frame_height_in_mbs =
+            (2 - self.frame_mbs_only_flag as u32) * (self.pic_height_in_map_units_minus1 + 1);
+        let height = frame_height_in_mbs * 16;
+        match self.frame_cropping {
+            None => height,
+            Some(ref crop) => {
+                let (_, crop_unit_y) = self.crop_units();
+                height.saturating_sub(crop_unit_y * (crop.top_offset + crop.bottom_offset))
+            }
+        }
+    }
+
+    /// The number of bits used for the _frame_num_ field of slice headers.
+    pub fn log2_max_frame_num(&self) -> u32 {
+        u32::from(self.log2_max_frame_num_minus4) + 4
+    }
+
+    /// The _max_num_reorder_frames_ value from the VUI bitstream restrictions, if present.
+    pub fn max_num_reorder_frames(&self) -> Option<u32> {
+        self.vui_parameters
+            .as_ref()
+            .and_then(|vui| vui.bitstream_restrictions.as_ref())
+            .map(|r| r.max_num_reorder_frames)
+    }
+
+    /// The frame rate implied by the VUI timing information, if present.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.vui_parameters
+            .as_ref()
+            .and_then(|vui| vui.timing_info.as_ref())
+            .filter(|t| t.num_units_in_tick != 0)
+            .map(|t| f64::from(t.time_scale) / (2.0 * f64::from(t.num_units_in_tick)))
+    }
+
+    /// The RFC 6381 codec string for this sequence, for example `"avc1.64001F"`.
+    pub fn codec_string(&self) -> String {
+        format!(
+            "avc1.{:02X}{:02X}{:02X}",
+            self.profile_idc, self.constraint_flags, self.level_idc
+        )
+    }
+}
+
+/// Slice group details of a Picture Parameter Set using Flexible Macroblock Ordering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceGroups {
+    /// the _num_slice_groups_minus1_ field
+    pub num_slice_groups_minus1: u32,
+    /// the _slice_group_map_type_ field
+    pub slice_group_map_type: u32,
+    /// the _slice_group_change_rate_minus1_ field, present for map types 3 to 5
+    pub slice_group_change_rate_minus1: Option<u32>,
+    /// the _pic_size_in_map_units_minus1_ field, present for map type 6
+    pub pic_size_in_map_units_minus1: Option<u32>,
+}
+impl SliceGroups {
+    fn read(
+        r: &mut BitReader<'_>,
+        num_slice_groups_minus1: u32,
+    ) -> Result<SliceGroups, ParamSetError> {
+        let mut groups = SliceGroups {
+            num_slice_groups_minus1,
+            slice_group_map_type: read_ue_max(r, 6, "slice_group_map_type")?,
+            slice_group_change_rate_minus1: None,
+            pic_size_in_map_units_minus1: None,
+        };
+        match groups.slice_group_map_type {
+            0 => {
+                for _ in 0..=num_slice_groups_minus1 {
+                    r.read_ue("run_length_minus1")?;
+                }
+            }
+            2 => {
+                for _ in 0..num_slice_groups_minus1 {
+                    r.read_ue("top_left")?;
+                    r.read_ue("bottom_right")?;
+                }
+            }
+            3..=5 => {
+                r.read_bool("slice_group_change_direction_flag")?;
+                groups.slice_group_change_rate_minus1 =
+                    Some(r.read_ue("slice_group_change_rate_minus1")?);
+            }
+            6 => {
+                let size = r.read_ue("pic_size_in_map_units_minus1")?;
+                groups.pic_size_in_map_units_minus1 = Some(size);
+                let bits = 32 - num_slice_groups_minus1.leading_zeros();
+                for _ in 0..=size {
+                    r.read_u32(bits, "slice_group_id")?;
+                }
+            }
+            _ => (),
+        }
+        Ok(groups)
+    }
+}
+
+/// A decoded Picture Parameter Set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PicParameterSet {
+    /// the _pic_parameter_set_id_ field
+    pub pic_parameter_set_id: u32,
+    /// the _seq_parameter_set_id_ field
+    pub seq_parameter_set_id: u32,
+    /// the _entropy_coding_mode_flag_ field; `true` for CABAC, `false` for CAVLC
+    pub entropy_coding_mode_flag: bool,
+    /// the _bottom_field_pic_order_in_frame_present_flag_ field
+    pub bottom_field_pic_order_in_frame_present_flag: bool,
+    /// slice group details, present if there is more than one slice group
+    pub slice_groups: Option<SliceGroups>,
+    /// the _num_ref_idx_l0_default_active_minus1_ field
+    pub num_ref_idx_l0_default_active_minus1: u32,
+    /// the _num_ref_idx_l1_default_active_minus1_ field
+    pub num_ref_idx_l1_default_active_minus1: u32,
+    /// the _weighted_pred_flag_ field
+    pub weighted_pred_flag: bool,
+    /// the _weighted_bipred_idc_ field
+    pub weighted_bipred_idc: u8,
+    /// the _pic_init_qp_minus26_ field
+    pub pic_init_qp_minus26: i32,
+    /// the _pic_init_qs_minus26_ field
+    pub pic_init_qs_minus26: i32,
+    /// the _chroma_qp_index_offset_ field
+    pub chroma_qp_index_offset: i32,
+    /// the _deblocking_filter_control_present_flag_ field
+    pub deblocking_filter_control_present_flag: bool,
+    /// the _constrained_intra_pred_flag_ field
+    pub constrained_intra_pred_flag: bool,
+    /// the _redundant_pic_cnt_present_flag_ field
+    pub redundant_pic_cnt_present_flag: bool,
+    /// the _transform_8x8_mode_flag_ field, if present
+    pub transform_8x8_mode_flag: bool,
+    /// the _pic_scaling_matrix_present_flag_ field (the scaling lists themselves are not
+    /// retained)
+    pub pic_scaling_matrix_present_flag: bool,
+    /// the _second_chroma_qp_index_offset_ field, which is equal to `chroma_qp_index_offset` if
+    /// not present
+    pub second_chroma_qp_index_offset: i32,
+}
+impl PicParameterSet {
+    /// Decode the given Picture Parameter Set NAL unit, starting with its NAL unit header byte.
+    ///
+    /// The Sequence Parameter Set that the PPS refers to must already have been added to
+    /// `param_sets`, since the interpretation of some fields depends on it.
+    pub fn from_bytes(
+        data: &[u8],
+        param_sets: &ParamSets,
+    ) -> Result<PicParameterSet, ParamSetError> {
+        let rbsp = nal_rbsp(data, NAL_UNIT_TYPE_PPS)?;
+        let mut r = BitReader::new(&rbsp);
+        let pic_parameter_set_id = read_ue_max(&mut r, 255, "pic_parameter_set_id")?;
+        let seq_parameter_set_id = read_ue_max(&mut r, 31, "seq_parameter_set_id")?;
+        let sps = param_sets.sps(seq_parameter_set_id).ok_or(
+            ParamSetError::UndefinedSeqParameterSetId(seq_parameter_set_id),
+        )?;
+        let entropy_coding_mode_flag = r.read_bool("entropy_coding_mode_flag")?;
+        let bottom_field_pic_order_in_frame_present_flag =
+            r.read_bool("bottom_field_pic_order_in_frame_present_flag")?;
+        let num_slice_groups_minus1 = read_ue_max(&mut r, 7, "num_slice_groups_minus1")?;
+        let slice_groups = if num_slice_groups_minus1 > 0 {
+            Some(SliceGroups::read(&mut r, num_slice_groups_minus1)?)
+        } else {
+            None
+        };
+        let num_ref_idx_l0_default_active_minus1 =
+            read_ue_max(&mut r, 31, "num_ref_idx_l0_default_active_minus1")?;
+        let num_ref_idx_l1_default_active_minus1 =
+            read_ue_max(&mut r, 31, "num_ref_idx_l1_default_active_minus1")?;
+        let weighted_pred_flag = r.read_bool("weighted_pred_flag")?;
+        let weighted_bipred_idc = r.read_u8(2, "weighted_bipred_idc")?;
+        let pic_init_qp_minus26 = r.read_se("pic_init_qp_minus26")?;
+        let pic_init_qs_minus26 = r.read_se("pic_init_qs_minus26")?;
+        let chroma_qp_index_offset = r.read_se("chroma_qp_index_offset")?;
+        let deblocking_filter_control_present_flag =
+            r.read_bool("deblocking_filter_control_present_flag")?;
+        let constrained_intra_pred_flag = r.read_bool("constrained_intra_pred_flag")?;
+        let redundant_pic_cnt_present_flag = r.read_bool("redundant_pic_cnt_present_flag")?;
+        let mut pps = PicParameterSet {
+            pic_parameter_set_id,
+            seq_parameter_set_id,
+            entropy_coding_mode_flag,
+            bottom_field_pic_order_in_frame_present_flag,
+            slice_groups,
+            num_ref_idx_l0_default_active_minus1,
+            num_ref_idx_l1_default_active_minus1,
+            weighted_pred_flag,
+            weighted_bipred_idc,
+            pic_init_qp_minus26,
+            pic_init_qs_minus26,
+            chroma_qp_index_offset,
+            deblocking_filter_control_present_flag,
+            constrained_intra_pred_flag,
+            redundant_pic_cnt_present_flag,
+            transform_8x8_mode_flag: false,
+            pic_scaling_matrix_present_flag: false,
+            second_chroma_qp_index_offset: chroma_qp_index_offset,
+        };
+        if r.has_more_rbsp_data() {
+            pps.transform_8x8_mode_flag = r.read_bool("transform_8x8_mode_flag")?;
+            pps.pic_scaling_matrix_present_flag = r.read_bool("pic_scaling_matrix_present_flag")?;
+            if pps.pic_scaling_matrix_present_flag {
+                let lists_8x8 = if !pps.transform_8x8_mode_flag {
+                    0
+                } else if sps.chroma_format == ChromaFormat::YUV444 {
+                    6
+                } else {
+                    2
+                };
+                skip_scaling_matrix(&mut r, 6 + lists_8x8)?;
+            }
+            pps.second_chroma_qp_index_offset = r.read_se("second_chroma_qp_index_offset")?;
+        }
+        Ok(pps)
+    }
+}
+
+/// The Sequence and Picture Parameter Sets seen so far in a stream, indexed by their ids.
+#[derive(Debug, Clone)]
+pub struct ParamSets {
+    sps: Vec<Option<SeqParameterSet>>,
+    pps: Vec<Option<PicParameterSet>>,
+}
+impl Default for ParamSets {
+    fn default() -> Self {
+        ParamSets {
+            sps: vec![None; 32],
+            pps: vec![None; 256],
+        }
+    }
+}
+impl ParamSets {
+    /// Create an instance holding no parameter sets.
+    pub fn new() -> ParamSets {
+        ParamSets::default()
+    }
+
+    /// Decode the given SPS NAL unit, and store it (replacing any previous SPS with the same
+    /// id).
+    pub fn put_sps(&mut self, data: &[u8]) -> Result<&SeqParameterSet, ParamSetError> {
+        let sps = SeqParameterSet::from_bytes(data)?;
+        let id = sps.seq_parameter_set_id as usize;
+        Ok(self.sps[id].insert(sps))
+    }
+
+    /// Decode the given PPS NAL unit, and store it (replacing any previous PPS with the same
+    /// id).
+    pub fn put_pps(&mut self, data: &[u8]) -> Result<&PicParameterSet, ParamSetError> {
+        let pps = PicParameterSet::from_bytes(data, self)?;
+        let id = pps.pic_parameter_set_id as usize;
+        Ok(self.pps[id].insert(pps))
+    }
+
+    /// The SPS with the given _seq_parameter_set_id_, if one has been seen.
+    pub fn sps(&self, id: u32) -> Option<&SeqParameterSet> {
+        self.sps.get(id as usize).and_then(|s| s.as_ref())
+    }
+
+    /// The PPS with the given _pic_parameter_set_id_, if one has been seen.
+    pub fn pps(&self, id: u32) -> Option<&PicParameterSet> {
+        self.pps.get(id as usize).and_then(|p| p.as_ref())
+    }
+}
+
+/// The _slice_type_ of a slice, giving the kinds of prediction its macroblocks may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceType {
+    /// intra prediction, or inter prediction from one reference picture per block
+    P,
+    /// intra prediction, or inter prediction from up to two reference pictures per block
+    B,
+    /// intra prediction only
+    I,
+    /// a 'switching' P slice
+    SP,
+    /// a 'switching' I slice
+    SI,
+}
+impl SliceType {
+    fn from_id(id: u32) -> SliceType {
+        match id % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::SP,
+            _ => SliceType::SI,
+        }
+    }
+}
+
+/// The leading fields of a slice header, up to and including those giving the picture order
+/// count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceHeader {
+    /// the address of the first macroblock in the slice
+    pub first_mb_in_slice: u32,
+    /// the kind of slice
+    pub slice_type: SliceType,
+    /// `true` if the _slice_type_ value (being in the range 5 to 9) indicates that all other
+    /// slices of the picture have the same type
+    pub slice_type_fixed: bool,
+    /// the _pic_parameter_set_id_ field
+    pub pic_parameter_set_id: u32,
+    /// the _colour_plane_id_ field, present if the SPS has _separate_colour_plane_flag_ set
+    pub colour_plane_id: Option<u8>,
+    /// the _frame_num_ field
+    pub frame_num: u32,
+    /// `true` if the slice belongs to a field, rather than a frame
+    pub field_pic_flag: bool,
+    /// `true` if the slice belongs to the bottom field
+    pub bottom_field_flag: bool,
+    /// the _idr_pic_id_ field, present only for slices of IDR pictures
+    pub idr_pic_id: Option<u32>,
+    /// the _pic_order_cnt_lsb_ field, present when the SPS uses _pic_order_cnt_type_ 0
+    pub pic_order_cnt_lsb: Option<u32>,
+    /// the _delta_pic_order_cnt_bottom_ field, or `0` if absent
+    pub delta_pic_order_cnt_bottom: i32,
+    /// the _delta_pic_order_cnt_ values, or `0` if absent
+    pub delta_pic_order_cnt: [i32; 2],
+}
+impl SliceHeader {
+    /// Decode the start of the slice header of the given slice NAL unit, starting with its NAL
+    /// unit header byte.
+    ///
+    /// The PPS and SPS that the slice refers to must already have been added to `param_sets`.
+    pub fn from_bytes(data: &[u8], param_sets: &ParamSets) -> Result<SliceHeader, ParamSetError> {
+        let nal_unit_type = match data.first() {
+            Some(header) => header & 0b1_1111,
+            None => {
+                return Err(ParamSetError::WrongNalUnitType {
+                    expected: NAL_UNIT_TYPE_NON_IDR,
+                    actual: None,
+                })
+            }
+        };
+        if nal_unit_type != NAL_UNIT_TYPE_NON_IDR && nal_unit_type != NAL_UNIT_TYPE_IDR {
+            return Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_NON_IDR,
+                actual: Some(nal_unit_type),
+            });
+        }
+        // the fields needed fit easily in this many bytes, and there's no need to remove the
+        // emulation prevention bytes from the whole of a large slice
+        const MAX_HEADER_SIZE: usize = 64;
+        let rbsp = rbsp::decode_nal(&data[1..data.len().min(1 + MAX_HEADER_SIZE)]);
+        let mut r = BitReader::new(&rbsp);
+        let first_mb_in_slice = r.read_ue("first_mb_in_slice")?;
+        let slice_type_id = read_ue_max(&mut r, 9, "slice_type")?;
+        let pic_parameter_set_id = read_ue_max(&mut r, 255, "pic_parameter_set_id")?;
+        let pps = param_sets.pps(pic_parameter_set_id).ok_or(
+            ParamSetError::UndefinedPicParameterSetId(pic_parameter_set_id),
+        )?;
+        let sps = param_sets.sps(pps.seq_parameter_set_id).ok_or(
+            ParamSetError::UndefinedSeqParameterSetId(pps.seq_parameter_set_id),
+        )?;
+        let colour_plane_id = if sps.separate_colour_plane_flag {
+            Some(r.read_u8(2, "colour_plane_id")?)
+        } else {
+            None
+        };
+        let frame_num = r.read_u32(sps.log2_max_frame_num(), "frame_num")?;
+        let mut field_pic_flag = false;
+        let mut bottom_field_flag = false;
+        if !sps.frame_mbs_only_flag {
+            field_pic_flag = r.read_bool("field_pic_flag")?;
+            if field_pic_flag {
+                bottom_field_flag = r.read_bool("bottom_field_flag")?;
+            }
+        }
+        let idr_pic_id = if nal_unit_type == NAL_UNIT_TYPE_IDR {
+            Some(read_ue_max(&mut r, 65535, "idr_pic_id")?)
+        } else {
+            None
+        };
+        let bottom_present = pps.bottom_field_pic_order_in_frame_present_flag && !field_pic_flag;
+        let mut header = SliceHeader {
+            first_mb_in_slice,
+            slice_type: SliceType::from_id(slice_type_id),
+            slice_type_fixed: slice_type_id >= 5,
+            pic_parameter_set_id,
+            colour_plane_id,
+            frame_num,
+            field_pic_flag,
+            bottom_field_flag,
+            idr_pic_id,
+            pic_order_cnt_lsb: None,
+            delta_pic_order_cnt_bottom: 0,
+            delta_pic_order_cnt: [0, 0],
+        };
+        match sps.pic_order_cnt {
+            PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4,
+            } => {
+                let bits = u32::from(log2_max_pic_order_cnt_lsb_minus4) + 4;
+                header.pic_order_cnt_lsb = Some(r.read_u32(bits, "pic_order_cnt_lsb")?);
+                if bottom_present {
+                    header.delta_pic_order_cnt_bottom = r.read_se("delta_pic_order_cnt_bottom")?;
+                }
+            }
+            PicOrderCntType::TypeOne {
+                delta_pic_order_always_zero_flag: false,
+                ..
+            } => {
+                header.delta_pic_order_cnt[0] = r.read_se("delta_pic_order_cnt")?;
+                if bottom_present {
+                    header.delta_pic_order_cnt[1] = r.read_se("delta_pic_order_cnt")?;
+                }
+            }
+            _ => {}
+        }
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_matches::assert_matches;
+    use hex_literal::hex;
+
+    // from the sample stream, a.dat
+    const BASELINE_SPS: [u8; 26] =
+        hex!("6742c01f da0360f7 9fc05a80 8080a000 007d2000 3a9801e3 0654");
+    const BASELINE_PPS: [u8; 4] = hex!("68ce0fc8");
+    // High profile, interlaced, with scaling lists, frame cropping, extended SAR, HRD
+    // parameters and bitstream restrictions
+    const HIGH_SPS: [u8; 42] = hex!(
+        "67640028 ad844108 b6501e01 13f7ff80 02000188 00001f48 00075307 00007d20
+         07d15ed5 c1da0884 5960"
+    );
+    // CABAC, with transform_8x8_mode_flag and a scaling matrix
+    const HIGH_PPS: [u8; 8] = hex!("685ae32c c0422580");
+
+    #[test]
+    fn baseline_sps() {
+        let sps = SeqParameterSet::from_bytes(&BASELINE_SPS).unwrap();
+        assert_eq!(sps.profile_idc, 66);
+        assert_eq!(sps.constraint_flags, 0xc0);
+        assert_eq!(sps.level_idc, 31);
+        assert_eq!(sps.seq_parameter_set_id, 0);
+        assert_eq!(sps.chroma_format, ChromaFormat::YUV420);
+        assert_eq!(sps.pic_order_cnt, PicOrderCntType::TypeTwo);
+        assert!(sps.frame_mbs_only_flag);
+        assert_eq!(
+            sps.frame_cropping,
+            Some(FrameCropping {
+                left_offset: 0,
+                right_offset: 6,
+                top_offset: 0,
+                bottom_offset: 0,
+            })
+        );
+        assert_eq!((sps.width(), sps.height()), (852, 480));
+        assert_eq!(sps.codec_string(), "avc1.42C01F");
+        let vui = sps.vui_parameters.as_ref().unwrap();
+        assert_eq!(vui.aspect_ratio_idc, Some(1));
+        assert_eq!(vui.colour_description, Some((1, 1, 1)));
+        assert_eq!(
+            vui.timing_info,
+            Some(TimingInfo {
+                num_units_in_tick: 1001,
+                time_scale: 120000,
+                fixed_frame_rate_flag: false,
+            })
+        );
+        assert!((sps.frame_rate().unwrap() - 59.94).abs() < 0.01);
+        assert_eq!(sps.max_num_reorder_frames(), Some(0));
+    }
+
+    #[test]
+    fn high_sps() {
+        let sps = SeqParameterSet::from_bytes(&HIGH_SPS).unwrap();
+        assert_eq!(sps.profile_idc, 100);
+        assert_eq!(sps.level_idc, 40);
+        assert_eq!(sps.chroma_format, ChromaFormat::YUV420);
+        assert!(sps.seq_scaling_matrix_present_flag);
+        assert_eq!(
+            sps.pic_order_cnt,
+            PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4: 2
+            }
+        );
+        assert_eq!(sps.max_num_ref_frames, 4);
+        assert!(!sps.frame_mbs_only_flag);
+        assert!(sps.mb_adaptive_frame_field_flag);
+        // 1088 lines less 2 * CropUnitY of 4
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!(sps.codec_string(), "avc1.640028");
+        let vui = sps.vui_parameters.as_ref().unwrap();
+        assert_eq!(vui.aspect_ratio_idc, Some(255));
+        assert_eq!(vui.sample_aspect_ratio, Some((4, 3)));
+        assert_eq!(
+            vui.nal_hrd_parameters,
+            Some(HrdParameters {
+                cpb_cnt_minus1: 0,
+                initial_cpb_removal_delay_length_minus1: 23,
+                cpb_removal_delay_length_minus1: 22,
+                dpb_output_delay_length_minus1: 21,
+                time_offset_length: 24,
+            })
+        );
+        assert_eq!(vui.vcl_hrd_parameters, None);
+        assert_eq!(vui.low_delay_hrd_flag, Some(false));
+        assert!(vui.pic_struct_present_flag);
+        assert_eq!(sps.max_num_reorder_frames(), Some(2));
+        assert!((sps.frame_rate().unwrap() - 29.97).abs() < 0.01);
+    }
+
+    #[test]
+    fn wrong_nal_unit_type() {
+        assert_eq!(
+            SeqParameterSet::from_bytes(&BASELINE_PPS),
+            Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_SPS,
+                actual: Some(NAL_UNIT_TYPE_PPS),
+            })
+        );
+        assert_eq!(
+            SeqParameterSet::from_bytes(&[]),
+            Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_SPS,
+                actual: None,
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_sps() {
+        assert_matches!(
+            SeqParameterSet::from_bytes(&BASELINE_SPS[..10]),
+            Err(ParamSetError::Bits(BitReaderError::NotEnoughData { .. }))
+        );
+    }
+
+    #[test]
+    fn baseline_pps() {
+        let mut param_sets = ParamSets::new();
+        assert_eq!(
+            PicParameterSet::from_bytes(&BASELINE_PPS, &param_sets),
+            Err(ParamSetError::UndefinedSeqParameterSetId(0))
+        );
+        param_sets.put_sps(&BASELINE_SPS).unwrap();
+        let pps = param_sets.put_pps(&BASELINE_PPS).unwrap().clone();
+        assert_eq!(pps.pic_parameter_set_id, 0);
+        assert_eq!(pps.seq_parameter_set_id, 0);
+        assert!(!pps.entropy_coding_mode_flag);
+        assert!(pps.slice_groups.is_none());
+        assert_eq!(pps.pic_init_qp_minus26, -3);
+        assert_eq!(pps.chroma_qp_index_offset, 0);
+        assert_eq!(pps.second_chroma_qp_index_offset, 0);
+        assert!(pps.deblocking_filter_control_present_flag);
+        assert!(!pps.transform_8x8_mode_flag);
+        assert_eq!(param_sets.pps(0), Some(&pps));
+        assert_eq!(param_sets.pps(1), None);
+    }
+
+    #[test]
+    fn high_pps() {
+        let mut param_sets = ParamSets::new();
+        param_sets.put_sps(&HIGH_SPS).unwrap();
+        let pps = param_sets.put_pps(&HIGH_PPS).unwrap();
+        assert_eq!(pps.pic_parameter_set_id, 1);
+        assert!(pps.entropy_coding_mode_flag);
+        assert_eq!(pps.num_ref_idx_l0_default_active_minus1, 2);
+        assert!(pps.transform_8x8_mode_flag);
+        assert!(pps.pic_scaling_matrix_present_flag);
+        assert_eq!(pps.chroma_qp_index_offset, -2);
+        assert_eq!(pps.second_chroma_qp_index_offset, -2);
+    }
+
+    #[test]
+    fn baseline_slices() {
+        let mut param_sets = ParamSets::new();
+        assert_eq!(
+            SliceHeader::from_bytes(&hex!("6588843f"), &param_sets),
+            Err(ParamSetError::UndefinedPicParameterSetId(0))
+        );
+        param_sets.put_sps(&BASELINE_SPS).unwrap();
+        param_sets.put_pps(&BASELINE_PPS).unwrap();
+        // the IDR slice and the following P slice from the sample stream
+        let idr = SliceHeader::from_bytes(&hex!("6588843f 11bbc920"), &param_sets).unwrap();
+        assert_eq!(idr.first_mb_in_slice, 0);
+        assert_eq!(idr.slice_type, SliceType::I);
+        assert!(idr.slice_type_fixed);
+        assert_eq!(idr.frame_num, 0);
+        assert_eq!(idr.idr_pic_id, Some(0));
+        assert_eq!(idr.pic_order_cnt_lsb, None);
+        let p = SliceHeader::from_bytes(&hex!("419a204f 3f5783ce"), &param_sets).unwrap();
+        assert_eq!(p.slice_type, SliceType::P);
+        assert_eq!(p.frame_num, 1);
+        assert_eq!(p.idr_pic_id, None);
+        assert_eq!(
+            SliceHeader::from_bytes(&BASELINE_PPS, &param_sets),
+            Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_NON_IDR,
+                actual: Some(NAL_UNIT_TYPE_PPS),
+            })
+        );
+    }
+
+    #[test]
+    fn high_slices() {
+        let mut param_sets = ParamSets::new();
+        param_sets.put_sps(&HIGH_SPS).unwrap();
+        param_sets.put_pps(&HIGH_PPS).unwrap();
+        let b = SliceHeader::from_bytes(&hex!("019d18ac"), &param_sets).unwrap();
+        assert_eq!(b.slice_type, SliceType::B);
+        assert!(b.slice_type_fixed);
+        assert_eq!(b.pic_parameter_set_id, 1);
+        assert_eq!(b.frame_num, 3);
+        assert!(!b.field_pic_flag);
+        assert_eq!(b.pic_order_cnt_lsb, Some(10));
+        // a bottom field I slice, starting part way through the picture
+        let i = SliceHeader::from_bytes(&hex!("2100c6b4 7970"), &param_sets).unwrap();
+        assert_eq!(i.first_mb_in_slice, 396);
+        assert_eq!(i.slice_type, SliceType::I);
+        assert!(!i.slice_type_fixed);
+        assert!(i.field_pic_flag && i.bottom_field_flag);
+        assert_eq!(i.pic_order_cnt_lsb, Some(11));
+    }
+
+    #[test]
+    fn slice_groups() {
+        let mut param_sets = ParamSets::new();
+        param_sets.put_sps(&BASELINE_SPS).unwrap();
+        let pps = param_sets.put_pps(&hex!("68719c83 18e2")).unwrap();
+        assert_eq!(pps.pic_parameter_set_id, 2);
+        assert_eq!(
+            pps.slice_groups,
+            Some(SliceGroups {
+                num_slice_groups_minus1: 2,
+                slice_group_map_type: 6,
+                slice_group_change_rate_minus1: None,
+                pic_size_in_map_units_minus1: Some(3),
+            })
+        );
+        assert!(!pps.transform_8x8_mode_flag);
+    }
+}
@@ -0,0 +1,399 @@
+//! Support for the NAL units of H.265 / HEVC streams, per _ITU-T H.265_ section 7.3.1.
+//!
+//! HEVC uses the same Annex B byte stream format as H.264 (so the
+//! [`AnnexBSplitter`](../annexb/struct.AnnexBSplitter.html) can be used to find NAL units) but
+//! has a two-byte NAL unit header, described by [`NalHeader`](struct.NalHeader.html).
+//!
+//! To produce [`DemuxedSegment`](../struct.DemuxedSegment.html)s from an HEVC stream, use
+//! [`NALParser::new_hevc()`](../struct.NALParser.html#method.new_hevc).
+//!
+//! The leading fields of the Sequence Parameter Set, which describe the profile, level,
+//! resolution and bit depth of the stream, can be decoded with
+//! [`SeqParameterSet`](struct.SeqParameterSet.html).
+
+use crate::parser::annexb::NalUnit;
+use crate::parser::h264::{read_ue_max, ChromaFormat, ParamSetError};
+use crate::parser::rbsp::{self, BitReader};
+
+/// _nal_unit_type_ of the first kind of IRAP picture, _BLA_W_LP_
+pub const NAL_UNIT_TYPE_BLA_W_LP: u8 = 16;
+/// _nal_unit_type_ of an IDR picture which may have associated RADL pictures, _IDR_W_RADL_
+pub const NAL_UNIT_TYPE_IDR_W_RADL: u8 = 19;
+/// _nal_unit_type_ of an IDR picture without leading pictures, _IDR_N_LP_
+pub const NAL_UNIT_TYPE_IDR_N_LP: u8 = 20;
+/// _nal_unit_type_ of a Clean Random Access picture, _CRA_NUT_
+pub const NAL_UNIT_TYPE_CRA: u8 = 21;
+/// _nal_unit_type_ of a Video Parameter Set
+pub const NAL_UNIT_TYPE_VPS: u8 = 32;
+/// _nal_unit_type_ of a Sequence Parameter Set
+pub const NAL_UNIT_TYPE_SPS: u8 = 33;
+/// _nal_unit_type_ of a Picture Parameter Set
+pub const NAL_UNIT_TYPE_PPS: u8 = 34;
+/// _nal_unit_type_ of an Access Unit Delimiter
+pub const NAL_UNIT_TYPE_AUD: u8 = 35;
+/// _nal_unit_type_ of an SEI message that precedes the picture it applies to
+pub const NAL_UNIT_TYPE_PREFIX_SEI: u8 = 39;
+/// _nal_unit_type_ of an SEI message that follows the picture it applies to
+pub const NAL_UNIT_TYPE_SUFFIX_SEI: u8 = 40;
+
+/// The two-byte HEVC NAL unit header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NalHeader {
+    /// the _nal_unit_type_ field
+    pub nal_unit_type: u8,
+    /// the _nuh_layer_id_ field, which is `0` except in multi-layer streams
+    pub nuh_layer_id: u8,
+    /// the _nuh_temporal_id_plus1_ field
+    pub nuh_temporal_id_plus1: u8,
+}
+impl NalHeader {
+    /// The size of the header; 2 bytes
+    pub const SIZE: usize = 2;
+
+    /// Parse the header of the given NAL unit, returning `None` if the NAL unit is too short to
+    /// have an HEVC header.
+    pub fn from_nal(nal: &NalUnit<'_>) -> Option<NalHeader> {
+        match nal.data() {
+            [b0, b1, ..] => Some(NalHeader {
+                nal_unit_type: b0 >> 1 & 0b11_1111,
+                nuh_layer_id: (b0 & 1) << 5 | b1 >> 3,
+                nuh_temporal_id_plus1: b1 & 0b111,
+            }),
+            _ => None,
+        }
+    }
+
+    /// `true` for NAL units containing a slice segment of a picture (types 0 to 31).
+    pub fn is_vcl(&self) -> bool {
+        self.nal_unit_type < 32
+    }
+
+    /// `true` for slices of _Intra Random Access Point_ pictures (BLA, IDR and CRA, and the
+    /// reserved IRAP types), from which decoding can start.
+    pub fn is_irap(&self) -> bool {
+        (NAL_UNIT_TYPE_BLA_W_LP..=23).contains(&self.nal_unit_type)
+    }
+
+    /// `true` for slices of IDR pictures.
+    pub fn is_idr(&self) -> bool {
+        self.nal_unit_type == NAL_UNIT_TYPE_IDR_W_RADL
+            || self.nal_unit_type == NAL_UNIT_TYPE_IDR_N_LP
+    }
+
+    /// `true` for prefix or suffix SEI NAL units.
+    pub fn is_sei(&self) -> bool {
+        self.nal_unit_type == NAL_UNIT_TYPE_PREFIX_SEI
+            || self.nal_unit_type == NAL_UNIT_TYPE_SUFFIX_SEI
+    }
+}
+
+/// The general profile, tier and level of a sequence, from the `profile_tier_level()`
+/// structure in the VPS and SPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileTierLevel {
+    /// the _general_profile_space_ field
+    pub general_profile_space: u8,
+    /// the _general_tier_flag_ field; `true` for the 'High' tier
+    pub general_tier_flag: bool,
+    /// the _general_profile_idc_ field (for example, `1` for the 'Main' profile)
+    pub general_profile_idc: u8,
+    /// the 32 _general_profile_compatibility_flag_ values, with flag 0 in the most significant
+    /// bit
+    pub general_profile_compatibility_flags: u32,
+    /// the 48 bits of constraint flags from _general_progressive_source_flag_ onwards
+    pub general_constraint_indicator_flags: u64,
+    /// the _general_level_idc_ field, which is 30 times the level number
+    pub general_level_idc: u8,
+}
+impl ProfileTierLevel {
+    fn read(
+        r: &mut BitReader<'_>,
+        max_sub_layers_minus1: u8,
+    ) -> Result<ProfileTierLevel, ParamSetError> {
+        let general_profile_space = r.read_u8(2, "general_profile_space")?;
+        let general_tier_flag = r.read_bool("general_tier_flag")?;
+        let general_profile_idc = r.read_u8(5, "general_profile_idc")?;
+        let general_profile_compatibility_flags =
+            r.read_u32(32, "general_profile_compatibility_flags")?;
+        let high = r.read_u32(16, "general_constraint_indicator_flags")?;
+        let low = r.read_u32(32, "general_constraint_indicator_flags")?;
+        let general_level_idc = r.read_u8(8, "general_level_idc")?;
+        let mut sub_layer_flags = vec![];
+        for _ in 0..max_sub_layers_minus1 {
+            let profile_present = r.read_bool("sub_layer_profile_present_flag")?;
+            let level_present = r.read_bool("sub_layer_level_present_flag")?;
+            sub_layer_flags.push((profile_present, level_present));
+        }
+        if max_sub_layers_minus1 > 0 {
+            r.skip(
+                2 * (8 - max_sub_layers_minus1 as usize),
+                "reserved_zero_2bits",
+            )?;
+        }
+        // the sub-layer details are not retained
+        for (profile_present, level_present) in sub_layer_flags {
+            if profile_present {
+                r.skip(88, "sub_layer_profile")?;
+            }
+            if level_present {
+                r.skip(8, "sub_layer_level_idc")?;
+            }
+        }
+        Ok(ProfileTierLevel {
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_constraint_indicator_flags: u64::from(high) << 32 | u64::from(low),
+            general_level_idc,
+        })
+    }
+}
+
+/// The conformance cropping window, in units of `SubWidthC` / `SubHeightC` luma samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceWindow {
+    /// the _conf_win_left_offset_ field
+    pub left_offset: u32,
+    /// the _conf_win_right_offset_ field
+    pub right_offset: u32,
+    /// the _conf_win_top_offset_ field
+    pub top_offset: u32,
+    /// the _conf_win_bottom_offset_ field
+    pub bottom_offset: u32,
+}
+
+/// The leading fields of an HEVC Sequence Parameter Set, up to and including the bit depths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqParameterSet {
+    /// the _sps_video_parameter_set_id_ field
+    pub sps_video_parameter_set_id: u8,
+    /// the _sps_max_sub_layers_minus1_ field
+    pub sps_max_sub_layers_minus1: u8,
+    /// the _sps_temporal_id_nesting_flag_ field
+    pub sps_temporal_id_nesting_flag: bool,
+    /// the general profile, tier and level of the sequence
+    pub profile_tier_level: ProfileTierLevel,
+    /// the _sps_seq_parameter_set_id_ field
+    pub sps_seq_parameter_set_id: u32,
+    /// the chroma format
+    pub chroma_format: ChromaFormat,
+    /// the _separate_colour_plane_flag_ field
+    pub separate_colour_plane_flag: bool,
+    /// the _pic_width_in_luma_samples_ field
+    pub pic_width_in_luma_samples: u32,
+    /// the _pic_height_in_luma_samples_ field
+    pub pic_height_in_luma_samples: u32,
+    /// the conformance cropping window, if present
+    pub conformance_window: Option<ConformanceWindow>,
+    /// the _bit_depth_luma_minus8_ field
+    pub bit_depth_luma_minus8: u32,
+    /// the _bit_depth_chroma_minus8_ field
+    pub bit_depth_chroma_minus8: u32,
+}
+impl SeqParameterSet {
+    /// Decode the given Sequence Parameter Set NAL unit, starting with its NAL unit header
+    /// (as returned by [`DemuxedSegment::sps()`](../struct.DemuxedSegment.html#method.sps)).
+    pub fn from_bytes(data: &[u8]) -> Result<SeqParameterSet, ParamSetError> {
+        let nal_unit_type = if data.len() >= NalHeader::SIZE {
+            Some(data[0] >> 1 & 0b11_1111)
+        } else {
+            None
+        };
+        if nal_unit_type != Some(NAL_UNIT_TYPE_SPS) {
+            return Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_SPS,
+                actual: nal_unit_type,
+            });
+        }
+        let rbsp = rbsp::decode_nal(&data[NalHeader::SIZE..]);
+        let mut r = BitReader::new(&rbsp);
+        let sps_video_parameter_set_id = r.read_u8(4, "sps_video_parameter_set_id")?;
+        let sps_max_sub_layers_minus1 = r.read_u8(3, "sps_max_sub_layers_minus1")?;
+        if sps_max_sub_layers_minus1 > 6 {
+            return Err(ParamSetError::FieldValueTooLarge {
+                field: "sps_max_sub_layers_minus1",
+                value: u32::from(sps_max_sub_layers_minus1),
+            });
+        }
+        let sps_temporal_id_nesting_flag = r.read_bool("sps_temporal_id_nesting_flag")?;
+        let profile_tier_level = ProfileTierLevel::read(&mut r, sps_max_sub_layers_minus1)?;
+        let sps_seq_parameter_set_id = read_ue_max(&mut r, 15, "sps_seq_parameter_set_id")?;
+        let chroma_format_idc = read_ue_max(&mut r, 3, "chroma_format_idc")?;
+        let separate_colour_plane_flag =
+            chroma_format_idc == 3 && r.read_bool("separate_colour_plane_flag")?;
+        let pic_width_in_luma_samples = r.read_ue("pic_width_in_luma_samples")?;
+        let pic_height_in_luma_samples = r.read_ue("pic_height_in_luma_samples")?;
+        let conformance_window = if r.read_bool("conformance_window_flag")? {
+            Some(ConformanceWindow {
+                left_offset: r.read_ue("conf_win_left_offset")?,
+                right_offset: r.read_ue("conf_win_right_offset")?,
+                top_offset: r.read_ue("conf_win_top_offset")?,
+                bottom_offset: r.read_ue("conf_win_bottom_offset")?,
+            })
+        } else {
+            None
+        };
+        Ok(SeqParameterSet {
+            sps_video_parameter_set_id,
+            sps_max_sub_layers_minus1,
+            sps_temporal_id_nesting_flag,
+            profile_tier_level,
+            sps_seq_parameter_set_id,
+            chroma_format: ChromaFormat::from_idc(chroma_format_idc),
+            separate_colour_plane_flag,
+            pic_width_in_luma_samples,
+            pic_height_in_luma_samples,
+            conformance_window,
+            bit_depth_luma_minus8: read_ue_max(&mut r, 8, "bit_depth_luma_minus8")?,
+            bit_depth_chroma_minus8: read_ue_max(&mut r, 8, "bit_depth_chroma_minus8")?,
+        })
+    }
+
+    /// `(SubWidthC, SubHeightC)`, the units of the conformance window offsets
+    fn crop_units(&self) -> (u32, u32) {
+        if self.separate_colour_plane_flag {
+            (1, 1)
+        } else {
+            self.chroma_format.subsampling().unwrap_or((1, 1))
+        }
+    }
+
+    /// The width of the decoded pictures in luma samples, after applying the conformance
+    /// window.
+    pub fn width(&self) -> u32 {
+        let (sub_width, _) = self.crop_units();
+        match self.conformance_window {
+            None => self.pic_width_in_luma_samples,
+            Some(ref win) => self
+                .pic_width_in_luma_samples
+                .saturating_sub(sub_width * (win.left_offset + win.right_offset)),
+        }
+    }
+
+    /// The height of the decoded pictures in luma samples, after applying the conformance
+    /// window.
+    pub fn height(&self) -> u32 {
+        let (_, sub_height) = self.crop_units();
+        match self.conformance_window {
+            None => self.pic_height_in_luma_samples,
+            Some(ref win) => self
+                .pic_height_in_luma_samples
+                .saturating_sub(sub_height * (win.top_offset + win.bottom_offset)),
+        }
+    }
+
+    /// The RFC 6381 codec string for this sequence, per _ISO/IEC 14496-15_ Annex E, for
+    /// example `"hvc1.1.6.L93.B0"`.
+    pub fn codec_string(&self) -> String {
+        let ptl = &self.profile_tier_level;
+        let profile_space = match ptl.general_profile_space {
+            1 => "A",
+            2 => "B",
+            3 => "C",
+            _ => "",
+        };
+        let mut codec = format!(
+            "hvc1.{}{}.{:X}.{}{}",
+            profile_space,
+            ptl.general_profile_idc,
+            ptl.general_profile_compatibility_flags.reverse_bits(),
+            if ptl.general_tier_flag { 'H' } else { 'L' },
+            ptl.general_level_idc
+        );
+        let constraints = ptl.general_constraint_indicator_flags.to_be_bytes();
+        // the six constraint bytes, omitting any trailing zero bytes
+        let count = constraints[2..]
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |i| i + 1);
+        for b in &constraints[2..2 + count] {
+            codec.push_str(&format!(".{:X}", b));
+        }
+        codec
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    // Main profile 1920x1080 with a conformance window
+    const MAIN_SPS: [u8; 26] = hex!("42010101 60000003 00900000 03000003 005da003 c0801107 cb96");
+    // Main 10 profile, High tier, 4:2:2, with two sub-layers
+    const MAIN10_SPS: [u8; 45] = hex!(
+        "42010322 20000003 00b00000 03000003 0078c000 00030000 03000003 00000300
+         0003 01235ab0 0280802d 136580"
+    );
+
+    #[test]
+    fn header() {
+        // IDR_W_RADL, layer 0, temporal id 0
+        let hdr = NalHeader::from_nal(&NalUnit::new(&[0x26, 0x01, 0xaf])).unwrap();
+        assert_eq!(
+            hdr,
+            NalHeader {
+                nal_unit_type: NAL_UNIT_TYPE_IDR_W_RADL,
+                nuh_layer_id: 0,
+                nuh_temporal_id_plus1: 1,
+            }
+        );
+        assert!(hdr.is_vcl() && hdr.is_irap() && hdr.is_idr() && !hdr.is_sei());
+
+        // CRA is IRAP but not IDR
+        let hdr = NalHeader::from_nal(&NalUnit::new(&[0x2a, 0x01])).unwrap();
+        assert_eq!(hdr.nal_unit_type, NAL_UNIT_TYPE_CRA);
+        assert!(hdr.is_irap() && !hdr.is_idr());
+
+        // suffix SEI with nuh_layer_id 33 and temporal id 2
+        let hdr = NalHeader::from_nal(&NalUnit::new(&[0x51, 0x0b])).unwrap();
+        assert_eq!(hdr.nal_unit_type, NAL_UNIT_TYPE_SUFFIX_SEI);
+        assert_eq!(hdr.nuh_layer_id, 33);
+        assert_eq!(hdr.nuh_temporal_id_plus1, 3);
+        assert!(hdr.is_sei() && !hdr.is_vcl());
+
+        assert_eq!(NalHeader::from_nal(&NalUnit::new(&[0x40])), None);
+    }
+
+    #[test]
+    fn main_sps() {
+        let sps = SeqParameterSet::from_bytes(&MAIN_SPS).unwrap();
+        assert_eq!(sps.sps_max_sub_layers_minus1, 0);
+        assert!(sps.sps_temporal_id_nesting_flag);
+        assert_eq!(
+            sps.profile_tier_level,
+            ProfileTierLevel {
+                general_profile_space: 0,
+                general_tier_flag: false,
+                general_profile_idc: 1,
+                general_profile_compatibility_flags: 0x6000_0000,
+                general_constraint_indicator_flags: 0x9000_0000_0000,
+                general_level_idc: 93,
+            }
+        );
+        assert_eq!(sps.chroma_format, ChromaFormat::YUV420);
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!(sps.bit_depth_luma_minus8, 0);
+        assert_eq!(sps.codec_string(), "hvc1.1.6.L93.90");
+    }
+
+    #[test]
+    fn main10_sps() {
+        let sps = SeqParameterSet::from_bytes(&MAIN10_SPS).unwrap();
+        assert_eq!(sps.sps_max_sub_layers_minus1, 1);
+        assert_eq!(sps.profile_tier_level.general_level_idc, 120);
+        assert_eq!(sps.chroma_format, ChromaFormat::YUV422);
+        assert_eq!((sps.width(), sps.height()), (1280, 720));
+        assert_eq!(sps.bit_depth_luma_minus8, 2);
+        assert_eq!(sps.bit_depth_chroma_minus8, 2);
+        assert_eq!(sps.codec_string(), "hvc1.2.4.H120.B0");
+        assert_eq!(
+            SeqParameterSet::from_bytes(&hex!("4401 c172")),
+            Err(ParamSetError::WrongNalUnitType {
+                expected: NAL_UNIT_TYPE_SPS,
+                actual: Some(NAL_UNIT_TYPE_PPS),
+            })
+        );
+    }
+}
@@ -0,0 +1,699 @@
+//! Splitting of AAC audio carried in the _Low Overhead Audio Stream_ (LOAS) format
+//! (`StreamType::Latm`) into access units, per _ISO/IEC 14496-3_.
+//!
+//! Each `AudioSyncStream()` frame of a LOAS stream begins with a _syncword_ and length, followed
+//! by an `AudioMuxElement()`.  This may carry a `StreamMuxConfig()`, giving the
+//! `AudioSpecificConfig()` of the audio, or may reuse the configuration most recently seen,
+//! followed by one or more raw AAC access units.
+//!
+//! A [`LatmParser`](struct.LatmParser.html) finds these frames in the payloads of PES packets
+//! and produces a [`LatmFrame`](struct.LatmFrame.html) for each access unit.  Each frame gives
+//! the raw AAC data along with the [`AudioSpecificConfig`](struct.AudioSpecificConfig.html)
+//! needed to decode it from an MP4 file, and can be converted to an ADTS frame with
+//! `to_adts()`.
+//!
+//! Only streams with a single program and layer, and with all streams sharing the same time
+//! framing, are supported, which covers the HE-AAC used by DVB and ISDB broadcasts.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::latm::LatmParser;
+//! # use mpeg2ts_reader::pes::Timestamp;
+//! # use hex_literal::hex;
+//! // AAC-LC 48kHz stereo; the first frame carries a StreamMuxConfig(), the second reuses it
+//! let data = hex!("56e009 200011901fe0110890  56e004 81108900");
+//! let mut parser = LatmParser::new();
+//! parser.begin_packet(Some(Timestamp::from_u64(90_000)), &data);
+//! parser.end_packet();
+//! let frames: Vec<_> = parser.drain_frames().collect();
+//! assert_eq!(frames.len(), 2);
+//! assert_eq!(frames[0].audio_specific_config().data(), [0x11, 0x90]);
+//! assert_eq!(frames[1].payload(), [0x21, 0x12]);
+//! assert_eq!(frames[1].pts(), Some(Timestamp::from_u64(91_920)));
+//! ```
+
+use crate::parser::adts::{self, AdtsFrame, AdtsHeader, AudioClock};
+use crate::parser::rbsp::{BitReader, BitReaderError};
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+/// The 11-bit _syncword_ at the start of each `AudioSyncStream()` frame.
+const SYNC_WORD: u32 = 0x2b7;
+/// The size of the _syncword_ and _audioMuxLengthBytes_ fields preceding each
+/// `AudioMuxElement()`.
+const SYNC_HEADER_SIZE: usize = 3;
+
+/// Problems which may be found in an `AudioMuxElement()` or `AudioSpecificConfig()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LatmError {
+    /// The data was truncated.
+    Bits(BitReaderError),
+    /// An `AudioMuxElement()` reused the previous `StreamMuxConfig()`, but none had been seen
+    /// yet.
+    MissingConfig,
+    /// A _samplingFrequencyIndex_ had a reserved value, or the explicitly given sampling
+    /// frequency was zero.
+    BadSamplingFrequencyIndex(u8),
+    /// The _ascLen_ field gave a length shorter than the `AudioSpecificConfig()` it described.
+    BadAscLength(u32),
+    /// The stream uses a feature not supported by this implementation, described by the given
+    /// field name.
+    Unsupported(&'static str),
+}
+impl From<BitReaderError> for LatmError {
+    fn from(e: BitReaderError) -> Self {
+        LatmError::Bits(e)
+    }
+}
+impl fmt::Display for LatmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatmError::Bits(e) => write!(f, "{}", e),
+            LatmError::MissingConfig => write!(f, "no StreamMuxConfig yet"),
+            LatmError::BadSamplingFrequencyIndex(idx) => {
+                write!(f, "invalid samplingFrequencyIndex {}", idx)
+            }
+            LatmError::BadAscLength(len) => write!(f, "ascLen {} too short", len),
+            LatmError::Unsupported(field) => write!(f, "unsupported {}", field),
+        }
+    }
+}
+impl std::error::Error for LatmError {}
+
+fn read_audio_object_type(r: &mut BitReader<'_>) -> Result<u8, LatmError> {
+    let audio_object_type = r.read_u8(5, "audioObjectType")?;
+    if audio_object_type == 31 {
+        Ok(32 + r.read_u8(6, "audioObjectTypeExt")?)
+    } else {
+        Ok(audio_object_type)
+    }
+}
+
+/// Reads a sampling frequency index, and the explicit frequency that follows if the index is
+/// `0xf`, returning both the index and the frequency in Hz.
+fn read_sampling_frequency(
+    r: &mut BitReader<'_>,
+    field: &'static str,
+) -> Result<(u8, u32), LatmError> {
+    let index = r.read_u8(4, field)?;
+    let rate = if index == 0xf {
+        r.read_u32(24, "samplingFrequency")?
+    } else {
+        adts::SAMPLE_RATES
+            .get(usize::from(index))
+            .copied()
+            .unwrap_or(0)
+    };
+    if rate == 0 {
+        Err(LatmError::BadSamplingFrequencyIndex(index))
+    } else {
+        Ok((index, rate))
+    }
+}
+
+/// Reads a `GASpecificConfig()`, returning the _frameLengthFlag_.
+fn read_ga_specific_config(
+    r: &mut BitReader<'_>,
+    audio_object_type: u8,
+    channel_configuration: u8,
+) -> Result<bool, LatmError> {
+    let frame_length_flag = r.read_bool("frameLengthFlag")?;
+    if r.read_bool("dependsOnCoreCoder")? {
+        r.skip(14, "coreCoderDelay")?;
+    }
+    let extension_flag = r.read_bool("extensionFlag")?;
+    if channel_configuration == 0 {
+        return Err(LatmError::Unsupported("program_config_element"));
+    }
+    if audio_object_type == 6 || audio_object_type == 20 {
+        r.skip(3, "layerNr")?;
+    }
+    if extension_flag {
+        if audio_object_type == 22 {
+            r.skip(5 + 11, "numOfSubFrame")?;
+        }
+        if matches!(audio_object_type, 17 | 19 | 20 | 23) {
+            r.skip(3, "aacSectionDataResilienceFlag")?;
+        }
+        r.skip(1, "extensionFlag3")?;
+    }
+    Ok(frame_length_flag)
+}
+
+/// Reads the _LatmGetValue()_ encoding of a value of up to four bytes.
+fn latm_get_value(r: &mut BitReader<'_>, field: &'static str) -> Result<u32, LatmError> {
+    let bytes_for_value = r.read_u8(2, "bytesForValue")?;
+    let mut value = 0;
+    for _ in 0..=bytes_for_value {
+        value = value << 8 | r.read_u32(8, field)?;
+    }
+    Ok(value)
+}
+
+/// The MPEG-4 `AudioSpecificConfig()` describing an audio stream, needed to decode the raw
+/// access units of a LATM stream, or to describe them in an MP4 `esds` box.
+///
+/// Only the AAC audio object types are supported, and not those requiring a
+/// `program_config_element()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    /// the _audioObjectType_ of the core audio (e.g. `2` for AAC-LC, even where SBR or PS is
+    /// signalled)
+    pub audio_object_type: u8,
+    /// the _samplingFrequencyIndex_ field, which is `0xf` if the rate is given explicitly
+    pub sampling_frequency_index: u8,
+    /// the sample rate of the core audio, in Hz
+    pub sampling_frequency: u32,
+    /// the _channelConfiguration_ field
+    pub channel_configuration: u8,
+    /// `true` if the use of Spectral Band Replication (HE-AAC) is signalled
+    pub sbr_present: bool,
+    /// `true` if the use of Parametric Stereo (HE-AAC v2) is signalled
+    pub ps_present: bool,
+    /// the output sample rate of the SBR tool, in Hz, if signalled
+    pub extension_sampling_frequency: Option<u32>,
+    /// the _frameLengthFlag_ field, which is `true` for frames of 960 samples rather than 1024
+    pub frame_length_flag: bool,
+    data: Vec<u8>,
+}
+impl AudioSpecificConfig {
+    /// Parse the given `AudioSpecificConfig()` data.
+    ///
+    /// ```
+    /// # use mpeg2ts_reader::parser::latm::AudioSpecificConfig;
+    /// let config = AudioSpecificConfig::from_bytes(&[0x11, 0x90]).unwrap();
+    /// assert_eq!(config.audio_object_type, 2);
+    /// assert_eq!(config.sampling_frequency, 48000);
+    /// assert_eq!(config.channel_configuration, 2);
+    /// ```
+    pub fn from_bytes(data: &[u8]) -> Result<AudioSpecificConfig, LatmError> {
+        Self::read(&mut BitReader::new(data), Some(data.len() * 8))
+    }
+
+    /// Reads the config, which has the given length in bits if known.  Only if the length is
+    /// known can the 'backward compatible' signalling of SBR and PS following the config be
+    /// found.
+    fn read(r: &mut BitReader<'_>, len: Option<usize>) -> Result<AudioSpecificConfig, LatmError> {
+        let start = r.clone();
+        let start_pos = r.position();
+        let mut audio_object_type = read_audio_object_type(r)?;
+        let (sampling_frequency_index, sampling_frequency) =
+            read_sampling_frequency(r, "samplingFrequencyIndex")?;
+        let channel_configuration = r.read_u8(4, "channelConfiguration")?;
+        let mut sbr_present = false;
+        let mut ps_present = false;
+        let mut extension_sampling_frequency = None;
+        if audio_object_type == 5 || audio_object_type == 29 {
+            sbr_present = true;
+            ps_present = audio_object_type == 29;
+            extension_sampling_frequency =
+                Some(read_sampling_frequency(r, "extensionSamplingFrequencyIndex")?.1);
+            audio_object_type = read_audio_object_type(r)?;
+            if audio_object_type == 22 {
+                r.skip(4, "extensionChannelConfiguration")?;
+            }
+        }
+        let frame_length_flag = match audio_object_type {
+            1 | 2 | 3 | 4 | 6 | 7 | 17 | 19 | 20 | 21 | 22 | 23 => {
+                read_ga_specific_config(r, audio_object_type, channel_configuration)?
+            }
+            _ => return Err(LatmError::Unsupported("audioObjectType")),
+        };
+        if matches!(audio_object_type, 17 | 19..=23) && r.read_u8(2, "epConfig")? > 1 {
+            return Err(LatmError::Unsupported("epConfig"));
+        }
+        let end = match len {
+            Some(len) => {
+                let end = start_pos + len;
+                if end < r.position() {
+                    return Err(LatmError::BadAscLength(len as u32));
+                }
+                if !sbr_present && end - r.position() >= 16 {
+                    let mut ext = r.clone();
+                    if ext.read_u32(11, "syncExtensionType")? == SYNC_WORD
+                        && read_audio_object_type(&mut ext)? == 5
+                        && ext.read_bool("sbrPresentFlag")?
+                    {
+                        sbr_present = true;
+                        let field = "extensionSamplingFrequencyIndex";
+                        extension_sampling_frequency =
+                            Some(read_sampling_frequency(&mut ext, field)?.1);
+                        if end.saturating_sub(ext.position()) >= 12
+                            && ext.read_u32(11, "syncExtensionType")? == 0x548
+                        {
+                            ps_present = ext.read_bool("psPresentFlag")?;
+                        }
+                    }
+                }
+                r.skip(end - r.position(), "fillBits")?;
+                end
+            }
+            None => r.position(),
+        };
+        // keep a copy of the config as it was encoded, padded to a whole number of bytes
+        let mut data = Vec::with_capacity((end - start_pos + 7) / 8);
+        let mut copy = start;
+        let mut remaining = end - start_pos;
+        while remaining > 0 {
+            let bits = remaining.min(8);
+            data.push(copy.read_u8(bits as u32, "AudioSpecificConfig")? << (8 - bits));
+            remaining -= bits;
+        }
+        Ok(AudioSpecificConfig {
+            audio_object_type,
+            sampling_frequency_index,
+            sampling_frequency,
+            channel_configuration,
+            sbr_present,
+            ps_present,
+            extension_sampling_frequency,
+            frame_length_flag,
+            data,
+        })
+    }
+
+    /// The encoded `AudioSpecificConfig()`, padded with zero bits to a whole number of bytes,
+    /// as needed for an MP4 `esds` box.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The number of core audio samples decoded from each access unit (which is doubled by the
+    /// SBR tool, if present).
+    pub fn samples(&self) -> u32 {
+        if self.frame_length_flag {
+            960
+        } else {
+            adts::SAMPLES_PER_RAW_DATA_BLOCK
+        }
+    }
+
+    /// An ADTS header describing this audio, or `None` if ADTS can't describe it.  Any SBR or
+    /// PS is left for the decoder to detect implicitly.
+    fn adts_header(&self) -> Option<AdtsHeader> {
+        if !(1..=4).contains(&self.audio_object_type)
+            || usize::from(self.sampling_frequency_index) >= adts::SAMPLE_RATES.len()
+            || !(1..=7).contains(&self.channel_configuration)
+            || self.frame_length_flag
+        {
+            return None;
+        }
+        Some(AdtsHeader {
+            mpeg2: false,
+            protection_absent: true,
+            profile: self.audio_object_type - 1,
+            sampling_frequency_index: self.sampling_frequency_index,
+            private_bit: false,
+            channel_configuration: self.channel_configuration,
+            original_copy: false,
+            home: false,
+            copyright_identification_bit: false,
+            copyright_identification_start: false,
+            frame_length: 0,
+            adts_buffer_fullness: 0x7ff,
+            number_of_raw_data_blocks_in_frame: 0,
+        })
+    }
+}
+
+/// The fields of a `StreamMuxConfig()` that describe how audio is carried in the
+/// `AudioMuxElement()`s that use it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamMuxConfig {
+    /// the _audioMuxVersion_ field
+    pub audio_mux_version: u8,
+    /// the _numSubFrames_ field, one less than the number of access units in each
+    /// `AudioMuxElement()`
+    pub num_sub_frames: u8,
+    /// the configuration of the audio
+    pub audio_specific_config: AudioSpecificConfig,
+    /// the _frameLengthType_ field, which is `0` for variable length access units, and `1`
+    /// for fixed length ones
+    pub frame_length_type: u8,
+    /// the _latmBufferFullness_ field of variable length access units, where `0xff` indicates
+    /// a variable rate stream
+    pub latm_buffer_fullness: Option<u8>,
+    /// the _frameLength_ field of fixed length access units, which are `frame_length + 20`
+    /// bytes long
+    pub frame_length: Option<u16>,
+    /// the number of bits of other data following the access units of each
+    /// `AudioMuxElement()`
+    pub other_data_len_bits: u32,
+    /// the _crcCheckSum_ field, if present
+    pub crc_check_sum: Option<u8>,
+}
+impl StreamMuxConfig {
+    fn read(r: &mut BitReader<'_>) -> Result<StreamMuxConfig, LatmError> {
+        let audio_mux_version = r.read_u8(1, "audioMuxVersion")?;
+        if audio_mux_version == 1 {
+            if r.read_bool("audioMuxVersionA")? {
+                return Err(LatmError::Unsupported("audioMuxVersionA"));
+            }
+            latm_get_value(r, "taraBufferFullness")?;
+        }
+        if !r.read_bool("allStreamsSameTimeFraming")? {
+            return Err(LatmError::Unsupported("allStreamsSameTimeFraming"));
+        }
+        let num_sub_frames = r.read_u8(6, "numSubFrames")?;
+        if r.read_u8(4, "numProgram")? != 0 {
+            return Err(LatmError::Unsupported("numProgram"));
+        }
+        if r.read_u8(3, "numLayer")? != 0 {
+            return Err(LatmError::Unsupported("numLayer"));
+        }
+        let audio_specific_config = if audio_mux_version == 1 {
+            let asc_len = latm_get_value(r, "ascLen")?;
+            AudioSpecificConfig::read(r, Some(asc_len as usize))?
+        } else {
+            AudioSpecificConfig::read(r, None)?
+        };
+        let frame_length_type = r.read_u8(3, "frameLengthType")?;
+        let (latm_buffer_fullness, frame_length) = match frame_length_type {
+            0 => (Some(r.read_u8(8, "latmBufferFullness")?), None),
+            1 => (None, Some(r.read_u16(9, "frameLength")?)),
+            _ => return Err(LatmError::Unsupported("frameLengthType")),
+        };
+        let other_data_len_bits = if !r.read_bool("otherDataPresent")? {
+            0
+        } else if audio_mux_version == 1 {
+            latm_get_value(r, "otherDataLenBits")?
+        } else {
+            let mut len = 0u32;
+            loop {
+                let esc = r.read_bool("otherDataLenEsc")?;
+                len = len
+                    .checked_mul(256)
+                    .ok_or(LatmError::Unsupported("otherDataLenBits"))?
+                    + r.read_u32(8, "otherDataLenTmp")?;
+                if !esc {
+                    break len;
+                }
+            }
+        };
+        let crc_check_sum = if r.read_bool("crcCheckPresent")? {
+            Some(r.read_u8(8, "crcCheckSum")?)
+        } else {
+            None
+        };
+        Ok(StreamMuxConfig {
+            audio_mux_version,
+            num_sub_frames,
+            audio_specific_config,
+            frame_length_type,
+            latm_buffer_fullness,
+            frame_length,
+            other_data_len_bits,
+            crc_check_sum,
+        })
+    }
+}
+
+/// A raw AAC access unit from a LATM stream, as produced by
+/// [`LatmParser`](struct.LatmParser.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatmFrame {
+    config: AudioSpecificConfig,
+    payload: Vec<u8>,
+    pts: Option<Timestamp>,
+}
+impl LatmFrame {
+    /// The configuration of the audio, from the `StreamMuxConfig()` in effect for this frame.
+    pub fn audio_specific_config(&self) -> &AudioSpecificConfig {
+        &self.config
+    }
+
+    /// The raw AAC data of this access unit.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// The presentation time of this frame.  This is the PTS of the PES packet for the first
+    /// frame starting within a packet, and is interpolated from the number of samples in the
+    /// preceding frames otherwise.  `None` if no PES packet so far in the stream had a PTS.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The duration of this frame, in 90kHz units.
+    pub fn duration(&self) -> u64 {
+        u64::from(self.config.samples()) * Timestamp::TIMEBASE
+            / u64::from(self.config.sampling_frequency)
+    }
+
+    /// This access unit repackaged as an ADTS frame, or `None` if the audio can't be described
+    /// by an ADTS header (i.e. it isn't AAC Main, LC, SSR or LTP, it doesn't use one of the
+    /// standard sample rates and channel configurations, it has 960 sample frames, or the
+    /// frame is too large).
+    pub fn to_adts(&self) -> Option<AdtsFrame> {
+        let header = self.config.adts_header()?;
+        if AdtsHeader::MIN_SIZE + self.payload.len() > 0x1fff {
+            return None;
+        }
+        Some(AdtsFrame::from_payload(header, &self.payload, self.pts))
+    }
+}
+
+/// Splits the payloads of the PES packets of a LATM stream, using the LOAS `AudioSyncStream()`
+/// format, into [`LatmFrame`](struct.LatmFrame.html)s, which can be retrieved with
+/// `drain_frames()`.
+///
+/// Data preceding a _syncword_ is skipped, as are any `AudioMuxElement()`s which can't be
+/// parsed.
+#[derive(Debug, Default)]
+pub struct LatmParser {
+    buf: Vec<u8>,
+    /// the position in the stream of the first byte of `buf`
+    buf_position: u64,
+    clock: AudioClock,
+    config: Option<StreamMuxConfig>,
+    frames: Vec<LatmFrame>,
+}
+impl LatmParser {
+    /// Create a parser with no data.
+    pub fn new() -> LatmParser {
+        LatmParser::default()
+    }
+
+    /// The `StreamMuxConfig()` most recently found in the stream, if any.
+    pub fn stream_mux_config(&self) -> Option<&StreamMuxConfig> {
+        self.config.as_ref()
+    }
+
+    /// Start a new PES packet, with the PTS from its header and the initial part of its
+    /// payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, payload: &[u8]) {
+        self.clock
+            .packet(self.buf_position + self.buf.len() as u64, pts);
+        self.continue_packet(payload);
+    }
+
+    /// Add more of the payload of the current PES packet.
+    pub fn continue_packet(&mut self, payload: &[u8]) {
+        self.buf.extend_from_slice(payload);
+        self.parse();
+    }
+
+    /// Signal the end of the current PES packet.  Since `AudioSyncStream()` frames may span PES
+    /// packets, this has no effect on the data buffered so far.
+    pub fn end_packet(&mut self) {}
+
+    /// Discard any incomplete frame at the end of the stream.
+    pub fn flush(&mut self) {
+        self.buf_position += self.buf.len() as u64;
+        self.buf.clear();
+    }
+
+    /// Removes and returns the frames found so far.
+    pub fn drain_frames(&mut self) -> impl Iterator<Item = LatmFrame> + '_ {
+        self.frames.drain(..)
+    }
+
+    fn parse(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        let mut pos = 0;
+        while buf.len() - pos >= SYNC_HEADER_SIZE {
+            let header =
+                u32::from(buf[pos]) << 16 | u32::from(buf[pos + 1]) << 8 | u32::from(buf[pos + 2]);
+            if header >> 13 != SYNC_WORD {
+                pos += 1;
+                continue;
+            }
+            let start = pos + SYNC_HEADER_SIZE;
+            let end = start + (header & 0x1fff) as usize;
+            if end > buf.len() {
+                break;
+            }
+            let position = self.buf_position + pos as u64;
+            if let Err(e) = self.read_audio_mux_element(position, &buf[start..end]) {
+                trace!("skipping LATM AudioMuxElement: {}", e);
+            }
+            pos = end;
+        }
+        self.buf = buf;
+        self.buf.drain(..pos);
+        self.buf_position += pos as u64;
+    }
+
+    fn read_audio_mux_element(&mut self, position: u64, data: &[u8]) -> Result<(), LatmError> {
+        let mut r = BitReader::new(data);
+        if !r.read_bool("useSameStreamMux")? {
+            // don't go on to use an old config if the new one can't be read
+            self.config = None;
+            self.config = Some(StreamMuxConfig::read(&mut r)?);
+        }
+        let config = self.config.as_ref().ok_or(LatmError::MissingConfig)?;
+        let mut payloads = Vec::with_capacity(usize::from(config.num_sub_frames) + 1);
+        for _ in 0..=config.num_sub_frames {
+            let len = match config.frame_length {
+                Some(frame_length) => usize::from(frame_length) + 20,
+                None => {
+                    let mut len = 0;
+                    loop {
+                        let tmp = r.read_u8(8, "MuxSlotLengthBytes")?;
+                        len += usize::from(tmp);
+                        if tmp != 255 {
+                            break len;
+                        }
+                    }
+                }
+            };
+            let mut payload = Vec::with_capacity(len);
+            for _ in 0..len {
+                payload.push(r.read_u8(8, "payload")?);
+            }
+            payloads.push(payload);
+        }
+        // any other data that follows is ignored
+        let asc = &config.audio_specific_config;
+        for payload in payloads {
+            let pts = self
+                .clock
+                .frame(position, asc.samples(), asc.sampling_frequency);
+            self.frames.push(LatmFrame {
+                config: asc.clone(),
+                payload,
+                pts,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    /// AAC-LC 48kHz stereo, with a new `StreamMuxConfig()` and a two byte payload
+    const FRAME_WITH_CONFIG: [u8; 12] = hex!("56e009 200011901fe0110890");
+    /// the same, reusing the previous config
+    const FRAME: [u8; 7] = hex!("56e004 81108900");
+
+    #[test]
+    fn audio_specific_config() {
+        let config = AudioSpecificConfig::from_bytes(&hex!("1190")).unwrap();
+        assert_eq!(
+            config,
+            AudioSpecificConfig {
+                audio_object_type: 2,
+                sampling_frequency_index: 3,
+                sampling_frequency: 48000,
+                channel_configuration: 2,
+                sbr_present: false,
+                ps_present: false,
+                extension_sampling_frequency: None,
+                frame_length_flag: false,
+                data: hex!("1190").to_vec(),
+            }
+        );
+        assert_eq!(config.samples(), 1024);
+
+        // AAC-LC 24kHz mono, with SBR and PS signalled by a sync extension following the
+        // config
+        let config = AudioSpecificConfig::from_bytes(&hex!("131056e59d4880")).unwrap();
+        assert_eq!(config.audio_object_type, 2);
+        assert_eq!(config.sampling_frequency, 24000);
+        assert!(config.sbr_present);
+        assert!(config.ps_present);
+        assert_eq!(config.extension_sampling_frequency, Some(48000));
+        assert_eq!(config.data(), hex!("131056e59d4880"));
+
+        assert_eq!(
+            AudioSpecificConfig::from_bytes(&hex!("1180")),
+            Err(LatmError::Unsupported("program_config_element"))
+        );
+        assert_eq!(
+            AudioSpecificConfig::from_bytes(&hex!("16a0")),
+            Err(LatmError::BadSamplingFrequencyIndex(13))
+        );
+        assert_eq!(
+            AudioSpecificConfig::from_bytes(&hex!("11")),
+            Err(LatmError::Bits(BitReaderError::NotEnoughData {
+                field: "samplingFrequencyIndex"
+            }))
+        );
+    }
+
+    #[test]
+    fn audio_mux_element() {
+        let mut parser = LatmParser::new();
+        // a frame reusing a config before any config is seen is skipped, as is junk
+        parser.begin_packet(Some(Timestamp::from_u64(0)), &FRAME);
+        parser.continue_packet(&hex!("0056"));
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::from_u64(1000)), &FRAME_WITH_CONFIG[..5]);
+        parser.continue_packet(&FRAME_WITH_CONFIG[5..]);
+        parser.continue_packet(&FRAME);
+        parser.continue_packet(&FRAME[..4]);
+        parser.end_packet();
+        parser.flush();
+        let config = parser.stream_mux_config().unwrap();
+        assert_eq!(config.audio_mux_version, 0);
+        assert_eq!(config.num_sub_frames, 0);
+        assert_eq!(config.latm_buffer_fullness, Some(0xff));
+        assert_eq!(config.crc_check_sum, None);
+        let frames: Vec<_> = parser.drain_frames().collect();
+        let pts: Vec<_> = frames.iter().map(|f| f.pts().unwrap().value()).collect();
+        assert_eq!(pts, [1000, 2920]);
+        assert!(frames.iter().all(|f| f.payload() == hex!("2112")));
+        assert_eq!(frames[0].duration(), 1920);
+        let adts = frames[0].to_adts().unwrap();
+        assert_eq!(adts.data(), hex!("fff14c80 013ffc 2112"));
+        assert_eq!(adts.pts(), Some(Timestamp::from_u64(1000)));
+    }
+
+    #[test]
+    fn version_1() {
+        // HE-AAC v2 (explicitly signalled) 24kHz core rate, with two access units, other data
+        // and a CRC
+        let data = hex!("56e013 47fc1000e7584c400ff8115a03aabbcc01ddee");
+        let mut parser = LatmParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(0)), &data);
+        parser.end_packet();
+        let config = parser.stream_mux_config().unwrap().clone();
+        assert_eq!(config.audio_mux_version, 1);
+        assert_eq!(config.num_sub_frames, 1);
+        assert_eq!(config.other_data_len_bits, 8);
+        assert_eq!(config.crc_check_sum, Some(0x5a));
+        let asc = &config.audio_specific_config;
+        assert_eq!(asc.audio_object_type, 2);
+        assert_eq!(asc.sampling_frequency, 24000);
+        assert_eq!(asc.channel_configuration, 1);
+        assert!(asc.sbr_present);
+        assert!(asc.ps_present);
+        assert_eq!(asc.extension_sampling_frequency, Some(48000));
+        // the 28 bits of ascLen
+        assert_eq!(asc.data(), hex!("eb098800"));
+        let frames: Vec<_> = parser.drain_frames().collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload(), hex!("aabbcc"));
+        assert_eq!(frames[1].payload(), hex!("dd"));
+        // 1024 samples at the 24kHz core rate
+        assert_eq!(frames[1].pts(), Some(Timestamp::from_u64(3840)));
+        // implicitly signalled HE-AAC in ADTS
+        assert_eq!(
+            frames[1].to_adts().unwrap().data(),
+            hex!("fff15840 011ffc dd")
+        );
+    }
+}
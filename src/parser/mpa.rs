@@ -0,0 +1,588 @@
+//! Splitting of MPEG-1 and MPEG-2 audio layers I, II and III (`StreamType::Iso11172Audio` and
+//! `StreamType::Iso138183Audio`) into frames, per _ISO/IEC 11172-3_ and _ISO/IEC 13818-3_.
+//!
+//! Each frame begins with a 4 byte header giving the format of the audio, from which the length
+//! of the frame can usually be calculated.  The exception is _free format_ streams, whose
+//! header does not give a bit rate; for these, an [`MpaParser`](struct.MpaParser.html) measures
+//! the distance from the first frame to the next header, and assumes the same length for the
+//! frames which follow (allowing for padding).
+//!
+//! The unofficial 'MPEG-2.5' extension for lower sample rates is also supported.
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::mpa::MpaParser;
+//! # use mpeg2ts_reader::pes::Timestamp;
+//! // a layer II frame, 48kHz, 192 kbit/s, which is 576 bytes long
+//! let mut frame = vec![0; 576];
+//! frame[..4].copy_from_slice(&[0xff, 0xfd, 0xa4, 0x04]);
+//! let mut parser = MpaParser::new();
+//! parser.begin_packet(Some(Timestamp::from_u64(90_000)), &frame.repeat(2));
+//! parser.end_packet();
+//! let pts: Vec<_> = parser.drain_frames().map(|f| f.pts().unwrap().value()).collect();
+//! // 1152 samples at 48kHz is 2160 ticks of the 90kHz clock
+//! assert_eq!(pts, [90_000, 92_160]);
+//! ```
+
+use crate::parser::adts::AudioClock;
+use crate::pes::Timestamp;
+use log::trace;
+use std::fmt;
+
+/// The MPEG-1 sample rates indicated by each value of _sampling_frequency_, which are halved for
+/// MPEG-2 and quartered for MPEG-2.5.
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+/// The bit rates, in kbit/s, indicated by each non-zero _bitrate_index_ value below 15.
+const MPEG1_LAYER1_BIT_RATES: [u32; 14] = [
+    32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const MPEG1_LAYER2_BIT_RATES: [u32; 14] = [
+    32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const MPEG1_LAYER3_BIT_RATES: [u32; 14] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_LAYER1_BIT_RATES: [u32; 14] = [
+    32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const MPEG2_LAYER23_BIT_RATES: [u32; 14] =
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+/// Free format frames longer than this are not looked for.
+const MAX_FREE_FORMAT_FRAME_SIZE: usize = 8192;
+
+/// Problems which may be found in an MPEG audio frame header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MpaError {
+    /// Fewer than the 4 bytes of the header were available.
+    NotEnoughData,
+    /// The data did not start with the 11-bit frame sync pattern.
+    BadSyncWord,
+    /// The version field had the reserved value `0b01`.
+    BadVersion,
+    /// The _layer_ field had the reserved value `0b00`.
+    BadLayer,
+    /// The _bitrate_index_ field had the forbidden value `0b1111`.
+    BadBitrateIndex(u8),
+    /// The _sampling_frequency_ field had the reserved value `0b11`.
+    BadSamplingFrequencyIndex(u8),
+    /// The _emphasis_ field had the reserved value `0b10`.
+    BadEmphasis(u8),
+}
+impl fmt::Display for MpaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MpaError::NotEnoughData => write!(f, "not enough data for MPEG audio header"),
+            MpaError::BadSyncWord => write!(f, "MPEG audio frame sync not found"),
+            MpaError::BadVersion => write!(f, "reserved MPEG audio version"),
+            MpaError::BadLayer => write!(f, "reserved MPEG audio layer"),
+            MpaError::BadBitrateIndex(idx) => write!(f, "invalid bitrate_index {}", idx),
+            MpaError::BadSamplingFrequencyIndex(idx) => {
+                write!(f, "reserved sampling_frequency {}", idx)
+            }
+            MpaError::BadEmphasis(emphasis) => write!(f, "reserved emphasis {}", emphasis),
+        }
+    }
+}
+impl std::error::Error for MpaError {}
+
+/// The version of the standard that a frame conforms to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// MPEG-1 audio, _ISO/IEC 11172-3_
+    Mpeg1,
+    /// the lower sample rate extension of MPEG-2 audio, _ISO/IEC 13818-3_
+    Mpeg2,
+    /// the unofficial 'MPEG-2.5' extension for still lower sample rates
+    Mpeg25,
+}
+
+/// The audio coding layer used by a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// Layer I
+    Layer1,
+    /// Layer II, commonly known as MP2
+    Layer2,
+    /// Layer III, commonly known as MP3
+    Layer3,
+}
+
+/// The arrangement of channels given by the _mode_ field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// two channels
+    Stereo,
+    /// two channels, coded using the redundancy between them
+    JointStereo,
+    /// two independent channels
+    DualChannel,
+    /// one channel
+    SingleChannel,
+}
+
+/// The header at the start of each MPEG audio frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpaHeader {
+    /// the version of the standard, given by the _ID_ bit (and the bit before it, for MPEG-2.5)
+    pub version: Version,
+    /// the _layer_ field
+    pub layer: Layer,
+    /// `false` if the _protection_bit_ field indicates that a CRC follows the header
+    pub protection_absent: bool,
+    /// the _bitrate_index_ field, where `0` indicates free format
+    pub bitrate_index: u8,
+    /// the _sampling_frequency_ field
+    pub sampling_frequency_index: u8,
+    /// the _padding_bit_ field, `true` if the frame contains an extra slot
+    pub padding: bool,
+    /// the _private_bit_ field
+    pub private_bit: bool,
+    /// the _mode_ field
+    pub mode: ChannelMode,
+    /// the _mode_extension_ field
+    pub mode_extension: u8,
+    /// the _copyright_ field
+    pub copyright: bool,
+    /// the _original/copy_ field
+    pub original: bool,
+    /// the _emphasis_ field
+    pub emphasis: u8,
+}
+impl MpaHeader {
+    /// The size of the header, not including any CRC.
+    pub const SIZE: usize = 4;
+
+    /// Parse the header at the start of the given data.
+    pub fn from_bytes(data: &[u8]) -> Result<MpaHeader, MpaError> {
+        if data.len() < 2 {
+            return Err(MpaError::NotEnoughData);
+        }
+        if data[0] != 0xff || data[1] & 0xe0 != 0xe0 {
+            return Err(MpaError::BadSyncWord);
+        }
+        let version = match data[1] >> 3 & 0b11 {
+            0b00 => Version::Mpeg25,
+            0b10 => Version::Mpeg2,
+            0b11 => Version::Mpeg1,
+            _ => return Err(MpaError::BadVersion),
+        };
+        let layer = match data[1] >> 1 & 0b11 {
+            0b01 => Layer::Layer3,
+            0b10 => Layer::Layer2,
+            0b11 => Layer::Layer1,
+            _ => return Err(MpaError::BadLayer),
+        };
+        if data.len() < Self::SIZE {
+            return Err(MpaError::NotEnoughData);
+        }
+        let bitrate_index = data[2] >> 4;
+        if bitrate_index == 0b1111 {
+            return Err(MpaError::BadBitrateIndex(bitrate_index));
+        }
+        let sampling_frequency_index = data[2] >> 2 & 0b11;
+        if usize::from(sampling_frequency_index) >= SAMPLE_RATES.len() {
+            return Err(MpaError::BadSamplingFrequencyIndex(
+                sampling_frequency_index,
+            ));
+        }
+        let emphasis = data[3] & 0b11;
+        if emphasis == 0b10 {
+            return Err(MpaError::BadEmphasis(emphasis));
+        }
+        Ok(MpaHeader {
+            version,
+            layer,
+            protection_absent: data[1] & 1 == 1,
+            bitrate_index,
+            sampling_frequency_index,
+            padding: data[2] & 0b10 != 0,
+            private_bit: data[2] & 1 != 0,
+            mode: match data[3] >> 6 {
+                0 => ChannelMode::Stereo,
+                1 => ChannelMode::JointStereo,
+                2 => ChannelMode::DualChannel,
+                _ => ChannelMode::SingleChannel,
+            },
+            mode_extension: data[3] >> 4 & 0b11,
+            copyright: data[3] & 0b1000 != 0,
+            original: data[3] & 0b100 != 0,
+            emphasis,
+        })
+    }
+
+    /// `true` if the header does not give a bit rate, so that the length of the frame can't be
+    /// calculated from it.
+    pub fn is_free_format(&self) -> bool {
+        self.bitrate_index == 0
+    }
+
+    /// The bit rate of the audio in bits per second, or `None` for a free format stream.
+    pub fn bit_rate(&self) -> Option<u32> {
+        let rates = match (self.version, self.layer) {
+            (Version::Mpeg1, Layer::Layer1) => &MPEG1_LAYER1_BIT_RATES,
+            (Version::Mpeg1, Layer::Layer2) => &MPEG1_LAYER2_BIT_RATES,
+            (Version::Mpeg1, Layer::Layer3) => &MPEG1_LAYER3_BIT_RATES,
+            (_, Layer::Layer1) => &MPEG2_LAYER1_BIT_RATES,
+            (_, _) => &MPEG2_LAYER23_BIT_RATES,
+        };
+        let index = usize::from(self.bitrate_index).checked_sub(1)?;
+        Some(rates[index] * 1000)
+    }
+
+    /// The sample rate of the audio, in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        let rate = SAMPLE_RATES[usize::from(self.sampling_frequency_index)];
+        match self.version {
+            Version::Mpeg1 => rate,
+            Version::Mpeg2 => rate / 2,
+            Version::Mpeg25 => rate / 4,
+        }
+    }
+
+    /// The number of channels in the audio.
+    pub fn channels(&self) -> u8 {
+        if self.mode == ChannelMode::SingleChannel {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// The number of PCM samples per channel decoded from this frame.
+    pub fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (Layer::Layer1, _) => 384,
+            (Layer::Layer2, _) | (Layer::Layer3, Version::Mpeg1) => 1152,
+            (Layer::Layer3, _) => 576,
+        }
+    }
+
+    /// The size of the extra _slot_ included in the frame when `padding` is set.
+    pub fn padding_size(&self) -> usize {
+        match (self.layer, self.padding) {
+            (_, false) => 0,
+            (Layer::Layer1, true) => 4,
+            (_, true) => 1,
+        }
+    }
+
+    /// The length of the whole frame including this header, or `None` for a free format
+    /// stream.
+    pub fn frame_length(&self) -> Option<usize> {
+        let bit_rate = self.bit_rate()? as usize;
+        let sample_rate = self.sample_rate() as usize;
+        // the number of bytes needed for the frame's samples at the bit rate, rounded down to a
+        // whole number of slots
+        let len = match self.layer {
+            Layer::Layer1 => 12 * bit_rate / sample_rate * 4,
+            _ => self.samples() as usize / 8 * bit_rate / sample_rate,
+        };
+        Some(len + self.padding_size())
+    }
+
+    /// `true` if the given data starts with a header having the same version, layer, sample
+    /// rate, bit rate and mode as this one, as frames following a free format frame should.
+    fn same_format(&self, data: &[u8]) -> bool {
+        match MpaHeader::from_bytes(data) {
+            Ok(next) => {
+                next.version == self.version
+                    && next.layer == self.layer
+                    && next.bitrate_index == self.bitrate_index
+                    && next.sampling_frequency_index == self.sampling_frequency_index
+                    && next.mode == self.mode
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A complete MPEG audio frame, as produced by [`MpaParser`](struct.MpaParser.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MpaFrame {
+    header: MpaHeader,
+    data: Vec<u8>,
+    pts: Option<Timestamp>,
+}
+impl MpaFrame {
+    /// The header of this frame.
+    pub fn header(&self) -> &MpaHeader {
+        &self.header
+    }
+
+    /// The whole frame, including its header.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The presentation time of this frame.  This is the PTS of the PES packet for the first
+    /// frame starting within a packet, and is interpolated from the number of samples in the
+    /// preceding frames otherwise.  `None` if no PES packet so far in the stream had a PTS.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.pts
+    }
+
+    /// The duration of this frame, in 90kHz units.
+    pub fn duration(&self) -> u64 {
+        u64::from(self.header.samples()) * Timestamp::TIMEBASE
+            / u64::from(self.header.sample_rate())
+    }
+}
+
+/// Splits the payloads of the PES packets of an MPEG audio stream into
+/// [`MpaFrame`](struct.MpaFrame.html)s, which can be retrieved with `drain_frames()`.
+///
+/// Data that does not form a valid frame header is skipped until the next frame sync.
+#[derive(Debug, Default)]
+pub struct MpaParser {
+    buf: Vec<u8>,
+    /// the position in the stream of the first byte of `buf`
+    buf_position: u64,
+    clock: AudioClock,
+    /// the length of the frames of a free format stream, excluding any padding
+    free_format_length: Option<usize>,
+    frames: Vec<MpaFrame>,
+}
+impl MpaParser {
+    /// Create a parser with no data.
+    pub fn new() -> MpaParser {
+        MpaParser::default()
+    }
+
+    /// Start a new PES packet, with the PTS from its header and the initial part of its
+    /// payload.
+    pub fn begin_packet(&mut self, pts: Option<Timestamp>, payload: &[u8]) {
+        self.clock
+            .packet(self.buf_position + self.buf.len() as u64, pts);
+        self.continue_packet(payload);
+    }
+
+    /// Add more of the payload of the current PES packet.
+    pub fn continue_packet(&mut self, payload: &[u8]) {
+        self.buf.extend_from_slice(payload);
+        self.parse();
+    }
+
+    /// Signal the end of the current PES packet.  Since frames may span PES packets, this has
+    /// no effect on the data buffered so far.
+    pub fn end_packet(&mut self) {}
+
+    /// Discard any incomplete frame at the end of the stream.
+    pub fn flush(&mut self) {
+        self.buf_position += self.buf.len() as u64;
+        self.buf.clear();
+    }
+
+    /// Removes and returns the frames found so far.
+    pub fn drain_frames(&mut self) -> impl Iterator<Item = MpaFrame> + '_ {
+        self.frames.drain(..)
+    }
+
+    fn parse(&mut self) {
+        let mut pos = 0;
+        while pos < self.buf.len() {
+            let header = match MpaHeader::from_bytes(&self.buf[pos..]) {
+                Ok(header) => header,
+                Err(MpaError::NotEnoughData) => break,
+                Err(e) => {
+                    if e != MpaError::BadSyncWord {
+                        trace!("skipping invalid MPEG audio header: {}", e);
+                    }
+                    pos += 1;
+                    continue;
+                }
+            };
+            let len = if let Some(len) = header.frame_length() {
+                self.free_format_length = None;
+                len
+            } else if let Some(len) = self.free_format_length {
+                len + header.padding_size()
+            } else {
+                match self.find_free_format_length(&header, pos) {
+                    Some(len) => {
+                        self.free_format_length = Some(len);
+                        len + header.padding_size()
+                    }
+                    // wait for more data, unless we already have more than enough
+                    None if self.buf.len() - pos < MAX_FREE_FORMAT_FRAME_SIZE => break,
+                    None => {
+                        trace!("no header following free format MPEG audio frame");
+                        pos += 1;
+                        continue;
+                    }
+                }
+            };
+            let end = pos + len;
+            if end > self.buf.len() {
+                break;
+            }
+            let pts = self.clock.frame(
+                self.buf_position + pos as u64,
+                header.samples(),
+                header.sample_rate(),
+            );
+            self.frames.push(MpaFrame {
+                header,
+                data: self.buf[pos..end].to_vec(),
+                pts,
+            });
+            pos = end;
+        }
+        self.buf.drain(..pos);
+        self.buf_position += pos as u64;
+    }
+
+    /// Find the length, excluding padding, of the free format frame with the given header at
+    /// the given position in the buffer, by looking for the header of the frame that follows.
+    /// Headers too close to give a length of at least `MpaHeader::SIZE` are ignored, since
+    /// frames of that length would never advance through the buffer.
+    fn find_free_format_length(&self, header: &MpaHeader, pos: usize) -> Option<usize> {
+        let start = pos + MpaHeader::SIZE + header.padding_size();
+        let end = self.buf.len().min(pos + MAX_FREE_FORMAT_FRAME_SIZE);
+        (start..end)
+            .find(|&next| self.buf[next] == 0xff && header.same_format(&self.buf[next..]))
+            .map(|next| next - pos - header.padding_size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    /// Build a frame of the given size starting with the given header.
+    fn frame(header: [u8; 4], size: usize) -> Vec<u8> {
+        let mut data = vec![0; size];
+        data[..4].copy_from_slice(&header);
+        data
+    }
+
+    #[test]
+    fn header() {
+        // MPEG-1 layer II, 48kHz, 192 kbit/s, stereo, original
+        let header = MpaHeader::from_bytes(&hex!("fffda404")).unwrap();
+        assert_eq!(
+            header,
+            MpaHeader {
+                version: Version::Mpeg1,
+                layer: Layer::Layer2,
+                protection_absent: true,
+                bitrate_index: 10,
+                sampling_frequency_index: 1,
+                padding: false,
+                private_bit: false,
+                mode: ChannelMode::Stereo,
+                mode_extension: 0,
+                copyright: false,
+                original: true,
+                emphasis: 0,
+            }
+        );
+        assert_eq!(header.bit_rate(), Some(192_000));
+        assert_eq!(header.sample_rate(), 48000);
+        assert_eq!(header.samples(), 1152);
+        assert_eq!(header.frame_length(), Some(576));
+        assert_eq!(header.channels(), 2);
+
+        // MPEG-1 layer III, 44.1kHz, 128 kbit/s, padded, joint stereo
+        let header = MpaHeader::from_bytes(&hex!("fffb9244")).unwrap();
+        assert_eq!(header.mode, ChannelMode::JointStereo);
+        assert_eq!(header.frame_length(), Some(418));
+        // MPEG-2 layer III, 24kHz, 64 kbit/s, mono
+        let header = MpaHeader::from_bytes(&hex!("fff384c0")).unwrap();
+        assert_eq!(header.version, Version::Mpeg2);
+        assert_eq!(header.sample_rate(), 24000);
+        assert_eq!(header.samples(), 576);
+        assert_eq!(header.frame_length(), Some(192));
+        assert_eq!(header.channels(), 1);
+        // MPEG-1 layer I, 32kHz, 384 kbit/s, padded by a four byte slot
+        let header = MpaHeader::from_bytes(&hex!("ffffca00")).unwrap();
+        assert_eq!(header.samples(), 384);
+        assert_eq!(header.frame_length(), Some(580));
+        // MPEG-2.5 layer III, 8kHz, 8 kbit/s
+        let header = MpaHeader::from_bytes(&hex!("ffe318c0")).unwrap();
+        assert_eq!(header.version, Version::Mpeg25);
+        assert_eq!(header.sample_rate(), 8000);
+        assert_eq!(header.frame_length(), Some(72));
+        // free format
+        let header = MpaHeader::from_bytes(&hex!("fffb04c0")).unwrap();
+        assert!(header.is_free_format());
+        assert_eq!(header.bit_rate(), None);
+        assert_eq!(header.frame_length(), None);
+
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("fffda4")),
+            Err(MpaError::NotEnoughData)
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("ffdda404")),
+            Err(MpaError::BadSyncWord)
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("ffeda404")),
+            Err(MpaError::BadVersion)
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("fff9a404")),
+            Err(MpaError::BadLayer)
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("fffdf404")),
+            Err(MpaError::BadBitrateIndex(15))
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("fffdac04")),
+            Err(MpaError::BadSamplingFrequencyIndex(3))
+        );
+        assert_eq!(
+            MpaHeader::from_bytes(&hex!("fffda406")),
+            Err(MpaError::BadEmphasis(2))
+        );
+    }
+
+    #[test]
+    fn split_across_packets() {
+        let data = frame(hex!("fffda404"), 576).repeat(3);
+        let mut parser = MpaParser::new();
+        parser.begin_packet(Some(Timestamp::from_u64(1000)), &hex!("00ff"));
+        parser.continue_packet(&data[..600]);
+        parser.end_packet();
+        parser.begin_packet(Some(Timestamp::from_u64(9000)), &data[600..]);
+        parser.end_packet();
+        let frames: Vec<_> = parser.drain_frames().collect();
+        let pts: Vec<_> = frames.iter().map(|f| f.pts().unwrap().value()).collect();
+        assert_eq!(pts, [1000, 3160, 9000]);
+        assert_eq!(frames[0].data().len(), 576);
+        assert_eq!(frames[0].duration(), 2160);
+    }
+
+    #[test]
+    fn free_format() {
+        let unpadded = frame(hex!("fffb04c0"), 100);
+        let padded = frame(hex!("fffb06c0"), 101);
+        let data = [&unpadded[..], &padded[..], &unpadded[..]].concat();
+        let mut parser = MpaParser::new();
+        // the length of the first frame is only known once the second header arrives
+        parser.begin_packet(Some(Timestamp::from_u64(0)), &data[..100]);
+        assert_eq!(parser.drain_frames().count(), 0);
+        parser.continue_packet(&data[100..]);
+        parser.end_packet();
+        parser.flush();
+        let frames: Vec<_> = parser.drain_frames().collect();
+        let lengths: Vec<_> = frames.iter().map(|f| f.data().len()).collect();
+        assert_eq!(lengths, [100, 101, 100]);
+        let pts: Vec<_> = frames.iter().map(|f| f.pts().unwrap().value()).collect();
+        assert_eq!(pts, [0, 2160, 4320]);
+    }
+
+    #[test]
+    fn free_format_padding_longer_than_frame() {
+        // the header following the first, padded, frame is only 4 bytes later, which would
+        // imply an unpadded length of 0 for the frames that follow
+        let data = hex!("ffff0200 ffff0200 ffff0000 ffff0000");
+        let mut parser = MpaParser::new();
+        parser.begin_packet(None, &data);
+        parser.end_packet();
+        parser.flush();
+        let lengths: Vec<_> = parser.drain_frames().map(|f| f.data().len()).collect();
+        assert_eq!(lengths, [8, 4, 4]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Access to the _Raw Byte Sequence Payload_ of NAL units, and a bit-level reader for the
+//! fixed-length and Exp-Golomb coded fields that the RBSP contains.
+//!
+//! Within a NAL unit, any byte sequence that would otherwise look like a start code is escaped by
+//! inserting an `emulation_prevention_three_byte` (`0x03`) after each pair of zero bytes.
+//! [`decode_nal()`](fn.decode_nal.html) removes these again, so that the fields of the RBSP can
+//! be read with a [`BitReader`](struct.BitReader.html).
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Removes any _emulation_prevention_three_byte_ values from the given NAL unit data, borrowing
+/// the data unchanged if there are none.
+///
+/// ```
+/// # use mpeg2ts_reader::parser::rbsp::decode_nal;
+/// assert_eq!(&decode_nal(&[0x67, 0, 0, 3, 1, 0, 0, 3])[..], &[0x67, 0, 0, 1, 0, 0]);
+/// ```
+pub fn decode_nal(data: &[u8]) -> Cow<'_, [u8]> {
+    let first = match find_emulation_prevention(data, 0) {
+        None => return Cow::Borrowed(data),
+        Some(pos) => pos,
+    };
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut start = 0;
+    let mut next = Some(first);
+    while let Some(pos) = next {
+        rbsp.extend_from_slice(&data[start..pos]);
+        start = pos + 1;
+        // the zeros that preceded this 0x03 don't count towards the next sequence
+        next = find_emulation_prevention(data, start);
+    }
+    rbsp.extend_from_slice(&data[start..]);
+    Cow::Owned(rbsp)
+}
+
+/// Finds the position of the next `0x03` byte at or after `from` that follows two zero bytes
+/// which also lie at or after `from`.
+fn find_emulation_prevention(data: &[u8], from: usize) -> Option<usize> {
+    let mut zeros = 0;
+    for (i, &b) in data.iter().enumerate().skip(from) {
+        if b == 3 && zeros >= 2 {
+            return Some(i);
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+    }
+    None
+}
+
+/// Problems which may be found while reading RBSP data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitReaderError {
+    /// The data ended before the field named could be read.
+    NotEnoughData {
+        /// the name of the field being read
+        field: &'static str,
+    },
+    /// An Exp-Golomb coded field had a value too large to be represented.
+    ExpGolombTooLarge {
+        /// the name of the field being read
+        field: &'static str,
+    },
+}
+impl fmt::Display for BitReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitReaderError::NotEnoughData { field } => {
+                write!(f, "not enough data to read {}", field)
+            }
+            BitReaderError::ExpGolombTooLarge { field } => {
+                write!(f, "Exp-Golomb value of {} too large", field)
+            }
+        }
+    }
+}
+impl std::error::Error for BitReaderError {}
+
+/// Reads big-endian bit fields from RBSP data (from which emulation prevention bytes have
+/// already been removed).
+///
+/// Each method takes the name of the field being read, which is included in any error.
+///
+/// ```
+/// # use mpeg2ts_reader::parser::rbsp::BitReader;
+/// // 0b1_010_011_0 -> u(1) = 1, ue(v) = 1, ue(v) = 2
+/// let mut r = BitReader::new(&[0b1010_0110]);
+/// assert_eq!(r.read_bool("a"), Ok(true));
+/// assert_eq!(r.read_ue("b"), Ok(1));
+/// assert_eq!(r.read_ue("c"), Ok(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BitReader<'buf> {
+    data: &'buf [u8],
+    /// the number of bits already consumed
+    pos: usize,
+}
+impl<'buf> BitReader<'buf> {
+    /// Create a reader positioned at the first bit of the given data.
+    pub fn new(data: &'buf [u8]) -> BitReader<'buf> {
+        BitReader { data, pos: 0 }
+    }
+
+    /// The number of bits consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The number of bits remaining to be read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    /// `true` if the reader is positioned at the start of a byte.
+    pub fn is_byte_aligned(&self) -> bool {
+        self.pos & 0b111 == 0
+    }
+
+    /// Read a single bit.
+    pub fn read_bool(&mut self, field: &'static str) -> Result<bool, BitReaderError> {
+        if self.pos >= self.data.len() * 8 {
+            return Err(BitReaderError::NotEnoughData { field });
+        }
+        let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+        self.pos += 1;
+        Ok(bit == 1)
+    }
+
+    /// Read an unsigned value of the given number of bits, which must be no more than 32.
+    pub fn read_u32(&mut self, bits: u32, field: &'static str) -> Result<u32, BitReaderError> {
+        assert!(bits <= 32);
+        if self.remaining() < bits as usize {
+            return Err(BitReaderError::NotEnoughData { field });
+        }
+        let mut val = 0u32;
+        for _ in 0..bits {
+            val = val << 1 | self.read_bool(field)? as u32;
+        }
+        Ok(val)
+    }
+
+    /// Read an unsigned value of the given number of bits, which must be no more than 8.
+    pub fn read_u8(&mut self, bits: u32, field: &'static str) -> Result<u8, BitReaderError> {
+        assert!(bits <= 8);
+        self.read_u32(bits, field).map(|v| v as u8)
+    }
+
+    /// Read an unsigned value of the given number of bits, which must be no more than 16.
+    pub fn read_u16(&mut self, bits: u32, field: &'static str) -> Result<u16, BitReaderError> {
+        assert!(bits <= 16);
+        self.read_u32(bits, field).map(|v| v as u16)
+    }
+
+    /// Read a two's complement signed value of the given number of bits, which must be no more
+    /// than 32, the `i(n)` descriptor of _ITU-T H.264_.
+    pub fn read_i32(&mut self, bits: u32, field: &'static str) -> Result<i32, BitReaderError> {
+        let val = self.read_u32(bits, field)?;
+        if bits == 0 || bits == 32 {
+            Ok(val as i32)
+        } else {
+            // sign-extend from the top bit of the field
+            let shift = 32 - bits;
+            Ok(((val << shift) as i32) >> shift)
+        }
+    }
+
+    /// Skip over the given number of bits.
+    pub fn skip(&mut self, bits: usize, field: &'static str) -> Result<(), BitReaderError> {
+        if self.remaining() < bits {
+            return Err(BitReaderError::NotEnoughData { field });
+        }
+        self.pos += bits;
+        Ok(())
+    }
+
+    /// Read an unsigned Exp-Golomb coded value, the `ue(v)` descriptor of _ITU-T H.264_.
+    pub fn read_ue(&mut self, field: &'static str) -> Result<u32, BitReaderError> {
+        let mut leading_zeros = 0;
+        while !self.read_bool(field)? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(BitReaderError::ExpGolombTooLarge { field });
+            }
+        }
+        let suffix = self.read_u32(leading_zeros, field)?;
+        // with at most 31 leading zeros, the largest possible value is 2^32 - 2
+        Ok((1u32 << leading_zeros) - 1 + suffix)
+    }
+
+    /// Read a signed Exp-Golomb coded value, the `se(v)` descriptor of _ITU-T H.264_.
+    pub fn read_se(&mut self, field: &'static str) -> Result<i32, BitReaderError> {
+        let k = i64::from(self.read_ue(field)?);
+        let val = if k & 1 == 1 { (k + 1) / 2 } else { -(k / 2) };
+        Ok(val as i32)
+    }
+
+    /// `true` if there is more data before the `rbsp_stop_one_bit` (the final `1` bit in the
+    /// data), the `more_rbsp_data()` function of _ITU-T H.264_.
+    pub fn has_more_rbsp_data(&self) -> bool {
+        match self.data.iter().rposition(|&b| b != 0) {
+            None => false,
+            Some(last) => {
+                let stop_bit = last * 8 + 7 - self.data[last].trailing_zeros() as usize;
+                self.pos < stop_bit
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn emulation_prevention() {
+        assert_eq!(&decode_nal(&[0x09, 0xf0])[..], &[0x09, 0xf0]);
+        assert!(matches!(decode_nal(&[0x09, 0xf0]), Cow::Borrowed(_)));
+        // the zeros of one sequence can't be shared with the next
+        assert_eq!(
+            &decode_nal(&[0, 0, 3, 0, 3, 0, 0, 3, 3])[..],
+            &[0, 0, 0, 3, 0, 0, 3]
+        );
+        // a 0x03 at the very end is still removed
+        assert_eq!(&decode_nal(&[1, 0, 0, 3])[..], &[1, 0, 0]);
+    }
+
+    #[test]
+    fn exp_golomb() {
+        // codes for 0, 1, 2, 3, 4 and 5 followed by 7 as se(v) (= 4), and then 0 as se(v)
+        // 1 010 011 00100 00101 00110 0001000 1 00
+        let data = [0b1010_0110, 0b0100_0010, 0b1001_1000, 0b0100_0100];
+        let mut r = BitReader::new(&data);
+        for expected in 0..=5 {
+            assert_eq!(r.read_ue("test"), Ok(expected));
+        }
+        assert_eq!(r.read_se("test"), Ok(4));
+        assert_eq!(r.read_se("test"), Ok(0));
+        assert_eq!(r.remaining(), 2);
+        r.skip(2, "padding").unwrap();
+        assert_eq!(
+            r.read_ue("last"),
+            Err(BitReaderError::NotEnoughData { field: "last" })
+        );
+    }
+
+    #[test]
+    fn signed_exp_golomb() {
+        // codes 1, 2, 3, 4 -> 1, -1, 2, -2
+        // 010 011 00100 00101
+        let data = [0b0100_1100, 0b1000_0101];
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.read_se("a"), Ok(1));
+        assert_eq!(r.read_se("b"), Ok(-1));
+        assert_eq!(r.read_se("c"), Ok(2));
+        assert_eq!(r.read_se("d"), Ok(-2));
+    }
+
+    #[test]
+    fn exp_golomb_too_large() {
+        let data = [0, 0, 0, 0, 0xff];
+        assert_eq!(
+            BitReader::new(&data).read_ue("big"),
+            Err(BitReaderError::ExpGolombTooLarge { field: "big" })
+        );
+        // 31 leading zeros and a suffix of all '1' bits is the largest value that can be read
+        let data = [0, 0, 0, 1, 0xff, 0xff, 0xff, 0xfe];
+        assert_eq!(BitReader::new(&data).read_ue("big"), Ok(u32::MAX - 1));
+    }
+
+    #[test]
+    fn fixed_length() {
+        let data = [0xab, 0xcd, 0xef];
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.read_u8(4, "a"), Ok(0xa));
+        assert!(!r.is_byte_aligned());
+        assert_eq!(r.read_u16(12, "b"), Ok(0xbcd));
+        assert!(r.is_byte_aligned());
+        assert_eq!(r.position(), 16);
+        r.skip(4, "c").unwrap();
+        assert_eq!(r.read_u32(4, "d"), Ok(0xf));
+        assert_eq!(
+            r.read_bool("e"),
+            Err(BitReaderError::NotEnoughData { field: "e" })
+        );
+    }
+
+    #[test]
+    fn signed_fixed_length() {
+        let data = [0b0111_1000, 0x80, 0, 0, 0];
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.read_i32(4, "a"), Ok(7));
+        assert_eq!(r.read_i32(4, "b"), Ok(-8));
+        assert_eq!(r.read_i32(32, "c"), Ok(i32::MIN));
+    }
+
+    #[test]
+    fn more_rbsp_data() {
+        // one data bit, then the rbsp_stop_one_bit and alignment zeros, then cabac_zero_words
+        let data = [0b1100_0000, 0, 0];
+        let mut r = BitReader::new(&data);
+        assert!(r.has_more_rbsp_data());
+        r.read_bool("a").unwrap();
+        assert!(!r.has_more_rbsp_data());
+    }
+}
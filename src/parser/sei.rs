@@ -0,0 +1,639 @@
+//! Supplemental Enhancement Information messages, per _ITU-T H.264_ section 7.3.2.3.1.  The
+//! same `sei_message()` syntax is used by _ITU-T H.265_.
+//!
+//! An SEI NAL unit may carry several messages.  [`SeiMessages`](struct.SeiMessages.html) splits
+//! the RBSP of the NAL unit into the individual messages, whose payload may then be decoded
+//! according to their `payload_type`.  Decoders are provided for,
+//!
+//!  - the H.264 `pic_timing` message, [`PicTiming`](struct.PicTiming.html), and the HEVC
+//!    `time_code` message, [`TimeCode`](struct.TimeCode.html), which give timecodes
+//!  - the `user_data_registered_itu_t_t35` message, [`ItuTT35`](struct.ItuTT35.html), from which
+//!    CEA-608 / CEA-708 captions in the ATSC A/53 format can be extracted as
+//!    [`CcData`](struct.CcData.html)
+//!  - the `user_data_unregistered` message,
+//!    [`UserDataUnregistered`](struct.UserDataUnregistered.html)
+//!  - the H.264 `recovery_point` message, [`RecoveryPoint`](struct.RecoveryPoint.html)
+//!
+//! ```
+//! # use mpeg2ts_reader::parser::sei::{self, SeiMessages};
+//! // an H.264 SEI NAL unit holding a recovery point message
+//! let nal = [0x06, 0x06, 0x01, 0xc4, 0x80];
+//! let rbsp = mpeg2ts_reader::parser::rbsp::decode_nal(&nal[1..]);
+//! let msg = SeiMessages::new(&rbsp).next().unwrap().unwrap();
+//! assert_eq!(msg.payload_type, sei::PAYLOAD_TYPE_RECOVERY_POINT);
+//! let recovery_point = sei::RecoveryPoint::from_payload(msg.payload).unwrap();
+//! assert!(recovery_point.exact_match_flag);
+//! ```
+
+use crate::parser::h264::SeqParameterSet;
+use crate::parser::rbsp::{BitReader, BitReaderError};
+use std::fmt;
+
+/// _payloadType_ of an H.264 picture timing SEI message
+pub const PAYLOAD_TYPE_PIC_TIMING: u32 = 1;
+/// _payloadType_ of a message carrying user data registered per _ITU-T T.35_
+pub const PAYLOAD_TYPE_USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+/// _payloadType_ of a message carrying user data identified by a UUID
+pub const PAYLOAD_TYPE_USER_DATA_UNREGISTERED: u32 = 5;
+/// _payloadType_ of a recovery point SEI message
+pub const PAYLOAD_TYPE_RECOVERY_POINT: u32 = 6;
+/// _payloadType_ of an HEVC time code SEI message
+pub const PAYLOAD_TYPE_TIME_CODE: u32 = 136;
+
+/// The _itu_t_t35_country_code_ of the United States, used by ATSC
+const COUNTRY_CODE_USA: u8 = 0xb5;
+/// The _itu_t_t35_provider_code_ assigned to ATSC
+const PROVIDER_CODE_ATSC: u16 = 0x0031;
+/// The ATSC A/53 _user_identifier_ for closed captions and bar data
+const USER_IDENTIFIER_GA94: [u8; 4] = *b"GA94";
+/// The ATSC A/53 _user_data_type_code_ of `cc_data()`
+const USER_DATA_TYPE_CC_DATA: u8 = 0x03;
+
+/// Problems which may be found while reading SEI messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeiError {
+    /// The data ended part way through the header or payload of a message.
+    Truncated,
+    /// The payload of a message could not be read.
+    Bits(BitReaderError),
+}
+impl From<BitReaderError> for SeiError {
+    fn from(e: BitReaderError) -> Self {
+        SeiError::Bits(e)
+    }
+}
+impl fmt::Display for SeiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeiError::Truncated => write!(f, "SEI message truncated"),
+            SeiError::Bits(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for SeiError {}
+
+/// A single SEI message, whose payload has not yet been decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeiMessage<'buf> {
+    /// the _payloadType_ of the message, identifying the syntax of the payload
+    pub payload_type: u32,
+    /// the _payloadSize_ bytes of the message payload
+    pub payload: &'buf [u8],
+}
+
+/// Iterates over the messages within the RBSP of an SEI NAL unit (i.e. the data following the
+/// NAL unit header, with emulation prevention bytes removed).
+///
+/// Iteration stops at the `rbsp_trailing_bits`, or after the first error.
+#[derive(Debug, Clone)]
+pub struct SeiMessages<'buf> {
+    rest: &'buf [u8],
+}
+impl<'buf> SeiMessages<'buf> {
+    /// Create an iterator over the messages in the given RBSP.
+    pub fn new(rbsp: &'buf [u8]) -> SeiMessages<'buf> {
+        SeiMessages { rest: rbsp }
+    }
+
+    /// Reads one of the variable length _payloadType_ / _payloadSize_ values, each `0xff` byte
+    /// of which adds 255 to the final byte.
+    fn read_value(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        loop {
+            let (&b, rest) = self.rest.split_first()?;
+            self.rest = rest;
+            value = value.checked_add(u32::from(b))?;
+            if b != 0xff {
+                return Some(value);
+            }
+        }
+    }
+
+    fn at_trailing_bits(&self) -> bool {
+        match self.rest.split_first() {
+            None => true,
+            Some((&first, rest)) => first == 0x80 && rest.iter().all(|&b| b == 0),
+        }
+    }
+}
+impl<'buf> Iterator for SeiMessages<'buf> {
+    type Item = Result<SeiMessage<'buf>, SeiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.at_trailing_bits() {
+            return None;
+        }
+        let header = self.read_value().zip(self.read_value());
+        match header {
+            Some((payload_type, size)) if size as usize <= self.rest.len() => {
+                let (payload, rest) = self.rest.split_at(size as usize);
+                self.rest = rest;
+                Some(Ok(SeiMessage {
+                    payload_type,
+                    payload,
+                }))
+            }
+            _ => {
+                self.rest = &[];
+                Some(Err(SeiError::Truncated))
+            }
+        }
+    }
+}
+
+/// The H.264 recovery point SEI message, which marks a picture from which decoding may start
+/// even though it is not an IDR picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryPoint {
+    /// the number of frames, in output order, after which decoded pictures will be correct
+    pub recovery_frame_cnt: u32,
+    /// `true` if pictures from the recovery point onwards will exactly match those decoded from
+    /// the start of the stream
+    pub exact_match_flag: bool,
+    /// `true` if there may be a splice point before this picture, so that some pictures which
+    /// follow cannot be decoded correctly
+    pub broken_link_flag: bool,
+    /// the _changing_slice_group_idc_ field
+    pub changing_slice_group_idc: u8,
+}
+impl RecoveryPoint {
+    /// Decode the payload of an SEI message having _payloadType_
+    /// [`PAYLOAD_TYPE_RECOVERY_POINT`](constant.PAYLOAD_TYPE_RECOVERY_POINT.html).
+    pub fn from_payload(payload: &[u8]) -> Result<RecoveryPoint, SeiError> {
+        let mut r = BitReader::new(payload);
+        Ok(RecoveryPoint {
+            recovery_frame_cnt: r.read_ue("recovery_frame_cnt")?,
+            exact_match_flag: r.read_bool("exact_match_flag")?,
+            broken_link_flag: r.read_bool("broken_link_flag")?,
+            changing_slice_group_idc: r.read_u8(2, "changing_slice_group_idc")?,
+        })
+    }
+}
+
+/// A `clock_timestamp` from an H.264 `pic_timing` or HEVC `time_code` SEI message, giving the
+/// timecode of a picture or field.
+///
+/// The hours, minutes and seconds may be omitted from the message when they are unchanged since
+/// the previous timestamp, in which case they are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockTimestamp {
+    /// the _ct_type_ field (progressive, interlaced, or unknown) of H.264 timestamps
+    pub ct_type: Option<u8>,
+    /// the _nuit_field_based_flag_ (H.264) or _units_field_based_flag_ (HEVC)
+    pub nuit_field_based_flag: bool,
+    /// the _counting_type_ field, describing any dropping of _n_frames_ values
+    pub counting_type: u8,
+    /// `true` if this timestamp is not continuous with the previous one
+    pub discontinuity_flag: bool,
+    /// `true` if _n_frames_ values were dropped, as in 'drop frame' NTSC timecode
+    pub cnt_dropped_flag: bool,
+    /// the frame count within the current second
+    pub n_frames: u16,
+    /// the seconds of the timecode, if present
+    pub seconds: Option<u8>,
+    /// the minutes of the timecode, if present
+    pub minutes: Option<u8>,
+    /// the hours of the timecode, if present
+    pub hours: Option<u8>,
+    /// the _time_offset_ field, or `0` if absent
+    pub time_offset: i32,
+}
+impl ClockTimestamp {
+    /// Reads the fields from _counting_type_ onwards, which have the same syntax in H.264 and
+    /// HEVC other than the size of _n_frames_, and up to (but not including) _time_offset_.
+    fn read_common(
+        r: &mut BitReader<'_>,
+        ct_type: Option<u8>,
+        nuit_field_based_flag: bool,
+        n_frames_bits: u32,
+    ) -> Result<ClockTimestamp, SeiError> {
+        let counting_type = r.read_u8(5, "counting_type")?;
+        let full_timestamp_flag = r.read_bool("full_timestamp_flag")?;
+        let discontinuity_flag = r.read_bool("discontinuity_flag")?;
+        let cnt_dropped_flag = r.read_bool("cnt_dropped_flag")?;
+        let n_frames = r.read_u16(n_frames_bits, "n_frames")?;
+        let mut ts = ClockTimestamp {
+            ct_type,
+            nuit_field_based_flag,
+            counting_type,
+            discontinuity_flag,
+            cnt_dropped_flag,
+            n_frames,
+            seconds: None,
+            minutes: None,
+            hours: None,
+            time_offset: 0,
+        };
+        if full_timestamp_flag {
+            ts.seconds = Some(r.read_u8(6, "seconds_value")?);
+            ts.minutes = Some(r.read_u8(6, "minutes_value")?);
+            ts.hours = Some(r.read_u8(5, "hours_value")?);
+        } else if r.read_bool("seconds_flag")? {
+            ts.seconds = Some(r.read_u8(6, "seconds_value")?);
+            if r.read_bool("minutes_flag")? {
+                ts.minutes = Some(r.read_u8(6, "minutes_value")?);
+                if r.read_bool("hours_flag")? {
+                    ts.hours = Some(r.read_u8(5, "hours_value")?);
+                }
+            }
+        }
+        Ok(ts)
+    }
+}
+
+/// The H.264 picture timing SEI message.
+///
+/// The syntax of this message depends on the HRD and VUI parameters of the active SPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PicTiming {
+    /// the _cpb_removal_delay_ field, present if the SPS has HRD parameters
+    pub cpb_removal_delay: Option<u32>,
+    /// the _dpb_output_delay_ field, present if the SPS has HRD parameters
+    pub dpb_output_delay: Option<u32>,
+    /// the _pic_struct_ field (frame, field, or repeated fields), present if the SPS VUI has
+    /// _pic_struct_present_flag_ set
+    pub pic_struct: Option<u8>,
+    /// the timestamps given for each of the frames or fields indicated by _pic_struct_
+    pub clock_timestamps: Vec<ClockTimestamp>,
+}
+impl PicTiming {
+    /// Decode the payload of an SEI message having _payloadType_
+    /// [`PAYLOAD_TYPE_PIC_TIMING`](constant.PAYLOAD_TYPE_PIC_TIMING.html), using the given SPS
+    /// (that which is active for the access unit containing the message).
+    pub fn from_payload(payload: &[u8], sps: &SeqParameterSet) -> Result<PicTiming, SeiError> {
+        let mut r = BitReader::new(payload);
+        let vui = sps.vui_parameters.as_ref();
+        let hrd = vui.and_then(|vui| {
+            vui.nal_hrd_parameters
+                .as_ref()
+                .or(vui.vcl_hrd_parameters.as_ref())
+        });
+        let mut timing = PicTiming {
+            cpb_removal_delay: None,
+            dpb_output_delay: None,
+            pic_struct: None,
+            clock_timestamps: Vec::new(),
+        };
+        if let Some(hrd) = hrd {
+            let bits = u32::from(hrd.cpb_removal_delay_length_minus1) + 1;
+            timing.cpb_removal_delay = Some(r.read_u32(bits, "cpb_removal_delay")?);
+            let bits = u32::from(hrd.dpb_output_delay_length_minus1) + 1;
+            timing.dpb_output_delay = Some(r.read_u32(bits, "dpb_output_delay")?);
+        }
+        if vui.map(|vui| vui.pic_struct_present_flag).unwrap_or(false) {
+            let pic_struct = r.read_u8(4, "pic_struct")?;
+            timing.pic_struct = Some(pic_struct);
+            // NumClockTS, per table D-1
+            let num_clock_ts = match pic_struct {
+                0..=2 => 1,
+                3 | 4 | 7 => 2,
+                5 | 6 | 8 => 3,
+                _ => 0,
+            };
+            let time_offset_length = hrd
+                .map(|hrd| u32::from(hrd.time_offset_length))
+                .unwrap_or(24);
+            for _ in 0..num_clock_ts {
+                if r.read_bool("clock_timestamp_flag")? {
+                    let ct_type = r.read_u8(2, "ct_type")?;
+                    let nuit_field_based_flag = r.read_bool("nuit_field_based_flag")?;
+                    let mut ts = ClockTimestamp::read_common(
+                        &mut r,
+                        Some(ct_type),
+                        nuit_field_based_flag,
+                        8,
+                    )?;
+                    if time_offset_length > 0 {
+                        ts.time_offset = r.read_i32(time_offset_length, "time_offset")?;
+                    }
+                    timing.clock_timestamps.push(ts);
+                }
+            }
+        }
+        Ok(timing)
+    }
+}
+
+/// The HEVC time code SEI message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeCode {
+    /// the timestamps given for each of the frames or fields of the picture
+    pub clock_timestamps: Vec<ClockTimestamp>,
+}
+impl TimeCode {
+    /// Decode the payload of an SEI message having _payloadType_
+    /// [`PAYLOAD_TYPE_TIME_CODE`](constant.PAYLOAD_TYPE_TIME_CODE.html).
+    pub fn from_payload(payload: &[u8]) -> Result<TimeCode, SeiError> {
+        let mut r = BitReader::new(payload);
+        let num_clock_ts = r.read_u8(2, "num_clock_ts")?;
+        let mut clock_timestamps = Vec::new();
+        for _ in 0..num_clock_ts {
+            if r.read_bool("clock_timestamp_flag")? {
+                let units_field_based_flag = r.read_bool("units_field_based_flag")?;
+                let mut ts = ClockTimestamp::read_common(&mut r, None, units_field_based_flag, 9)?;
+                let time_offset_length = r.read_u32(5, "time_offset_length")?;
+                if time_offset_length > 0 {
+                    ts.time_offset = r.read_i32(time_offset_length, "time_offset_value")?;
+                }
+                clock_timestamps.push(ts);
+            }
+        }
+        Ok(TimeCode { clock_timestamps })
+    }
+}
+
+/// User data carried in a _user_data_unregistered_ SEI message, which is identified only by a
+/// UUID chosen by whoever defined its format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserDataUnregistered<'buf> {
+    /// the _uuid_iso_iec_11578_ field
+    pub uuid: [u8; 16],
+    /// the _user_data_payload_byte_ values
+    pub payload: &'buf [u8],
+}
+impl<'buf> UserDataUnregistered<'buf> {
+    /// Decode the payload of an SEI message having _payloadType_
+    /// [`PAYLOAD_TYPE_USER_DATA_UNREGISTERED`](constant.PAYLOAD_TYPE_USER_DATA_UNREGISTERED.html).
+    pub fn from_payload(payload: &'buf [u8]) -> Result<UserDataUnregistered<'buf>, SeiError> {
+        if payload.len() < 16 {
+            return Err(SeiError::Truncated);
+        }
+        let (uuid, payload) = payload.split_at(16);
+        let mut result = UserDataUnregistered {
+            uuid: [0; 16],
+            payload,
+        };
+        result.uuid.copy_from_slice(uuid);
+        Ok(result)
+    }
+}
+
+/// User data carried in a _user_data_registered_itu_t_t35_ SEI message, whose format is
+/// identified by a country code (and usually a provider code within the payload) assigned per
+/// _ITU-T T.35_.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItuTT35<'buf> {
+    /// the _itu_t_t35_country_code_ field
+    pub country_code: u8,
+    /// the _itu_t_t35_country_code_extension_byte_, present if the _country_code_ is `0xff`
+    pub country_code_extension: Option<u8>,
+    /// the remaining _itu_t_t35_payload_byte_ values
+    pub payload: &'buf [u8],
+}
+impl<'buf> ItuTT35<'buf> {
+    /// Decode the payload of an SEI message having _payloadType_
+    /// [`PAYLOAD_TYPE_USER_DATA_REGISTERED_ITU_T_T35`](constant.PAYLOAD_TYPE_USER_DATA_REGISTERED_ITU_T_T35.html).
+    pub fn from_payload(payload: &'buf [u8]) -> Result<ItuTT35<'buf>, SeiError> {
+        match payload {
+            [0xff, ext, rest @ ..] => Ok(ItuTT35 {
+                country_code: 0xff,
+                country_code_extension: Some(*ext),
+                payload: rest,
+            }),
+            [0xff] | [] => Err(SeiError::Truncated),
+            [country_code, rest @ ..] => Ok(ItuTT35 {
+                country_code: *country_code,
+                country_code_extension: None,
+                payload: rest,
+            }),
+        }
+    }
+
+    /// The closed caption data carried by this message, if it uses the ATSC A/53 format (as
+    /// used for CEA-608 and CEA-708 captions in H.264 and HEVC streams generally, not only in
+    /// ATSC broadcasts).  Returns `Ok(None)` if the message contains some other kind of data.
+    pub fn atsc_cc_data(&self) -> Result<Option<CcData>, SeiError> {
+        if self.country_code != COUNTRY_CODE_USA {
+            return Ok(None);
+        }
+        match self.payload {
+            [p0, p1, u0, u1, u2, u3, USER_DATA_TYPE_CC_DATA, cc_data @ ..]
+                if u16::from_be_bytes([*p0, *p1]) == PROVIDER_CODE_ATSC
+                    && [*u0, *u1, *u2, *u3] == USER_IDENTIFIER_GA94 =>
+            {
+                CcData::from_bytes(cc_data).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The kind of data in a `CcConstruct`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcType {
+    /// a pair of CEA-608 bytes for field 1
+    Ntsc608Field1,
+    /// a pair of CEA-608 bytes for field 2
+    Ntsc608Field2,
+    /// a pair of bytes continuing a CEA-708 DTVCC packet
+    DtvccPacketData,
+    /// a pair of bytes starting a CEA-708 DTVCC packet
+    DtvccPacketStart,
+}
+
+/// One pair of caption bytes from `CcData`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CcConstruct {
+    /// `false` if the bytes are padding, and should be ignored
+    pub cc_valid: bool,
+    /// the kind of caption data
+    pub cc_type: CcType,
+    /// the _cc_data_1_ and _cc_data_2_ bytes
+    pub data: [u8; 2],
+}
+
+/// The `cc_data()` structure defined by _ATSC A/53 Part 4_ and _CEA-708_, carrying CEA-608 and
+/// CEA-708 caption data for one picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CcData {
+    /// `true` if the `constructs` should be processed, `false` if they should be ignored
+    pub process_cc_data_flag: bool,
+    /// the caption data
+    pub constructs: Vec<CcConstruct>,
+}
+impl CcData {
+    fn from_bytes(data: &[u8]) -> Result<CcData, SeiError> {
+        let (flags, rest) = match data {
+            // the em_data byte following the flags is ignored
+            [flags, _em_data, rest @ ..] => (flags, rest),
+            _ => return Err(SeiError::Truncated),
+        };
+        let cc_count = usize::from(flags & 0b1_1111);
+        if rest.len() < cc_count * 3 {
+            return Err(SeiError::Truncated);
+        }
+        let constructs = rest[..cc_count * 3]
+            .chunks(3)
+            .map(|c| CcConstruct {
+                cc_valid: c[0] & 0b100 != 0,
+                cc_type: match c[0] & 0b11 {
+                    0 => CcType::Ntsc608Field1,
+                    1 => CcType::Ntsc608Field2,
+                    2 => CcType::DtvccPacketData,
+                    _ => CcType::DtvccPacketStart,
+                },
+                data: [c[1], c[2]],
+            })
+            .collect();
+        Ok(CcData {
+            process_cc_data_flag: flags & 0b0100_0000 != 0,
+            constructs,
+        })
+    }
+
+    /// The valid CEA-608 byte pairs for the given field (`1` or `2`).
+    pub fn cea608_field(&self, field: u8) -> impl Iterator<Item = [u8; 2]> + '_ {
+        let cc_type = if field == 1 {
+            CcType::Ntsc608Field1
+        } else {
+            CcType::Ntsc608Field2
+        };
+        self.constructs
+            .iter()
+            .filter(move |c| c.cc_valid && c.cc_type == cc_type)
+            .map(|c| c.data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn messages() {
+        // a 300 byte message of payload type 5, a recovery point, then a truncated message
+        let mut rbsp = hex!("05 ff 2d").to_vec();
+        rbsp.extend(std::iter::repeat(0xaa).take(300));
+        rbsp.extend_from_slice(&hex!("06 01 c4  ff 04 05 0102"));
+        let mut messages = SeiMessages::new(&rbsp);
+        let msg = messages.next().unwrap().unwrap();
+        assert_eq!(msg.payload_type, 5);
+        assert_eq!(msg.payload.len(), 300);
+        let msg = messages.next().unwrap().unwrap();
+        assert_eq!(msg.payload_type, PAYLOAD_TYPE_RECOVERY_POINT);
+        assert_eq!(
+            RecoveryPoint::from_payload(msg.payload),
+            Ok(RecoveryPoint {
+                recovery_frame_cnt: 0,
+                exact_match_flag: true,
+                broken_link_flag: false,
+                changing_slice_group_idc: 0,
+            })
+        );
+        assert_eq!(messages.next(), Some(Err(SeiError::Truncated)));
+        assert_eq!(messages.next(), None);
+    }
+
+    #[test]
+    fn captions() {
+        // an HEVC prefix SEI NAL unit with ATSC A/53 caption data, holding a CEA-608 pair for
+        // each field, and a padding pair
+        let nal = hex!("4e01 04 14 b5 0031 47413934 03 c3ff fc9420 fd8080 fa0000 ff 80");
+        let rbsp = crate::parser::rbsp::decode_nal(&nal[2..]);
+        let msg = SeiMessages::new(&rbsp).next().unwrap().unwrap();
+        assert_eq!(
+            msg.payload_type,
+            PAYLOAD_TYPE_USER_DATA_REGISTERED_ITU_T_T35
+        );
+        let t35 = ItuTT35::from_payload(msg.payload).unwrap();
+        assert_eq!(t35.country_code, 0xb5);
+        let cc = t35.atsc_cc_data().unwrap().unwrap();
+        assert!(cc.process_cc_data_flag);
+        assert_eq!(cc.constructs.len(), 3);
+        assert_eq!(
+            cc.constructs[1],
+            CcConstruct {
+                cc_valid: true,
+                cc_type: CcType::Ntsc608Field2,
+                data: [0x80, 0x80],
+            }
+        );
+        assert_eq!(cc.cea608_field(1).collect::<Vec<_>>(), vec![[0x94, 0x20]]);
+        // AFD data uses a different user_data_type_code
+        let afd = ItuTT35::from_payload(&hex!("b5 0031 44544731 41 f8")).unwrap();
+        assert_eq!(afd.atsc_cc_data(), Ok(None));
+        // cc_count claims more data than is present
+        let short = ItuTT35::from_payload(&hex!("b5 0031 47413934 03 c3ff fc9420")).unwrap();
+        assert_eq!(short.atsc_cc_data(), Err(SeiError::Truncated));
+    }
+
+    #[test]
+    fn user_data_unregistered() {
+        // the x264 version information message
+        let payload = hex!("dc45e9bd e6d948b7 962cd820 d923eeef 78323634 00");
+        let data = UserDataUnregistered::from_payload(&payload).unwrap();
+        assert_eq!(data.uuid, hex!("dc45e9bd e6d948b7 962cd820 d923eeef"));
+        assert_eq!(data.payload, b"x264\0");
+        assert_eq!(
+            UserDataUnregistered::from_payload(&payload[..15]),
+            Err(SeiError::Truncated)
+        );
+    }
+
+    #[test]
+    fn pic_timing() {
+        // an SPS with HRD parameters (23 and 22 bit delays, 24 bit time offset), and
+        // pic_struct_present_flag
+        let sps = SeqParameterSet::from_bytes(&hex!(
+            "67640028 ad844108 b6501e01 13f7ff80 02000188 00001f48 00075307 00007d20
+             07d15ed5 c1da0884 5960"
+        ))
+        .unwrap();
+        // pic_struct 3 (two fields), each with a clock timestamp, the second only giving seconds
+        let payload = hex!("00000400 0021d128 645c2bff ffee8843 71000000 20");
+        let timing = PicTiming::from_payload(&payload, &sps).unwrap();
+        assert_eq!(timing.cpb_removal_delay, Some(2));
+        assert_eq!(timing.dpb_output_delay, Some(4));
+        assert_eq!(timing.pic_struct, Some(3));
+        assert_eq!(
+            timing.clock_timestamps,
+            vec![
+                ClockTimestamp {
+                    ct_type: Some(1),
+                    nuit_field_based_flag: false,
+                    counting_type: 4,
+                    discontinuity_flag: false,
+                    cnt_dropped_flag: true,
+                    n_frames: 12,
+                    seconds: Some(34),
+                    minutes: Some(56),
+                    hours: Some(10),
+                    time_offset: -5,
+                },
+                ClockTimestamp {
+                    ct_type: Some(1),
+                    nuit_field_based_flag: false,
+                    counting_type: 4,
+                    discontinuity_flag: false,
+                    cnt_dropped_flag: true,
+                    n_frames: 13,
+                    seconds: Some(34),
+                    minutes: None,
+                    hours: None,
+                    time_offset: 0,
+                },
+            ]
+        );
+        assert!(PicTiming::from_payload(&payload[..10], &sps).is_err());
+    }
+
+    #[test]
+    fn time_code() {
+        let timecode = TimeCode::from_payload(&hex!("604960210c10")).unwrap();
+        assert_eq!(timecode.clock_timestamps.len(), 1);
+        let ts = timecode.clock_timestamps[0];
+        assert_eq!(ts.ct_type, None);
+        assert_eq!(ts.n_frames, 300);
+        assert_eq!(
+            (ts.hours, ts.minutes, ts.seconds),
+            (Some(3), Some(2), Some(1))
+        );
+    }
+
+    #[test]
+    fn trailing_bits() {
+        assert_eq!(SeiMessages::new(&hex!("80")).count(), 0);
+        assert_eq!(SeiMessages::new(&hex!("0601c4 80 00")).count(), 1);
+    }
+}
@@ -10,13 +10,21 @@
 //! [`ElementaryStreamConsumer`](trait.ElementaryStreamConsumer.html), and register this with the
 //! `StreamConstructor` object passed to the
 //! [`Demultiplex`](../demultiplex/struct.Demultiplex.html) instance.
+//!
+//! Alternatively, if the convenience of having each PES packet in a single buffer is worth the
+//! cost of copying the data, a
+//! [`BufferingElementaryStreamConsumer`](struct.BufferingElementaryStreamConsumer.html) can be
+//! used to pass complete PES packets to an implementation of the simpler
+//! [`PesPacketConsumer`](trait.PesPacketConsumer.html) trait.
+//!
+//! To extract the raw elementary stream data (for example, to produce a `.h264` or `.aac`
+//! file), an [`ElementaryStreamWriter`](struct.ElementaryStreamWriter.html) can be used.
 
 use crate::demultiplex;
 use crate::packet;
 use crate::packet::ClockRef;
-use log::warn;
 use std::marker;
-use std::{fmt, num};
+use std::{fmt, io, num};
 
 /// Trait for types that will receive call-backs as pieces of a specific elementary stream are
 /// encounted within a transport stream.
@@ -43,7 +51,9 @@ pub trait ElementaryStreamConsumer<Ctx> {
     /// some data in the original Transport Stream did not reach the parser.
     fn continuity_error(&mut self, ctx: &mut Ctx);
 
-    // TODO: end_stream() for symmetry?
+    /// called when the end of the stream is reached, after the final call to `end_packet()` (if
+    /// any).  The default implementation does nothing.
+    fn end_stream(&mut self, _ctx: &mut Ctx) {}
 }
 
 #[derive(Debug, PartialEq)]
@@ -87,17 +97,11 @@ where
     fn is_continuous(&self, packet: &packet::Packet<'_>) -> bool {
         if let Some(cc) = self.ccounter {
             // counter only increases if the packet has a payload,
-            let result = if packet.adaptation_control().has_payload() {
-                println!("Adaptation control has payload");
+            if packet.adaptation_control().has_payload() {
                 packet.continuity_counter().follows(cc)
             } else {
-                println!("Adaptation control does not have payload with count 1 = {} and 2 = {}", packet.continuity_counter().count(), cc.count());
                 packet.continuity_counter().count() == cc.count()
-            };
-            if !result {
-                //warn!("discontinuity at packet with {:?} last={} this={} ({:?})", packet.pid(), cc.count(), packet.continuity_counter().count(), packet.adaptation_control());
             }
-            result
         } else {
             true
         }
@@ -113,6 +117,11 @@ where
     #[inline(always)]
     fn consume(&mut self, ctx: &mut Self::Ctx, packet: &packet::Packet<'_>) {
         if !self.is_continuous(packet) {
+            let offset = packet.offset();
+            ctx.event(demultiplex::DemuxEvent::ContinuityError {
+                pid: packet.pid(),
+                offset,
+            });
             self.stream_consumer.continuity_error(ctx);
             self.state = PesState::IgnoreRest;
         }
@@ -133,8 +142,24 @@ where
                 self.state = PesState::Started;
             }
             if let Some(payload) = packet.payload() {
-                if let Some(header) = PesHeader::from_bytes(payload) {
-                    self.stream_consumer.begin_packet(ctx, header);
+                match PesHeader::from_bytes(payload) {
+                    Ok(header) => {
+                        if let Err(error) = header.check_contents() {
+                            ctx.event(demultiplex::DemuxEvent::PesHeaderInvalid {
+                                pid: packet.pid(),
+                                offset: packet.offset(),
+                                error,
+                            });
+                        }
+                        self.stream_consumer.begin_packet(ctx, header);
+                    }
+                    Err(error) => {
+                        ctx.event(demultiplex::DemuxEvent::PesHeaderInvalid {
+                            pid: packet.pid(),
+                            offset: packet.offset(),
+                            error,
+                        });
+                    }
                 }
             }
         } else {
@@ -147,12 +172,348 @@ where
                     }
                 }
                 PesState::Begin => {
-                    warn!("{:?}: Ignoring elementary stream content without a payload_start_indicator", packet.pid());
+                    let offset = packet.offset();
+                    ctx.event(demultiplex::DemuxEvent::PesContinuationWithoutStart {
+                        pid: packet.pid(),
+                        offset,
+                    });
                 }
                 PesState::IgnoreRest => (),
             }
         }
     }
+
+    fn end_stream(&mut self, ctx: &mut Self::Ctx) {
+        match self.state {
+            PesState::Started | PesState::IgnoreRest => self.stream_consumer.end_packet(ctx),
+            PesState::Begin => (),
+        }
+        self.stream_consumer.end_stream(ctx);
+        self.ccounter = None;
+        self.state = PesState::Begin;
+    }
+}
+
+/// Trait for types that will receive whole PES packets from a
+/// [`BufferingElementaryStreamConsumer`](struct.BufferingElementaryStreamConsumer.html).
+pub trait PesPacketConsumer<Ctx> {
+    /// called with each complete PES packet found in the elementary stream
+    fn pes_packet(&mut self, ctx: &mut Ctx, packet: &PesPacket<'_>);
+
+    /// called when the end of the stream is reached, after the final call to `pes_packet()` (if
+    /// any).  The default implementation does nothing.
+    fn end_stream(&mut self, _ctx: &mut Ctx) {}
+}
+
+/// A complete PES packet, as reassembled by a
+/// [`BufferingElementaryStreamConsumer`](struct.BufferingElementaryStreamConsumer.html).
+pub struct PesPacket<'buf> {
+    pid: packet::Pid,
+    header: PesHeader<'buf>,
+}
+impl<'buf> PesPacket<'buf> {
+    /// The `Pid` of the transport stream packets which carried this PES packet.
+    pub fn pid(&self) -> packet::Pid {
+        self.pid
+    }
+
+    /// The header of the PES packet, from which the complete payload is available.
+    pub fn header(&self) -> &PesHeader<'buf> {
+        &self.header
+    }
+
+    /// Shortcut for `header().stream_id()`
+    pub fn stream_id(&self) -> StreamId {
+        self.header.stream_id()
+    }
+
+    /// The additional header fields of the PES packet, or `None` if the `stream_id()` indicates
+    /// that they are not present, or if they could not be parsed.
+    pub fn parsed_contents(&self) -> Option<PesParsedContents<'buf>> {
+        match self.header.contents() {
+            PesContents::Parsed(parsed) => parsed,
+            PesContents::Payload(_) => None,
+        }
+    }
+
+    /// The Presentation Time Stamp of the PES packet, if present and valid.
+    pub fn pts(&self) -> Option<Timestamp> {
+        self.parsed_contents()?.pts_dts().ok()?.pts()
+    }
+
+    /// The Decode Time Stamp of the PES packet, if present and valid.  Note that when only a
+    /// PTS is present, the decode time is the same as the presentation time, but `None` is
+    /// returned here.
+    pub fn dts(&self) -> Option<Timestamp> {
+        self.parsed_contents()?.pts_dts().ok()?.dts()
+    }
+
+    /// The complete elementary stream data carried in this PES packet.  This will be empty if
+    /// the PES packet's header could not be parsed.
+    pub fn payload(&self) -> &'buf [u8] {
+        match self.header.contents() {
+            PesContents::Parsed(Some(parsed)) => parsed.payload(),
+            PesContents::Parsed(None) => &[],
+            PesContents::Payload(payload) => payload,
+        }
+    }
+}
+
+enum BufferState {
+    /// waiting for the start of the next PES packet
+    Idle,
+    /// collecting the data of a PES packet, which will have the given total size if this is
+    /// indicated by _PES_packet_length_
+    Buffering { expected: Option<usize> },
+    /// the data of the current PES packet is being dropped
+    Discarding,
+}
+
+/// Implementation of [`ElementaryStreamConsumer`](trait.ElementaryStreamConsumer.html) which
+/// reassembles the pieces of each PES packet, and passes complete packets to the given
+/// [`PesPacketConsumer`](trait.PesPacketConsumer.html).
+///
+/// When the PES header gives a _PES_packet_length_, the packet is delivered as soon as that much
+/// data has been received.  Otherwise (as is common for video streams), the packet is delivered
+/// once the start of the next PES packet is seen, or at the end of the stream.
+///
+/// PES packets larger than the maximum size (`DEFAULT_MAX_SIZE` unless changed with
+/// `with_max_size()`) are dropped, as are packets which are missing data due to a continuity
+/// error.  This means that the consumer is not given a packet from which some data is
+/// missing.
+pub struct BufferingElementaryStreamConsumer<P> {
+    pid: packet::Pid,
+    consumer: P,
+    max_size: usize,
+    buf: Vec<u8>,
+    state: BufferState,
+}
+impl<P> BufferingElementaryStreamConsumer<P> {
+    /// The default limit on the size of the PES packets that will be buffered, 4MiB
+    pub const DEFAULT_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Create a consumer for the elementary stream carried in packets with the given `Pid`,
+    /// which will pass complete PES packets to the given `PesPacketConsumer`.
+    pub fn new(pid: packet::Pid, consumer: P) -> BufferingElementaryStreamConsumer<P> {
+        BufferingElementaryStreamConsumer {
+            pid,
+            consumer,
+            max_size: Self::DEFAULT_MAX_SIZE,
+            buf: vec![],
+            state: BufferState::Idle,
+        }
+    }
+
+    /// Returns this consumer, changed to drop any PES packet (including its header) larger
+    /// than the given number of bytes.
+    pub fn with_max_size(self, max_size: usize) -> BufferingElementaryStreamConsumer<P> {
+        BufferingElementaryStreamConsumer { max_size, ..self }
+    }
+
+    /// Borrow the `PesPacketConsumer` to which PES packets are delivered.
+    pub fn consumer(&self) -> &P {
+        &self.consumer
+    }
+
+    /// Mutably borrow the `PesPacketConsumer` to which PES packets are delivered.
+    pub fn consumer_mut(&mut self) -> &mut P {
+        &mut self.consumer
+    }
+
+    fn append<Ctx>(&mut self, ctx: &mut Ctx, data: &[u8])
+    where
+        Ctx: demultiplex::DemuxContext,
+        P: PesPacketConsumer<Ctx>,
+    {
+        let expected = match self.state {
+            BufferState::Buffering { expected } => expected,
+            BufferState::Idle | BufferState::Discarding => return,
+        };
+        let limit = expected.unwrap_or(self.max_size);
+        let space = limit - self.buf.len();
+        if data.len() > space && expected.is_none() {
+            let offset = ctx.packet_position().offset();
+            ctx.event(demultiplex::DemuxEvent::PesPacketTooLarge {
+                pid: self.pid,
+                offset,
+                max_size: self.max_size,
+            });
+            self.buf.clear();
+            self.state = BufferState::Discarding;
+            return;
+        }
+        // any data beyond the indicated PES_packet_length is ignored
+        self.buf.extend_from_slice(&data[..data.len().min(space)]);
+        if Some(self.buf.len()) == expected {
+            self.deliver(ctx);
+            self.state = BufferState::Idle;
+        }
+    }
+
+    fn deliver<Ctx>(&mut self, ctx: &mut Ctx)
+    where
+        P: PesPacketConsumer<Ctx>,
+    {
+        if let Ok(header) = PesHeader::from_bytes(&self.buf[..]) {
+            let packet = PesPacket {
+                pid: self.pid,
+                header,
+            };
+            self.consumer.pes_packet(ctx, &packet);
+        }
+        self.buf.clear();
+    }
+}
+impl<Ctx, P> ElementaryStreamConsumer<Ctx> for BufferingElementaryStreamConsumer<P>
+where
+    Ctx: demultiplex::DemuxContext,
+    P: PesPacketConsumer<Ctx>,
+{
+    fn start_stream(&mut self, _ctx: &mut Ctx) {
+        self.buf.clear();
+        self.state = BufferState::Idle;
+    }
+
+    fn begin_packet(&mut self, ctx: &mut Ctx, header: PesHeader<'_>) {
+        self.buf.clear();
+        let expected = match header.pes_packet_length() {
+            PesLength::Bounded(len) => Some(PesHeader::FIXED_HEADER_SIZE + usize::from(len.get())),
+            PesLength::Unbounded => None,
+        };
+        if expected.map(|e| e > self.max_size).unwrap_or(false) {
+            let offset = ctx.packet_position().offset();
+            ctx.event(demultiplex::DemuxEvent::PesPacketTooLarge {
+                pid: self.pid,
+                offset,
+                max_size: self.max_size,
+            });
+            self.state = BufferState::Discarding;
+            return;
+        }
+        self.state = BufferState::Buffering { expected };
+        self.append(ctx, header.buf);
+    }
+
+    fn continue_packet(&mut self, ctx: &mut Ctx, data: &[u8]) {
+        self.append(ctx, data);
+    }
+
+    fn end_packet(&mut self, ctx: &mut Ctx) {
+        match self.state {
+            BufferState::Buffering {
+                expected: Some(expected),
+            } => {
+                let offset = ctx.packet_position().offset();
+                ctx.event(demultiplex::DemuxEvent::PesPacketTruncated {
+                    pid: self.pid,
+                    offset,
+                    expected,
+                    actual: self.buf.len(),
+                });
+                self.buf.clear();
+            }
+            BufferState::Buffering { expected: None } => self.deliver(ctx),
+            BufferState::Idle | BufferState::Discarding => (),
+        }
+        self.state = BufferState::Idle;
+    }
+
+    fn continuity_error(&mut self, _ctx: &mut Ctx) {
+        if let BufferState::Buffering { .. } = self.state {
+            self.buf.clear();
+            self.state = BufferState::Discarding;
+        }
+    }
+
+    fn end_stream(&mut self, ctx: &mut Ctx) {
+        self.consumer.end_stream(ctx);
+    }
+}
+
+/// Implementation of [`ElementaryStreamConsumer`](trait.ElementaryStreamConsumer.html) which
+/// writes the payload of each PES packet to an `io::Write` implementation, producing the raw
+/// elementary stream (for example Annex B video, or ADTS audio).
+///
+/// PES headers are not written, and nor is the content of _padding_stream_ packets, or of packets
+/// whose header could not be parsed.  Since the consumer callbacks can't return errors, the
+/// first error from the underlying writer is retained (and nothing more is written), to be
+/// returned by `into_inner()`.
+pub struct ElementaryStreamWriter<W: io::Write> {
+    writer: W,
+    /// `false` if the data of the current PES packet is being skipped
+    writing: bool,
+    bytes_written: u64,
+    error: Option<io::Error>,
+}
+impl<W: io::Write> ElementaryStreamWriter<W> {
+    /// Create a consumer which will write elementary stream data to the given writer.
+    pub fn new(writer: W) -> ElementaryStreamWriter<W> {
+        ElementaryStreamWriter {
+            writer,
+            writing: false,
+            bytes_written: 0,
+            error: None,
+        }
+    }
+
+    /// The number of bytes of elementary stream data written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Borrow the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns the underlying writer, or the first error that was encountered while writing to
+    /// it.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if !self.writing || self.error.is_some() {
+            return;
+        }
+        match self.writer.write_all(data) {
+            Ok(()) => self.bytes_written += data.len() as u64,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+impl<Ctx, W: io::Write> ElementaryStreamConsumer<Ctx> for ElementaryStreamWriter<W> {
+    fn start_stream(&mut self, _ctx: &mut Ctx) {}
+
+    fn begin_packet(&mut self, _ctx: &mut Ctx, header: PesHeader<'_>) {
+        self.writing = header.stream_id() != StreamId::PaddingStream;
+        match header.contents() {
+            PesContents::Parsed(Some(parsed)) => self.write(parsed.payload()),
+            PesContents::Parsed(None) => self.writing = false,
+            PesContents::Payload(payload) => self.write(payload),
+        }
+    }
+
+    fn continue_packet(&mut self, _ctx: &mut Ctx, data: &[u8]) {
+        self.write(data);
+    }
+
+    fn end_packet(&mut self, _ctx: &mut Ctx) {
+        self.writing = false;
+    }
+
+    fn continuity_error(&mut self, _ctx: &mut Ctx) {}
+
+    fn end_stream(&mut self, _ctx: &mut Ctx) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
 }
 
 /// Type for the length of a PES packet
@@ -221,7 +582,7 @@ pub enum StreamId {
     Unknown(u8),
 }
 impl StreamId {
-    fn is_parsed(&self) -> bool {
+    pub(crate) fn is_parsed(&self) -> bool {
         !matches!(
             self,
             StreamId::ProgramStreamMap
@@ -264,6 +625,35 @@ impl From<u8> for StreamId {
         }
     }
 }
+impl From<StreamId> for u8 {
+    fn from(id: StreamId) -> Self {
+        match id {
+            StreamId::ProgramStreamMap => 0b1011_1100,
+            StreamId::PrivateStream1 => 0b1011_1101,
+            StreamId::PaddingStream => 0b1011_1110,
+            StreamId::PrivateStream2 => 0b1011_1111,
+            StreamId::Audio(v) => 0b1100_0000 | (v & 0b0001_1111),
+            StreamId::Video(v) => 0b1110_0000 | (v & 0b0000_1111),
+            StreamId::EcmStream => 0b1111_0000,
+            StreamId::EmmStream => 0b1111_0001,
+            StreamId::DsmCc => 0b1111_0010,
+            StreamId::Iso13522Stream => 0b1111_0011,
+            StreamId::H2221TypeA => 0b1111_0100,
+            StreamId::H2221TypeB => 0b1111_0101,
+            StreamId::H2221TypeC => 0b1111_0110,
+            StreamId::H2221TypeD => 0b1111_0111,
+            StreamId::H2221TypeE => 0b1111_1000,
+            StreamId::AncillaryStream => 0b1111_1001,
+            StreamId::SlPacketizedStream => 0b1111_1010,
+            StreamId::FlexMuxStream => 0b1111_1011,
+            StreamId::MetadataStream => 0b1111_1100,
+            StreamId::ExtendedStreamId => 0b1111_1101,
+            StreamId::ReservedDataStream => 0b1111_1110,
+            StreamId::ProgramStreamDirectory => 0b1111_1111,
+            StreamId::Unknown(v) => v,
+        }
+    }
+}
 
 /// Header at the start of every PES packet.
 ///
@@ -287,27 +677,26 @@ impl<'buf> PesHeader<'buf> {
     /// Wraps the given slice in a PesHeader, which will then provide method to parse the header
     /// fields within the slice.
     ///
-    /// Returns `None` if the buffer is too small to hold the PES header, or if the PES
-    /// 'start code prefix' is missing.
-    ///
-    /// TODO: should probably return `Result`.
-    pub fn from_bytes(buf: &'buf [u8]) -> Option<PesHeader<'buf>> {
+    /// Returns `Err(PesError::NotEnoughData)` if the buffer is too small to hold the PES header,
+    /// or `Err(PesError::PacketStartCodePrefixInvalid)` if the PES 'start code prefix' is
+    /// missing.
+    pub fn from_bytes(buf: &'buf [u8]) -> Result<PesHeader<'buf>, PesError> {
         // TODO: could the header straddle the boundary between TS packets?
         //       ..In which case we'd need to implement buffering.
         if buf.len() < Self::FIXED_HEADER_SIZE {
-            warn!("Buffer size {} too small to hold PES header", buf.len());
-            return None;
+            return Err(PesError::NotEnoughData {
+                requested: Self::FIXED_HEADER_SIZE,
+                available: buf.len(),
+            });
         }
         let packet_start_code_prefix =
             u32::from(buf[0]) << 16 | u32::from(buf[1]) << 8 | u32::from(buf[2]);
         if packet_start_code_prefix != 1 {
-            warn!(
-                "invalid packet_start_code_prefix 0x{:06x}, expected 0x000001",
-                packet_start_code_prefix
-            );
-            return None;
+            return Err(PesError::PacketStartCodePrefixInvalid(
+                packet_start_code_prefix,
+            ));
         }
-        Some(PesHeader { buf })
+        Ok(PesHeader { buf })
     }
 
     /// Indicator of the type of stream per _ISO/IEC 13818-1_, _Table 2-18_.
@@ -332,15 +721,25 @@ impl<'buf> PesHeader<'buf> {
     pub fn contents(&self) -> PesContents<'buf> {
         let rest = &self.buf[Self::FIXED_HEADER_SIZE..];
         if self.stream_id().is_parsed() {
-            PesContents::Parsed(PesParsedContents::from_bytes(rest))
+            PesContents::Parsed(PesParsedContents::from_bytes(rest).ok())
         } else {
             PesContents::Payload(rest)
         }
     }
+
+    /// Checks that the optional header fields that `contents()` would attempt to parse are
+    /// valid, giving the reason why `contents()` would produce `PesContents::Parsed(None)` if
+    /// not.
+    fn check_contents(&self) -> Result<(), PesError> {
+        if self.stream_id().is_parsed() {
+            PesParsedContents::from_bytes(&self.buf[Self::FIXED_HEADER_SIZE..])?;
+        }
+        Ok(())
+    }
 }
 
 /// Errors which may be encountered while processing PES data.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PesError {
     /// The value of an optional field was requested, but the field is not actually present in the
     /// given PES data
@@ -358,6 +757,20 @@ pub enum PesError {
     /// Marker bits are expected to always have the value `1` -- the value `0` presumably implies
     /// a parsing error.
     MarkerBitNotSet,
+    /// The PES packet did not start with the _packet_start_code_prefix_ value `0x000001`; the
+    /// value found is given.
+    PacketStartCodePrefixInvalid(u32),
+    /// The two bits at the start of the optional PES header fields did not have the required
+    /// value `0b10`; the value found is given.
+    CheckBitsInvalid(u8),
+    /// The _PES_header_data_length_ field was too small to hold the optional fields that the
+    /// header's flags indicate are present.
+    HeaderDataLengthInvalid {
+        /// the number of bytes required by the optional fields that are present
+        required: usize,
+        /// the value of _PES_header_data_length_
+        header_data_len: usize,
+    },
 }
 
 /// Either `PesContents::Payload`, when the `PesHeader` has no extra fields, or
@@ -482,44 +895,35 @@ impl<'buf> PesParsedContents<'buf> {
     /// Wrap the given slice in a `ParsedPesContents` whose methods can parse the structure's
     /// fields
     ///
-    /// Returns `None` if the buffer is too short to hold the expected structure, or if the
-    /// 'check bit' values within the buffer do not have the expected values.
-    ///
-    /// TODO: return `Result`
-    pub fn from_bytes(buf: &'buf [u8]) -> Option<PesParsedContents<'buf>> {
+    /// Returns `Err(PesError::NotEnoughData)` if the buffer is too short to hold the expected
+    /// structure, `Err(PesError::CheckBitsInvalid)` if the 'check bit' values within the buffer
+    /// do not have the expected values, or `Err(PesError::HeaderDataLengthInvalid)` if the
+    /// optional fields indicated by the header's flags do not fit within _PES_header_data_length_.
+    pub fn from_bytes(buf: &'buf [u8]) -> Result<PesParsedContents<'buf>, PesError> {
         if buf.len() < Self::FIXED_HEADER_SIZE {
-            warn!(
-                "buf not large enough to hold PES parsed header: {} bytes",
-                buf.len()
-            );
-            return None;
+            return Err(PesError::NotEnoughData {
+                requested: Self::FIXED_HEADER_SIZE,
+                available: buf.len(),
+            });
         }
         let check_bits = buf[0] >> 6;
         if check_bits != 0b10 {
-            warn!(
-                "unexpected check-bits value {:#b}, expected 0b10",
-                check_bits
-            );
-            return None;
+            return Err(PesError::CheckBitsInvalid(check_bits));
         }
         let contents = PesParsedContents { buf };
         if (Self::FIXED_HEADER_SIZE + contents.pes_header_data_len()) > buf.len() {
-            warn!(
-                "reported PES header length {} does not fit within remaining buffer length {}",
-                contents.pes_header_data_len(),
-                buf.len() - Self::FIXED_HEADER_SIZE,
-            );
-            return None;
+            return Err(PesError::NotEnoughData {
+                requested: Self::FIXED_HEADER_SIZE + contents.pes_header_data_len(),
+                available: buf.len(),
+            });
         }
         if contents.pes_crc_end() > (Self::FIXED_HEADER_SIZE + contents.pes_header_data_len()) {
-            warn!(
-                "calculated PES header data length {} does not fit with in recorded PES_header_length {}",
-                contents.pes_crc_end() - Self::FIXED_HEADER_SIZE,
-                contents.pes_header_data_len(),
-            );
-            return None;
+            return Err(PesError::HeaderDataLengthInvalid {
+                required: contents.pes_crc_end() - Self::FIXED_HEADER_SIZE,
+                header_data_len: contents.pes_header_data_len(),
+            });
         }
-        Some(contents)
+        Ok(contents)
     }
 
     /// value 1 indicates higher priority and 0 indicates lower priority
@@ -975,6 +1379,24 @@ pub enum PtsDts {
         dts: Result<Timestamp, TimestampError>,
     },
 }
+impl PtsDts {
+    /// The Presentation Time Stamp, if present and valid.
+    pub fn pts(&self) -> Option<Timestamp> {
+        match self {
+            PtsDts::PtsOnly(Ok(pts)) | PtsDts::Both { pts: Ok(pts), .. } => Some(*pts),
+            _ => None,
+        }
+    }
+
+    /// The Decode Time Stamp, if present and valid.  Note that when only a PTS is present, the
+    /// decode time is the same as the presentation time, but `None` is returned here.
+    pub fn dts(&self) -> Option<Timestamp> {
+        match self {
+            PtsDts::Both { dts: Ok(dts), .. } => Some(*dts),
+            _ => None,
+        }
+    }
+}
 
 /// Indicates if the start of some 'unit' of Elementary Stream content is immediately at the start of the PES
 /// packet payload.
@@ -1014,6 +1436,7 @@ pub enum OriginalOrCopy {
 mod test {
     use crate::demultiplex;
     use crate::demultiplex::PacketFilter;
+    use crate::mux;
     use crate::packet;
     use crate::pes;
     use assert_matches::assert_matches;
@@ -1291,6 +1714,8 @@ mod test {
         start_stream_called: bool,
         begin_packet_called: bool,
         continuity_error_called: bool,
+        end_packet_count: usize,
+        end_stream_called: bool,
     }
     impl MockState {
         fn new() -> MockState {
@@ -1298,6 +1723,8 @@ mod test {
                 start_stream_called: false,
                 begin_packet_called: false,
                 continuity_error_called: false,
+                end_packet_count: 0,
+                end_stream_called: false,
             }
         }
     }
@@ -1317,10 +1744,18 @@ mod test {
             self.state.borrow_mut().begin_packet_called = true;
         }
         fn continue_packet(&mut self, _ctx: &mut NullDemuxContext, _data: &[u8]) {}
-        fn end_packet(&mut self, _ctx: &mut NullDemuxContext) {}
+        fn end_packet(&mut self, _ctx: &mut NullDemuxContext) {
+            let mut state = self.state.borrow_mut();
+            // end_stream() must come last
+            assert!(!state.end_stream_called);
+            state.end_packet_count += 1;
+        }
         fn continuity_error(&mut self, _ctx: &mut NullDemuxContext) {
             self.state.borrow_mut().continuity_error_called = true;
         }
+        fn end_stream(&mut self, _ctx: &mut NullDemuxContext) {
+            self.state.borrow_mut().end_stream_called = true;
+        }
     }
 
     #[test]
@@ -1348,6 +1783,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn end_stream() {
+        let state = std::rc::Rc::new(std::cell::RefCell::new(MockState::new()));
+        let mock = MockElementaryStreamConsumer::new(state.clone());
+        let mut pes_filter = pes::PesPacketFilter::new(mock);
+        let mut ctx = NullDemuxContext::new();
+        // no PES packet has started, so there's no packet to end, but the stream still ends
+        pes_filter.end_stream(&mut ctx);
+        assert_eq!(state.borrow().end_packet_count, 0);
+        assert!(state.borrow().end_stream_called);
+        state.borrow_mut().end_stream_called = false;
+
+        let buf = hex!("4741F510000001E0000084C00A355DDD11B1155DDBF5910000000109100000000167640029AD843FFFC21FFFE10FFFF087FFF843FFFC21FFFE10FFFFFFFFFFFFFFFF087FFFFFFFFFFFFFFF2CC501E0113F780A1010101F00000303E80000C350940000000168FF3CB0000001060001C006018401103A0408D2BA80000050204E95D400000302040AB500314454473141FEFF53040000C815540DF04F77FFFFFFFFFFFFFFFFFFFF80000000016588800005DB001008673FC365F48EAE");
+        let pk = packet::Packet::new(&buf[..]);
+        pes_filter.consume(&mut ctx, &pk);
+        assert_eq!(state.borrow().end_packet_count, 0);
+        pes_filter.end_stream(&mut ctx);
+        let state = state.borrow();
+        assert_eq!(state.end_packet_count, 1);
+        assert!(state.end_stream_called);
+    }
+
     #[test]
     fn header_length_doesnt_fit() {
         let data = make_test_data(|w| {
@@ -1409,5 +1866,191 @@ mod test {
         });
         let header = pes::PesHeader::from_bytes(&data[..]).unwrap();
         assert!(matches!(header.contents(), pes::PesContents::Parsed(None)));
+        assert_eq!(
+            pes::PesParsedContents::from_bytes(&data[6..]).err(),
+            Some(pes::PesError::HeaderDataLengthInvalid {
+                required: 2,
+                header_data_len: 1,
+            })
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingPesPacketConsumer {
+        packets: Vec<(packet::Pid, Option<pes::Timestamp>, Vec<u8>)>,
+        end_stream_called: bool,
+    }
+    impl pes::PesPacketConsumer<NullDemuxContext> for RecordingPesPacketConsumer {
+        fn pes_packet(&mut self, _ctx: &mut NullDemuxContext, packet: &pes::PesPacket<'_>) {
+            self.packets
+                .push((packet.pid(), packet.pts(), packet.payload().to_vec()));
+        }
+        fn end_stream(&mut self, _ctx: &mut NullDemuxContext) {
+            self.end_stream_called = true;
+        }
+    }
+
+    /// Split a PES packet with a PTS, carrying the given payload, into TS packets
+    fn make_ts_packets(
+        packetiser: &mut mux::PesPacketiser,
+        pts: u64,
+        bounded: bool,
+        payload: &[u8],
+    ) -> Vec<Vec<u8>> {
+        let header = mux::PesHeaderBuilder::new(pes::StreamId::Video(0))
+            .pts(pes::Timestamp::from_u64(pts))
+            .unbounded(!bounded);
+        let mut data = vec![];
+        packetiser
+            .write_packet(&mut data, &header, payload, None)
+            .unwrap();
+        data.chunks(packet::Packet::SIZE)
+            .map(|pk| pk.to_vec())
+            .collect()
+    }
+
+    fn buffering_filter(
+        max_size: usize,
+    ) -> pes::PesPacketFilter<
+        NullDemuxContext,
+        pes::BufferingElementaryStreamConsumer<RecordingPesPacketConsumer>,
+    > {
+        pes::PesPacketFilter::new(
+            pes::BufferingElementaryStreamConsumer::new(
+                packet::Pid::new(0x101),
+                RecordingPesPacketConsumer::default(),
+            )
+            .with_max_size(max_size),
+        )
+    }
+
+    fn consume_all<F: demultiplex::PacketFilter<Ctx = NullDemuxContext>>(
+        filter: &mut F,
+        ctx: &mut NullDemuxContext,
+        packets: &[Vec<u8>],
+    ) {
+        for pk in packets {
+            filter.consume(ctx, &packet::Packet::new(&pk[..]));
+        }
+    }
+
+    #[test]
+    fn buffering_unbounded() {
+        let payload: Vec<u8> = (0..500).map(|i| i as u8).collect();
+        let mut packetiser = mux::PesPacketiser::new(packet::Pid::new(0x101));
+        let first = make_ts_packets(&mut packetiser, 1234, false, &payload);
+        assert_eq!(first.len(), 3);
+        let second = make_ts_packets(&mut packetiser, 5678, false, &[1, 2, 3]);
+        let mut ctx = NullDemuxContext::new();
+        let mut filter = buffering_filter(1 << 20);
+        consume_all(&mut filter, &mut ctx, &first);
+        // not delivered until we know that the PES packet has ended
+        assert!(filter.stream_consumer.consumer().packets.is_empty());
+        consume_all(&mut filter, &mut ctx, &second);
+        filter.end_stream(&mut ctx);
+        let consumer = filter.stream_consumer.consumer();
+        assert_eq!(
+            consumer.packets,
+            vec![
+                (
+                    packet::Pid::new(0x101),
+                    Some(pes::Timestamp::from_u64(1234)),
+                    payload
+                ),
+                (
+                    packet::Pid::new(0x101),
+                    Some(pes::Timestamp::from_u64(5678)),
+                    vec![1, 2, 3]
+                ),
+            ]
+        );
+        assert!(consumer.end_stream_called);
+    }
+
+    #[test]
+    fn buffering_bounded() {
+        let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut packetiser = mux::PesPacketiser::new(packet::Pid::new(0x101));
+        let packets = make_ts_packets(&mut packetiser, 1234, true, &payload);
+        let mut ctx = NullDemuxContext::new();
+        let mut filter = buffering_filter(1 << 20);
+        consume_all(&mut filter, &mut ctx, &packets);
+        // delivered as soon as PES_packet_length bytes are available
+        assert_eq!(
+            filter.stream_consumer.consumer().packets,
+            vec![(
+                packet::Pid::new(0x101),
+                Some(pes::Timestamp::from_u64(1234)),
+                payload
+            )]
+        );
+    }
+
+    #[test]
+    fn buffering_drops_incomplete_packets() {
+        let payload = [0xaa; 1000];
+        let mut packetiser = mux::PesPacketiser::new(packet::Pid::new(0x101));
+        let too_large_unbounded = make_ts_packets(&mut packetiser, 1, false, &payload);
+        let too_large_bounded = make_ts_packets(&mut packetiser, 1, true, &payload);
+        let mut discontinuous = make_ts_packets(&mut packetiser, 1, false, &payload);
+        discontinuous.remove(1);
+        let mut truncated = make_ts_packets(&mut packetiser, 1, true, &payload);
+        truncated.pop();
+        let ok = make_ts_packets(&mut packetiser, 2, false, &payload[..10]);
+        for (max_size, mut packets) in [
+            (500, too_large_unbounded),
+            (500, too_large_bounded),
+            (2000, discontinuous),
+            (2000, truncated),
+        ] {
+            // follow on from the last packet of this case without a continuity error
+            let last_cc = packets.last().unwrap()[3] & 0xf;
+            packets.extend(ok.iter().enumerate().map(|(i, pk)| {
+                let mut pk = pk.clone();
+                pk[3] = (pk[3] & 0xf0) | ((last_cc + 1 + i as u8) & 0xf);
+                pk
+            }));
+            let mut ctx = NullDemuxContext::new();
+            let mut filter = buffering_filter(max_size);
+            consume_all(&mut filter, &mut ctx, &packets);
+            filter.end_stream(&mut ctx);
+            assert_eq!(
+                filter.stream_consumer.consumer().packets,
+                vec![(
+                    packet::Pid::new(0x101),
+                    Some(pes::Timestamp::from_u64(2)),
+                    payload[..10].to_vec(),
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn es_writer() {
+        let payload: Vec<u8> = (0..500).map(|i| i as u8).collect();
+        let mut packetiser = mux::PesPacketiser::new(packet::Pid::new(0x101));
+        let mut packets = make_ts_packets(&mut packetiser, 1234, false, &payload);
+        packets.extend(make_ts_packets(&mut packetiser, 5678, true, &[1, 2, 3]));
+        let mut ctx = NullDemuxContext::new();
+        let mut filter = pes::PesPacketFilter::new(pes::ElementaryStreamWriter::new(vec![]));
+        consume_all(&mut filter, &mut ctx, &packets);
+        filter.end_stream(&mut ctx);
+        let expected = [&payload[..], &[1, 2, 3]].concat();
+        assert_eq!(
+            filter.stream_consumer.bytes_written(),
+            expected.len() as u64
+        );
+        assert_eq!(filter.stream_consumer.into_inner().unwrap(), expected);
+
+        // the first error is kept, and returned in place of the writer
+        let mut buf = [0; 100];
+        let mut filter = pes::PesPacketFilter::new(pes::ElementaryStreamWriter::new(&mut buf[..]));
+        consume_all(&mut filter, &mut ctx, &packets);
+        filter.end_stream(&mut ctx);
+        assert_eq!(filter.stream_consumer.bytes_written(), 0);
+        assert_eq!(
+            filter.stream_consumer.into_inner().unwrap_err().kind(),
+            io::ErrorKind::WriteZero
+        );
     }
 }
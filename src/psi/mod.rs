@@ -24,11 +24,22 @@
 pub mod pat;
 pub mod pmt;
 
+use crate::demultiplex;
 use crate::mpegts_crc;
 use crate::packet;
-use log::warn;
 use std::fmt;
 
+/// Report an event relating to the packet currently being processed, given a function to build
+/// the event from that packet's Pid and offset.
+fn report<Ctx, F>(ctx: &mut Ctx, event: F)
+where
+    Ctx: demultiplex::DemuxContext,
+    F: FnOnce(packet::Pid, u64) -> demultiplex::DemuxEvent,
+{
+    let position = ctx.packet_position();
+    ctx.event(event(position.pid(), position.offset()));
+}
+
 // TODO: there is quite some duplication between XxxSectionSyntaxYyy and XxxCompactSyntaxYyy types
 //       refactor to reduce the repeated code.
 
@@ -54,15 +65,15 @@ pub trait SectionProcessor {
     /// Note that the first 3 bytes of `section_data` contain the header fields that have also
     /// been supplied to this call in the `header` parameter.  This is to allow implementers to
     /// calculate a CRC over the whole section if required.
-    fn start_section<'a>(
+    fn start_section(
         &mut self,
         ctx: &mut Self::Context,
         header: &SectionCommonHeader,
-        section_data: &'a [u8],
+        section_data: &[u8],
     );
     /// may be called to pass the implementation additional slices of section data, if the
     /// complete section was not already passed.
-    fn continue_section<'a>(&mut self, ctx: &mut Self::Context, section_data: &'a [u8]);
+    fn continue_section(&mut self, ctx: &mut Self::Context, section_data: &[u8]);
 
     /// called if there is a problem in the transport stream that means any in-progress section
     /// data should be discarded.
@@ -70,7 +81,7 @@ pub trait SectionProcessor {
 }
 
 /// Represents the value of the Transport Stream `current_next_indicator` field.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CurrentNext {
     /// The section version number applies to the currently applicable section data
     Current,
@@ -157,6 +168,128 @@ impl<'buf> fmt::Debug for TableSyntaxHeader<'buf> {
     }
 }
 
+/// Problems which may prevent a section from being built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SectionBuildError {
+    /// The section's data would not fit within the maximum _section_length_.
+    TooLong {
+        /// The _section_length_ the section would have needed
+        section_length: usize,
+        /// The largest permitted _section_length_
+        limit: usize,
+    },
+}
+impl fmt::Display for SectionBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionBuildError::TooLong {
+                section_length,
+                limit,
+            } => write!(
+                f,
+                "section_length {} exceeds the limit of {}",
+                section_length, limit
+            ),
+        }
+    }
+}
+impl std::error::Error for SectionBuildError {}
+
+/// Builds a complete 'section syntax' PSI section, the counterpart of `SectionCommonHeader` and
+/// `TableSyntaxHeader`.
+///
+/// The section data produced includes the common header, the table syntax header, the given
+/// table-specific payload and the trailing CRC_32, ready to be passed to a
+/// [`SectionPacketiser`](../mux/struct.SectionPacketiser.html).
+#[derive(Debug, Clone)]
+pub struct SyntaxSectionBuilder {
+    table_id: u8,
+    id: u16,
+    version: u8,
+    current_next_indicator: CurrentNext,
+    section_number: u8,
+    last_section_number: u8,
+}
+impl SyntaxSectionBuilder {
+    /// The largest _section_length_ permitted for the tables defined in _ISO/IEC 13818-1_.
+    pub const SECTION_LIMIT: usize = 1021;
+    const CRC_SIZE: usize = 4;
+
+    /// Create a builder for a section of the table with the given `table_id`, and with the given
+    /// value of the `TableSyntaxHeader::id()` field.  The section will initially have version
+    /// `0`, apply to the current table, and be the only section in the table.
+    pub fn new(table_id: u8, id: u16) -> SyntaxSectionBuilder {
+        SyntaxSectionBuilder {
+            table_id,
+            id,
+            version: 0,
+            current_next_indicator: CurrentNext::Current,
+            section_number: 0,
+            last_section_number: 0,
+        }
+    }
+
+    /// Set the _version_number_ field.
+    ///
+    /// Panics if the value does not fit within the 5-bit field (i.e. is greater than `31`).
+    pub fn version(self, version: u8) -> SyntaxSectionBuilder {
+        assert!(version < 32, "invalid version_number {}", version);
+        SyntaxSectionBuilder { version, ..self }
+    }
+
+    /// Set the _current_next_indicator_ field.
+    pub fn current_next_indicator(self, val: CurrentNext) -> SyntaxSectionBuilder {
+        SyntaxSectionBuilder {
+            current_next_indicator: val,
+            ..self
+        }
+    }
+
+    /// Set the _section_number_ and _last_section_number_ fields.
+    pub fn section_number(
+        self,
+        section_number: u8,
+        last_section_number: u8,
+    ) -> SyntaxSectionBuilder {
+        SyntaxSectionBuilder {
+            section_number,
+            last_section_number,
+            ..self
+        }
+    }
+
+    /// Produce the complete section with the given table-specific payload, or an error if the
+    /// payload is too large to fit within a section.
+    pub fn build(&self, payload: &[u8]) -> Result<Vec<u8>, SectionBuildError> {
+        let section_length = TableSyntaxHeader::SIZE + payload.len() + Self::CRC_SIZE;
+        if section_length > Self::SECTION_LIMIT {
+            return Err(SectionBuildError::TooLong {
+                section_length,
+                limit: Self::SECTION_LIMIT,
+            });
+        }
+        let current_next = match self.current_next_indicator {
+            CurrentNext::Current => 1,
+            CurrentNext::Next => 0,
+        };
+        let mut sect = Vec::with_capacity(SectionCommonHeader::SIZE + section_length);
+        sect.extend_from_slice(&[
+            self.table_id,
+            0b1011_0000 | (section_length >> 8) as u8,
+            section_length as u8,
+            (self.id >> 8) as u8,
+            self.id as u8,
+            0b1100_0000 | self.version << 1 | current_next,
+            self.section_number,
+            self.last_section_number,
+        ]);
+        sect.extend_from_slice(payload);
+        let crc = mpegts_crc::sum32(&sect);
+        sect.extend_from_slice(&crc.to_be_bytes());
+        Ok(sect)
+    }
+}
+
 /// An implementation of `WholeSectionSyntaxPayloadParser` which will delegate to another
 /// instance of `WholeSectionSyntaxPayloadParser` only if the CRC of the section data is
 /// correct.
@@ -182,6 +315,7 @@ where
 impl<P> WholeSectionSyntaxPayloadParser for CrcCheckWholeSectionSyntaxPayloadParser<P>
 where
     P: WholeSectionSyntaxPayloadParser,
+    P::Context: demultiplex::DemuxContext,
 {
     type Context = P::Context;
 
@@ -195,17 +329,26 @@ where
         assert!(header.section_syntax_indicator);
         if data.len() < SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE + Self::CRC_SIZE {
             // must be big enough to hold the CRC!
-            warn!(
-                "section data length too small for table_id {}: {}",
-                header.table_id,
-                data.len()
-            );
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::SectionTooShort {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                    length: data.len(),
+                }
+            });
             return;
         }
         // don't apply CRC checks when fuzzing, to give more chances of test data triggering
         // parser bugs,
         if !cfg!(fuzzing) && mpegts_crc::sum32(data) != 0 {
-            warn!("section crc check failed for table_id {}", header.table_id,);
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::SectionCrcMismatch {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                }
+            });
             return;
         }
         self.inner.section(ctx, header, table_syntax_header, data);
@@ -239,7 +382,7 @@ pub trait WholeCompactSyntaxPayloadParser {
 
     /// Method that will receive a complete PSI table section, where the `data` parameter will
     /// be `header.section_length` bytes long
-    fn section<'a>(&mut self, _: &mut Self::Context, header: &SectionCommonHeader, data: &'a [u8]);
+    fn section(&mut self, _: &mut Self::Context, header: &SectionCommonHeader, data: &[u8]);
 }
 
 enum BufferSectionState {
@@ -275,6 +418,7 @@ where
 impl<P> SectionSyntaxPayloadParser for BufferSectionSyntaxParser<P>
 where
     P: WholeSectionSyntaxPayloadParser,
+    P::Context: demultiplex::DemuxContext,
 {
     type Context = P::Context;
 
@@ -304,10 +448,12 @@ where
         }
     }
 
-    fn continue_syntax_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]) {
+    fn continue_syntax_section(&mut self, ctx: &mut Self::Context, data: &[u8]) {
         match self.state {
             BufferSectionState::Complete => {
-                warn!("attempt to add extra data when section already complete");
+                report(ctx, |pid, offset| {
+                    demultiplex::DemuxEvent::UnexpectedSectionData { pid, offset }
+                });
             }
             BufferSectionState::Buffering(remaining) => {
                 let new_remaining = if data.len() > remaining {
@@ -364,14 +510,15 @@ where
 impl<P> CompactSyntaxPayloadParser for BufferCompactSyntaxParser<P>
 where
     P: WholeCompactSyntaxPayloadParser,
+    P::Context: demultiplex::DemuxContext,
 {
     type Context = P::Context;
 
-    fn start_compact_section<'a>(
+    fn start_compact_section(
         &mut self,
         ctx: &mut Self::Context,
         header: &SectionCommonHeader,
-        data: &'a [u8],
+        data: &[u8],
     ) {
         let section_length_with_header = header.section_length + SectionCommonHeader::SIZE;
         if section_length_with_header <= data.len() {
@@ -388,10 +535,12 @@ where
         }
     }
 
-    fn continue_compact_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]) {
+    fn continue_compact_section(&mut self, ctx: &mut Self::Context, data: &[u8]) {
         match self.state {
             BufferSectionState::Complete => {
-                warn!("attempt to add extra data when section already complete");
+                report(ctx, |pid, offset| {
+                    demultiplex::DemuxEvent::UnexpectedSectionData { pid, offset }
+                });
             }
             BufferSectionState::Buffering(remaining) => {
                 let new_remaining = if data.len() > remaining {
@@ -470,7 +619,7 @@ where
             .start_syntax_section(ctx, header, table_syntax_header, data);
     }
 
-    fn continue_syntax_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]) {
+    fn continue_syntax_section(&mut self, ctx: &mut Self::Context, data: &[u8]) {
         if !self.ignore_rest {
             self.inner.continue_syntax_section(ctx, data)
         }
@@ -500,7 +649,7 @@ pub trait SectionSyntaxPayloadParser {
 
     /// may be called to pass the implementation additional slices of section data, if the
     /// complete section was not already passed.
-    fn continue_syntax_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]);
+    fn continue_syntax_section(&mut self, ctx: &mut Self::Context, data: &[u8]);
 
     /// called if there is a problem in the transport stream that means any in-progress section
     /// data should be discarded.
@@ -515,16 +664,16 @@ pub trait CompactSyntaxPayloadParser {
     /// NB the `data` buffer passed to _will_ include the bytes which are represented by `header`
     /// (in order that the called code can check any CRC that covers the
     /// whole section).
-    fn start_compact_section<'a>(
+    fn start_compact_section(
         &mut self,
         ctx: &mut Self::Context,
         header: &SectionCommonHeader,
-        data: &'a [u8],
+        data: &[u8],
     );
 
     /// may be called to pass the implementation additional slices of section data, if the
     /// complete section was not already passed.
-    fn continue_compact_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]);
+    fn continue_compact_section(&mut self, ctx: &mut Self::Context, data: &[u8]);
 
     /// called if there is a problem in the transport stream that means any in-progress section
     /// data should be discarded.
@@ -560,34 +709,49 @@ where
 impl<SP> SectionProcessor for CompactSyntaxSectionProcessor<SP>
 where
     SP: CompactSyntaxPayloadParser,
+    SP::Context: demultiplex::DemuxContext,
 {
     type Context = SP::Context;
 
-    fn start_section<'a>(
+    fn start_section(
         &mut self,
         ctx: &mut Self::Context,
         header: &SectionCommonHeader,
-        data: &'a [u8],
+        data: &[u8],
     ) {
         if header.section_syntax_indicator {
             // Maybe this should actually be allowed in some cases?
-            warn!(
-                "CompactSyntaxSectionProcessor requires that section_syntax_indicator NOT be set in the section header"
-            );
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::UnexpectedSectionSyntax {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                    section_syntax_indicator: true,
+                }
+            });
             self.ignore_rest = true;
             return;
         }
         if data.len() < SectionCommonHeader::SIZE {
-            warn!("CompactSyntaxSectionProcessor data {} too short for header {} (TODO: implement buffering)", data.len(), SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE);
+            // TODO: implement buffering
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::SectionTooShort {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                    length: data.len(),
+                }
+            });
             self.ignore_rest = true;
             return;
         }
         if header.section_length > Self::SECTION_LIMIT {
-            warn!(
-                "CompactSyntaxSectionProcessor section_length={} is too large (limit {})",
-                header.section_length,
-                Self::SECTION_LIMIT
-            );
+            report(ctx, |pid, offset| demultiplex::DemuxEvent::SectionTooLong {
+                pid,
+                offset,
+                table_id: header.table_id,
+                section_length: header.section_length,
+            });
             self.ignore_rest = true;
             return;
         }
@@ -595,7 +759,7 @@ where
         self.payload_parser.start_compact_section(ctx, header, data)
     }
 
-    fn continue_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]) {
+    fn continue_section(&mut self, ctx: &mut Self::Context, data: &[u8]) {
         if !self.ignore_rest {
             self.payload_parser.continue_compact_section(ctx, data)
         }
@@ -635,33 +799,48 @@ where
 impl<SP> SectionProcessor for SectionSyntaxSectionProcessor<SP>
 where
     SP: SectionSyntaxPayloadParser,
+    SP::Context: demultiplex::DemuxContext,
 {
     type Context = SP::Context;
 
-    fn start_section<'a>(
+    fn start_section(
         &mut self,
         ctx: &mut Self::Context,
         header: &SectionCommonHeader,
-        data: &'a [u8],
+        data: &[u8],
     ) {
         if !header.section_syntax_indicator {
-            warn!(
-                "SectionSyntaxSectionProcessor requires that section_syntax_indicator be set in the section header"
-            );
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::UnexpectedSectionSyntax {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                    section_syntax_indicator: false,
+                }
+            });
             self.ignore_rest = true;
             return;
         }
         if data.len() < SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE {
-            warn!("SectionSyntaxSectionProcessor data {} too short for header {} (TODO: implement buffering)", data.len(), SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE);
+            // TODO: implement buffering
+            report(ctx, |pid, offset| {
+                demultiplex::DemuxEvent::SectionTooShort {
+                    pid,
+                    offset,
+                    table_id: header.table_id,
+                    length: data.len(),
+                }
+            });
             self.ignore_rest = true;
             return;
         }
         if header.section_length > Self::SECTION_LIMIT {
-            warn!(
-                "SectionSyntaxSectionProcessor section_length={} is too large (limit {})",
-                header.section_length,
-                Self::SECTION_LIMIT
-            );
+            report(ctx, |pid, offset| demultiplex::DemuxEvent::SectionTooLong {
+                pid,
+                offset,
+                table_id: header.table_id,
+                section_length: header.section_length,
+            });
             self.ignore_rest = true;
             return;
         }
@@ -671,7 +850,7 @@ where
             .start_syntax_section(ctx, header, &table_syntax_header, data)
     }
 
-    fn continue_section<'a>(&mut self, ctx: &mut Self::Context, data: &'a [u8]) {
+    fn continue_section(&mut self, ctx: &mut Self::Context, data: &[u8]) {
         if !self.ignore_rest {
             self.payload_parser.continue_syntax_section(ctx, data)
         }
@@ -729,6 +908,7 @@ where
 impl<P, Ctx> SectionPacketConsumer<P>
 where
     P: SectionProcessor<Context = Ctx>,
+    Ctx: demultiplex::DemuxContext,
 {
     /// Construct a new instance that will delegate processing of section data found in TS packet
     /// payloads to the given `SectionProcessor` instance.
@@ -747,7 +927,10 @@ where
                     let section_data = &pk_buf[1..];
                     if pointer > 0 {
                         if pointer >= section_data.len() {
-                            warn!("PSI pointer beyond end of packet payload");
+                            ctx.event(demultiplex::DemuxEvent::PsiPointerOverflow {
+                                pid: pk.pid(),
+                                offset: pk.offset(),
+                            });
                             self.parser.reset();
                             return;
                         }
@@ -758,9 +941,11 @@ where
                     }
                     let next_sect = &section_data[pointer..];
                     if next_sect.len() < SectionCommonHeader::SIZE {
-                        warn!(
-                            "TODO: not enough bytes to read section header - implement buffering"
-                        );
+                        // TODO: implement buffering
+                        ctx.event(demultiplex::DemuxEvent::SectionHeaderTruncated {
+                            pid: pk.pid(),
+                            offset: pk.offset(),
+                        });
                         self.parser.reset();
                         return;
                     }
@@ -772,10 +957,19 @@ where
                 }
             }
             None => {
-                warn!("no payload present in PSI packet");
+                ctx.event(demultiplex::DemuxEvent::PsiNoPayload {
+                    pid: pk.pid(),
+                    offset: pk.offset(),
+                });
             }
         }
     }
+
+    /// discard any in-progress section data, for instance because the end of the stream has been
+    /// reached and the rest of the section will never arrive.
+    pub fn reset(&mut self) {
+        self.parser.reset();
+    }
 }
 
 #[cfg(test)]
@@ -806,14 +1000,14 @@ mod test {
     struct NullSectionProcessor;
     impl SectionProcessor for NullSectionProcessor {
         type Context = NullDemuxContext;
-        fn start_section<'a>(
+        fn start_section(
             &mut self,
             _ctx: &mut Self::Context,
             _header: &SectionCommonHeader,
-            _section_data: &'a [u8],
+            _section_data: &[u8],
         ) {
         }
-        fn continue_section<'a>(&mut self, _ctx: &mut Self::Context, _section_data: &'a [u8]) {}
+        fn continue_section(&mut self, _ctx: &mut Self::Context, _section_data: &[u8]) {}
         fn reset(&mut self) {}
     }
 
@@ -849,7 +1043,7 @@ mod test {
             state: Rc<RefCell<bool>>,
         }
         impl WholeSectionSyntaxPayloadParser for MockSectParse {
-            type Context = ();
+            type Context = NullDemuxContext;
             fn section<'a>(
                 &mut self,
                 _: &mut Self::Context,
@@ -865,7 +1059,7 @@ mod test {
                 state: state.clone(),
             },
         ));
-        let ctx = &mut ();
+        let ctx = &mut NullDemuxContext::new();
         {
             let sect = hex!(
                 "
@@ -923,6 +1117,62 @@ mod test {
         assert!(!format!("{:?}", header).is_empty());
     }
 
+    #[test]
+    fn build_syntax_section() {
+        // id, version, section_number, last_section_number, payload
+        type Recorded = (u16, u8, u8, u8, Vec<u8>);
+        struct Mock {
+            sections: Rc<RefCell<Vec<Recorded>>>,
+        }
+        impl WholeSectionSyntaxPayloadParser for Mock {
+            type Context = NullDemuxContext;
+            fn section<'a>(
+                &mut self,
+                _: &mut Self::Context,
+                header: &SectionCommonHeader,
+                table_syntax_header: &TableSyntaxHeader<'a>,
+                data: &'a [u8],
+            ) {
+                assert_eq!(header.table_id, 0x42);
+                let start = SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE;
+                self.sections.borrow_mut().push((
+                    table_syntax_header.id(),
+                    table_syntax_header.version(),
+                    table_syntax_header.section_number(),
+                    table_syntax_header.last_section_number(),
+                    data[start..data.len() - 4].to_vec(),
+                ));
+            }
+        }
+        let sections = Rc::new(RefCell::new(vec![]));
+        let mut consumer = SectionPacketConsumer::new(SectionSyntaxSectionProcessor::new(
+            BufferSectionSyntaxParser::new(CrcCheckWholeSectionSyntaxPayloadParser::new(Mock {
+                sections: sections.clone(),
+            })),
+        ));
+        let sect = SyntaxSectionBuilder::new(0x42, 0x1234)
+            .version(31)
+            .section_number(1, 2)
+            .build(&[7; 300])
+            .unwrap();
+        let mut packetiser = crate::mux::SectionPacketiser::new(packet::Pid::new(0x11));
+        let mut out = vec![];
+        packetiser.write_sections(&mut out, &[&sect[..]]).unwrap();
+        let ctx = &mut NullDemuxContext::new();
+        for pk in out.chunks(Packet::SIZE) {
+            consumer.consume(ctx, &Packet::new(pk));
+        }
+        assert_eq!(*sections.borrow(), vec![(0x1234, 31, 1, 2, vec![7; 300])]);
+
+        assert_eq!(
+            SyntaxSectionBuilder::new(0x42, 1).build(&[0; 1013]),
+            Err(SectionBuildError::TooLong {
+                section_length: 1022,
+                limit: SyntaxSectionBuilder::SECTION_LIMIT,
+            })
+        );
+    }
+
     #[test]
     fn dedup_section() {
         struct CallCounts {
@@ -939,7 +1189,7 @@ mod test {
             reset: 0,
         }));
         impl SectionSyntaxPayloadParser for Mock {
-            type Context = ();
+            type Context = NullDemuxContext;
 
             fn start_syntax_section<'a>(
                 &mut self,
@@ -951,7 +1201,7 @@ mod test {
                 self.inner.borrow_mut().start += 1;
             }
 
-            fn continue_syntax_section<'a>(&mut self, _ctx: &mut Self::Context, _data: &'a [u8]) {
+            fn continue_syntax_section(&mut self, _ctx: &mut Self::Context, _data: &[u8]) {
                 self.inner.borrow_mut().cont += 1;
             }
 
@@ -969,7 +1219,7 @@ mod test {
         let table_header = TableSyntaxHeader::new(&sect[SectionCommonHeader::SIZE..]);
         assert_eq!(table_header.version(), 20);
 
-        let ctx = &mut ();
+        let ctx = &mut NullDemuxContext::new();
         dedup.start_syntax_section(ctx, &common_header, &table_header, &[]);
         dedup.continue_syntax_section(ctx, &[]);
         assert_eq!(counts.borrow().start, 1);
@@ -1017,18 +1267,18 @@ mod test {
             inner: Rc<RefCell<CallCounts>>,
         }
         impl CompactSyntaxPayloadParser for Mock {
-            type Context = ();
+            type Context = NullDemuxContext;
 
-            fn start_compact_section<'a>(
+            fn start_compact_section(
                 &mut self,
                 _ctx: &mut Self::Context,
                 _header: &SectionCommonHeader,
-                _data: &'a [u8],
+                _data: &[u8],
             ) {
                 self.inner.borrow_mut().start += 1;
             }
 
-            fn continue_compact_section<'a>(&mut self, _ctx: &mut Self::Context, _data: &'a [u8]) {
+            fn continue_compact_section(&mut self, _ctx: &mut Self::Context, _data: &[u8]) {
                 todo!()
             }
 
@@ -1041,7 +1291,7 @@ mod test {
             inner: counts.clone(),
         });
 
-        let ctx = &mut ();
+        let ctx = &mut NullDemuxContext::new();
 
         // section_syntax_indicator is 0 in the table header, so this still not be passed through
         // to the mock
@@ -1086,13 +1336,13 @@ mod test {
             section_count: usize,
         }
         impl WholeCompactSyntaxPayloadParser for Mock {
-            type Context = ();
+            type Context = NullDemuxContext;
 
-            fn section<'a>(
+            fn section(
                 &mut self,
                 _: &mut Self::Context,
                 header: &SectionCommonHeader,
-                data: &'a [u8],
+                data: &[u8],
             ) {
                 assert_eq!(0x42, header.table_id);
                 // trim of the last byte which we've deliberately supplied, but which is not
@@ -1103,7 +1353,7 @@ mod test {
         }
         let mock = Mock { section_count: 0 };
         let mut parser = BufferCompactSyntaxParser::new(mock);
-        let ctx = &mut ();
+        let ctx = &mut NullDemuxContext::new();
 
         let common_header = SectionCommonHeader::new(&SECT[..SectionCommonHeader::SIZE]);
 
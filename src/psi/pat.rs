@@ -1,11 +1,15 @@
 //! Types related to the _Program Association Table_
 
+use crate::demultiplex::DemuxError;
 use crate::packet;
-use log::warn;
+use crate::psi;
 
 /// The identifier of TS Packets containing Program Association Table sections, with value `0`.
 pub const PAT_PID: packet::Pid = packet::Pid::new(0);
 
+/// The _table_id_ of Program Association Table sections, with value `0`.
+pub const PAT_TABLE_ID: u8 = 0x00;
+
 /// Identifiers related to a specific program within the Transport Stream
 #[derive(Clone, Debug)]
 pub enum ProgramDescriptor {
@@ -41,6 +45,22 @@ impl ProgramDescriptor {
         }
     }
 
+    /// Produces the 4 byte encoding of this entry, as it appears within a PAT section; the
+    /// inverse of `from_bytes()`.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        let program_number = match *self {
+            ProgramDescriptor::Network { .. } => 0,
+            ProgramDescriptor::Program { program_number, .. } => program_number,
+        };
+        let pid = u16::from(self.pid());
+        [
+            (program_number >> 8) as u8,
+            program_number as u8,
+            0b1110_0000 | (pid >> 8) as u8,
+            pid as u8,
+        ]
+    }
+
     /// produces the Pid of either the NIT or PMT, depending on which type of `ProgramDescriptor`
     /// this is
     pub fn pid(&self) -> packet::Pid {
@@ -65,31 +85,162 @@ impl<'buf> PatSection<'buf> {
         PatSection { data }
     }
     /// Returns an iterator over the entries in this program association table section.
+    ///
+    /// Any trailing bytes too few to hold a complete entry are ignored.
     pub fn programs(&self) -> impl Iterator<Item = ProgramDescriptor> + 'buf {
+        self.program_entries().map_while(Result::ok)
+    }
+
+    /// Like `programs()`, but ends with an error if there are trailing bytes too few to hold a
+    /// complete entry.
+    pub(crate) fn program_entries(
+        &self,
+    ) -> impl Iterator<Item = Result<ProgramDescriptor, DemuxError>> + 'buf {
         ProgramIter { buf: self.data }
     }
 }
 
+/// Builds a Program Association Table section, the counterpart of `PatSection`.
+///
+/// ```
+/// # use mpeg2ts_reader::packet;
+/// # use mpeg2ts_reader::psi::pat;
+/// let sect = pat::PatSectionBuilder::new(1)
+///     .program(pat::ProgramDescriptor::Program {
+///         program_number: 1,
+///         pid: packet::Pid::new(0x1000),
+///     })
+///     .build()
+///     .unwrap();
+/// assert_eq!(sect.len(), 3 + 5 + 4 + 4);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PatSectionBuilder {
+    section: psi::SyntaxSectionBuilder,
+    programs: Vec<ProgramDescriptor>,
+}
+impl PatSectionBuilder {
+    /// Create a builder for a PAT section of the Transport Stream with the given
+    /// _transport_stream_id_, initially listing no programs.
+    pub fn new(transport_stream_id: u16) -> PatSectionBuilder {
+        PatSectionBuilder {
+            section: psi::SyntaxSectionBuilder::new(PAT_TABLE_ID, transport_stream_id),
+            programs: vec![],
+        }
+    }
+
+    /// Set the _version_number_ of the section.
+    ///
+    /// Panics if the value is greater than `31`.
+    pub fn version(self, version: u8) -> PatSectionBuilder {
+        PatSectionBuilder {
+            section: self.section.version(version),
+            ..self
+        }
+    }
+
+    /// Set the _current_next_indicator_ of the section.
+    pub fn current_next_indicator(self, val: psi::CurrentNext) -> PatSectionBuilder {
+        PatSectionBuilder {
+            section: self.section.current_next_indicator(val),
+            ..self
+        }
+    }
+
+    /// Add the given entry to the list of programs in the section.
+    pub fn program(mut self, program: ProgramDescriptor) -> PatSectionBuilder {
+        self.programs.push(program);
+        self
+    }
+
+    /// Produce the complete section data, including the CRC, or an error if there are too many
+    /// programs to fit in a single section.
+    pub fn build(&self) -> Result<Vec<u8>, psi::SectionBuildError> {
+        let payload: Vec<u8> = self.programs.iter().flat_map(|p| p.to_bytes()).collect();
+        self.section.build(&payload)
+    }
+}
+
 /// Iterate over the list of programs in a `PatSection`.
 struct ProgramIter<'buf> {
     buf: &'buf [u8],
 }
 impl<'buf> Iterator for ProgramIter<'buf> {
-    type Item = ProgramDescriptor;
+    type Item = Result<ProgramDescriptor, DemuxError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.buf.is_empty() {
             return None;
         }
         if self.buf.len() < 4 {
-            warn!(
-                "too few bytes remaining for PAT descriptor: {}",
-                self.buf.len()
-            );
-            return None;
+            let actual = self.buf.len();
+            self.buf = &[];
+            return Some(Err(DemuxError::NotEnoughData {
+                field: "program_association_entry",
+                expected: 4,
+                actual,
+            }));
         }
         let (head, tail) = self.buf.split_at(4);
         self.buf = tail;
-        Some(ProgramDescriptor::from_bytes(head))
+        Some(Ok(ProgramDescriptor::from_bytes(head)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::psi::{SectionBuildError, SectionCommonHeader, TableSyntaxHeader};
+
+    #[test]
+    fn build_round_trip() {
+        let sect = PatSectionBuilder::new(0x1234)
+            .version(7)
+            .program(ProgramDescriptor::Network {
+                pid: packet::Pid::new(0x10),
+            })
+            .program(ProgramDescriptor::Program {
+                program_number: 0x101,
+                pid: packet::Pid::new(0x1fff),
+            })
+            .build()
+            .unwrap();
+        assert_eq!(crate::mpegts_crc::sum32(&sect), 0);
+        let header = SectionCommonHeader::new(&sect[..SectionCommonHeader::SIZE]);
+        assert_eq!(header.table_id, PAT_TABLE_ID);
+        assert!(header.section_syntax_indicator);
+        assert_eq!(
+            header.section_length,
+            sect.len() - SectionCommonHeader::SIZE
+        );
+        let table_header = TableSyntaxHeader::new(&sect[SectionCommonHeader::SIZE..]);
+        assert_eq!(table_header.id(), 0x1234);
+        assert_eq!(table_header.version(), 7);
+        assert_eq!(
+            table_header.current_next_indicator(),
+            psi::CurrentNext::Current
+        );
+        let start = SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE;
+        let pat = PatSection::new(&sect[start..sect.len() - 4]);
+        let programs: Vec<_> = pat.programs().map(|p| p.to_bytes()).collect();
+        assert_eq!(programs, vec![[0, 0, 0xe0, 0x10], [0x01, 0x01, 0xff, 0xff]]);
+    }
+
+    #[test]
+    fn build_too_long() {
+        let mut builder = PatSectionBuilder::new(1);
+        for i in 1..=254 {
+            builder = builder.program(ProgramDescriptor::Program {
+                program_number: i,
+                pid: packet::Pid::new(0x100 + i),
+            });
+        }
+        assert_eq!(
+            builder.build(),
+            Err(SectionBuildError::TooLong {
+                section_length: 5 + 254 * 4 + 4,
+                limit: 1021,
+            })
+        );
     }
 }
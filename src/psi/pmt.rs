@@ -3,10 +3,13 @@
 use crate::demultiplex::DemuxError;
 use crate::descriptor;
 use crate::packet;
+use crate::psi;
 use crate::StreamType;
-use log::warn;
 use std::fmt;
 
+/// The _table_id_ of Program Map Table sections, with value `2`.
+pub const PMT_TABLE_ID: u8 = 0x02;
+
 /// Sections of the _Program Map Table_ give details of the streams within a particular program
 pub struct PmtSection<'buf> {
     data: &'buf [u8],
@@ -74,6 +77,9 @@ impl<'buf> PmtSection<'buf> {
     fn program_info_length(&self) -> u16 {
         u16::from(self.data[2] & 0b0000_1111) << 8 | u16::from(self.data[3])
     }
+    fn descriptor_bytes(&self) -> &'buf [u8] {
+        &self.data[Self::HEADER_SIZE..Self::HEADER_SIZE + self.program_info_length() as usize]
+    }
     /// Returns an iterator over the descriptors attached to this PMT section.
     pub fn descriptors<Desc: descriptor::Descriptor<'buf> + 'buf>(
         &self,
@@ -83,19 +89,21 @@ impl<'buf> PmtSection<'buf> {
         descriptor::DescriptorIter::new(descriptor_data)
     }
     /// Returns an iterator over the streams of which this program is composed
+    ///
+    /// If the remaining section data is too short to hold the details of a stream, that stream
+    /// and any following it are not produced.
     pub fn streams(&self) -> impl Iterator<Item = StreamInfo<'buf>> {
+        self.stream_entries().map_while(Result::ok)
+    }
+
+    /// Like `streams()`, but ends with an error if the remaining section data is too short to
+    /// hold the details of a stream.
+    pub(crate) fn stream_entries(
+        &self,
+    ) -> impl Iterator<Item = Result<StreamInfo<'buf>, DemuxError>> {
+        // from_bytes() has already checked that program_info_length lies within the section
         let descriptor_end = Self::HEADER_SIZE + self.program_info_length() as usize;
-        if descriptor_end > self.data.len() {
-            warn!(
-                "program_info_length={} extends beyond end of PMT section (section_length={})",
-                self.program_info_length(),
-                self.data.len()
-            );
-            // return an iterator that will produce no items,
-            StreamInfoIter::new(&self.data[0..0])
-        } else {
-            StreamInfoIter::new(&self.data[descriptor_end..])
-        }
+        StreamInfoIter::new(&self.data[descriptor_end..])
     }
 }
 /// Iterator over the `StreamInfo` entries in a `PmtSection`.
@@ -108,17 +116,21 @@ impl<'buf> StreamInfoIter<'buf> {
     }
 }
 impl<'buf> Iterator for StreamInfoIter<'buf> {
-    type Item = StreamInfo<'buf>;
+    type Item = Result<StreamInfo<'buf>, DemuxError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.buf.is_empty() {
             return None;
         }
-        if let Some((stream_info, info_len)) = StreamInfo::from_bytes(self.buf) {
-            self.buf = &self.buf[info_len..];
-            Some(stream_info)
-        } else {
-            None
+        match StreamInfo::from_bytes(self.buf) {
+            Ok((stream_info, info_len)) => {
+                self.buf = &self.buf[info_len..];
+                Some(Ok(stream_info))
+            }
+            Err(e) => {
+                self.buf = &[];
+                Some(Err(e))
+            }
         }
     }
 }
@@ -129,8 +141,8 @@ impl<'buf> Iterator for StreamInfoIter<'buf> {
 ///  - The `elementry_pid` property allows us to find Transport Stream packets that belong to the
 ///    elementry stream
 ///  - `descriptors` _may_ provide extra metadata describing some of the
-///     stream's properties (for example, the streams 'language' might be given in a descriptor; or
-///     it might not)
+///    stream's properties (for example, the streams 'language' might be given in a descriptor; or
+///    it might not)
 pub struct StreamInfo<'buf> {
     data: &'buf [u8],
 }
@@ -138,28 +150,25 @@ pub struct StreamInfo<'buf> {
 impl<'buf> StreamInfo<'buf> {
     const HEADER_SIZE: usize = 5;
 
-    fn from_bytes(data: &'buf [u8]) -> Option<(StreamInfo<'buf>, usize)> {
+    fn from_bytes(data: &'buf [u8]) -> Result<(StreamInfo<'buf>, usize), DemuxError> {
         if data.len() < Self::HEADER_SIZE {
-            warn!(
-                "only {} bytes remaining for stream info, at least {} required {:?}",
-                data.len(),
-                Self::HEADER_SIZE,
-                data
-            );
-            return None;
+            return Err(DemuxError::NotEnoughData {
+                field: "stream_info",
+                expected: Self::HEADER_SIZE,
+                actual: data.len(),
+            });
         }
         let result = StreamInfo { data };
 
         let descriptor_end = Self::HEADER_SIZE + result.es_info_length() as usize;
         if descriptor_end > data.len() {
-            warn!(
-                "PMT section of size {} is not large enough to contain es_info_length of {}",
-                data.len(),
-                result.es_info_length()
-            );
-            return None;
+            return Err(DemuxError::NotEnoughData {
+                field: "es_info",
+                expected: descriptor_end,
+                actual: data.len(),
+            });
         }
-        Some((result, descriptor_end))
+        Ok((result, descriptor_end))
     }
 
     /// The type of this stream
@@ -174,6 +183,10 @@ impl<'buf> StreamInfo<'buf> {
         u16::from(self.data[3] & 0b0000_1111) << 8 | u16::from(self.data[4])
     }
 
+    fn descriptor_bytes(&self) -> &'buf [u8] {
+        &self.data[Self::HEADER_SIZE..Self::HEADER_SIZE + self.es_info_length() as usize]
+    }
+
     /// Returns an iterator over the descriptors attached to this stream
     pub fn descriptors<Desc: descriptor::Descriptor<'buf> + 'buf>(
         &self,
@@ -200,12 +213,164 @@ impl<'buf> fmt::Debug for StreamInfoDescriptorsDebug<'buf> {
     }
 }
 
+/// Builds an entry in the list of streams of a PMT section, the counterpart of `StreamInfo`.
+#[derive(Clone, Debug)]
+pub struct StreamInfoBuilder {
+    stream_type: StreamType,
+    elementary_pid: packet::Pid,
+    descriptors: Vec<u8>,
+}
+impl StreamInfoBuilder {
+    /// Create a builder for a stream of the given type, carried in packets with the given Pid,
+    /// and initially having no descriptors.
+    pub fn new(stream_type: StreamType, elementary_pid: packet::Pid) -> StreamInfoBuilder {
+        StreamInfoBuilder {
+            stream_type,
+            elementary_pid,
+            descriptors: vec![],
+        }
+    }
+
+    /// Append the given data, which must be the encoding of one or more complete descriptors, to
+    /// the descriptors of this stream.
+    pub fn descriptors(mut self, data: &[u8]) -> StreamInfoBuilder {
+        self.descriptors.extend_from_slice(data);
+        self
+    }
+
+    /// The type of this stream
+    pub fn stream_type(&self) -> StreamType {
+        self.stream_type
+    }
+
+    /// The Pid of packets carrying this stream
+    pub fn elementary_pid(&self) -> packet::Pid {
+        self.elementary_pid
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let pid = u16::from(self.elementary_pid);
+        let es_info_length = self.descriptors.len();
+        out.extend_from_slice(&[
+            self.stream_type.into(),
+            0b1110_0000 | (pid >> 8) as u8,
+            pid as u8,
+            0b1111_0000 | (es_info_length >> 8) as u8,
+            es_info_length as u8,
+        ]);
+        out.extend_from_slice(&self.descriptors);
+    }
+}
+impl<'buf> From<&StreamInfo<'buf>> for StreamInfoBuilder {
+    fn from(info: &StreamInfo<'buf>) -> Self {
+        StreamInfoBuilder::new(info.stream_type(), info.elementary_pid())
+            .descriptors(info.descriptor_bytes())
+    }
+}
+
+/// Builds a Program Map Table section, the counterpart of `PmtSection`.
+///
+/// For example, to rewrite a PMT to remove all but the H.264 streams,
+///
+/// ```
+/// # use mpeg2ts_reader::psi::pmt;
+/// # use mpeg2ts_reader::StreamType;
+/// fn h264_only(program_number: u16, sect: &pmt::PmtSection<'_>) -> Vec<u8> {
+///     sect.streams()
+///         .filter(|s| s.stream_type() == StreamType::H264)
+///         .fold(
+///             pmt::PmtSectionBuilder::new(program_number, sect.pcr_pid()),
+///             |b, s| b.stream((&s).into()),
+///         )
+///         .build()
+///         .unwrap()
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PmtSectionBuilder {
+    section: psi::SyntaxSectionBuilder,
+    pcr_pid: packet::Pid,
+    descriptors: Vec<u8>,
+    streams: Vec<StreamInfoBuilder>,
+}
+impl PmtSectionBuilder {
+    /// Create a builder for the PMT section of the given program, whose PCR is carried in
+    /// packets with the given Pid.  The section initially has no descriptors and no streams.
+    pub fn new(program_number: u16, pcr_pid: packet::Pid) -> PmtSectionBuilder {
+        PmtSectionBuilder {
+            section: psi::SyntaxSectionBuilder::new(PMT_TABLE_ID, program_number),
+            pcr_pid,
+            descriptors: vec![],
+            streams: vec![],
+        }
+    }
+
+    /// Create a builder initialised with the PCR Pid, descriptors and streams of the given
+    /// existing section, which can then be extended.
+    pub fn from_section(program_number: u16, sect: &PmtSection<'_>) -> PmtSectionBuilder {
+        let builder = PmtSectionBuilder::new(program_number, sect.pcr_pid())
+            .descriptors(sect.descriptor_bytes());
+        sect.streams().fold(builder, |b, s| b.stream((&s).into()))
+    }
+
+    /// Set the _version_number_ of the section.
+    ///
+    /// Panics if the value is greater than `31`.
+    pub fn version(self, version: u8) -> PmtSectionBuilder {
+        PmtSectionBuilder {
+            section: self.section.version(version),
+            ..self
+        }
+    }
+
+    /// Set the _current_next_indicator_ of the section.
+    pub fn current_next_indicator(self, val: psi::CurrentNext) -> PmtSectionBuilder {
+        PmtSectionBuilder {
+            section: self.section.current_next_indicator(val),
+            ..self
+        }
+    }
+
+    /// Append the given data, which must be the encoding of one or more complete descriptors, to
+    /// the program-level descriptors of this section.
+    pub fn descriptors(mut self, data: &[u8]) -> PmtSectionBuilder {
+        self.descriptors.extend_from_slice(data);
+        self
+    }
+
+    /// Add the given entry to the list of streams in the section.
+    pub fn stream(mut self, stream: StreamInfoBuilder) -> PmtSectionBuilder {
+        self.streams.push(stream);
+        self
+    }
+
+    /// Produce the complete section data, including the CRC, or an error if the streams and
+    /// descriptors are too large to fit in a section.
+    pub fn build(&self) -> Result<Vec<u8>, psi::SectionBuildError> {
+        let pcr_pid = u16::from(self.pcr_pid);
+        let program_info_length = self.descriptors.len();
+        let mut payload = vec![
+            0b1110_0000 | (pcr_pid >> 8) as u8,
+            pcr_pid as u8,
+            0b1111_0000 | (program_info_length >> 8) as u8,
+            program_info_length as u8,
+        ];
+        payload.extend_from_slice(&self.descriptors);
+        for stream in &self.streams {
+            stream.write(&mut payload);
+        }
+        self.section.build(&payload)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::demultiplex::test::make_test_data;
     use crate::demultiplex::DemuxError;
     use crate::descriptor::CoreDescriptors;
-    use crate::psi::pmt::PmtSection;
+    use crate::psi::pmt::{PmtSection, PmtSectionBuilder, StreamInfoBuilder, PMT_TABLE_ID};
+    use crate::psi::{SectionCommonHeader, TableSyntaxHeader};
+    use crate::{packet, StreamType};
     use assert_matches::assert_matches;
     use bitstream_io::BitWrite;
     use hex_literal::hex;
@@ -260,4 +425,56 @@ mod test {
         assert_matches!(iter.next(), Some(Err(_)));
         assert_matches!(iter.next(), None);
     }
+
+    #[test]
+    fn build_round_trip() {
+        let lang = hex!("0a04656e6700");
+        let sect = PmtSectionBuilder::new(0x1234, packet::Pid::new(0x101))
+            .version(3)
+            .descriptors(&hex!("52010b"))
+            .stream(StreamInfoBuilder::new(
+                StreamType::H264,
+                packet::Pid::new(0x101),
+            ))
+            .stream(
+                StreamInfoBuilder::new(StreamType::Adts, packet::Pid::new(0x102))
+                    .descriptors(&lang),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(crate::mpegts_crc::sum32(&sect), 0);
+        let header = SectionCommonHeader::new(&sect[..SectionCommonHeader::SIZE]);
+        assert_eq!(header.table_id, PMT_TABLE_ID);
+        assert_eq!(
+            header.section_length,
+            sect.len() - SectionCommonHeader::SIZE
+        );
+        let table_header = TableSyntaxHeader::new(&sect[SectionCommonHeader::SIZE..]);
+        assert_eq!(table_header.id(), 0x1234);
+        assert_eq!(table_header.version(), 3);
+
+        let start = SectionCommonHeader::SIZE + TableSyntaxHeader::SIZE;
+        let payload = &sect[start..sect.len() - 4];
+        let pmt = PmtSection::from_bytes(payload).unwrap();
+        assert_eq!(pmt.pcr_pid(), packet::Pid::new(0x101));
+        assert_eq!(pmt.descriptors::<CoreDescriptors<'_>>().count(), 1);
+        let streams: Vec<_> = pmt.streams().collect();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].stream_type(), StreamType::H264);
+        assert_eq!(streams[0].elementary_pid(), packet::Pid::new(0x101));
+        assert_eq!(streams[0].descriptors::<CoreDescriptors<'_>>().count(), 0);
+        assert_eq!(streams[1].stream_type(), StreamType::Adts);
+        assert_eq!(streams[1].elementary_pid(), packet::Pid::new(0x102));
+        assert_matches!(
+            streams[1].descriptors::<CoreDescriptors<'_>>().next(),
+            Some(Ok(CoreDescriptors::ISO639Language(_)))
+        );
+
+        // rebuilding from the parsed section should give identical data
+        let rebuilt = PmtSectionBuilder::from_section(0x1234, &pmt)
+            .version(3)
+            .build()
+            .unwrap();
+        assert_eq!(rebuilt, sect);
+    }
 }
@@ -0,0 +1,166 @@
+//! Checks that demultiplexing a complete stream does not write anything to stdout, which would
+//! corrupt the output of applications that use stdout for their own purposes (for instance when
+//! writing an elementary stream to a pipe).
+//!
+//! The default test harness captures stdout, so this test uses its own `main()`, and runs the
+//! demultiplexer in a child process whose stdout it can inspect.
+
+#[macro_use]
+extern crate mpeg2ts_reader;
+
+use mpeg2ts_reader::demultiplex;
+use mpeg2ts_reader::mux;
+use mpeg2ts_reader::packet;
+use mpeg2ts_reader::parser::NALParser;
+use mpeg2ts_reader::pes;
+use mpeg2ts_reader::psi;
+use mpeg2ts_reader::StreamType;
+use std::process::Command;
+
+/// Raw H.264 elementary stream, with each access unit starting with an access unit delimiter
+const SAMPLE_ES: &[u8] = include_bytes!("../a.dat");
+const CHILD_ENV: &str = "MPEG2TS_READER_NO_STDOUT_CHILD";
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+
+packet_filter_switch! {
+    NoStdoutFilterSwitch<NoStdoutDemuxContext> {
+        Pes: pes::PesPacketFilter<NoStdoutDemuxContext,NalElementaryStreamConsumer>,
+        Pat: demultiplex::PatPacketFilter<NoStdoutDemuxContext>,
+        Pmt: demultiplex::PmtPacketFilter<NoStdoutDemuxContext>,
+        Null: demultiplex::NullPacketFilter<NoStdoutDemuxContext>,
+    }
+}
+demux_context!(NoStdoutDemuxContext, NoStdoutFilterSwitch);
+impl NoStdoutDemuxContext {
+    fn do_construct(&mut self, req: demultiplex::FilterRequest<'_, '_>) -> NoStdoutFilterSwitch {
+        match req {
+            demultiplex::FilterRequest::ByPid(psi::pat::PAT_PID) => {
+                NoStdoutFilterSwitch::Pat(demultiplex::PatPacketFilter::default())
+            }
+            demultiplex::FilterRequest::ByPid(_) => {
+                NoStdoutFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+            }
+            demultiplex::FilterRequest::ByStream {
+                stream_type: StreamType::H264,
+                ..
+            } => {
+                NoStdoutFilterSwitch::Pes(pes::PesPacketFilter::new(NalElementaryStreamConsumer {
+                    nal_parser: NALParser::new(),
+                }))
+            }
+            demultiplex::FilterRequest::ByStream { .. } => {
+                NoStdoutFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+            }
+            demultiplex::FilterRequest::Pmt {
+                pid,
+                program_number,
+            } => NoStdoutFilterSwitch::Pmt(demultiplex::PmtPacketFilter::new(pid, program_number)),
+            demultiplex::FilterRequest::Nit { .. } => {
+                NoStdoutFilterSwitch::Null(demultiplex::NullPacketFilter::default())
+            }
+        }
+    }
+}
+
+pub struct NalElementaryStreamConsumer {
+    nal_parser: NALParser,
+}
+impl pes::ElementaryStreamConsumer<NoStdoutDemuxContext> for NalElementaryStreamConsumer {
+    fn start_stream(&mut self, _ctx: &mut NoStdoutDemuxContext) {}
+    fn begin_packet(&mut self, _ctx: &mut NoStdoutDemuxContext, header: pes::PesHeader) {
+        if let pes::PesContents::Parsed(Some(parsed)) = header.contents() {
+            let pts_dts = parsed.pts_dts().ok();
+            let pts = pts_dts.as_ref().and_then(|t| t.pts());
+            let dts = pts_dts.as_ref().and_then(|t| t.dts());
+            self.nal_parser.begin_packet(pts, dts, parsed.payload());
+        }
+    }
+    fn continue_packet(&mut self, _ctx: &mut NoStdoutDemuxContext, data: &[u8]) {
+        self.nal_parser.continue_packet(data);
+    }
+    fn end_packet(&mut self, _ctx: &mut NoStdoutDemuxContext) {
+        self.nal_parser.end_packet();
+        self.nal_parser.drain_segments().for_each(drop);
+    }
+    fn continuity_error(&mut self, _ctx: &mut NoStdoutDemuxContext) {}
+}
+
+/// Splits the sample stream into access units, at each access unit delimiter
+fn access_units(es: &[u8]) -> Vec<&[u8]> {
+    const AUD: [u8; 5] = [0, 0, 0, 1, 9];
+    let mut starts: Vec<usize> = es
+        .windows(AUD.len())
+        .enumerate()
+        .filter(|(_, w)| *w == AUD)
+        .map(|(i, _)| i)
+        .collect();
+    starts.push(es.len());
+    starts.windows(2).map(|w| &es[w[0]..w[1]]).collect()
+}
+
+fn sample_stream() -> Vec<u8> {
+    let pmt_pid = packet::Pid::new(PMT_PID);
+    let video_pid = packet::Pid::new(VIDEO_PID);
+    let pat = psi::pat::PatSectionBuilder::new(1)
+        .program(psi::pat::ProgramDescriptor::Program {
+            program_number: 1,
+            pid: pmt_pid,
+        })
+        .build()
+        .unwrap();
+    let pmt = psi::pmt::PmtSectionBuilder::new(1, video_pid)
+        .stream(psi::pmt::StreamInfoBuilder::new(
+            StreamType::H264,
+            video_pid,
+        ))
+        .build()
+        .unwrap();
+    let mut ts = vec![];
+    mux::SectionPacketiser::new(psi::pat::PAT_PID)
+        .write_sections(&mut ts, &[&pat])
+        .unwrap();
+    mux::SectionPacketiser::new(pmt_pid)
+        .write_sections(&mut ts, &[&pmt])
+        .unwrap();
+    let mut video = mux::PesPacketiser::new(video_pid);
+    for (i, au) in access_units(SAMPLE_ES).iter().enumerate() {
+        let pts = pes::Timestamp::from_u64(90_000 + i as u64 * 3_000);
+        let header = mux::PesHeaderBuilder::new(pes::StreamId::Video(0))
+            .pts(pts)
+            .unbounded(true);
+        video.write_packet(&mut ts, &header, au, None).unwrap();
+    }
+    ts
+}
+
+fn demux(ts: &[u8]) {
+    let mut ctx = NoStdoutDemuxContext::new();
+    let mut demux = demultiplex::Demultiplex::new(&mut ctx);
+    for chunk in ts.chunks(188 * 1024) {
+        demux.push(&mut ctx, chunk);
+    }
+    demux.flush(&mut ctx);
+}
+
+fn main() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        demux(&sample_stream());
+        return;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .env(CHILD_ENV, "1")
+        .output()
+        .expect("failed to run child process");
+    assert!(
+        output.status.success(),
+        "child process failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        output.stdout.is_empty(),
+        "unexpected output on stdout: {:?}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    println!("test no_stdout ... ok");
+}